@@ -0,0 +1,976 @@
+//! Key ceremony workflow for the entry of key components.
+//!
+//! # Description
+//!
+//! In a key ceremony a key is established from several key components, each held by a
+//! different custodian. The components are combined by XOR, so that no single custodian
+//! knows the resulting key. Each custodian enters the component together with its key check
+//! value (KCV), which is verified immediately so that typing errors are detected before the
+//! next custodian continues.
+//!
+//! The `KeyCeremony` state machine implements this workflow:
+//! 1. Create the ceremony with the key type, the expected number of components and optionally
+//!    the expected KCV of the final key.
+//! 2. Add the components one at a time with their claimed KCVs. Each component is verified,
+//!    combined into the key and then wiped.
+//! 3. Call `finish` to obtain the combined key and a ceremony report.
+//!
+//! A component with a wrong KCV aborts the ceremony and wipes the combined state, the ceremony
+//! has to be restarted from the first component.
+//!
+//! # Key Check Values
+//!
+//! - CMAC method: the leftmost 5 bytes of the CMAC of a zero block (X9.24-1), the default for
+//!   AES keys.
+//! - Legacy method: the leftmost 3 bytes of the encryption of a zero block (X9.24-1), the
+//!   default for TDES keys.
+//!
+//! # Example
+//! ```
+//! use paysec::ceremony::{CeremonyKeyType, KeyCeremony};
+//!
+//! let mut ceremony = KeyCeremony::new(CeremonyKeyType::Tdes, 2, Some("F9F4FB")).unwrap();
+//!
+//! let component_1 = hex::decode("0123456789ABCDEFFEDCBA9876543210").unwrap();
+//! ceremony.add_component(component_1, "08D7B4").unwrap();
+//!
+//! let component_2 = hex::decode("89ABCDEF0123456776543210FEDCBA98").unwrap();
+//! ceremony.add_component(component_2, "EB7A8D").unwrap();
+//!
+//! let (key, report) = ceremony.finish().unwrap();
+//! # #[cfg(not(feature = "no-clear-secrets"))]
+//! assert_eq!(key.as_bytes(), hex::decode("88888888888888888888888888888888").unwrap());
+//! assert_eq!(report.final_kcv, "F9F4FB");
+//! ```
+
+use crate::crypto::aes::{aes_cmac, aes_enc_ecb, AES_BLOCK_LEN};
+use crate::crypto::tdes::{tdes_cmac, tdes_enc_block, TDES_BLOCK_LEN};
+use crate::error::PaysecError;
+#[cfg(not(feature = "no-clear-secrets"))]
+use crate::utils::adjust_odd_parity;
+use crate::utils::{ct_hex_encode_upper, wipe};
+use std::fmt;
+use std::time::SystemTime;
+
+/// The type of key established in a key ceremony.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CeremonyKeyType {
+    /// AES key of 16, 24 or 32 bytes, verified with a CMAC KCV by default.
+    Aes,
+    /// TDES key of 16 or 24 bytes, verified with a legacy KCV by default.
+    Tdes,
+}
+
+impl CeremonyKeyType {
+    // Check whether the key length is valid for the key type.
+    fn is_valid_key_len(&self, len: usize) -> bool {
+        match self {
+            CeremonyKeyType::Aes => matches!(len, 16 | 24 | 32),
+            CeremonyKeyType::Tdes => matches!(len, 16 | 24),
+        }
+    }
+
+    // The KCV method used for the key type if none is given.
+    fn default_kcv_method(&self) -> KcvMethod {
+        match self {
+            CeremonyKeyType::Aes => KcvMethod::Cmac,
+            CeremonyKeyType::Tdes => KcvMethod::Legacy,
+        }
+    }
+
+    // Calculate the KCV of a key as uppercase hex string.
+    fn kcv(&self, kcv_method: KcvMethod, key: &[u8]) -> Result<String, PaysecError> {
+        let zero_block = [0u8; AES_BLOCK_LEN];
+        let kcv = match (self, kcv_method) {
+            (CeremonyKeyType::Aes, KcvMethod::Cmac) => aes_cmac(&zero_block, key)?.to_vec(),
+            (CeremonyKeyType::Aes, KcvMethod::Legacy) => aes_enc_ecb(&zero_block, key)?,
+            (CeremonyKeyType::Tdes, KcvMethod::Cmac) => {
+                tdes_cmac(&[0u8; TDES_BLOCK_LEN], key)?.to_vec()
+            }
+            (CeremonyKeyType::Tdes, KcvMethod::Legacy) => {
+                tdes_enc_block(&[0u8; TDES_BLOCK_LEN], key)?.to_vec()
+            }
+        };
+        Ok(ct_hex_encode_upper(&kcv[..kcv_method.len()]))
+    }
+}
+
+/// The method of calculating the KCV of a key or key component.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KcvMethod {
+    /// The leftmost 5 bytes of the CMAC of a zero block.
+    Cmac,
+    /// The leftmost 3 bytes of the encryption of a zero block.
+    Legacy,
+}
+
+impl KcvMethod {
+    // The length of the KCV in bytes.
+    fn len(&self) -> usize {
+        match self {
+            KcvMethod::Cmac => 5,
+            KcvMethod::Legacy => 3,
+        }
+    }
+
+    // Detect the KCV method from the length of a KCV in hex.
+    #[cfg(not(feature = "no-clear-secrets"))]
+    fn from_hex_len(len: usize) -> Option<Self> {
+        match len {
+            10 => Some(KcvMethod::Cmac),
+            6 => Some(KcvMethod::Legacy),
+            _ => None,
+        }
+    }
+}
+
+/// An error of a key ceremony.
+///
+/// Errors about a single component carry its position in the order of entry, starting at 1, so
+/// the custodian can be identified without the component value.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum CeremonyError {
+    /// Fewer than 2 components are expected or given.
+    TooFewComponents(usize),
+    /// The ceremony was aborted by an earlier KCV mismatch.
+    Aborted,
+    /// All expected components have already been entered.
+    AllComponentsEntered(usize),
+    /// The ceremony was finished before all components were entered.
+    MissingComponents {
+        /// The number of expected components.
+        expected: usize,
+        /// The number of components entered.
+        entered: usize,
+    },
+    /// The component is not valid hex.
+    InvalidComponentHex {
+        /// The position of the component.
+        component: usize,
+    },
+    /// The length of the component is not valid for the key type or differs from the earlier
+    /// components.
+    InvalidComponentLength {
+        /// The position of the component.
+        component: usize,
+        /// The length of the component in bytes.
+        len: usize,
+    },
+    /// The length of the declared KCV is neither that of a CMAC nor of a legacy KCV.
+    InvalidKcvLength {
+        /// The position of the component.
+        component: usize,
+        /// The length of the KCV in hex characters.
+        len: usize,
+    },
+    /// The length of the declared KCV differs from the KCV of the first component.
+    KcvLengthMismatch {
+        /// The position of the component.
+        component: usize,
+    },
+    /// The KCV of the component does not match the declared KCV, the ceremony is aborted.
+    KcvMismatch {
+        /// The position of the component.
+        component: usize,
+    },
+    /// The KCV of the combined key does not match the expected final KCV.
+    FinalKcvMismatch,
+    /// The KCV calculation failed.
+    Kcv(PaysecError),
+}
+
+impl CeremonyError {
+    /// Return the position of the component the error is about, starting at 1, if any.
+    pub fn component(&self) -> Option<usize> {
+        match self {
+            CeremonyError::InvalidComponentHex { component }
+            | CeremonyError::InvalidComponentLength { component, .. }
+            | CeremonyError::InvalidKcvLength { component, .. }
+            | CeremonyError::KcvLengthMismatch { component }
+            | CeremonyError::KcvMismatch { component } => Some(*component),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for CeremonyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CeremonyError::TooFewComponents(count) => write!(
+                f,
+                "ERROR CEREMONY: At least 2 components are required, got {}",
+                count
+            ),
+            CeremonyError::Aborted => write!(f, "ERROR CEREMONY: Ceremony was aborted"),
+            CeremonyError::AllComponentsEntered(count) => write!(
+                f,
+                "ERROR CEREMONY: All {} components have already been entered",
+                count
+            ),
+            CeremonyError::MissingComponents { expected, entered } => write!(
+                f,
+                "ERROR CEREMONY: Expected {} components, got {}",
+                expected, entered
+            ),
+            CeremonyError::InvalidComponentHex { component } => write!(
+                f,
+                "ERROR CEREMONY: Component {} is not valid hex",
+                component
+            ),
+            CeremonyError::InvalidComponentLength { component, len } => write!(
+                f,
+                "ERROR CEREMONY: Invalid length of component {}: {}",
+                component, len
+            ),
+            CeremonyError::InvalidKcvLength { component, len } => write!(
+                f,
+                "ERROR CEREMONY: Invalid KCV length of component {}: {} (expected 6 or 10 hex characters)",
+                component, len
+            ),
+            CeremonyError::KcvLengthMismatch { component } => write!(
+                f,
+                "ERROR CEREMONY: KCV length of component {} does not match component 1",
+                component
+            ),
+            CeremonyError::KcvMismatch { component } => write!(
+                f,
+                "ERROR CEREMONY: KCV mismatch for component {}, ceremony aborted",
+                component
+            ),
+            CeremonyError::FinalKcvMismatch => write!(f, "ERROR CEREMONY: Final KCV mismatch"),
+            CeremonyError::Kcv(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for CeremonyError {}
+
+impl From<PaysecError> for CeremonyError {
+    fn from(err: PaysecError) -> Self {
+        CeremonyError::Kcv(err)
+    }
+}
+
+/// The key resulting from a key ceremony.
+///
+/// The key is wiped from memory when the value is dropped.
+pub struct CeremonyKey {
+    key: Vec<u8>,
+}
+
+impl CeremonyKey {
+    /// Return the key bytes.
+    ///
+    /// Not available with the `no-clear-secrets` feature.
+    #[cfg(not(feature = "no-clear-secrets"))]
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.key
+    }
+}
+
+impl fmt::Debug for CeremonyKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CeremonyKey")
+            .field("key", &"<redacted>")
+            .field("key_len", &self.key.len())
+            .finish()
+    }
+}
+
+impl Drop for CeremonyKey {
+    fn drop(&mut self) {
+        wipe(&mut self.key);
+    }
+}
+
+/// Report of a completed key ceremony.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CeremonyReport {
+    /// The verified KCVs of the components in the order of entry.
+    pub component_kcvs: Vec<String>,
+    /// The KCV of the combined key.
+    pub final_kcv: String,
+    /// Time the ceremony was created.
+    pub started_at: SystemTime,
+    /// Time each component was accepted, in the order of entry.
+    pub component_times: Vec<SystemTime>,
+    /// Time the ceremony was finished.
+    pub finished_at: SystemTime,
+}
+
+/// State machine for the entry of key components in a key ceremony.
+pub struct KeyCeremony {
+    key_type: CeremonyKeyType,
+    kcv_method: KcvMethod,
+    expected_components: usize,
+    expected_final_kcv: Option<String>,
+    combined: Vec<u8>,
+    component_kcvs: Vec<String>,
+    component_times: Vec<SystemTime>,
+    started_at: SystemTime,
+    aborted: bool,
+}
+
+impl KeyCeremony {
+    /// Create a new key ceremony verifying the KCVs with the default method of the key type.
+    ///
+    /// # Arguments
+    ///
+    /// * `key_type` - The type of the key to be established.
+    /// * `expected_components` - The number of components, at least 2.
+    /// * `expected_final_kcv` - Optional KCV of the final key as hex string.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if fewer than 2 components are expected.
+    pub fn new(
+        key_type: CeremonyKeyType,
+        expected_components: usize,
+        expected_final_kcv: Option<&str>,
+    ) -> Result<Self, CeremonyError> {
+        Self::new_with_kcv_method(
+            key_type,
+            key_type.default_kcv_method(),
+            expected_components,
+            expected_final_kcv,
+        )
+    }
+
+    /// Create a new key ceremony verifying the KCVs of the components and of the final key with
+    /// the given method, e.g. for AES components with legacy KCVs.
+    ///
+    /// # Arguments
+    ///
+    /// * `key_type` - The type of the key to be established.
+    /// * `kcv_method` - The method of the KCVs.
+    /// * `expected_components` - The number of components, at least 2.
+    /// * `expected_final_kcv` - Optional KCV of the final key as hex string.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if fewer than 2 components are expected.
+    pub fn new_with_kcv_method(
+        key_type: CeremonyKeyType,
+        kcv_method: KcvMethod,
+        expected_components: usize,
+        expected_final_kcv: Option<&str>,
+    ) -> Result<Self, CeremonyError> {
+        if expected_components < 2 {
+            return Err(CeremonyError::TooFewComponents(expected_components));
+        }
+
+        Ok(KeyCeremony {
+            key_type,
+            kcv_method,
+            expected_components,
+            expected_final_kcv: expected_final_kcv.map(|kcv| kcv.to_ascii_uppercase()),
+            combined: Vec::new(),
+            component_kcvs: Vec::new(),
+            component_times: Vec::new(),
+            started_at: SystemTime::now(),
+            aborted: false,
+        })
+    }
+
+    /// Return the number of components accepted so far.
+    pub fn components_entered(&self) -> usize {
+        self.component_kcvs.len()
+    }
+
+    /// Add a key component with the KCV claimed by its custodian.
+    ///
+    /// The KCV of the component is verified before the component is combined into the key.
+    /// The component is wiped in any case. If the KCV does not match, the ceremony is aborted
+    /// and the combined state is wiped.
+    ///
+    /// # Arguments
+    ///
+    /// * `component` - The key component, taken by value so it can be wiped.
+    /// * `claimed_kcv` - The KCV of the component as hex string (case-insensitive).
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if:
+    /// - The ceremony was aborted before or all components have already been entered.
+    /// - The component length is not valid for the key type or differs from earlier components.
+    /// - The KCV of the component does not match the claimed KCV (the ceremony is aborted).
+    pub fn add_component(
+        &mut self,
+        mut component: Vec<u8>,
+        claimed_kcv: &str,
+    ) -> Result<(), CeremonyError> {
+        let result = self.combine_component(&component, claimed_kcv);
+        wipe(&mut component);
+        result
+    }
+
+    /// Finish the ceremony and return the combined key and the ceremony report.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the ceremony was aborted, not all components have been entered, or
+    /// the KCV of the combined key does not match the expected final KCV. The combined state is
+    /// wiped in any case.
+    pub fn finish(mut self) -> Result<(CeremonyKey, CeremonyReport), CeremonyError> {
+        if self.aborted {
+            return Err(CeremonyError::Aborted);
+        }
+        if self.components_entered() != self.expected_components {
+            return Err(CeremonyError::MissingComponents {
+                expected: self.expected_components,
+                entered: self.components_entered(),
+            });
+        }
+
+        let final_kcv = self.key_type.kcv(self.kcv_method, &self.combined)?;
+        if let Some(expected_final_kcv) = &self.expected_final_kcv {
+            if &final_kcv != expected_final_kcv {
+                return Err(CeremonyError::FinalKcvMismatch);
+            }
+        }
+
+        let key = CeremonyKey {
+            key: std::mem::take(&mut self.combined),
+        };
+        let report = CeremonyReport {
+            component_kcvs: std::mem::take(&mut self.component_kcvs),
+            final_kcv,
+            started_at: self.started_at,
+            component_times: std::mem::take(&mut self.component_times),
+            finished_at: SystemTime::now(),
+        };
+
+        Ok((key, report))
+    }
+
+    // Verify a component and combine it into the key, aborting the ceremony on a KCV mismatch.
+    fn combine_component(
+        &mut self,
+        component: &[u8],
+        claimed_kcv: &str,
+    ) -> Result<(), CeremonyError> {
+        if self.aborted {
+            return Err(CeremonyError::Aborted);
+        }
+
+        let number = self.components_entered() + 1;
+        if number > self.expected_components {
+            return Err(CeremonyError::AllComponentsEntered(
+                self.expected_components,
+            ));
+        }
+
+        if !self.key_type.is_valid_key_len(component.len())
+            || (!self.combined.is_empty() && component.len() != self.combined.len())
+        {
+            return Err(CeremonyError::InvalidComponentLength {
+                component: number,
+                len: component.len(),
+            });
+        }
+
+        let kcv = self.key_type.kcv(self.kcv_method, component)?;
+        if kcv != claimed_kcv.to_ascii_uppercase() {
+            self.abort();
+            return Err(CeremonyError::KcvMismatch { component: number });
+        }
+
+        if self.combined.is_empty() {
+            self.combined = component.to_vec();
+        } else {
+            self.combined
+                .iter_mut()
+                .zip(component.iter())
+                .for_each(|(c, k)| *c ^= k);
+        }
+        self.component_kcvs.push(kcv);
+        self.component_times.push(SystemTime::now());
+
+        Ok(())
+    }
+
+    // Wipe the combined state and mark the ceremony as aborted.
+    fn abort(&mut self) {
+        wipe(&mut self.combined);
+        self.combined.clear();
+        self.component_kcvs.clear();
+        self.component_times.clear();
+        self.aborted = true;
+    }
+}
+
+// The combined key is redacted, the component KCVs are shown.
+impl fmt::Debug for KeyCeremony {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KeyCeremony")
+            .field("key_type", &self.key_type)
+            .field("kcv_method", &self.kcv_method)
+            .field("expected_components", &self.expected_components)
+            .field("expected_final_kcv", &self.expected_final_kcv)
+            .field("combined", &"<redacted>")
+            .field("combined_len", &self.combined.len())
+            .field("component_kcvs", &self.component_kcvs)
+            .field("started_at", &self.started_at)
+            .field("aborted", &self.aborted)
+            .finish()
+    }
+}
+
+impl Drop for KeyCeremony {
+    fn drop(&mut self) {
+        wipe(&mut self.combined);
+    }
+}
+
+/// A key component and the KCV declared by its custodian, as entered at a console.
+///
+/// The `Debug` output redacts the component.
+#[derive(Clone, PartialEq, Eq)]
+pub struct ComponentEntry {
+    /// The key component as hex string (case-insensitive).
+    pub component_hex: String,
+    /// The KCV of the component declared by the custodian as hex string (case-insensitive).
+    pub declared_kcv: String,
+}
+
+impl ComponentEntry {
+    // Decode the component, naming the custodian by the position of the entry on error.
+    #[cfg(not(feature = "no-clear-secrets"))]
+    fn decode(&self, number: usize) -> Result<Vec<u8>, CeremonyError> {
+        hex::decode(&self.component_hex)
+            .map_err(|_| CeremonyError::InvalidComponentHex { component: number })
+    }
+}
+
+impl fmt::Debug for ComponentEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ComponentEntry")
+            .field("component_hex", &"<redacted>")
+            .field("component_hex_len", &self.component_hex.len())
+            .field("declared_kcv", &self.declared_kcv)
+            .finish()
+    }
+}
+
+/// Verify manually entered key components and combine them into a Key Block Protection Key.
+///
+/// The KCV method is detected from the length of the declared KCVs: a 5-byte KCV is a CMAC KCV
+/// and a 3-byte KCV a legacy KCV. The key type is taken from the length of the components: a
+/// 32-byte component is an AES key, while 16 and 24 bytes are valid for both AES and TDES and
+/// the key type is the algorithm under which the KCV of the first component matches its
+/// declared KCV. An AES component with a legacy KCV thus stays an AES key.
+///
+/// The components are verified and combined with a `KeyCeremony`, so every decoded component is
+/// wiped after use. The parity of a combined TDES key is adjusted to odd, which does not change
+/// its KCV.
+///
+/// # Arguments
+///
+/// * `entries` - The components in the order of the custodians, at least 2.
+/// * `expected_final_kcv` - Optional KCV of the combined key as hex string, calculated with the
+///   same method as the component KCVs.
+///
+/// # Errors
+///
+/// Returns an error if fewer than 2 components are given, a component is not valid hex or has an
+/// invalid length, the KCV lengths are invalid or differ, or a KCV does not match. Errors about a
+/// component carry the position of the entry, see `CeremonyError::component`, and never contain
+/// the component value.
+///
+/// Not available with the `no-clear-secrets` feature, as it returns the clear KBPK.
+///
+/// # Example
+/// ```
+/// use paysec::ceremony::{assemble_kbpk, CeremonyError, ComponentEntry};
+///
+/// let mut entries = vec![
+///     ComponentEntry {
+///         component_hex: "0123456789ABCDEFFEDCBA9876543210".to_string(),
+///         declared_kcv: "08D7B4".to_string(),
+///     },
+///     ComponentEntry {
+///         component_hex: "89ABCDEF0123456776543210FEDCBA98".to_string(),
+///         declared_kcv: "EB7A8D".to_string(),
+///     },
+/// ];
+///
+/// let kbpk = assemble_kbpk(&entries, Some("F9F4FB")).unwrap();
+/// assert_eq!(kbpk.as_bytes(), hex::decode("89898989898989898989898989898989").unwrap());
+///
+/// entries[1].declared_kcv = "000000".to_string();
+/// let err = assemble_kbpk(&entries, None).unwrap_err();
+/// assert_eq!(err, CeremonyError::KcvMismatch { component: 2 });
+/// assert_eq!(err.component(), Some(2));
+/// ```
+#[cfg(not(feature = "no-clear-secrets"))]
+pub fn assemble_kbpk(
+    entries: &[ComponentEntry],
+    expected_final_kcv: Option<&str>,
+) -> Result<CeremonyKey, CeremonyError> {
+    let Some(first) = entries.first() else {
+        return Err(CeremonyError::TooFewComponents(0));
+    };
+    let kcv_method = KcvMethod::from_hex_len(first.declared_kcv.len()).ok_or(
+        CeremonyError::InvalidKcvLength {
+            component: 1,
+            len: first.declared_kcv.len(),
+        },
+    )?;
+    let mut component = first.decode(1)?;
+    let key_type = detect_key_type(&component, kcv_method, &first.declared_kcv);
+    wipe(&mut component);
+    let key_type = key_type?;
+
+    let mut ceremony =
+        KeyCeremony::new_with_kcv_method(key_type, kcv_method, entries.len(), expected_final_kcv)?;
+    for (index, entry) in entries.iter().enumerate() {
+        let number = index + 1;
+        if entry.declared_kcv.len() != first.declared_kcv.len() {
+            return Err(CeremonyError::KcvLengthMismatch { component: number });
+        }
+        ceremony.add_component(entry.decode(number)?, &entry.declared_kcv)?;
+    }
+
+    let (mut key, _) = ceremony.finish()?;
+    if key_type == CeremonyKeyType::Tdes {
+        adjust_odd_parity(&mut key.key);
+    }
+    Ok(key)
+}
+
+// Take the key type from the length of the first component. A length valid for both AES and
+// TDES is resolved by the algorithm under which the KCV of the component matches.
+#[cfg(not(feature = "no-clear-secrets"))]
+fn detect_key_type(
+    component: &[u8],
+    kcv_method: KcvMethod,
+    declared_kcv: &str,
+) -> Result<CeremonyKeyType, CeremonyError> {
+    match component.len() {
+        32 => Ok(CeremonyKeyType::Aes),
+        16 | 24 => {
+            let aes_kcv = CeremonyKeyType::Aes.kcv(kcv_method, component)?;
+            if aes_kcv == declared_kcv.to_ascii_uppercase() {
+                Ok(CeremonyKeyType::Aes)
+            } else {
+                Ok(CeremonyKeyType::Tdes)
+            }
+        }
+        len => Err(CeremonyError::InvalidComponentLength { component: 1, len }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const AES_COMPONENTS: [(&str, &str); 3] = [
+        ("0123456789ABCDEFFEDCBA9876543210", "2090A67375"),
+        ("11111111111111112222222222222222", "F74D6E24B0"),
+        ("0F0E0D0C0B0A09080706050403020100", "2CDB450A1A"),
+    ];
+
+    const TDES_COMPONENTS: [(&str, &str); 3] = [
+        ("0123456789ABCDEFFEDCBA9876543210", "08D7B4"),
+        ("89ABCDEF0123456776543210FEDCBA98", "EB7A8D"),
+        ("C1C1C1C1C1C1C1C1C1C1C1C1C1C1C1C1", "0F2016"),
+    ];
+
+    #[test]
+    fn test_key_ceremony_three_custodians_aes() {
+        let mut ceremony = KeyCeremony::new(CeremonyKeyType::Aes, 3, Some("44e968e195")).unwrap();
+
+        for (component, kcv) in AES_COMPONENTS {
+            ceremony
+                .add_component(hex::decode(component).unwrap(), kcv)
+                .unwrap();
+        }
+        assert_eq!(ceremony.components_entered(), 3);
+
+        let (key, report) = ceremony.finish().unwrap();
+        assert_eq!(key.key.len(), 16);
+        #[cfg(not(feature = "no-clear-secrets"))]
+        assert_eq!(
+            key.as_bytes(),
+            hex::decode("1F3C597A93B0D5F6DBF89DBE57741132").unwrap()
+        );
+        assert_eq!(
+            report.component_kcvs,
+            vec!["2090A67375", "F74D6E24B0", "2CDB450A1A"]
+        );
+        assert_eq!(report.final_kcv, "44E968E195");
+        assert_eq!(report.component_times.len(), 3);
+        assert!(report.finished_at >= report.started_at);
+    }
+
+    #[test]
+    fn test_key_ceremony_debug_redacts_key() {
+        let mut ceremony = KeyCeremony::new(CeremonyKeyType::Aes, 2, None).unwrap();
+        let (component, kcv) = AES_COMPONENTS[0];
+        ceremony
+            .add_component(hex::decode(component).unwrap(), kcv)
+            .unwrap();
+
+        let debug = format!("{:?}", ceremony);
+        assert!(!debug.contains(component));
+        assert!(!debug.contains(&format!("{:?}", ceremony.combined)));
+        assert!(debug.contains("combined_len: 16"));
+        assert!(debug.contains(kcv));
+
+        let (component, kcv) = AES_COMPONENTS[1];
+        ceremony
+            .add_component(hex::decode(component).unwrap(), kcv)
+            .unwrap();
+        let (key, _) = ceremony.finish().unwrap();
+
+        let debug = format!("{:?}", key);
+        #[cfg(not(feature = "no-clear-secrets"))]
+        {
+            assert!(!debug.contains(&hex::encode_upper(key.as_bytes())));
+            assert!(!debug.contains(&format!("{:?}", key.as_bytes())));
+        }
+        assert_eq!(debug, "CeremonyKey { key: \"<redacted>\", key_len: 16 }");
+    }
+
+    #[test]
+    fn test_key_ceremony_wrong_component_aborts() {
+        let mut ceremony = KeyCeremony::new(CeremonyKeyType::Aes, 3, None).unwrap();
+
+        let (component, kcv) = AES_COMPONENTS[0];
+        ceremony
+            .add_component(hex::decode(component).unwrap(), kcv)
+            .unwrap();
+
+        // The second custodian mistypes the last digit of the component.
+        let res = ceremony.add_component(
+            hex::decode("11111111111111112222222222222223").unwrap(),
+            AES_COMPONENTS[1].1,
+        );
+        assert_eq!(
+            res.err().unwrap().to_string(),
+            "ERROR CEREMONY: KCV mismatch for component 2, ceremony aborted"
+        );
+        assert_eq!(ceremony.components_entered(), 0);
+        assert!(ceremony.combined.is_empty());
+
+        // The aborted ceremony does not accept further components.
+        let (component, kcv) = AES_COMPONENTS[2];
+        let res = ceremony.add_component(hex::decode(component).unwrap(), kcv);
+        assert_eq!(
+            res.err().unwrap().to_string(),
+            "ERROR CEREMONY: Ceremony was aborted"
+        );
+        assert!(ceremony.finish().is_err());
+    }
+
+    #[test]
+    fn test_key_ceremony_final_kcv_mismatch() {
+        let mut ceremony = KeyCeremony::new(CeremonyKeyType::Tdes, 2, Some("000000")).unwrap();
+        ceremony
+            .add_component(
+                hex::decode("0123456789ABCDEFFEDCBA9876543210").unwrap(),
+                "08D7B4",
+            )
+            .unwrap();
+        ceremony
+            .add_component(
+                hex::decode("89ABCDEF0123456776543210FEDCBA98").unwrap(),
+                "EB7A8D",
+            )
+            .unwrap();
+
+        assert_eq!(
+            ceremony.finish().err().unwrap().to_string(),
+            "ERROR CEREMONY: Final KCV mismatch"
+        );
+    }
+
+    #[test]
+    fn test_key_ceremony_incomplete() {
+        let mut ceremony = KeyCeremony::new(CeremonyKeyType::Tdes, 2, None).unwrap();
+        ceremony
+            .add_component(
+                hex::decode("0123456789ABCDEFFEDCBA9876543210").unwrap(),
+                "08D7B4",
+            )
+            .unwrap();
+
+        assert_eq!(
+            ceremony.finish().err().unwrap().to_string(),
+            "ERROR CEREMONY: Expected 2 components, got 1"
+        );
+    }
+
+    fn entries(components: &[(&str, &str)]) -> Vec<ComponentEntry> {
+        components
+            .iter()
+            .map(|(component, kcv)| ComponentEntry {
+                component_hex: component.to_string(),
+                declared_kcv: kcv.to_string(),
+            })
+            .collect()
+    }
+
+    #[cfg(not(feature = "no-clear-secrets"))]
+    #[test]
+    fn test_assemble_kbpk_aes() {
+        let kbpk = assemble_kbpk(&entries(&AES_COMPONENTS[..2]), Some("A032A17A6E")).unwrap();
+        assert_eq!(
+            kbpk.as_bytes(),
+            hex::decode("1032547698BADCFEDCFE98BA54761032").unwrap()
+        );
+
+        let kbpk = assemble_kbpk(&entries(&AES_COMPONENTS), Some("44E968E195")).unwrap();
+        assert_eq!(
+            kbpk.as_bytes(),
+            hex::decode("1F3C597A93B0D5F6DBF89DBE57741132").unwrap()
+        );
+    }
+
+    #[cfg(not(feature = "no-clear-secrets"))]
+    #[test]
+    fn test_assemble_kbpk_aes_legacy_kcv() {
+        // The key type follows the components, so the combined key is not adjusted to odd
+        // parity like a TDES key.
+        let legacy_components = [
+            (AES_COMPONENTS[0].0, "D5C825"),
+            (AES_COMPONENTS[1].0, "C53E7B"),
+            (AES_COMPONENTS[2].0, "E53113"),
+        ];
+        let kbpk = assemble_kbpk(&entries(&legacy_components), Some("401C32")).unwrap();
+        assert_eq!(
+            kbpk.as_bytes(),
+            hex::decode("1F3C597A93B0D5F6DBF89DBE57741132").unwrap()
+        );
+
+        let aes_256_components = [
+            (
+                "00112233445566778899AABBCCDDEEFF0123456789ABCDEFFEDCBA9876543210",
+                "22ADAE",
+            ),
+            (
+                "FFEEDDCCBBAA99887766554433221100F0E1D2C3B4A5968778695A4B3C2D1E0F",
+                "190D88",
+            ),
+        ];
+        let kbpk = assemble_kbpk(&entries(&aes_256_components), Some("A813E3")).unwrap();
+        assert_eq!(
+            kbpk.as_bytes(),
+            hex::decode("FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF1C297A43D0E5B6886B5E0D34A792C1F")
+                .unwrap()
+        );
+    }
+
+    #[cfg(not(feature = "no-clear-secrets"))]
+    #[test]
+    fn test_assemble_kbpk_tdes() {
+        // The XOR of the two components has even parity and is adjusted to odd parity.
+        let kbpk = assemble_kbpk(&entries(&TDES_COMPONENTS[..2]), Some("f9f4fb")).unwrap();
+        assert_eq!(
+            kbpk.as_bytes(),
+            hex::decode("89898989898989898989898989898989").unwrap()
+        );
+
+        let kbpk = assemble_kbpk(&entries(&TDES_COMPONENTS), Some("D549CA")).unwrap();
+        assert_eq!(
+            kbpk.as_bytes(),
+            hex::decode("49494949494949494949494949494949").unwrap()
+        );
+    }
+
+    #[cfg(not(feature = "no-clear-secrets"))]
+    #[test]
+    fn test_assemble_kbpk_wrong_second_component() {
+        let wrong_component = "11111111111111112222222222222223";
+        let mut aes_entries = entries(&AES_COMPONENTS);
+        aes_entries[1].component_hex = wrong_component.to_string();
+        let err = assemble_kbpk(&aes_entries, None).unwrap_err();
+        assert_eq!(err, CeremonyError::KcvMismatch { component: 2 });
+        assert_eq!(err.component(), Some(2));
+        assert_eq!(
+            err.to_string(),
+            "ERROR CEREMONY: KCV mismatch for component 2, ceremony aborted"
+        );
+        assert!(!err.to_string().contains(wrong_component));
+        assert!(!format!("{:?}", err).contains(wrong_component));
+
+        let mut tdes_entries = entries(&TDES_COMPONENTS[..2]);
+        tdes_entries[1].declared_kcv = "08D7B4".to_string();
+        assert_eq!(
+            assemble_kbpk(&tdes_entries, None).unwrap_err().to_string(),
+            "ERROR CEREMONY: KCV mismatch for component 2, ceremony aborted"
+        );
+    }
+
+    #[cfg(not(feature = "no-clear-secrets"))]
+    #[test]
+    fn test_assemble_kbpk_errors() {
+        assert_eq!(
+            assemble_kbpk(&[], None).unwrap_err().to_string(),
+            "ERROR CEREMONY: At least 2 components are required, got 0"
+        );
+        assert_eq!(
+            assemble_kbpk(&entries(&AES_COMPONENTS[..1]), None)
+                .unwrap_err()
+                .to_string(),
+            "ERROR CEREMONY: At least 2 components are required, got 1"
+        );
+        assert_eq!(
+            assemble_kbpk(&entries(&[TDES_COMPONENTS[0], AES_COMPONENTS[1]]), None)
+                .unwrap_err()
+                .to_string(),
+            "ERROR CEREMONY: KCV length of component 2 does not match component 1"
+        );
+        assert_eq!(
+            assemble_kbpk(&entries(&[("0123", "08D7"), TDES_COMPONENTS[1]]), None)
+                .unwrap_err()
+                .to_string(),
+            "ERROR CEREMONY: Invalid KCV length of component 1: 4 (expected 6 or 10 hex characters)"
+        );
+        assert_eq!(
+            assemble_kbpk(
+                &entries(&[
+                    TDES_COMPONENTS[0],
+                    ("89ABCDEF0123456776543210FEDCBAXY", "EB7A8D")
+                ]),
+                None
+            )
+            .unwrap_err()
+            .to_string(),
+            "ERROR CEREMONY: Component 2 is not valid hex"
+        );
+        assert_eq!(
+            assemble_kbpk(
+                &entries(&[("0123456789ABCDEF", "08D7B4"), TDES_COMPONENTS[1]]),
+                None
+            )
+            .unwrap_err(),
+            CeremonyError::InvalidComponentLength {
+                component: 1,
+                len: 8
+            }
+        );
+        assert_eq!(
+            assemble_kbpk(&entries(&[TDES_COMPONENTS[0], AES_COMPONENTS[1]]), None)
+                .unwrap_err()
+                .component(),
+            Some(2)
+        );
+        assert_eq!(
+            assemble_kbpk(&entries(&TDES_COMPONENTS[..2]), Some("000000"))
+                .unwrap_err()
+                .to_string(),
+            "ERROR CEREMONY: Final KCV mismatch"
+        );
+    }
+
+    #[test]
+    fn test_component_entry_debug_redacts_component() {
+        let entry = &entries(&TDES_COMPONENTS[..1])[0];
+        assert_eq!(
+            format!("{:?}", entry),
+            "ComponentEntry { component_hex: \"<redacted>\", component_hex_len: 32, declared_kcv: \"08D7B4\" }"
+        );
+    }
+}
@@ -0,0 +1,357 @@
+//! Internal AES primitives with a selectable backend.
+//!
+//! The key block and PIN block modules only need AES in ECB and CBC mode without padding and
+//! AES-CMAC. These operations are defined by the `AesBackend` trait and implemented by:
+//!
+//! - `SoftAesBackend` (feature `backend-soft-aes`, default): uses the `soft-aes` crate, which
+//!   lacks protections against side-channel attacks.
+//! - `RustCryptoBackend` (feature `backend-rustcrypto`): uses the constant-time `aes` and `cmac`
+//!   crates of the RustCrypto project.
+//!
+//! If both features are enabled, the RustCrypto backend is selected. The free functions of this
+//! module always dispatch to the selected backend.
+
+// Not every operation is needed when only one of the `keyblock` and `pin` modules is enabled.
+#![cfg_attr(
+    any(
+        not(all(feature = "keyblock", feature = "pin")),
+        feature = "no-clear-secrets"
+    ),
+    allow(dead_code)
+)]
+
+use std::error::Error;
+
+#[cfg(not(any(feature = "backend-soft-aes", feature = "backend-rustcrypto")))]
+compile_error!(
+    "paysec requires an AES backend: enable the `backend-soft-aes` or `backend-rustcrypto` feature"
+);
+
+/// The AES cipher block length in bytes.
+pub const AES_BLOCK_LEN: usize = 16;
+
+/// The AES operations required by this crate.
+///
+/// All operations work on data that is a multiple of the block length, no padding is applied.
+pub trait AesBackend {
+    /// Encrypt data in ECB mode.
+    fn enc_ecb(data: &[u8], key: &[u8]) -> Result<Vec<u8>, Box<dyn Error>>;
+
+    /// Decrypt data in ECB mode.
+    fn dec_ecb(data: &[u8], key: &[u8]) -> Result<Vec<u8>, Box<dyn Error>>;
+
+    /// Encrypt data in CBC mode.
+    fn enc_cbc(
+        data: &[u8],
+        key: &[u8],
+        iv: &[u8; AES_BLOCK_LEN],
+    ) -> Result<Vec<u8>, Box<dyn Error>>;
+
+    /// Decrypt data in CBC mode.
+    fn dec_cbc(
+        data: &[u8],
+        key: &[u8],
+        iv: &[u8; AES_BLOCK_LEN],
+    ) -> Result<Vec<u8>, Box<dyn Error>>;
+
+    /// Calculate the AES-CMAC (NIST SP 800-38B) of a message.
+    fn cmac(message: &[u8], key: &[u8]) -> Result<[u8; AES_BLOCK_LEN], Box<dyn Error>>;
+}
+
+/// AES backend based on the `soft-aes` crate.
+#[cfg(all(feature = "backend-soft-aes", not(feature = "backend-rustcrypto")))]
+pub struct SoftAesBackend;
+
+#[cfg(all(feature = "backend-soft-aes", not(feature = "backend-rustcrypto")))]
+impl AesBackend for SoftAesBackend {
+    fn enc_ecb(data: &[u8], key: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+        soft_aes::aes::aes_enc_ecb(data, key, None)
+    }
+
+    fn dec_ecb(data: &[u8], key: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+        soft_aes::aes::aes_dec_ecb(data, key, None)
+    }
+
+    fn enc_cbc(
+        data: &[u8],
+        key: &[u8],
+        iv: &[u8; AES_BLOCK_LEN],
+    ) -> Result<Vec<u8>, Box<dyn Error>> {
+        soft_aes::aes::aes_enc_cbc(data, key, iv, None)
+    }
+
+    fn dec_cbc(
+        data: &[u8],
+        key: &[u8],
+        iv: &[u8; AES_BLOCK_LEN],
+    ) -> Result<Vec<u8>, Box<dyn Error>> {
+        soft_aes::aes::aes_dec_cbc(data, key, iv, None)
+    }
+
+    fn cmac(message: &[u8], key: &[u8]) -> Result<[u8; AES_BLOCK_LEN], Box<dyn Error>> {
+        soft_aes::aes::aes_cmac(message, key)
+    }
+}
+
+/// AES backend based on the RustCrypto `aes` and `cmac` crates.
+#[cfg(feature = "backend-rustcrypto")]
+pub struct RustCryptoBackend;
+
+#[cfg(feature = "backend-rustcrypto")]
+mod rustcrypto {
+    use super::AES_BLOCK_LEN;
+    use aes::cipher::generic_array::GenericArray;
+    use aes::cipher::{BlockDecrypt, BlockEncrypt, KeyInit};
+    use aes::{Aes128, Aes192, Aes256};
+    use cmac::{Cmac, Mac};
+    use std::error::Error;
+
+    /// The AES cipher for one of the supported key lengths.
+    pub enum Cipher {
+        Aes128(Aes128),
+        Aes192(Aes192),
+        Aes256(Aes256),
+    }
+
+    impl Cipher {
+        pub fn new(key: &[u8]) -> Result<Self, Box<dyn Error>> {
+            let invalid = |_| format!("ERROR AES: Invalid key length: {}", key.len());
+            match key.len() {
+                16 => Ok(Cipher::Aes128(
+                    Aes128::new_from_slice(key).map_err(invalid)?,
+                )),
+                24 => Ok(Cipher::Aes192(
+                    Aes192::new_from_slice(key).map_err(invalid)?,
+                )),
+                32 => Ok(Cipher::Aes256(
+                    Aes256::new_from_slice(key).map_err(invalid)?,
+                )),
+                _ => Err(format!("ERROR AES: Invalid key length: {}", key.len()).into()),
+            }
+        }
+
+        pub fn encrypt_block(&self, block: &mut [u8]) {
+            let block = GenericArray::from_mut_slice(block);
+            match self {
+                Cipher::Aes128(c) => c.encrypt_block(block),
+                Cipher::Aes192(c) => c.encrypt_block(block),
+                Cipher::Aes256(c) => c.encrypt_block(block),
+            }
+        }
+
+        pub fn decrypt_block(&self, block: &mut [u8]) {
+            let block = GenericArray::from_mut_slice(block);
+            match self {
+                Cipher::Aes128(c) => c.decrypt_block(block),
+                Cipher::Aes192(c) => c.decrypt_block(block),
+                Cipher::Aes256(c) => c.decrypt_block(block),
+            }
+        }
+    }
+
+    /// Ensure the data is a non-empty multiple of the AES block length.
+    pub fn check_data_len(data: &[u8]) -> Result<(), Box<dyn Error>> {
+        if data.is_empty() || !data.chunks_exact(AES_BLOCK_LEN).remainder().is_empty() {
+            return Err(format!(
+                "ERROR AES: Data length {} is not a multiple of the block length {}",
+                data.len(),
+                AES_BLOCK_LEN
+            )
+            .into());
+        }
+        Ok(())
+    }
+
+    fn cmac_with<C: Mac + KeyInit>(message: &[u8], key: &[u8]) -> [u8; AES_BLOCK_LEN] {
+        let mut mac = <C as KeyInit>::new_from_slice(key).expect("key length checked by caller");
+        mac.update(message);
+        let mut output = [0u8; AES_BLOCK_LEN];
+        output.copy_from_slice(&mac.finalize().into_bytes());
+        output
+    }
+
+    pub fn cmac(message: &[u8], key: &[u8]) -> Result<[u8; AES_BLOCK_LEN], Box<dyn Error>> {
+        match key.len() {
+            16 => Ok(cmac_with::<Cmac<Aes128>>(message, key)),
+            24 => Ok(cmac_with::<Cmac<Aes192>>(message, key)),
+            32 => Ok(cmac_with::<Cmac<Aes256>>(message, key)),
+            _ => Err(format!("ERROR AES: Invalid key length: {}", key.len()).into()),
+        }
+    }
+}
+
+#[cfg(feature = "backend-rustcrypto")]
+impl AesBackend for RustCryptoBackend {
+    fn enc_ecb(data: &[u8], key: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+        rustcrypto::check_data_len(data)?;
+        let cipher = rustcrypto::Cipher::new(key)?;
+        let mut output = data.to_vec();
+        for block in output.chunks_mut(AES_BLOCK_LEN) {
+            cipher.encrypt_block(block);
+        }
+        Ok(output)
+    }
+
+    fn dec_ecb(data: &[u8], key: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+        rustcrypto::check_data_len(data)?;
+        let cipher = rustcrypto::Cipher::new(key)?;
+        let mut output = data.to_vec();
+        for block in output.chunks_mut(AES_BLOCK_LEN) {
+            cipher.decrypt_block(block);
+        }
+        Ok(output)
+    }
+
+    fn enc_cbc(
+        data: &[u8],
+        key: &[u8],
+        iv: &[u8; AES_BLOCK_LEN],
+    ) -> Result<Vec<u8>, Box<dyn Error>> {
+        rustcrypto::check_data_len(data)?;
+        let cipher = rustcrypto::Cipher::new(key)?;
+        let mut output = data.to_vec();
+        let mut chain = *iv;
+        for block in output.chunks_mut(AES_BLOCK_LEN) {
+            block
+                .iter_mut()
+                .zip(chain.iter())
+                .for_each(|(b, c)| *b ^= c);
+            cipher.encrypt_block(block);
+            chain.copy_from_slice(block);
+        }
+        Ok(output)
+    }
+
+    fn dec_cbc(
+        data: &[u8],
+        key: &[u8],
+        iv: &[u8; AES_BLOCK_LEN],
+    ) -> Result<Vec<u8>, Box<dyn Error>> {
+        rustcrypto::check_data_len(data)?;
+        let cipher = rustcrypto::Cipher::new(key)?;
+        let mut output = data.to_vec();
+        let mut chain = *iv;
+        for block in output.chunks_mut(AES_BLOCK_LEN) {
+            let mut next_chain = [0u8; AES_BLOCK_LEN];
+            next_chain.copy_from_slice(block);
+            cipher.decrypt_block(block);
+            block
+                .iter_mut()
+                .zip(chain.iter())
+                .for_each(|(b, c)| *b ^= c);
+            chain = next_chain;
+        }
+        Ok(output)
+    }
+
+    fn cmac(message: &[u8], key: &[u8]) -> Result<[u8; AES_BLOCK_LEN], Box<dyn Error>> {
+        rustcrypto::cmac(message, key)
+    }
+}
+
+/// The AES backend selected by the enabled cargo features.
+#[cfg(feature = "backend-rustcrypto")]
+pub type SelectedBackend = RustCryptoBackend;
+
+/// The AES backend selected by the enabled cargo features.
+#[cfg(all(feature = "backend-soft-aes", not(feature = "backend-rustcrypto")))]
+pub type SelectedBackend = SoftAesBackend;
+
+/// Encrypt data in AES-ECB mode with the selected backend.
+pub fn aes_enc_ecb(data: &[u8], key: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+    SelectedBackend::enc_ecb(data, key)
+}
+
+/// Decrypt data in AES-ECB mode with the selected backend.
+pub fn aes_dec_ecb(data: &[u8], key: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+    SelectedBackend::dec_ecb(data, key)
+}
+
+/// Encrypt data in AES-CBC mode with the selected backend.
+pub fn aes_enc_cbc(
+    data: &[u8],
+    key: &[u8],
+    iv: &[u8; AES_BLOCK_LEN],
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    SelectedBackend::enc_cbc(data, key, iv)
+}
+
+/// Decrypt data in AES-CBC mode with the selected backend.
+pub fn aes_dec_cbc(
+    data: &[u8],
+    key: &[u8],
+    iv: &[u8; AES_BLOCK_LEN],
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    SelectedBackend::dec_cbc(data, key, iv)
+}
+
+/// Calculate the AES-CMAC of a message with the selected backend.
+pub fn aes_cmac(message: &[u8], key: &[u8]) -> Result<[u8; AES_BLOCK_LEN], Box<dyn Error>> {
+    SelectedBackend::cmac(message, key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The vectors are taken from NIST SP 800-38A (F.1.1, F.2.1) and SP 800-38B (D.1) and run
+    // against whichever backend is selected by the enabled features.
+
+    const KEY_128: &str = "2b7e151628aed2a6abf7158809cf4f3c";
+    const PLAINTEXT: &str = "6bc1bee22e409f96e93d7e117393172aae2d8a571e03ac9c9eb76fac45af8e51";
+
+    #[test]
+    fn test_aes_ecb_sp800_38a() {
+        let key = hex::decode(KEY_128).unwrap();
+        let plaintext = hex::decode(PLAINTEXT).unwrap();
+        let expected =
+            hex::decode("3ad77bb40d7a3660a89ecaf32466ef97f5d3d58503b9699de785895a96fdbaaf")
+                .unwrap();
+
+        let ciphertext = aes_enc_ecb(&plaintext, &key).unwrap();
+        assert_eq!(ciphertext, expected);
+        assert_eq!(aes_dec_ecb(&ciphertext, &key).unwrap(), plaintext);
+    }
+
+    #[test]
+    fn test_aes_cbc_sp800_38a() {
+        let key = hex::decode(KEY_128).unwrap();
+        let iv: [u8; AES_BLOCK_LEN] = hex::decode("000102030405060708090a0b0c0d0e0f")
+            .unwrap()
+            .try_into()
+            .unwrap();
+        let plaintext = hex::decode(PLAINTEXT).unwrap();
+        let expected =
+            hex::decode("7649abac8119b246cee98e9b12e9197d5086cb9b507219ee95db113a917678b2")
+                .unwrap();
+
+        let ciphertext = aes_enc_cbc(&plaintext, &key, &iv).unwrap();
+        assert_eq!(ciphertext, expected);
+        assert_eq!(aes_dec_cbc(&ciphertext, &key, &iv).unwrap(), plaintext);
+    }
+
+    #[test]
+    fn test_aes_cmac_sp800_38b() {
+        let key = hex::decode(KEY_128).unwrap();
+
+        assert_eq!(
+            aes_cmac(&[], &key).unwrap().to_vec(),
+            hex::decode("bb1d6929e95937287fa37d129b756746").unwrap()
+        );
+        assert_eq!(
+            aes_cmac(&hex::decode(&PLAINTEXT[..32]).unwrap(), &key)
+                .unwrap()
+                .to_vec(),
+            hex::decode("070a16b46b4d4144f79bdd9dd04a287c").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_aes_invalid_key_length() {
+        let key = [0u8; 17];
+        let data = [0u8; AES_BLOCK_LEN];
+
+        assert!(aes_enc_ecb(&data, &key).is_err());
+        assert!(aes_cmac(&data, &key).is_err());
+    }
+}
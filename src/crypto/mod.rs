@@ -0,0 +1,3 @@
+pub mod aes;
+#[cfg(feature = "keyblock")]
+pub mod tdes;
@@ -0,0 +1,265 @@
+//! Internal TDEA (Triple DES) primitives.
+//!
+//! This module wraps the block cipher of the `des` crate into the handful of operations needed by
+//! the TDEA based key block versions. Both double-length (2-key, 16 bytes) and triple-length
+//! (3-key, 24 bytes) keys are supported.
+
+use des::cipher::generic_array::GenericArray;
+use des::cipher::{BlockDecrypt, BlockEncrypt, KeyInit};
+#[cfg(feature = "mac")]
+use des::Des;
+use des::{TdesEde2, TdesEde3};
+use std::error::Error;
+
+/// The TDEA cipher block length in bytes.
+pub const TDES_BLOCK_LEN: usize = 8;
+
+// Constant used for the CMAC subkey generation with a 64 bit block cipher (NIST SP 800-38B).
+const CMAC_RB_64: u8 = 0x1B;
+
+/// Encrypt a single 8-byte block with TDEA in ECB mode.
+///
+/// # Errors
+///
+/// Returns an error if the key is not 16 or 24 bytes long.
+pub fn tdes_enc_block(
+    block: &[u8; TDES_BLOCK_LEN],
+    key: &[u8],
+) -> Result<[u8; TDES_BLOCK_LEN], Box<dyn Error>> {
+    let mut output = GenericArray::clone_from_slice(block);
+
+    match key.len() {
+        16 => TdesEde2::new_from_slice(key)
+            .map_err(|_| "ERROR TDES: Invalid key length")?
+            .encrypt_block(&mut output),
+        24 => TdesEde3::new_from_slice(key)
+            .map_err(|_| "ERROR TDES: Invalid key length")?
+            .encrypt_block(&mut output),
+        _ => return Err("ERROR TDES: Invalid key length".into()),
+    }
+
+    Ok(output.into())
+}
+
+/// Decrypt a single 8-byte block with TDEA in ECB mode.
+///
+/// # Errors
+///
+/// Returns an error if the key is not 16 or 24 bytes long.
+pub fn tdes_dec_block(
+    block: &[u8; TDES_BLOCK_LEN],
+    key: &[u8],
+) -> Result<[u8; TDES_BLOCK_LEN], Box<dyn Error>> {
+    let mut output = GenericArray::clone_from_slice(block);
+
+    match key.len() {
+        16 => TdesEde2::new_from_slice(key)
+            .map_err(|_| "ERROR TDES: Invalid key length")?
+            .decrypt_block(&mut output),
+        24 => TdesEde3::new_from_slice(key)
+            .map_err(|_| "ERROR TDES: Invalid key length")?
+            .decrypt_block(&mut output),
+        _ => return Err("ERROR TDES: Invalid key length".into()),
+    }
+
+    Ok(output.into())
+}
+
+/// Encrypt data in TDEA-CBC mode without padding.
+///
+/// # Errors
+///
+/// Returns an error if the key is not 16 or 24 bytes long or the data length is not a multiple
+/// of the block length.
+pub fn tdes_enc_cbc(
+    data: &[u8],
+    key: &[u8],
+    iv: &[u8; TDES_BLOCK_LEN],
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    check_cbc_data_len(data)?;
+
+    let mut output = Vec::with_capacity(data.len());
+    let mut state = *iv;
+    for block in data.chunks_exact(TDES_BLOCK_LEN) {
+        for (s, b) in state.iter_mut().zip(block) {
+            *s ^= b;
+        }
+        state = tdes_enc_block(&state, key)?;
+        output.extend_from_slice(&state);
+    }
+    Ok(output)
+}
+
+/// Decrypt data in TDEA-CBC mode without padding.
+///
+/// # Errors
+///
+/// Returns an error if the key is not 16 or 24 bytes long or the data length is not a multiple
+/// of the block length.
+pub fn tdes_dec_cbc(
+    data: &[u8],
+    key: &[u8],
+    iv: &[u8; TDES_BLOCK_LEN],
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    check_cbc_data_len(data)?;
+
+    let mut output = Vec::with_capacity(data.len());
+    let mut previous = *iv;
+    for block in data.chunks_exact(TDES_BLOCK_LEN) {
+        let block: [u8; TDES_BLOCK_LEN] = block.try_into()?;
+        let mut plain = tdes_dec_block(&block, key)?;
+        for (p, b) in plain.iter_mut().zip(previous.iter()) {
+            *p ^= b;
+        }
+        output.extend_from_slice(&plain);
+        previous = block;
+    }
+    Ok(output)
+}
+
+// The CBC mode functions do not pad, so the data must consist of complete blocks.
+fn check_cbc_data_len(data: &[u8]) -> Result<(), Box<dyn Error>> {
+    if !data.len().is_multiple_of(TDES_BLOCK_LEN) {
+        return Err(format!(
+            "ERROR TDES: Data length {} is not a multiple of the block length",
+            data.len()
+        )
+        .into());
+    }
+    Ok(())
+}
+
+/// Encrypt a single 8-byte block with single DES, as needed for the ISO 9797-1 MAC algorithm 3.
+///
+/// # Errors
+///
+/// Returns an error if the key is not 8 bytes long.
+#[cfg(feature = "mac")]
+pub fn des_enc_block(
+    block: &[u8; TDES_BLOCK_LEN],
+    key: &[u8],
+) -> Result<[u8; TDES_BLOCK_LEN], Box<dyn Error>> {
+    let mut output = GenericArray::clone_from_slice(block);
+    Des::new_from_slice(key)
+        .map_err(|_| "ERROR DES: Invalid key length")?
+        .encrypt_block(&mut output);
+    Ok(output.into())
+}
+
+/// Decrypt a single 8-byte block with single DES, as needed for the ISO 9797-1 MAC algorithm 3.
+///
+/// # Errors
+///
+/// Returns an error if the key is not 8 bytes long.
+#[cfg(feature = "mac")]
+pub fn des_dec_block(
+    block: &[u8; TDES_BLOCK_LEN],
+    key: &[u8],
+) -> Result<[u8; TDES_BLOCK_LEN], Box<dyn Error>> {
+    let mut output = GenericArray::clone_from_slice(block);
+    Des::new_from_slice(key)
+        .map_err(|_| "ERROR DES: Invalid key length")?
+        .decrypt_block(&mut output);
+    Ok(output.into())
+}
+
+/// Compute the TDEA CMAC (NIST SP 800-38B) of a message.
+///
+/// # Errors
+///
+/// Returns an error if the key is not 16 or 24 bytes long.
+pub fn tdes_cmac(message: &[u8], key: &[u8]) -> Result<[u8; TDES_BLOCK_LEN], Box<dyn Error>> {
+    // Derive the subkeys K1 and K2
+    let l = tdes_enc_block(&[0u8; TDES_BLOCK_LEN], key)?;
+    let k1 = cmac_dbl(&l);
+    let k2 = cmac_dbl(&k1);
+
+    // Split the message into blocks, the last one is either complete or padded with 0x80 00..
+    let num_blocks = std::cmp::max(1, message.len().div_ceil(TDES_BLOCK_LEN));
+    let last_start = (num_blocks - 1) * TDES_BLOCK_LEN;
+    let last_len = message.len() - last_start;
+    let last_complete = last_len == TDES_BLOCK_LEN;
+
+    let mut last_block = [0u8; TDES_BLOCK_LEN];
+    last_block[..last_len].copy_from_slice(&message[last_start..]);
+    let subkey = if last_complete {
+        k1
+    } else {
+        last_block[last_len] = 0x80;
+        k2
+    };
+    for (b, k) in last_block.iter_mut().zip(subkey.iter()) {
+        *b ^= k;
+    }
+
+    // CBC-MAC over all blocks with a zero IV
+    let mut state = [0u8; TDES_BLOCK_LEN];
+    for chunk in message[..last_start].chunks(TDES_BLOCK_LEN) {
+        for (s, b) in state.iter_mut().zip(chunk.iter()) {
+            *s ^= b;
+        }
+        state = tdes_enc_block(&state, key)?;
+    }
+    for (s, b) in state.iter_mut().zip(last_block.iter()) {
+        *s ^= b;
+    }
+
+    tdes_enc_block(&state, key)
+}
+
+// Multiply a 64 bit value by x in GF(2^64) as required for the CMAC subkeys.
+fn cmac_dbl(input: &[u8; TDES_BLOCK_LEN]) -> [u8; TDES_BLOCK_LEN] {
+    let mut output = [0u8; TDES_BLOCK_LEN];
+    for i in 0..TDES_BLOCK_LEN {
+        output[i] = input[i] << 1;
+        if i + 1 < TDES_BLOCK_LEN {
+            output[i] |= input[i + 1] >> 7;
+        }
+    }
+    if input[0] & 0x80 != 0 {
+        output[TDES_BLOCK_LEN - 1] ^= CMAC_RB_64;
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tdes_cmac_sp800_38b() {
+        // NIST SP 800-38B, D.2 Three Key TDEA, examples 1 to 3
+        let key = hex::decode("8aa83bf8cbda10620bc1bf19fbb6cd58bc313d4a371ca8b5").unwrap();
+
+        let mac = tdes_cmac(&[], &key).unwrap();
+        assert_eq!(hex::encode(mac), "b7a688e122ffaf95");
+
+        let message = hex::decode("6bc1bee22e409f96").unwrap();
+        let mac = tdes_cmac(&message, &key).unwrap();
+        assert_eq!(hex::encode(mac), "8e8f293136283797");
+
+        let message = hex::decode("6bc1bee22e409f96e93d7e117393172aae2d8a57").unwrap();
+        let mac = tdes_cmac(&message, &key).unwrap();
+        assert_eq!(hex::encode(mac), "743ddbe0ce2dc2ed");
+    }
+
+    #[test]
+    fn test_tdes_cbc_nist_example() {
+        // NIST TDES-CBC example with three keys, blocks 1 and 2
+        let key = hex::decode("0123456789ABCDEF23456789ABCDEF01456789ABCDEF0123").unwrap();
+        let iv = hex::decode("F69F2445DF4F9B17").unwrap().try_into().unwrap();
+        let plaintext = hex::decode("6BC1BEE22E409F96E93D7E117393172A").unwrap();
+
+        let ciphertext = tdes_enc_cbc(&plaintext, &key, &iv).unwrap();
+        assert_eq!(hex::encode(&ciphertext), "2079c3d53aa763e193b79e2569ab5262");
+        assert_eq!(tdes_dec_cbc(&ciphertext, &key, &iv).unwrap(), plaintext);
+
+        assert!(tdes_enc_cbc(&plaintext[..12], &key, &iv).is_err());
+    }
+
+    #[test]
+    fn test_tdes_invalid_key_length() {
+        assert!(tdes_enc_block(&[0u8; TDES_BLOCK_LEN], &[0u8; 8]).is_err());
+        assert!(tdes_cmac(&[0u8; 8], &[0u8; 17]).is_err());
+    }
+}
@@ -0,0 +1,390 @@
+//! AES DUKPT initial key derivation and key injection batches.
+//!
+//! # Standard
+//!
+//! Accredited Standards Committee X9: "ANSI X9.24-3-2017: Retail Financial Services Symmetric Key
+//! Management Part 3: Derived Unique Key Per Transaction"
+//! In the following referenced as "X9.24-3: 2017"
+//!
+//! # Description
+//!
+//! With AES DUKPT every device is loaded with an Initial DUKPT Key (IPEK) which is derived from a
+//! Base Derivation Key (BDK) and the 8-byte Initial Key ID of the device. The Initial Key ID is the
+//! concatenation of the 4-byte BDK ID and the 4-byte Derivation ID of the device.
+//!
+//! Key injection facilities deliver the IPEK to the device in a TR-31 key block with key usage
+//! `B1` (Initial DUKPT Key) and an `IK` optional block carrying the Initial Key ID in hex-ASCII.
+//! `generate_injection_batch` produces these key blocks for a list of devices.
+//!
+//! The Key Serial Number (KSN) sent with each transaction ends with a transaction counter.
+//! `ksn_counter` extracts the counter and `ksn_delta` the number of transactions between two KSNs
+//! of the same device, for both AES DUKPT (12-byte KSN, 32-bit counter) and TDES DUKPT (10-byte
+//! KSN, 21-bit counter).
+//!
+//! This module is only available with the `dukpt` feature.
+//!
+//! # Example
+//! ```
+//! # #[cfg(not(feature = "no-clear-secrets"))]
+//! # {
+//! use paysec::dukpt::{derive_initial_key, DukptKeyType};
+//!
+//! let bdk = hex::decode("FEDCBA9876543210F1F1F1F1F1F1F1F1").unwrap();
+//! let initial_key_id = [0x12, 0x34, 0x56, 0x78, 0x90, 0x12, 0x34, 0x56];
+//!
+//! let ipek = derive_initial_key(&bdk, &initial_key_id, DukptKeyType::Aes128).unwrap();
+//! assert_eq!(hex::encode_upper(ipek), "1273671EA26AC29AFA4D1084127652A1");
+//! # }
+//! ```
+
+use crate::crypto::aes::{aes_cmac, aes_enc_ecb, AES_BLOCK_LEN};
+use crate::keyblock::{calculate_padding_length, tr31_wrap, KeyBlockHeader, OptBlock, SecretKey};
+use crate::utils::{ct_hex_encode_upper, SeedSource};
+use std::error::Error;
+
+/// The length of the Initial Key ID (BDK ID and Derivation ID) in bytes.
+pub const INITIAL_KEY_ID_LEN: usize = 8;
+/// The length of an AES DUKPT Key Serial Number in bytes.
+pub const KSN_AES_LEN: usize = 12;
+/// The length of a TDES DUKPT Key Serial Number in bytes.
+pub const KSN_TDES_LEN: usize = 10;
+
+// Transaction counter masks of the last 4 bytes of a KSN
+const KSN_AES_COUNTER_MASK: u32 = 0xFFFF_FFFF;
+const KSN_TDES_COUNTER_MASK: u32 = 0x001F_FFFF;
+
+// Derivation data fields for the Initial Key derivation (X9.24-3: 2017, 6.3.2)
+const DERIVATION_DATA_VERSION: u8 = 0x01;
+const KEY_USAGE_INITIAL_KEY: [u8; 2] = [0x80, 0x01];
+
+/// The AES key type of a derived DUKPT key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DukptKeyType {
+    /// AES-128 key of 16 bytes.
+    Aes128,
+    /// AES-192 key of 24 bytes.
+    Aes192,
+    /// AES-256 key of 32 bytes.
+    Aes256,
+}
+
+impl DukptKeyType {
+    /// Determine the key type from a key length in bytes.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the length is not 16, 24 or 32 bytes.
+    pub fn from_key_len(key_len: usize) -> Result<Self, Box<dyn Error>> {
+        match key_len {
+            16 => Ok(DukptKeyType::Aes128),
+            24 => Ok(DukptKeyType::Aes192),
+            32 => Ok(DukptKeyType::Aes256),
+            _ => Err(format!("ERROR DUKPT: Invalid AES key length: {}", key_len).into()),
+        }
+    }
+
+    /// Return the key length in bytes.
+    pub fn key_len(&self) -> usize {
+        match self {
+            DukptKeyType::Aes128 => 16,
+            DukptKeyType::Aes192 => 24,
+            DukptKeyType::Aes256 => 32,
+        }
+    }
+
+    // Algorithm indicator of the derivation data.
+    fn algorithm_indicator(&self) -> [u8; 2] {
+        match self {
+            DukptKeyType::Aes128 => [0x00, 0x02],
+            DukptKeyType::Aes192 => [0x00, 0x03],
+            DukptKeyType::Aes256 => [0x00, 0x04],
+        }
+    }
+}
+
+/// Derive the Initial DUKPT Key (IPEK) of a device from the BDK.
+///
+/// The derivation data consists of the version, a block counter, the key usage indicator for
+/// initial keys (`8001`), the algorithm indicator and the length in bits of the derived key,
+/// followed by the Initial Key ID. The derivation data is encrypted with the BDK for as many
+/// block counters as needed for the length of the derived key. The IPEK is returned as a
+/// `SecretKey`, which is wiped when dropped.
+///
+/// # Arguments
+///
+/// * `bdk` - The Base Derivation Key of 16, 24 or 32 bytes.
+/// * `initial_key_id` - The Initial Key ID (BDK ID and Derivation ID) of the device.
+/// * `key_type` - The AES key type of the derived key.
+///
+/// # Errors
+///
+/// Returns an error if the BDK length is invalid.
+///
+/// Not available with the `no-clear-secrets` feature, as it returns the clear IPEK.
+#[cfg(not(feature = "no-clear-secrets"))]
+pub fn derive_initial_key(
+    bdk: &[u8],
+    initial_key_id: &[u8; INITIAL_KEY_ID_LEN],
+    key_type: DukptKeyType,
+) -> Result<SecretKey, Box<dyn Error>> {
+    initial_key(bdk, initial_key_id, key_type)
+}
+
+// Derive the IPEK of a device, see `derive_initial_key`.
+fn initial_key(
+    bdk: &[u8],
+    initial_key_id: &[u8; INITIAL_KEY_ID_LEN],
+    key_type: DukptKeyType,
+) -> Result<SecretKey, Box<dyn Error>> {
+    DukptKeyType::from_key_len(bdk.len())
+        .map_err(|_| format!("ERROR DUKPT: Invalid BDK length: {}", bdk.len()))?;
+
+    let key_len = key_type.key_len();
+    let length_bits = ((key_len * 8) as u16).to_be_bytes();
+
+    // The derivation data of all blocks is encrypted at once, so the key material is only held
+    // in values wiped on drop
+    let num_blocks = key_len.div_ceil(AES_BLOCK_LEN);
+    let mut derivation_data = Vec::with_capacity(num_blocks * AES_BLOCK_LEN);
+    for counter in 1..=num_blocks {
+        derivation_data.push(DERIVATION_DATA_VERSION);
+        derivation_data.push(counter as u8);
+        derivation_data.extend_from_slice(&KEY_USAGE_INITIAL_KEY);
+        derivation_data.extend_from_slice(&key_type.algorithm_indicator());
+        derivation_data.extend_from_slice(&length_bits);
+        derivation_data.extend_from_slice(initial_key_id);
+    }
+    let blocks = SecretKey::new(aes_enc_ecb(&derivation_data, bdk)?);
+
+    Ok(SecretKey::new(blocks[..key_len].to_vec()))
+}
+
+/// Extract the transaction counter of a Key Serial Number.
+///
+/// The counter is the last 4 bytes of an AES DUKPT KSN and the rightmost 21 bits of a TDES
+/// DUKPT KSN.
+///
+/// # Errors
+///
+/// Returns an error if the KSN is neither 12 bytes (AES) nor 10 bytes (TDES) long.
+pub fn ksn_counter(ksn: &[u8]) -> Result<u32, Box<dyn Error>> {
+    let mask = ksn_counter_mask(ksn)?;
+    let mut counter = [0u8; 4];
+    counter.copy_from_slice(&ksn[ksn.len() - 4..]);
+    Ok(u32::from_be_bytes(counter) & mask)
+}
+
+/// Compute the number of transactions from KSN `a` to KSN `b`.
+///
+/// The result is the counter of `b` minus the counter of `a`, so it is negative if `b` precedes
+/// `a`.
+///
+/// # Errors
+///
+/// Returns an error if a KSN has an invalid length, the KSNs differ in length or they do not
+/// belong to the same device, i.e. differ outside the transaction counter.
+pub fn ksn_delta(a: &[u8], b: &[u8]) -> Result<i64, Box<dyn Error>> {
+    let mask = ksn_counter_mask(a)?;
+    if a.len() != b.len() {
+        return Err(format!(
+            "ERROR DUKPT: KSN lengths differ: {} and {}",
+            a.len(),
+            b.len()
+        )
+        .into());
+    }
+
+    let split = a.len() - 4;
+    let device_a = u32::from_be_bytes(a[split..].try_into()?) & !mask;
+    let device_b = u32::from_be_bytes(b[split..].try_into()?) & !mask;
+    if a[..split] != b[..split] || device_a != device_b {
+        return Err("ERROR DUKPT: KSNs belong to different devices".into());
+    }
+
+    Ok(i64::from(ksn_counter(b)?) - i64::from(ksn_counter(a)?))
+}
+
+// The transaction counter mask of the last 4 bytes of a KSN.
+fn ksn_counter_mask(ksn: &[u8]) -> Result<u32, Box<dyn Error>> {
+    match ksn.len() {
+        KSN_AES_LEN => Ok(KSN_AES_COUNTER_MASK),
+        KSN_TDES_LEN => Ok(KSN_TDES_COUNTER_MASK),
+        _ => Err(format!("ERROR DUKPT: Invalid KSN length: {}", ksn.len()).into()),
+    }
+}
+
+/// A single device record of a key injection batch.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InjectionRecord {
+    /// The Derivation ID of the device in hex-ASCII.
+    pub device_id: String,
+    /// The Initial Key ID (value of the `IK` optional block) in hex-ASCII.
+    pub initial_key_id: String,
+    /// The key check value of the IPEK (leftmost 5 bytes of the AES-CMAC of a zero block).
+    pub ipek_kcv: String,
+    /// The IPEK wrapped as TR-31 key block version 'D' ready to be sent to the device.
+    pub key_block: String,
+}
+
+/// Generate the key injection records for a list of devices.
+///
+/// For each Initial Key ID the IPEK is derived from the BDK with the key type of the BDK and
+/// wrapped under the KBPK in a TR-31 key block version 'D' with key usage `B1`, algorithm `A`,
+/// mode of use `X` (key derivation), exportability `N` and an `IK` optional block. The padding
+/// of each key block is taken from the seed source.
+///
+/// # Arguments
+///
+/// * `bdk` - The Base Derivation Key of 16, 24 or 32 bytes.
+/// * `kbpk` - The Key Block Protection Key of the injection facility and the devices.
+/// * `initial_key_ids` - The Initial Key IDs (BDK ID and Derivation ID) of the devices.
+/// * `seed_source` - Source of the random padding of the key blocks.
+///
+/// # Errors
+///
+/// Returns an error if the key derivation, the header construction or the wrapping fails.
+pub fn generate_injection_batch(
+    bdk: &[u8],
+    kbpk: &[u8],
+    initial_key_ids: &[[u8; INITIAL_KEY_ID_LEN]],
+    seed_source: &mut impl SeedSource,
+) -> Result<Vec<InjectionRecord>, Box<dyn Error>> {
+    let key_type = DukptKeyType::from_key_len(bdk.len())
+        .map_err(|_| format!("ERROR DUKPT: Invalid BDK length: {}", bdk.len()))?;
+
+    let mut records = Vec::with_capacity(initial_key_ids.len());
+    for initial_key_id in initial_key_ids {
+        let ipek = initial_key(bdk, initial_key_id, key_type)?;
+        let ipek_kcv = ct_hex_encode_upper(&aes_cmac(&[0u8; AES_BLOCK_LEN], &ipek)?[..5]);
+        let initial_key_id_hex = hex::encode_upper(initial_key_id);
+
+        let mut header = KeyBlockHeader::new_with_values("D", "B1", "A", "X", "00", "N")?;
+        header.set_opt_blocks(Some(Box::new(OptBlock::new(
+            "IK",
+            &initial_key_id_hex,
+            None,
+        )?)));
+        header.finalize()?;
+
+        let padding_len = calculate_padding_length(ipek.len(), 0, AES_BLOCK_LEN)?;
+        let padding_seed = seed_source.seed(padding_len)?;
+        let key_block = tr31_wrap(kbpk, header, &ipek, 0, &padding_seed)?;
+
+        records.push(InjectionRecord {
+            device_id: hex::encode_upper(&initial_key_id[4..]),
+            initial_key_id: initial_key_id_hex,
+            ipek_kcv,
+            key_block,
+        });
+    }
+
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(not(feature = "no-clear-secrets"))]
+    use crate::keyblock::tr31_unwrap;
+    #[cfg(not(feature = "no-clear-secrets"))]
+    use crate::utils::DeterministicRng;
+
+    #[cfg(not(feature = "no-clear-secrets"))]
+    const BDK: &str = "FEDCBA9876543210F1F1F1F1F1F1F1F1";
+
+    #[cfg(not(feature = "no-clear-secrets"))]
+    #[test]
+    fn test_derive_initial_key_aes_128() {
+        // X9.24-3: 2017 test vector for an AES-128 BDK and AES-128 initial key
+        let bdk = hex::decode(BDK).unwrap();
+        let initial_key_id = [0x12, 0x34, 0x56, 0x78, 0x90, 0x12, 0x34, 0x56];
+
+        let ipek = derive_initial_key(&bdk, &initial_key_id, DukptKeyType::Aes128).unwrap();
+        assert_eq!(hex::encode_upper(ipek), "1273671EA26AC29AFA4D1084127652A1");
+    }
+
+    #[cfg(not(feature = "no-clear-secrets"))]
+    #[test]
+    fn test_derive_initial_key_invalid_bdk_length() {
+        let res = derive_initial_key(&[0u8; 17], &[0u8; 8], DukptKeyType::Aes128);
+        assert_eq!(
+            res.err().unwrap().to_string(),
+            "ERROR DUKPT: Invalid BDK length: 17"
+        );
+    }
+
+    #[test]
+    fn test_ksn_counter() {
+        let ksn = hex::decode("123456789012345600000001").unwrap();
+        assert_eq!(ksn_counter(&ksn).unwrap(), 1);
+        let ksn = hex::decode("1234567890123456FFFFFFFF").unwrap();
+        assert_eq!(ksn_counter(&ksn).unwrap(), u32::MAX);
+
+        // The top 3 bits of the last 3 bytes belong to the device ID of a TDES KSN
+        let ksn = hex::decode("FFFF9876543210E00008").unwrap();
+        assert_eq!(ksn_counter(&ksn).unwrap(), 8);
+        let ksn = hex::decode("FFFF98765432101FFFFF").unwrap();
+        assert_eq!(ksn_counter(&ksn).unwrap(), 0x1F_FFFF);
+
+        assert_eq!(
+            ksn_counter(&[0u8; 11]).unwrap_err().to_string(),
+            "ERROR DUKPT: Invalid KSN length: 11"
+        );
+    }
+
+    #[test]
+    fn test_ksn_delta() {
+        let a = hex::decode("123456789012345600000010").unwrap();
+        let b = hex::decode("123456789012345600000110").unwrap();
+        assert_eq!(ksn_delta(&a, &b).unwrap(), 256);
+        assert_eq!(ksn_delta(&b, &a).unwrap(), -256);
+        assert_eq!(ksn_delta(&a, &a).unwrap(), 0);
+
+        let a = hex::decode("FFFF9876543210E00001").unwrap();
+        let b = hex::decode("FFFF9876543210E00008").unwrap();
+        assert_eq!(ksn_delta(&a, &b).unwrap(), 7);
+
+        let other = hex::decode("FFFF9876543210C00008").unwrap();
+        assert_eq!(
+            ksn_delta(&a, &other).unwrap_err().to_string(),
+            "ERROR DUKPT: KSNs belong to different devices"
+        );
+        let aes = hex::decode("123456789012345600000010").unwrap();
+        assert!(ksn_delta(&a, &aes).is_err());
+    }
+
+    #[cfg(not(feature = "no-clear-secrets"))]
+    #[test]
+    fn test_generate_injection_batch_two_devices() {
+        let bdk = hex::decode(BDK).unwrap();
+        let kbpk = hex::decode("88E1AB2A2E3DD38C1FA039A536500CC8A87AB9D62DC92C01058FA79F44657DE6")
+            .unwrap();
+        let initial_key_ids = [
+            [0x12, 0x34, 0x56, 0x78, 0x90, 0x12, 0x34, 0x56],
+            [0x12, 0x34, 0x56, 0x78, 0x00, 0x00, 0x00, 0x02],
+        ];
+        let mut rng = DeterministicRng::from_seed(b"injection batch").unwrap();
+
+        let records = generate_injection_batch(&bdk, &kbpk, &initial_key_ids, &mut rng).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].device_id, "90123456");
+        assert_eq!(records[1].device_id, "00000002");
+
+        for (record, initial_key_id) in records.iter().zip(initial_key_ids.iter()) {
+            let (header, ipek) = tr31_unwrap(&kbpk, &record.key_block).unwrap();
+            let expected_ipek =
+                derive_initial_key(&bdk, initial_key_id, DukptKeyType::Aes128).unwrap();
+
+            assert_eq!(ipek, expected_ipek);
+            assert_eq!(header.key_usage(), "B1");
+            assert_eq!(
+                header.opt_blocks().as_ref().unwrap().data(),
+                record.initial_key_id
+            );
+            assert_eq!(
+                record.ipek_kcv,
+                hex::encode_upper(&aes_cmac(&[0u8; AES_BLOCK_LEN], &ipek).unwrap()[..5])
+            );
+        }
+    }
+}
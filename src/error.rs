@@ -0,0 +1,709 @@
+//! Typed errors of the key block and PIN block functions.
+//!
+//! # Description
+//!
+//! The TR-31 key block and ISO 9564 PIN block functions return `Result<_, PaysecError>`.
+//! Errors which callers commonly need to tell apart have their own variant, so downstream code can
+//! match variants instead of comparing error messages. Malformed key block input is reported as
+//! `PaysecError::Parse` with the located `ParseError`, which carries a more specific variant as
+//! its `source` if there is one. Errors without a variant of their own are `PaysecError::Other`.
+//!
+//! The other modules of this crate still return `Box<dyn Error>`. `PaysecError::find` recovers
+//! the specific variant from a `PaysecError` as well as from a boxed error.
+//!
+//! The messages of a variant only differ by the prefix of the reporting function, e.g.
+//! "PIN BLOCK ISO 4 ERROR" for a `SeedTooShort` error of the format 4 PIN field encoder.
+//!
+//! # Error codes
+//!
+//! Each variant has a stable error code, returned by `PaysecError::error_code` and prepended to
+//! the message by `Display`, e.g. "TR31-0003: ERROR TR-31: MAC check failed". The alternate
+//! `Display` format (`{:#}`) omits the code, e.g. to embed the message in another one.
+//! `PaysecError::catalog` lists all codes with an English description, e.g. to build translation
+//! tables. A `Parse` error has the code of the variant in its source, if any. The PIN block codes
+//! contain the number of the PIN block format, e.g. "PIN4-0003" for the control field of a
+//! format 4 PIN block.
+//!
+//! | Code        | Variant                  | Message                                                  |
+//! |-------------|--------------------------|----------------------------------------------------------|
+//! | `TR31-0001` | `UnsupportedVersion`     | "ERROR TR-31: Key block version not supported by ..."    |
+//! | `TR31-0002` | `DeprecatedVersion`      | "ERROR TR-31: Key block version ... is deprecated ..."   |
+//! | `TR31-0003` | `MacCheckFailed`         | "ERROR TR-31: MAC check failed"                          |
+//! | `TR31-0004` | `KbpkKcvMismatch`        | "ERROR TR-31: KBPK KCV mismatch (wrong protection key)"  |
+//! | `TR31-0005` | `KeyBlockLengthMismatch` | "ERROR TR-31: Key block length does not match ..."       |
+//! | `TR31-0006` | `InvalidOptBlockId`      | "ERROR TR-31 OPT BLOCK: Invalid ID: ..."                 |
+//! | `TR31-0007` | `Parse`                  | the message of the `ParseError`                          |
+//! | `TR31-0008` | `ModeOfUseViolation`     | "ERROR TR-31: Mode of use ... does not permit ..."       |
+//! | `TR31-0009` | `KeyKcvMismatch`         | "ERROR TR-31: Key KCV mismatch"                          |
+//! | `TR31-0010` | `NonExportable`          | "ERROR TR-31: Key block with exportability N ..."        |
+//! | `TR31-0011` | `InvalidHeaderField`     | "ERROR TR-31 HEADER: Invalid ...: ..."                   |
+//! | `TR31-0012` | `TdesParity`             | "ERROR TR-31: Recovered TDES key does not have ..."      |
+//! | `PINn-0001` | `InvalidPinLength`       | "PIN BLOCK ISO n ERROR: PIN length must be ..."          |
+//! | `PINn-0002` | `InvalidPinFiller`       | "PIN BLOCK ISO n ERROR: PIN block filler is incorrect"   |
+//! | `PINn-0003` | `InvalidControlField`    | "PIN BLOCK ISO n ERROR: PIN block is not ISO format ..." |
+//! | `PINn-0004` | `InvalidPinDigit`        | "PIN BLOCK ISO n ERROR: PIN contains invalid digit"      |
+//! | `SEED-0001` | `SeedTooShort`           | "...: Random seed is too short: ..."                     |
+//! | `PSEC-0000` | `Other`                  | any other message                                        |
+//!
+//! # Example
+//! ```
+//! # #[cfg(feature = "keyblock")]
+//! # {
+//! use paysec::error::PaysecError;
+//! use paysec::keyblock::{tr31_wrap, KeyBlockHeader};
+//!
+//! let header = KeyBlockHeader::new_with_values("D", "P0", "A", "E", "00", "E").unwrap();
+//! let err = tr31_wrap(&[0x11; 32], header, &[0x22; 16], 0, &[0x33; 4]).unwrap_err();
+//!
+//! assert!(matches!(
+//!     err,
+//!     PaysecError::SeedTooShort { len: 4, required: 14, .. }
+//! ));
+//! # }
+//! ```
+
+use std::error::Error;
+use std::fmt;
+
+#[cfg(feature = "keyblock")]
+use crate::keyblock::{ModeOfUseViolation, ParseError};
+#[cfg(feature = "pin")]
+use crate::pin::PinFormat;
+
+/// An error of the key block or PIN block functions which callers can match on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum PaysecError {
+    /// The key block version is not implemented.
+    UnsupportedVersion(String),
+    /// The key block version is deprecated and can only be unwrapped.
+    DeprecatedVersion(String),
+    /// The MAC of a key block does not match, e.g. because of a wrong KBPK or a modified key
+    /// block.
+    MacCheckFailed,
+    /// The KCV of the KBPK does not match the `KP` optional block of a key block.
+    KbpkKcvMismatch,
+    /// The length of a key block does not match the key block length field of its header.
+    KeyBlockLengthMismatch {
+        /// The key block length declared in the header.
+        declared: usize,
+        /// The actual length of the key block.
+        actual: usize,
+        /// Set if the excess characters of a too long key block are all whitespace.
+        trailing_whitespace: bool,
+    },
+    /// The ID of an optional block is not defined by TR-31.
+    InvalidOptBlockId(String),
+    /// The KCV of an unwrapped key does not match the `KC` optional block of its key block.
+    KeyKcvMismatch,
+    /// A key block with exportability 'N' (non-exportable) was to be wrapped again.
+    NonExportable,
+    /// A field of a key block header has a value not defined by TR-31.
+    InvalidHeaderField {
+        /// The name of the field, e.g. "mode of use".
+        field: &'static str,
+        /// The invalid value.
+        value: String,
+    },
+    /// A TDES key recovered from a key block does not have odd parity.
+    TdesParity,
+    /// The PIN length of a PIN or PIN block is not between 4 and 12.
+    #[cfg(feature = "pin")]
+    InvalidPinLength {
+        /// The PIN block format.
+        format: PinFormat,
+        /// The PIN length.
+        len: usize,
+    },
+    /// A filler nibble of a PIN block is not the value defined by the format.
+    #[cfg(feature = "pin")]
+    InvalidPinFiller {
+        /// The PIN block format.
+        format: PinFormat,
+    },
+    /// The control field of a PIN block does not match the format.
+    #[cfg(feature = "pin")]
+    InvalidControlField {
+        /// The expected PIN block format.
+        format: PinFormat,
+        /// The control field of the PIN block.
+        control: u8,
+    },
+    /// A PIN or a PIN digit of a PIN block is not a decimal digit.
+    #[cfg(feature = "pin")]
+    InvalidPinDigit {
+        /// The PIN block format.
+        format: PinFormat,
+    },
+    /// A caller-provided random seed is shorter than required.
+    SeedTooShort {
+        /// The error prefix of the reporting function, e.g. "ERROR SEED".
+        context: &'static str,
+        /// The length of the provided seed in bytes.
+        len: usize,
+        /// The required seed length in bytes.
+        required: usize,
+    },
+    /// Malformed key block input, located by a `ParseError`. Its `source` is the more specific
+    /// variant, if any, which `PaysecError::find` returns.
+    #[cfg(feature = "keyblock")]
+    Parse(Box<ParseError>),
+    /// An operation not permitted by the mode of use of a `UsageBoundKey`.
+    #[cfg(feature = "keyblock")]
+    ModeOfUseViolation(ModeOfUseViolation),
+    /// Any other error, e.g. an invalid argument or a failed cryptographic operation, with its
+    /// error message.
+    Other(String),
+}
+
+// The error codes of the PIN block errors by format, in the order length, filler, control field
+// and digit.
+const PIN_CODES: [[&str; 4]; 5] = [
+    ["PIN0-0001", "PIN0-0002", "PIN0-0003", "PIN0-0004"],
+    ["PIN1-0001", "PIN1-0002", "PIN1-0003", "PIN1-0004"],
+    ["PIN2-0001", "PIN2-0002", "PIN2-0003", "PIN2-0004"],
+    ["PIN3-0001", "PIN3-0002", "PIN3-0003", "PIN3-0004"],
+    ["PIN4-0001", "PIN4-0002", "PIN4-0003", "PIN4-0004"],
+];
+
+// The error codes with their default English descriptions, see `PaysecError::catalog`.
+const CATALOG: [(&str, &str); 34] = [
+    ("TR31-0001", "Key block version not supported"),
+    (
+        "TR31-0002",
+        "Key block version deprecated and only supported for unwrapping",
+    ),
+    ("TR31-0003", "Key block MAC check failed"),
+    ("TR31-0004", "KBPK KCV mismatch (wrong protection key)"),
+    (
+        "TR31-0005",
+        "Key block length does not match its length in the header",
+    ),
+    ("TR31-0006", "Invalid optional block ID"),
+    ("TR31-0007", "Malformed key block input"),
+    ("TR31-0008", "Mode of use does not permit the operation"),
+    ("TR31-0009", "Key KCV mismatch"),
+    (
+        "TR31-0010",
+        "Non-exportable key block cannot be wrapped again",
+    ),
+    ("TR31-0011", "Invalid key block header field"),
+    ("TR31-0012", "Recovered TDES key does not have odd parity"),
+    (
+        "PIN0-0001",
+        "ISO 9564 format 0 PIN length not between 4 and 12",
+    ),
+    ("PIN0-0002", "ISO 9564 format 0 PIN block filler incorrect"),
+    ("PIN0-0003", "PIN block is not ISO 9564 format 0"),
+    ("PIN0-0004", "ISO 9564 format 0 PIN contains invalid digit"),
+    (
+        "PIN1-0001",
+        "ISO 9564 format 1 PIN length not between 4 and 12",
+    ),
+    ("PIN1-0002", "ISO 9564 format 1 PIN block filler incorrect"),
+    ("PIN1-0003", "PIN block is not ISO 9564 format 1"),
+    ("PIN1-0004", "ISO 9564 format 1 PIN contains invalid digit"),
+    (
+        "PIN2-0001",
+        "ISO 9564 format 2 PIN length not between 4 and 12",
+    ),
+    ("PIN2-0002", "ISO 9564 format 2 PIN block filler incorrect"),
+    ("PIN2-0003", "PIN block is not ISO 9564 format 2"),
+    ("PIN2-0004", "ISO 9564 format 2 PIN contains invalid digit"),
+    (
+        "PIN3-0001",
+        "ISO 9564 format 3 PIN length not between 4 and 12",
+    ),
+    ("PIN3-0002", "ISO 9564 format 3 PIN block filler incorrect"),
+    ("PIN3-0003", "PIN block is not ISO 9564 format 3"),
+    ("PIN3-0004", "ISO 9564 format 3 PIN contains invalid digit"),
+    (
+        "PIN4-0001",
+        "ISO 9564 format 4 PIN length not between 4 and 12",
+    ),
+    ("PIN4-0002", "ISO 9564 format 4 PIN block filler incorrect"),
+    ("PIN4-0003", "PIN block is not ISO 9564 format 4"),
+    ("PIN4-0004", "ISO 9564 format 4 PIN contains invalid digit"),
+    ("SEED-0001", "Random seed too short"),
+    ("PSEC-0000", "Other error"),
+];
+
+impl PaysecError {
+    /// Return the stable error code of the error, e.g. "TR31-0003" for `MacCheckFailed`.
+    ///
+    /// A `Parse` error returns the code of the more specific variant in its source, if any.
+    pub fn error_code(&self) -> &'static str {
+        match self {
+            PaysecError::UnsupportedVersion(_) => "TR31-0001",
+            PaysecError::DeprecatedVersion(_) => "TR31-0002",
+            PaysecError::MacCheckFailed => "TR31-0003",
+            PaysecError::KbpkKcvMismatch => "TR31-0004",
+            PaysecError::KeyBlockLengthMismatch { .. } => "TR31-0005",
+            PaysecError::InvalidOptBlockId(_) => "TR31-0006",
+            PaysecError::KeyKcvMismatch => "TR31-0009",
+            PaysecError::NonExportable => "TR31-0010",
+            PaysecError::InvalidHeaderField { .. } => "TR31-0011",
+            PaysecError::TdesParity => "TR31-0012",
+            #[cfg(feature = "pin")]
+            PaysecError::InvalidPinLength { format, .. } => {
+                PIN_CODES[pin_format_number(*format)][0]
+            }
+            #[cfg(feature = "pin")]
+            PaysecError::InvalidPinFiller { format } => PIN_CODES[pin_format_number(*format)][1],
+            #[cfg(feature = "pin")]
+            PaysecError::InvalidControlField { format, .. } => {
+                PIN_CODES[pin_format_number(*format)][2]
+            }
+            #[cfg(feature = "pin")]
+            PaysecError::InvalidPinDigit { format } => PIN_CODES[pin_format_number(*format)][3],
+            #[cfg(feature = "keyblock")]
+            PaysecError::Parse(_) => PaysecError::find(self).map_or("TR31-0007", Self::error_code),
+            #[cfg(feature = "keyblock")]
+            PaysecError::ModeOfUseViolation(_) => "TR31-0008",
+            PaysecError::SeedTooShort { .. } => "SEED-0001",
+            PaysecError::Other(_) => "PSEC-0000",
+        }
+    }
+
+    /// Return all error codes with their default English descriptions, e.g. to build
+    /// translation tables.
+    pub fn catalog() -> &'static [(&'static str, &'static str)] {
+        &CATALOG
+    }
+
+    /// Find a `PaysecError` in an error or in the chain of its sources.
+    ///
+    /// A `Parse` error is skipped in favour of the variant in its source, so `None` is returned
+    /// for a parse error without a more specific variant.
+    pub fn find<'a>(err: &'a (dyn Error + 'static)) -> Option<&'a PaysecError> {
+        let mut current = Some(err);
+        while let Some(err) = current {
+            match err.downcast_ref::<PaysecError>() {
+                #[cfg(feature = "keyblock")]
+                Some(PaysecError::Parse(_)) => {}
+                Some(paysec_error) => return Some(paysec_error),
+                None => {}
+            }
+            current = err.source();
+        }
+        None
+    }
+
+    /// Return the located parse error of a `Parse` error.
+    #[cfg(feature = "keyblock")]
+    pub fn parse_error(&self) -> Option<&ParseError> {
+        match self {
+            PaysecError::Parse(parse_error) => Some(parse_error),
+            _ => None,
+        }
+    }
+}
+
+// The number of a PIN block format, e.g. 4 for format 4.
+#[cfg(feature = "pin")]
+fn pin_format_number(format: PinFormat) -> usize {
+    match format {
+        PinFormat::Format0 => 0,
+        PinFormat::Format1 => 1,
+        PinFormat::Format2 => 2,
+        PinFormat::Format3 => 3,
+        PinFormat::Format4 => 4,
+    }
+}
+
+impl fmt::Display for PaysecError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // The alternate format omits the error code
+        if !f.alternate() {
+            write!(f, "{}: ", self.error_code())?;
+        }
+        match self {
+            PaysecError::UnsupportedVersion(version_id) => write!(
+                f,
+                "ERROR TR-31: Key block version not supported by implementation: {}",
+                version_id
+            ),
+            PaysecError::DeprecatedVersion(version_id) => write!(
+                f,
+                "ERROR TR-31: Key block version {} is deprecated and only supported for unwrapping",
+                version_id
+            ),
+            PaysecError::MacCheckFailed => write!(f, "ERROR TR-31: MAC check failed"),
+            PaysecError::KbpkKcvMismatch => {
+                write!(f, "ERROR TR-31: KBPK KCV mismatch (wrong protection key)")
+            }
+            PaysecError::KeyBlockLengthMismatch {
+                declared,
+                actual,
+                trailing_whitespace,
+            } => {
+                write!(
+                    f,
+                    "ERROR TR-31: Key block length does not match its length in the header: declared {}, actual {}, {} character(s) ",
+                    declared,
+                    actual,
+                    actual.abs_diff(*declared)
+                )?;
+                if actual < declared {
+                    write!(f, "too short")
+                } else if *trailing_whitespace {
+                    write!(f, "too long, trailing whitespace")
+                } else {
+                    write!(f, "too long")
+                }
+            }
+            PaysecError::InvalidOptBlockId(id) => {
+                write!(f, "ERROR TR-31 OPT BLOCK: Invalid ID: {}", id)
+            }
+            PaysecError::KeyKcvMismatch => write!(f, "ERROR TR-31: Key KCV mismatch"),
+            PaysecError::NonExportable => write!(
+                f,
+                "ERROR TR-31: Key block with exportability N cannot be wrapped again"
+            ),
+            PaysecError::InvalidHeaderField { field, value } => {
+                write!(f, "ERROR TR-31 HEADER: Invalid {}: {}", field, value)
+            }
+            PaysecError::TdesParity => write!(
+                f,
+                "ERROR TR-31: Recovered TDES key does not have odd parity"
+            ),
+            #[cfg(feature = "pin")]
+            PaysecError::InvalidPinLength { format, len } => write!(
+                f,
+                "PIN BLOCK ISO {} ERROR: PIN length must be between 4 and 12: {}",
+                pin_format_number(*format),
+                len
+            ),
+            #[cfg(feature = "pin")]
+            PaysecError::InvalidPinFiller { format } => write!(
+                f,
+                "PIN BLOCK ISO {} ERROR: PIN block filler is incorrect",
+                pin_format_number(*format)
+            ),
+            #[cfg(feature = "pin")]
+            PaysecError::InvalidControlField { format, control } => write!(
+                f,
+                "PIN BLOCK ISO {0} ERROR: PIN block is not ISO format {0}: control field {1:X}",
+                pin_format_number(*format),
+                control
+            ),
+            #[cfg(feature = "pin")]
+            PaysecError::InvalidPinDigit { format } => write!(
+                f,
+                "PIN BLOCK ISO {} ERROR: PIN contains invalid digit",
+                pin_format_number(*format)
+            ),
+            PaysecError::SeedTooShort {
+                context,
+                len,
+                required,
+            } => write!(
+                f,
+                "{}: Random seed is too short: {} bytes, required {} bytes of CSPRNG output",
+                context, len, required
+            ),
+            #[cfg(feature = "keyblock")]
+            PaysecError::Parse(parse_error) => write!(f, "{}", parse_error),
+            #[cfg(feature = "keyblock")]
+            PaysecError::ModeOfUseViolation(violation) => write!(f, "{}", violation),
+            PaysecError::Other(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl Error for PaysecError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            // A parse error is reported as such, so the source is the one of the parse error
+            #[cfg(feature = "keyblock")]
+            PaysecError::Parse(parse_error) => parse_error.source(),
+            _ => None,
+        }
+    }
+}
+
+impl From<&str> for PaysecError {
+    fn from(message: &str) -> Self {
+        PaysecError::Other(message.to_string())
+    }
+}
+
+impl From<String> for PaysecError {
+    fn from(message: String) -> Self {
+        PaysecError::Other(message)
+    }
+}
+
+#[cfg(feature = "keyblock")]
+impl From<ParseError> for PaysecError {
+    fn from(parse_error: ParseError) -> Self {
+        PaysecError::Parse(Box::new(parse_error))
+    }
+}
+
+#[cfg(feature = "keyblock")]
+impl From<ModeOfUseViolation> for PaysecError {
+    fn from(violation: ModeOfUseViolation) -> Self {
+        PaysecError::ModeOfUseViolation(violation)
+    }
+}
+
+// Errors of the standard library and the `hex` crate only keep their message.
+impl From<fmt::Error> for PaysecError {
+    fn from(err: fmt::Error) -> Self {
+        PaysecError::Other(err.to_string())
+    }
+}
+
+impl From<std::num::TryFromIntError> for PaysecError {
+    fn from(err: std::num::TryFromIntError) -> Self {
+        PaysecError::Other(err.to_string())
+    }
+}
+
+impl From<std::string::FromUtf8Error> for PaysecError {
+    fn from(err: std::string::FromUtf8Error) -> Self {
+        PaysecError::Other(err.to_string())
+    }
+}
+
+impl From<hex::FromHexError> for PaysecError {
+    fn from(err: hex::FromHexError) -> Self {
+        PaysecError::Other(err.to_string())
+    }
+}
+
+// Errors of the modules still returning `Box<dyn Error>`, e.g. the crypto primitives, keep their
+// variant if they are a `PaysecError`, a `ParseError` or a `ModeOfUseViolation`.
+impl From<Box<dyn Error>> for PaysecError {
+    fn from(err: Box<dyn Error>) -> Self {
+        let err = match err.downcast::<PaysecError>() {
+            Ok(paysec_error) => return *paysec_error,
+            Err(err) => err,
+        };
+        #[cfg(feature = "keyblock")]
+        let err = match err.downcast::<ParseError>() {
+            Ok(parse_error) => return PaysecError::Parse(parse_error),
+            Err(err) => err,
+        };
+        #[cfg(feature = "keyblock")]
+        let err = match err.downcast::<ModeOfUseViolation>() {
+            Ok(violation) => return PaysecError::ModeOfUseViolation(*violation),
+            Err(err) => err,
+        };
+        PaysecError::Other(err.to_string())
+    }
+}
+
+#[cfg(all(test, feature = "keyblock"))]
+mod tests {
+    use super::*;
+    use crate::keyblock::{
+        tr31_verify, tr31_wrap, KeyBlockHeader, KeyOperation, OptBlock, ParseError, UsageBoundKey,
+    };
+
+    const KEY_BLOCK: &str = "D0112P0AE00E0000B82679114F470F540165EDFBF7E250FCEA43F810D215F8D207E2E417C07156A27E8E31DA05F7425509593D03A457DC34";
+    const KBPK: &str = "88E1AB2A2E3DD38C1FA039A536500CC8A87AB9D62DC92C01058FA79F44657DE6";
+
+    #[test]
+    fn test_find_mac_check_failed() {
+        let mut kbpk = hex::decode(KBPK).unwrap();
+        kbpk[0] ^= 0x01;
+
+        let err = tr31_verify(&kbpk, KEY_BLOCK).unwrap_err();
+        assert_eq!(err, PaysecError::MacCheckFailed);
+        assert_eq!(PaysecError::find(&err), Some(&PaysecError::MacCheckFailed));
+        assert_eq!(err.to_string(), "TR31-0003: ERROR TR-31: MAC check failed");
+    }
+
+    #[test]
+    fn test_find_in_parse_error_source() {
+        let kbpk = hex::decode(KBPK).unwrap();
+
+        // The located parse error is available with `parse_error`
+        let err = tr31_verify(&kbpk, &KEY_BLOCK[..110]).unwrap_err();
+        assert_eq!(err.parse_error().unwrap().offset(), 110);
+        assert_eq!(
+            PaysecError::find(&err),
+            Some(&PaysecError::KeyBlockLengthMismatch {
+                declared: 112,
+                actual: 110,
+                trailing_whitespace: false,
+            })
+        );
+        assert_eq!(
+            err.to_string(),
+            "TR31-0005: ERROR TR-31: Key block length does not match its length in the header: declared 112, actual 110, 2 character(s) too short"
+        );
+
+        let err = OptBlock::new_from_str("ZZ04", 1).unwrap_err();
+        assert_eq!(
+            PaysecError::find(&err),
+            Some(&PaysecError::InvalidOptBlockId("ZZ".to_string()))
+        );
+
+        // The source is kept when the optional block is parsed as part of a header
+        let err = KeyBlockHeader::new_from_str("D0000P0AE00E0100ZZ04").unwrap_err();
+        assert_eq!(
+            PaysecError::find(&err),
+            Some(&PaysecError::InvalidOptBlockId("ZZ".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_find_wrap_errors() {
+        let key = [0x22; 16];
+
+        let header = KeyBlockHeader::new_with_values("A", "P0", "T", "E", "00", "E").unwrap();
+        let err = tr31_wrap(&[0x11; 16], header, &key, 0, &[0x33; 8]).unwrap_err();
+        assert_eq!(err, PaysecError::DeprecatedVersion("A".to_string()));
+
+        let header = KeyBlockHeader::new_with_values("D", "P0", "A", "E", "00", "E").unwrap();
+        let err = tr31_wrap(&[0x11; 32], header, &key, 0, &[0x33; 4]).unwrap_err();
+        assert_eq!(
+            err,
+            PaysecError::SeedTooShort {
+                context: "ERROR TR-31 PAYLOAD",
+                len: 4,
+                required: 14,
+            }
+        );
+    }
+
+    #[test]
+    fn test_find_other_errors() {
+        let err: Box<dyn Error> = "ERROR TR-31: Other error".into();
+        assert_eq!(PaysecError::find(err.as_ref()), None);
+
+        // A parse error without a more specific variant
+        let err = KeyBlockHeader::new_from_str("D01X2P0AE00E0000").unwrap_err();
+        assert!(err.parse_error().is_some());
+        assert_eq!(PaysecError::find(&err), None);
+    }
+
+    #[test]
+    fn test_catalog_codes_unique() {
+        let catalog = PaysecError::catalog();
+        let codes: std::collections::HashSet<_> = catalog.iter().map(|(code, _)| code).collect();
+        assert_eq!(codes.len(), catalog.len());
+
+        let mut errors = vec![
+            PaysecError::UnsupportedVersion("E".to_string()),
+            PaysecError::DeprecatedVersion("A".to_string()),
+            PaysecError::MacCheckFailed,
+            PaysecError::KbpkKcvMismatch,
+            PaysecError::KeyBlockLengthMismatch {
+                declared: 112,
+                actual: 110,
+                trailing_whitespace: false,
+            },
+            PaysecError::InvalidOptBlockId("ZZ".to_string()),
+            KeyBlockHeader::new_from_str("D01X2P0AE00E0000").unwrap_err(),
+            UsageBoundKey::new(
+                &KeyBlockHeader::new_with_values("D", "P0", "A", "E", "00", "E").unwrap(),
+                vec![0x11; 16],
+            )
+            .key_for(KeyOperation::Decrypt)
+            .unwrap_err(),
+            PaysecError::KeyKcvMismatch,
+            PaysecError::NonExportable,
+            PaysecError::InvalidHeaderField {
+                field: "mode of use",
+                value: "Z".to_string(),
+            },
+            PaysecError::TdesParity,
+        ];
+        #[cfg(feature = "pin")]
+        errors.extend(
+            [
+                PinFormat::Format0,
+                PinFormat::Format1,
+                PinFormat::Format2,
+                PinFormat::Format3,
+                PinFormat::Format4,
+            ]
+            .into_iter()
+            .flat_map(|format| {
+                [
+                    PaysecError::InvalidPinLength { format, len: 3 },
+                    PaysecError::InvalidPinFiller { format },
+                    PaysecError::InvalidControlField {
+                        format,
+                        control: 0xF,
+                    },
+                    PaysecError::InvalidPinDigit { format },
+                ]
+            }),
+        );
+        errors.extend([
+            PaysecError::SeedTooShort {
+                context: "ERROR SEED",
+                len: 4,
+                required: 8,
+            },
+            PaysecError::Other("ERROR TR-31: Other error".to_string()),
+        ]);
+        let error_codes: Vec<_> = errors.iter().map(|e| e.error_code()).collect();
+        // The PIN block errors only exist with the `pin` feature
+        let catalog_codes: Vec<_> = catalog
+            .iter()
+            .map(|(code, _)| *code)
+            .filter(|code| cfg!(feature = "pin") || !code.starts_with("PIN"))
+            .collect();
+        assert_eq!(error_codes, catalog_codes);
+    }
+
+    #[test]
+    fn test_error_code() {
+        let err = PaysecError::MacCheckFailed;
+        assert_eq!(err.error_code(), "TR31-0003");
+        assert_eq!(err.to_string(), "TR31-0003: ERROR TR-31: MAC check failed");
+        assert_eq!(format!("{:#}", err), "ERROR TR-31: MAC check failed");
+
+        // A parse error has the code of the variant in its source
+        let err = OptBlock::new_from_str("ZZ04", 1).unwrap_err();
+        assert!(err.parse_error().is_some());
+        assert_eq!(err.error_code(), "TR31-0006");
+        assert_eq!(
+            err.to_string(),
+            "TR31-0006: ERROR TR-31 OPT BLOCK: Invalid ID: ZZ"
+        );
+    }
+
+    #[cfg(feature = "pin")]
+    #[test]
+    fn test_error_code_pin() {
+        let err = PaysecError::InvalidControlField {
+            format: PinFormat::Format4,
+            control: 3,
+        };
+        assert_eq!(err.error_code(), "PIN4-0003");
+        assert_eq!(
+            err.to_string(),
+            "PIN4-0003: PIN BLOCK ISO 4 ERROR: PIN block is not ISO format 4: control field 3"
+        );
+
+        let err = PaysecError::InvalidPinLength {
+            format: PinFormat::Format0,
+            len: 13,
+        };
+        assert_eq!(err.error_code(), "PIN0-0001");
+    }
+
+    #[test]
+    fn test_from_boxed_error() {
+        let err: Box<dyn Error> = PaysecError::MacCheckFailed.into();
+        assert_eq!(PaysecError::from(err), PaysecError::MacCheckFailed);
+
+        let err: Box<dyn Error> = ParseError::new("key block", 1, 4, "ERROR TR-31: Parse").into();
+        let err = PaysecError::from(err);
+        assert_eq!(err.parse_error().unwrap().range(), 1..5);
+        assert_eq!(err.to_string(), "TR31-0007: ERROR TR-31: Parse");
+
+        let err: Box<dyn Error> = "ERROR TR-31: Other error".into();
+        assert_eq!(
+            PaysecError::from(err),
+            PaysecError::Other("ERROR TR-31: Other error".to_string())
+        );
+    }
+}
@@ -0,0 +1,219 @@
+//! JSON key manifests for key ceremony tooling.
+//!
+//! A key ceremony produces a manifest listing each generated key as a TR-31 key block together
+//! with its name, key check value (KCV), key usage, creation time and the KCV (KP value) of the
+//! Key Block Protection Key (KBPK) the key block is wrapped under.
+//!
+//! The manifest only contains wrapped keys and check values. Loading and validating a manifest
+//! does not require the KBPK: the validation checks that the clear key block header of each entry
+//! matches the declared key usage and that the check values are well-formed hex.
+//!
+//! This module is only available with the `serde` feature.
+//!
+//! # Example
+//! ```
+//! use paysec::keyblock::manifest::{KeyEntry, KeyManifest};
+//!
+//! let mut manifest = KeyManifest::new();
+//! manifest.entries.push(KeyEntry {
+//!     name: "PIN encryption key".to_string(),
+//!     key_block: "D0112P0AE00E0000B82679114F470F540165EDFBF7E250FCEA43F810D215F8D207E2E417C07156A27E8E31DA05F7425509593D03A457DC34".to_string(),
+//!     kcv: "0A1B2C".to_string(),
+//!     key_usage: "P0".to_string(),
+//!     created_at: "2024-01-15T10:00:00Z".to_string(),
+//!     kbpk_kp: "3D4E5F".to_string(),
+//! });
+//!
+//! let json = manifest.to_json().unwrap();
+//! let loaded = KeyManifest::from_json(&json).unwrap();
+//! assert_eq!(loaded, manifest);
+//! ```
+
+use super::KeyBlockHeader;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+
+// Maximum length of a check value in hex digits (a full 8-byte cipher block).
+const MAX_CHECK_VALUE_HEX_LEN: usize = 16;
+
+/// A single key of a key manifest.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeyEntry {
+    /// Name or label of the key.
+    pub name: String,
+    /// The key wrapped as TR-31 key block.
+    pub key_block: String,
+    /// Key check value of the wrapped key as hex string.
+    pub kcv: String,
+    /// Declared key usage, which must match the key usage of the key block header.
+    pub key_usage: String,
+    /// Creation time of the key, e.g. as RFC 3339 timestamp.
+    pub created_at: String,
+    /// Key check value (KP value) of the KBPK the key block is wrapped under as hex string.
+    pub kbpk_kp: String,
+}
+
+impl KeyEntry {
+    /// Validate the key entry without unwrapping the key block.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if:
+    /// - The key block header cannot be parsed.
+    /// - The key usage of the key block header does not match the declared key usage.
+    /// - The KCV or the KBPK KP value is not well-formed hex.
+    pub fn validate(&self) -> Result<(), Box<dyn Error>> {
+        let header = KeyBlockHeader::new_from_str(&self.key_block).map_err(|e| {
+            format!(
+                "ERROR TR-31 MANIFEST: Invalid key block for entry '{}': {}",
+                self.name, e
+            )
+        })?;
+
+        if header.key_usage() != self.key_usage {
+            return Err(format!(
+                "ERROR TR-31 MANIFEST: Key usage mismatch for entry '{}': declared {}, key block header {}",
+                self.name,
+                self.key_usage,
+                header.key_usage()
+            )
+            .into());
+        }
+
+        validate_check_value(&self.name, "KCV", &self.kcv)?;
+        validate_check_value(&self.name, "KBPK KP value", &self.kbpk_kp)?;
+
+        Ok(())
+    }
+}
+
+/// A key manifest as produced by a key ceremony.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeyManifest {
+    /// The keys of the manifest.
+    pub entries: Vec<KeyEntry>,
+}
+
+impl KeyManifest {
+    /// Create a new empty key manifest.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Validate all entries of the manifest.
+    ///
+    /// # Errors
+    ///
+    /// Returns the error of the first entry that fails validation.
+    pub fn validate(&self) -> Result<(), Box<dyn Error>> {
+        for entry in &self.entries {
+            entry.validate()?;
+        }
+        Ok(())
+    }
+
+    /// Validate the manifest and serialize it to a JSON string.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the validation or the serialization fails.
+    pub fn to_json(&self) -> Result<String, Box<dyn Error>> {
+        self.validate()?;
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    /// Deserialize a manifest from a JSON string and validate it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the JSON is malformed or the validation fails.
+    pub fn from_json(json: &str) -> Result<Self, Box<dyn Error>> {
+        let manifest: KeyManifest = serde_json::from_str(json)
+            .map_err(|e| format!("ERROR TR-31 MANIFEST: Invalid JSON: {}", e))?;
+        manifest.validate()?;
+        Ok(manifest)
+    }
+}
+
+// Ensure a check value is a non-empty, even length hex string of at most a cipher block.
+fn validate_check_value(name: &str, field: &str, value: &str) -> Result<(), Box<dyn Error>> {
+    if value.is_empty()
+        || value.len() % 2 == 1
+        || value.len() > MAX_CHECK_VALUE_HEX_LEN
+        || !value.chars().all(|c| c.is_ascii_hexdigit())
+    {
+        return Err(format!(
+            "ERROR TR-31 MANIFEST: Malformed {} for entry '{}': {}",
+            field, name, value
+        )
+        .into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_manifest() -> KeyManifest {
+        KeyManifest {
+            entries: vec![
+                KeyEntry {
+                    name: "PEK 1".to_string(),
+                    key_block: "D0112P0AE00E0000B82679114F470F540165EDFBF7E250FCEA43F810D215F8D207E2E417C07156A27E8E31DA05F7425509593D03A457DC34".to_string(),
+                    kcv: "0A1B2C".to_string(),
+                    key_usage: "P0".to_string(),
+                    created_at: "2024-01-15T10:00:00Z".to_string(),
+                    kbpk_kp: "3D4E5F".to_string(),
+                },
+                KeyEntry {
+                    name: "PEK 2".to_string(),
+                    key_block: "D0144P0TE00N0200KS1800604B120F9292800000PB080000F2A795BB540447553D9FA3812E64E76A577DA04A1E0DD9FA9EFDE394BE936D4532BF5BA7E57063B63FCD90F9C2020F77".to_string(),
+                    kcv: "1234567890".to_string(),
+                    key_usage: "P0".to_string(),
+                    created_at: "2024-01-15T10:05:00Z".to_string(),
+                    kbpk_kp: "ABCDEF0123".to_string(),
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn test_manifest_json_roundtrip() {
+        let manifest = sample_manifest();
+
+        let json = manifest.to_json().unwrap();
+        let loaded = KeyManifest::from_json(&json).unwrap();
+
+        assert_eq!(loaded, manifest);
+        assert_eq!(loaded.entries.len(), 2);
+    }
+
+    #[test]
+    fn test_manifest_key_usage_mismatch() {
+        let mut manifest = sample_manifest();
+        manifest.entries[1].key_usage = "K0".to_string();
+
+        assert_eq!(
+            manifest.to_json().err().unwrap().to_string(),
+            "ERROR TR-31 MANIFEST: Key usage mismatch for entry 'PEK 2': declared K0, key block header P0"
+        );
+
+        let json = serde_json::to_string(&manifest).unwrap();
+        assert!(KeyManifest::from_json(&json).is_err());
+    }
+
+    #[test]
+    fn test_manifest_malformed_kcv() {
+        let mut manifest = sample_manifest();
+        manifest.entries[0].kcv = "0A1B2".to_string();
+
+        assert_eq!(
+            manifest.validate().err().unwrap().to_string(),
+            "ERROR TR-31 MANIFEST: Malformed KCV for entry 'PEK 1': 0A1B2"
+        );
+
+        manifest.entries[0].kcv = "0A1B2G".to_string();
+        assert!(manifest.validate().is_err());
+    }
+}
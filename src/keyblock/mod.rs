@@ -1,3 +1,6 @@
 mod tr31_2018;
 
+#[cfg(feature = "serde")]
+pub mod manifest;
+
 pub use tr31_2018::*;
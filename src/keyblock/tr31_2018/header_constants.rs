@@ -11,9 +11,28 @@
 /// Note: Numeric key block Version IDs are reserved for proprietary key block definitions.
 ///       Multiple key block versions may be in use at any time.
 ///       It is not recommended that Version ‘B’ or ‘C’ blocks be converted to version ‘A’ blocks.
-///       Currently only version `D` is implemented in the wrapping mechanisms.
+///       Currently versions `B`, `C` and `D` are implemented in the wrapping mechanisms, see
+///       `supported_versions`.
 pub const ALLOWED_VERSION_IDS: [&'static str; 4] = ["A", "B", "C", "D"];
 
+/// Check whether a key block version ID is deprecated.
+///
+/// Only version `A` is deprecated, it should not be used in new applications.
+pub fn version_is_deprecated(version_id: &str) -> bool {
+    version_id == "A"
+}
+
+/// Get the key block binding method of a version ID, or `None` for an unknown version ID.
+pub fn version_description(version_id: &str) -> Option<&'static str> {
+    match version_id {
+        "A" => Some("Key Variant Binding Method"),
+        "B" => Some("TDEA Key Derivation Binding Method"),
+        "C" => Some("TDEA Key Variant Binding Method"),
+        "D" => Some("AES Key Derivation Binding Method"),
+        _ => None,
+    }
+}
+
 /// Predefined allowed key usages for the key block.
 ///
 /// Key usage defines the type of the key and its intended function, whether it's used for encrypting data,
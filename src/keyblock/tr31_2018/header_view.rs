@@ -0,0 +1,308 @@
+//! Module for borrowed, allocation-free views of TR-31 Key Block Headers.
+//!
+//! # Description
+//!
+//! A `HeaderView` validates a key block header like `KeyBlockHeader::new_from_str_with_options`
+//! but does not copy any part of it. The fields are slices of the input string and the optional
+//! blocks are read from the input on iteration, so parsing a header does not allocate on success.
+//! This is intended for constrained environments, e.g. a smartcard HAL, where heap allocations
+//! must be avoided. Errors are `PaysecError` values and do allocate.
+//!
+//! The owned `KeyBlockHeader` can be built from a view with `KeyBlockHeader::from_view`.
+//!
+//! # Example
+//! ```
+//! use paysec::keyblock::{HeaderView, KeyBlockHeader};
+//!
+//! let key_block = "D0144P0AE00E0200KS1800604B120F9292800000PB0800001234";
+//! let view = HeaderView::parse(key_block).unwrap();
+//! assert_eq!(view.key_usage(), "P0");
+//! assert_eq!(view.len(), 48);
+//!
+//! let opt_block = view.opt_blocks().next().unwrap();
+//! assert_eq!((opt_block.id(), opt_block.data()), ("KS", "00604B120F9292800000"));
+//!
+//! let header = KeyBlockHeader::from_view(&view).unwrap();
+//! assert_eq!(header.export_str().unwrap(), view.as_str());
+//! ```
+
+use crate::error::PaysecError;
+
+use super::key_block_header::{
+    validate_algorithm, validate_exportability, validate_key_usage, validate_key_version_number,
+    validate_mode_of_use, validate_reserved_field, validate_version_id,
+};
+use super::opt_block::OptBlock;
+use super::parse_error::ParseError;
+use crate::validation::ValidationConfig;
+
+/// A validated key block header borrowed from its string representation.
+///
+/// The accessors return the same values as the getters of `KeyBlockHeader`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeaderView<'a> {
+    header: &'a str,
+    kb_length: u16,
+    num_opt_blocks: u8,
+}
+
+/// An optional block borrowed from a `HeaderView`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OptBlockView<'a> {
+    id: &'a str,
+    data: &'a str,
+    offset: usize,
+    length: usize,
+}
+
+/// Iterator over the optional blocks of a `HeaderView`.
+#[derive(Debug, Clone)]
+pub struct OptBlockViews<'a> {
+    header: &'a str,
+    offset: usize,
+    remaining: u8,
+}
+
+impl<'a> HeaderView<'a> {
+    /// Parse and validate the key block header at the start of `input`.
+    ///
+    /// `input` may be a complete key block, the view covers the header only.
+    ///
+    /// # Errors
+    ///
+    /// Returns the same errors as `KeyBlockHeader::new_from_str`.
+    pub fn parse(input: &'a str) -> Result<Self, PaysecError> {
+        Self::parse_with_options(input, &ValidationConfig::default())
+    }
+
+    /// Parse and validate the key block header at the start of `input` with a validation policy.
+    ///
+    /// # Errors
+    ///
+    /// Returns the same errors as `KeyBlockHeader::new_from_str_with_options`.
+    pub fn parse_with_options(
+        input: &'a str,
+        config: &ValidationConfig,
+    ) -> Result<Self, PaysecError> {
+        if input.len() < 16 {
+            return Err(ParseError::located(
+                "header",
+                0,
+                input.len(),
+                "ERROR TR-31 HEADER: Invalid data length",
+            ));
+        }
+
+        let field = |start: usize, end: usize, name: &'static str| {
+            input.get(start..end).ok_or_else(|| {
+                ParseError::located(
+                    name,
+                    start,
+                    end - start,
+                    "ERROR TR-31 HEADER: Invalid non-ASCII character",
+                )
+            })
+        };
+
+        let version_id = field(0, 1, "version ID")?;
+        let kb_length = field(1, 5, "key block length")?
+            .parse::<u16>()
+            .map_err(|_| {
+                ParseError::located(
+                    "key block length",
+                    1,
+                    4,
+                    "ERROR TR-31 HEADER: Invalid key block length",
+                )
+            })?;
+        let key_usage = field(5, 7, "key usage")?;
+        let algorithm = field(7, 8, "algorithm")?;
+        let mode_of_use = field(8, 9, "mode of use")?;
+        let key_version_number = field(9, 11, "key version number")?;
+        let exportability = field(11, 12, "exportability")?;
+        let num_opt_blocks = field(12, 14, "number of optional blocks")?
+            .parse::<u8>()
+            .map_err(|_| {
+                ParseError::located(
+                    "number of optional blocks",
+                    12,
+                    2,
+                    "ERROR TR-31 HEADER: Invalid number of optional blocks",
+                )
+            })?;
+        let reserved_field = field(14, 16, "reserved field")?;
+
+        validate_version_id(version_id)
+            .map_err(|e| ParseError::located_kind("version ID", 0, 1, e))?;
+        validate_key_usage(key_usage)
+            .map_err(|e| ParseError::located_kind("key usage", 5, 2, e))?;
+        validate_algorithm(algorithm)
+            .map_err(|e| ParseError::located_kind("algorithm", 7, 1, e))?;
+        if config.known_modes_of_use || !mode_of_use.bytes().all(|b| b.is_ascii_digit()) {
+            validate_mode_of_use(mode_of_use)
+                .map_err(|e| ParseError::located_kind("mode of use", 8, 1, e))?;
+        }
+        validate_key_version_number(key_version_number)
+            .map_err(|e| ParseError::located_kind("key version number", 9, 2, e))?;
+        validate_exportability(exportability)
+            .map_err(|e| ParseError::located_kind("exportability", 11, 1, e))?;
+        if config.zero_reserved_field || !reserved_field.bytes().all(|b| b.is_ascii_alphanumeric())
+        {
+            validate_reserved_field(reserved_field)
+                .map_err(|e| ParseError::located_kind("reserved field", 14, 2, e))?;
+        }
+
+        if num_opt_blocks > 0 && input.len() < 20 {
+            return Err(ParseError::located(
+                "optional blocks",
+                16,
+                input.len() - 16,
+                "ERROR TR-31 HEADER: Invalid header length containing optional blocks",
+            ));
+        }
+
+        // Walk the optional blocks once to validate them and find the end of the header
+        let mut header_len = 16;
+        for _ in 0..num_opt_blocks {
+            let (_, _, length) = OptBlock::scan_from_str_with_options(&input[header_len..], config)
+                .map_err(|e| {
+                    ParseError::shift(
+                        e,
+                        header_len,
+                        "ERROR TR-31 HEADER: Failed to parse optional blocks: ",
+                    )
+                })?;
+            header_len += length;
+        }
+
+        Ok(HeaderView {
+            header: &input[..header_len],
+            kb_length,
+            num_opt_blocks,
+        })
+    }
+
+    /// Returns the header string covered by the view, including the optional blocks.
+    pub fn as_str(&self) -> &'a str {
+        self.header
+    }
+
+    /// Get the header length including the length of optional blocks.
+    pub fn len(&self) -> usize {
+        self.header.len()
+    }
+
+    /// Always `false`, a parsed header has at least 16 characters.
+    pub fn is_empty(&self) -> bool {
+        self.header.is_empty()
+    }
+
+    /// Get the version ID of the key block header.
+    pub fn version_id(&self) -> &'a str {
+        &self.header[0..1]
+    }
+
+    /// Get the key block length.
+    pub fn kb_length(&self) -> u16 {
+        self.kb_length
+    }
+
+    /// Get the key usage of the key block header.
+    pub fn key_usage(&self) -> &'a str {
+        &self.header[5..7]
+    }
+
+    /// Get the algorithm of the key block header.
+    pub fn algorithm(&self) -> &'a str {
+        &self.header[7..8]
+    }
+
+    /// Get the mode of use of the key block header.
+    pub fn mode_of_use(&self) -> &'a str {
+        &self.header[8..9]
+    }
+
+    /// Get the key version number of the key block header.
+    pub fn key_version_number(&self) -> &'a str {
+        &self.header[9..11]
+    }
+
+    /// Get the exportability of the key block header.
+    pub fn exportability(&self) -> &'a str {
+        &self.header[11..12]
+    }
+
+    /// Get the number of optional blocks in the key block header.
+    pub fn num_optional_blocks(&self) -> u8 {
+        self.num_opt_blocks
+    }
+
+    /// Get the value of the reserved field in the key block header.
+    pub fn reserved_field(&self) -> &'a str {
+        &self.header[14..16]
+    }
+
+    /// Return the serialized optional blocks, i.e. the part of the header after the first 16
+    /// characters.
+    pub fn opt_blocks_str(&self) -> &'a str {
+        &self.header[16..]
+    }
+
+    /// Returns an iterator over the optional blocks in the order of the header.
+    pub fn opt_blocks(&self) -> OptBlockViews<'a> {
+        OptBlockViews {
+            header: self.header,
+            offset: 16,
+            remaining: self.num_opt_blocks,
+        }
+    }
+}
+
+impl<'a> OptBlockView<'a> {
+    /// Returns the ID of the optional block.
+    pub fn id(&self) -> &'a str {
+        self.id
+    }
+
+    /// Returns the data of the optional block.
+    pub fn data(&self) -> &'a str {
+        self.data
+    }
+
+    /// Returns the offset of the optional block in the header.
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// Returns the length of the optional block as given by its length field.
+    pub fn length(&self) -> usize {
+        self.length
+    }
+}
+
+impl<'a> Iterator for OptBlockViews<'a> {
+    type Item = OptBlockView<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+
+        // The blocks were validated when the view was parsed. The checks are relaxed, since a
+        // view parsed with a lenient policy may contain proprietary IDs.
+        let (id, data, length) = OptBlock::scan_from_str_with_options(
+            &self.header[self.offset..],
+            &ValidationConfig::lenient(),
+        )
+        .ok()?;
+        let opt_block = OptBlockView {
+            id,
+            data,
+            offset: self.offset,
+            length,
+        };
+        self.offset += length;
+        Some(opt_block)
+    }
+}
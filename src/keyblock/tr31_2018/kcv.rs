@@ -0,0 +1,174 @@
+//! Module for Key Check Values (KCV) in TR-31 Key Blocks.
+//!
+//! # Standard
+//!
+//! - ASC X9.24-1: 2017, Annex A: "Key Check Values"
+//! - TR-31: 2018, p. 30-31 (optional blocks `KC` and `KP`)
+//!
+//! # Description
+//!
+//! A KCV is used to confirm the value of a key without revealing it. The `KC` optional block
+//! carries the KCV of the wrapped key and the `KP` optional block the KCV of the KBPK. The data of
+//! both blocks is the KCV algorithm followed by the leftmost bytes of the KCV in hex:
+//! - `00`: Legacy KCV, the encryption of a zero block, truncated to 3 bytes for TDES keys.
+//! - `01`: CMAC KCV, the CMAC of a zero block, truncated to 5 bytes for AES keys.
+//!
+//! # Example
+//! ```
+//! use paysec::keyblock::{compute_kcv_aes, KcvKind, OptBlock};
+//!
+//! let kbpk =
+//!     hex::decode("88E1AB2A2E3DD38C1FA039A536500CC8A87AB9D62DC92C01058FA79F44657DE6").unwrap();
+//! assert_eq!(hex::encode_upper(compute_kcv_aes(&kbpk).unwrap()), "2331550BC9");
+//!
+//! let kp_block = OptBlock::new_kcv(KcvKind::Kp, "A", &kbpk).unwrap();
+//! assert_eq!(kp_block.export_str().unwrap(), "KP10012331550BC9");
+//! ```
+
+use crate::error::PaysecError;
+
+use super::opt_block::OptBlock;
+use crate::crypto::aes::aes_enc_ecb;
+use crate::crypto::aes::{aes_cmac, AES_BLOCK_LEN};
+use crate::crypto::tdes::tdes_cmac;
+use crate::crypto::tdes::{tdes_enc_block, TDES_BLOCK_LEN};
+
+/// The length of the CMAC KCV of an AES key in bytes.
+pub const KCV_AES_LEN: usize = 5;
+/// The length of the legacy KCV of a TDES key in bytes.
+pub const KCV_TDES_LEN: usize = 3;
+/// The length of the legacy KCV of an AES key in bytes.
+pub const KCV_AES_LEGACY_LEN: usize = 3;
+
+/// The optional block carrying a KCV.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KcvKind {
+    /// `KC`: KCV of the wrapped key.
+    Kc,
+    /// `KP`: KCV of the Key Block Protection Key.
+    Kp,
+}
+
+impl KcvKind {
+    /// Returns the ID of the optional block.
+    pub fn id(&self) -> &'static str {
+        match self {
+            KcvKind::Kc => "KC",
+            KcvKind::Kp => "KP",
+        }
+    }
+}
+
+/// Compute the 5-byte CMAC KCV of an AES key.
+///
+/// # Errors
+///
+/// Returns an error if the key is not a valid AES key.
+pub fn compute_kcv_aes(key: &[u8]) -> Result<[u8; KCV_AES_LEN], PaysecError> {
+    let mac = aes_cmac(&[0u8; AES_BLOCK_LEN], key)?;
+    let mut kcv = [0u8; KCV_AES_LEN];
+    kcv.copy_from_slice(&mac[..KCV_AES_LEN]);
+    Ok(kcv)
+}
+
+/// Compute the 3-byte legacy KCV of an AES key, the AES-ECB encryption of a zero block.
+///
+/// TR-31 uses the CMAC KCV of `compute_kcv_aes` for AES keys in `KC` and `KP` blocks, the legacy
+/// KCV is for key logs and peers which still exchange it (KCV algorithm `00`).
+///
+/// # Errors
+///
+/// Returns an error if the key is not a valid AES key.
+pub fn compute_kcv_aes_legacy(key: &[u8]) -> Result<[u8; KCV_AES_LEGACY_LEN], PaysecError> {
+    let block = aes_enc_ecb(&[0u8; AES_BLOCK_LEN], key)?;
+    let mut kcv = [0u8; KCV_AES_LEGACY_LEN];
+    kcv.copy_from_slice(&block[..KCV_AES_LEGACY_LEN]);
+    Ok(kcv)
+}
+
+/// Compute the 3-byte legacy KCV of a TDES key.
+///
+/// # Errors
+///
+/// Returns an error if the key is not a valid TDES key.
+pub fn compute_kcv_tdes(key: &[u8]) -> Result<[u8; KCV_TDES_LEN], PaysecError> {
+    let block = tdes_enc_block(&[0u8; TDES_BLOCK_LEN], key)?;
+    let mut kcv = [0u8; KCV_TDES_LEN];
+    kcv.copy_from_slice(&block[..KCV_TDES_LEN]);
+    Ok(kcv)
+}
+
+impl OptBlock {
+    /// Create a `KC` or `KP` optional block with the KCV of a key.
+    ///
+    /// AES keys get the 5-byte CMAC KCV (algorithm `01`) and TDES keys the 3-byte legacy KCV
+    /// (algorithm `00`).
+    ///
+    /// # Arguments
+    ///
+    /// * `kind` - Whether the block carries the KCV of the wrapped key or of the KBPK.
+    /// * `algorithm` - The algorithm of the key as in the key block header, `A` or `T`.
+    /// * `key` - The key to compute the KCV of.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the algorithm is neither AES nor TDES or the key is not valid for the
+    /// algorithm.
+    pub fn new_kcv(kind: KcvKind, algorithm: &str, key: &[u8]) -> Result<Self, PaysecError> {
+        let data = match algorithm {
+            "A" => format!("01{}", hex::encode_upper(compute_kcv_aes(key)?)),
+            "T" => format!("00{}", hex::encode_upper(compute_kcv_tdes(key)?)),
+            _ => {
+                return Err(format!(
+                    "ERROR TR-31 OPT BLOCK: KCV not supported for key algorithm {}",
+                    algorithm
+                )
+                .into())
+            }
+        };
+        Self::new(kind.id(), &data, None)
+    }
+}
+
+// Check the KCV of a key against the data of a `KP` or `KC` optional block.
+pub(crate) fn kcv_block_matches(
+    block: &OptBlock,
+    key: &[u8],
+    algorithm: &str,
+) -> Result<bool, PaysecError> {
+    let data = block.data();
+    let invalid = || {
+        format!(
+            "ERROR TR-31: Invalid KCV in optional block {}: {}",
+            block.id(),
+            data
+        )
+    };
+
+    if data.len() < 4 {
+        return Err(invalid().into());
+    }
+    let (kcv_algorithm, kcv_hex) = data.split_at(2);
+    let expected_kcv = hex::decode(kcv_hex).map_err(|_| invalid())?;
+
+    let kcv = match (kcv_algorithm, algorithm) {
+        ("00", "A") => aes_enc_ecb(&[0u8; AES_BLOCK_LEN], key)?,
+        ("01", "A") => aes_cmac(&[0u8; AES_BLOCK_LEN], key)?.to_vec(),
+        ("00", "T") => tdes_enc_block(&[0u8; TDES_BLOCK_LEN], key)?.to_vec(),
+        ("01", "T") => tdes_cmac(&[0u8; TDES_BLOCK_LEN], key)?.to_vec(),
+        _ => {
+            return Err(format!(
+            "ERROR TR-31: KCV algorithm {} not supported for key algorithm {} in optional block {}",
+            kcv_algorithm,
+            algorithm,
+            block.id()
+        )
+            .into())
+        }
+    };
+
+    if expected_kcv.len() > kcv.len() {
+        return Err(invalid().into());
+    }
+    Ok(kcv[..expected_kcv.len()] == expected_kcv[..])
+}
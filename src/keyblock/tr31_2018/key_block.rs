@@ -0,0 +1,76 @@
+//! Module for a parsed TR-31 key block.
+//!
+//! # Description
+//!
+//! `tr31_wrap` returns a key block as a string and `tr31_unwrap` returns the header and the clear
+//! key. Audit logs often need the encrypted payload and the MAC as well, e.g. to record the MAC
+//! and the length of the ciphertext without handling the key. `KeyBlock::parse` splits a key block
+//! into the parsed header, the encrypted payload and the MAC, with the same structural checks as
+//! `tr31_validate_structure`. No KBPK is needed and nothing is decrypted.
+//!
+//! # Example
+//! ```
+//! use paysec::keyblock::KeyBlock;
+//!
+//! let s = "D0112P0AE00E0000B82679114F470F540165EDFBF7E250FCEA43F810D215F8D207E2E417C07156A27E8E31DA05F7425509593D03A457DC34";
+//! let key_block = KeyBlock::parse(s).unwrap();
+//!
+//! assert_eq!(key_block.header.key_usage(), "P0");
+//! assert_eq!(key_block.encrypted_payload.len(), 32);
+//! assert_eq!(hex::encode_upper(&key_block.mac), "7E8E31DA05F7425509593D03A457DC34");
+//! assert_eq!(key_block.to_string(), s);
+//! ```
+
+use crate::error::PaysecError;
+use std::fmt;
+
+use super::key_block_header::KeyBlockHeader;
+use super::tr31::parse_key_block_structure;
+use crate::utils::ct_hex_encode_upper;
+use crate::validation::ValidationConfig;
+
+/// A TR-31 key block split into the parsed header, the encrypted payload and the MAC.
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyBlock {
+    /// The parsed key block header.
+    pub header: KeyBlockHeader,
+    /// The encrypted payload.
+    pub encrypted_payload: Vec<u8>,
+    /// The MAC of the key block.
+    pub mac: Vec<u8>,
+}
+
+impl KeyBlock {
+    /// Parse a key block string.
+    ///
+    /// Upper- and lowercase hex is accepted for the encrypted payload and the MAC.
+    ///
+    /// # Errors
+    ///
+    /// Returns the same errors as `tr31_validate_structure`, e.g. if the header cannot be parsed
+    /// or the key block length does not match the length in the header.
+    pub fn parse(s: &str) -> Result<Self, PaysecError> {
+        let (header, encrypted_payload, mac) =
+            parse_key_block_structure(s, &ValidationConfig::default())?;
+
+        Ok(KeyBlock {
+            header,
+            encrypted_payload,
+            mac,
+        })
+    }
+}
+
+/// Formats the key block as a string with the encrypted payload and the MAC in uppercase hex.
+impl fmt::Display for KeyBlock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let header_str = self.header.export_str().map_err(|_| fmt::Error)?;
+        write!(
+            f,
+            "{}{}{}",
+            header_str,
+            ct_hex_encode_upper(&self.encrypted_payload),
+            ct_hex_encode_upper(&self.mac)
+        )
+    }
+}
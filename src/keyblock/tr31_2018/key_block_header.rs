@@ -66,9 +66,15 @@ use super::header_constants::{
     ALLOWED_VERSION_IDS,
 };
 
+use super::header_view::HeaderView;
 use super::opt_block::OptBlock;
+use super::parse_error::ParseError;
+use crate::validation::ValidationConfig;
 
-use std::error::Error;
+use crate::error::PaysecError;
+#[cfg(feature = "chrono")]
+use chrono::{DateTime, Duration, NaiveDateTime, Utc};
+use std::collections::HashMap;
 
 /// Represents the header of a TR-31 Key Block.
 ///
@@ -92,7 +98,7 @@ use std::error::Error;
 /// - `reserved_field`: Reserved for future use, currently filled with zero characters.
 /// - `opt_blocks`: Contains additional optional blocks of data if present.
 ///
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
 pub struct KeyBlockHeader {
     version_id: String,
     kb_length: u16,
@@ -142,7 +148,7 @@ impl KeyBlockHeader {
     ///
     /// # Returns
     ///
-    /// A `Result` which is `Ok` with the new `KeyBlockHeader`, or an `Err` with a boxed error.
+    /// A `Result` which is `Ok` with the new `KeyBlockHeader`, or an `Err` with a `PaysecError`.
     pub fn new_with_values(
         version_id: &str,
         key_usage: &str,
@@ -150,7 +156,7 @@ impl KeyBlockHeader {
         mode_of_use: &str,
         key_version_number: &str,
         exportability: &str,
-    ) -> Result<Self, Box<dyn Error>> {
+    ) -> Result<Self, PaysecError> {
         let mut header = KeyBlockHeader::new_empty();
         header.set_version_id(version_id)?;
         header.set_key_usage(key_usage)?;
@@ -175,56 +181,420 @@ impl KeyBlockHeader {
     /// # Returns
     ///
     /// A `Result` which is `Ok` with a new `KeyBlockHeader` if parsing is successful,
-    /// or an `Err` containing a boxed error describing the issue.
-    pub fn new_from_str(header_str: &str) -> Result<Self, Box<dyn Error>> {
+    /// or an `Err` containing a `PaysecError` describing the issue.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `PaysecError::Parse` error locating the offending field in `header_str` if the string
+    /// is too short or a field or optional block is invalid.
+    pub fn new_from_str(header_str: &str) -> Result<Self, PaysecError> {
+        Self::new_from_str_with_options(header_str, &ValidationConfig::default())
+    }
+
+    /// Parse a `KeyBlockHeader` from a string representation with a validation policy.
+    ///
+    /// Same as `new_from_str`, with the following checks controlled by `config`:
+    /// - `zero_reserved_field`: If disabled, any two alphanumeric characters are accepted in the
+    ///   reserved field.
+    /// - `known_opt_block_ids`: If disabled, optional blocks with proprietary IDs are accepted,
+    ///   see `OptBlock::new_from_str_with_options`.
+    /// - `known_modes_of_use`: If disabled, a proprietary numeric mode of use is accepted. The
+    ///   mode of use is kept as is and exported unchanged by `export_str`.
+    ///
+    /// # Errors
+    ///
+    /// Returns the same errors as `new_from_str`.
+    ///
+    /// # Example
+    /// ```
+    /// use paysec::keyblock::KeyBlockHeader;
+    /// use paysec::validation::ValidationConfig;
+    ///
+    /// let header_str = "D0112P0AE00E0000";
+    /// let header =
+    ///     KeyBlockHeader::new_from_str_with_options(header_str, &ValidationConfig::lenient());
+    /// assert!(header.is_ok());
+    /// ```
+    pub fn new_from_str_with_options(
+        header_str: &str,
+        config: &ValidationConfig,
+    ) -> Result<Self, PaysecError> {
         if header_str.len() < 16 {
-            return Err(Box::<dyn Error>::from(
+            return Err(ParseError::located(
+                "header",
+                0,
+                header_str.len(),
                 "ERROR TR-31 HEADER: Invalid data length",
             ));
         }
 
-        let version_id = header_str[0..1].to_string();
-        let kb_length = header_str[1..5]
+        let field = |start: usize, end: usize, name: &'static str| {
+            header_str.get(start..end).ok_or_else(|| {
+                ParseError::located(
+                    name,
+                    start,
+                    end - start,
+                    "ERROR TR-31 HEADER: Invalid non-ASCII character",
+                )
+            })
+        };
+
+        let version_id = field(0, 1, "version ID")?.to_string();
+        let kb_length = field(1, 5, "key block length")?
             .parse::<u16>()
-            .map_err(|_| Box::<dyn Error>::from("ERROR TR-31 HEADER: Invalid key block length"))?;
-        let key_usage = header_str[5..7].to_string();
-        let algorithm = header_str[7..8].to_string();
-        let mode_of_use = header_str[8..9].to_string();
-        let key_version_number = header_str[9..11].to_string();
-        let exportability = header_str[11..12].to_string();
-        let num_optional_blocks = header_str[12..14].parse::<u8>().map_err(|_| {
-            Box::<dyn Error>::from("ERROR TR-31 HEADER: Invalid number of optional blocks")
-        })?;
-        let reserved_field = header_str[14..16].to_string();
+            .map_err(|_| {
+                ParseError::located(
+                    "key block length",
+                    1,
+                    4,
+                    "ERROR TR-31 HEADER: Invalid key block length",
+                )
+            })?;
+        let key_usage = field(5, 7, "key usage")?.to_string();
+        let algorithm = field(7, 8, "algorithm")?.to_string();
+        let mode_of_use = field(8, 9, "mode of use")?.to_string();
+        let key_version_number = field(9, 11, "key version number")?.to_string();
+        let exportability = field(11, 12, "exportability")?.to_string();
+        let num_optional_blocks = field(12, 14, "number of optional blocks")?
+            .parse::<u8>()
+            .map_err(|_| {
+                ParseError::located(
+                    "number of optional blocks",
+                    12,
+                    2,
+                    "ERROR TR-31 HEADER: Invalid number of optional blocks",
+                )
+            })?;
+        let reserved_field = field(14, 16, "reserved field")?.to_string();
 
         let mut header = Self::new_empty();
-        header.set_version_id(&version_id)?;
-        header.set_kb_length(kb_length)?;
-        header.set_key_usage(&key_usage)?;
-        header.set_algorithm(&algorithm)?;
-        header.set_mode_of_use(&mode_of_use)?;
-        header.set_key_version_number(&key_version_number)?;
-        header.set_exportability(&exportability)?;
-        header.set_num_optional_blocks(num_optional_blocks)?;
-        header.set_reserved_field(&reserved_field)?;
+        header
+            .set_version_id(&version_id)
+            .map_err(|e| ParseError::located_kind("version ID", 0, 1, e))?;
+        header
+            .set_kb_length(kb_length)
+            .map_err(|e| ParseError::located_kind("key block length", 1, 4, e))?;
+        header
+            .set_key_usage(&key_usage)
+            .map_err(|e| ParseError::located_kind("key usage", 5, 2, e))?;
+        header
+            .set_algorithm(&algorithm)
+            .map_err(|e| ParseError::located_kind("algorithm", 7, 1, e))?;
+        if !config.known_modes_of_use && mode_of_use.bytes().all(|b| b.is_ascii_digit()) {
+            header.mode_of_use = mode_of_use;
+        } else {
+            header
+                .set_mode_of_use(&mode_of_use)
+                .map_err(|e| ParseError::located_kind("mode of use", 8, 1, e))?;
+        }
+        header
+            .set_key_version_number(&key_version_number)
+            .map_err(|e| ParseError::located_kind("key version number", 9, 2, e))?;
+        header
+            .set_exportability(&exportability)
+            .map_err(|e| ParseError::located_kind("exportability", 11, 1, e))?;
+        header
+            .set_num_optional_blocks(num_optional_blocks)
+            .map_err(|e| ParseError::located_kind("number of optional blocks", 12, 2, e))?;
+        if !config.zero_reserved_field && reserved_field.bytes().all(|b| b.is_ascii_alphanumeric())
+        {
+            header.reserved_field = reserved_field;
+        } else {
+            header
+                .set_reserved_field(&reserved_field)
+                .map_err(|e| ParseError::located_kind("reserved field", 14, 2, e))?;
+        }
 
         if num_optional_blocks > 0 && header_str.len() < 20 {
-            return Err(
-                "ERROR TR-31 HEADER: Invalid header length containing optional blocks".into(),
-            );
+            return Err(ParseError::located(
+                "optional blocks",
+                16,
+                header_str.len() - 16,
+                "ERROR TR-31 HEADER: Invalid header length containing optional blocks",
+            ));
         }
 
         if num_optional_blocks > 0 {
             let opt_block_str = &header_str[16..];
-            let opt_block_res = OptBlock::new_from_str(opt_block_str, num_optional_blocks as usize);
+            let opt_block = OptBlock::new_from_str_with_options(
+                opt_block_str,
+                num_optional_blocks as usize,
+                config,
+            )
+            .map_err(|e| {
+                ParseError::shift(
+                    e,
+                    16,
+                    "ERROR TR-31 HEADER: Failed to parse optional blocks: ",
+                )
+            })?;
+
+            header.opt_blocks = Some(Box::new(opt_block));
+        }
+
+        Ok(header)
+    }
+
+    /// Build an owned `KeyBlockHeader` from a validated `HeaderView`.
+    ///
+    /// The fields are copied as they are, so a view parsed with a lenient policy results in the
+    /// same header as `new_from_str_with_options` with that policy.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if an optional block of the view cannot be created.
+    pub fn from_view(view: &HeaderView<'_>) -> Result<Self, PaysecError> {
+        let opt_blocks = view
+            .opt_blocks()
+            .map(|opt_block| OptBlock::new_scanned(opt_block.id(), opt_block.data()))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(KeyBlockHeader {
+            version_id: view.version_id().to_string(),
+            kb_length: view.kb_length(),
+            key_usage: view.key_usage().to_string(),
+            algorithm: view.algorithm().to_string(),
+            mode_of_use: view.mode_of_use().to_string(),
+            key_version_number: view.key_version_number().to_string(),
+            exportability: view.exportability().to_string(),
+            num_opt_blocks: view.num_optional_blocks(),
+            reserved_field: view.reserved_field().to_string(),
+            opt_blocks: opt_blocks.into_iter().collect(),
+        })
+    }
+
+    /// Parse a `KeyBlockHeader` from a string representation, tolerating an extra trailing
+    /// padding block.
+    ///
+    /// Some producers append a `PB` optional block beyond the number of optional blocks declared
+    /// in the header. This function parses the header like `new_from_str` and, if a well-formed
+    /// `PB` block directly follows the declared optional blocks, either includes it in the header
+    /// (incrementing the number of optional blocks) or ignores it. Either case is reported in the
+    /// returned list of warnings.
+    ///
+    /// # Arguments
+    ///
+    /// * `header_str` - A string slice representing the key block header.
+    /// * `include_extra_pb` - Whether an extra trailing `PB` block is included in the header.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which is `Ok` with the parsed `KeyBlockHeader` and a list of warnings, or an
+    /// `Err` containing a `PaysecError` describing the issue.
+    ///
+    /// # Errors
+    ///
+    /// Returns the same errors as `new_from_str`. A malformed trailing block is not reported
+    /// and left unparsed.
+    pub fn new_from_str_tolerant(
+        header_str: &str,
+        include_extra_pb: bool,
+    ) -> Result<(Self, Vec<String>), PaysecError> {
+        let mut header = Self::new_from_str(header_str)?;
+        let mut warnings = Vec::new();
+
+        let remainder = &header_str[header.len()..];
+        if remainder.starts_with("PB") {
+            if let Ok(extra_pb) = OptBlock::new_from_str(remainder, 1) {
+                let declared = header.num_optional_blocks();
+                if include_extra_pb {
+                    header.append_opt_blocks(extra_pb);
+                    warnings.push(format!(
+                        "WARNING TR-31 HEADER: Included extra PB optional block beyond declared count: {:02}",
+                        declared
+                    ));
+                } else {
+                    warnings.push(format!(
+                        "WARNING TR-31 HEADER: Ignored extra PB optional block beyond declared count: {:02}",
+                        declared
+                    ));
+                }
+            }
+        }
+
+        Ok((header, warnings))
+    }
 
-            if let Err(e) = opt_block_res {
-                return Err(
-                    format!("ERROR TR-31 HEADER: Failed to parse optional blocks: {}", e).into(),
-                );
+    /// Create a finalized `KeyBlockHeader` from the short notation used in test specifications.
+    ///
+    /// The notation is `usage/algorithm/mode/exportability` followed by optional comma separated
+    /// tokens:
+    /// - `v=X`: Key block version ID, `D` if omitted.
+    /// - `kv=NN`: Key version number, `00` if omitted, e.g. `c1` for the first key component.
+    /// - `opt=ID:data`: Optional block, may be repeated. The blocks keep the order of the tokens.
+    ///
+    /// The data of an optional block cannot contain a comma. A padding block is added by
+    /// `finalize` if needed.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the notation does not have four `/` separated fields, a token is
+    /// unknown or repeated, or a field or optional block is invalid.
+    ///
+    /// # Example
+    /// ```
+    /// use paysec::keyblock::KeyBlockHeader;
+    ///
+    /// let header = KeyBlockHeader::from_short_notation("P0/A/E/N,kv=01,opt=KS:00604B").unwrap();
+    /// assert_eq!(header.export_str().unwrap(), "D0000P0AE01N0200KS0A00604BPB0600");
+    /// assert_eq!(header.to_short_notation(), "P0/A/E/N,kv=01,opt=KS:00604B");
+    /// ```
+    pub fn from_short_notation(s: &str) -> Result<Self, PaysecError> {
+        let mut tokens = s.split(',');
+        let fields: Vec<&str> = tokens.next().unwrap_or_default().split('/').collect();
+        let [key_usage, algorithm, mode_of_use, exportability] = fields[..] else {
+            return Err(format!(
+                "ERROR TR-31 HEADER: Short notation must start with usage/algorithm/mode/exportability: {}",
+                s
+            )
+            .into());
+        };
+
+        let mut version_id = None;
+        let mut key_version_number = None;
+        let mut opt_blocks = Vec::new();
+        for token in tokens {
+            let repeated = || {
+                format!(
+                    "ERROR TR-31 HEADER: Repeated token in short notation: {}",
+                    token
+                )
+            };
+            match token.split_once('=') {
+                Some(("v", value)) => {
+                    if version_id.replace(value).is_some() {
+                        return Err(repeated().into());
+                    }
+                }
+                Some(("kv", value)) => {
+                    if key_version_number.replace(value).is_some() {
+                        return Err(repeated().into());
+                    }
+                }
+                Some(("opt", value)) => {
+                    let (id, data) = value.split_once(':').ok_or_else(|| {
+                        format!(
+                            "ERROR TR-31 HEADER: Optional block in short notation must be ID:data: {}",
+                            value
+                        )
+                    })?;
+                    opt_blocks.push(OptBlock::new(id, data, None)?);
+                }
+                _ => {
+                    return Err(format!(
+                        "ERROR TR-31 HEADER: Unknown token in short notation: {}",
+                        token
+                    )
+                    .into())
+                }
             }
+        }
+
+        let mut header = KeyBlockHeader::new_with_values(
+            version_id.unwrap_or("D"),
+            key_usage,
+            algorithm,
+            mode_of_use,
+            key_version_number.unwrap_or("00"),
+            exportability,
+        )?;
+        header.set_opt_blocks(opt_blocks.into_iter().collect());
+        header.finalize()?;
+
+        Ok(header)
+    }
+
+    /// Return the short notation of the header, the inverse of `from_short_notation`.
+    ///
+    /// The version and key version number are omitted if they are `D` and `00`. Padding blocks
+    /// are omitted, since `finalize` adds them again.
+    pub fn to_short_notation(&self) -> String {
+        let mut notation = format!(
+            "{}/{}/{}/{}",
+            self.key_usage, self.algorithm, self.mode_of_use, self.exportability
+        );
+        if self.version_id != "D" {
+            notation.push_str(&format!(",v={}", self.version_id));
+        }
+        if self.key_version_number != "00" {
+            notation.push_str(&format!(",kv={}", self.key_version_number));
+        }
+        for opt_block in std::iter::successors(self.opt_blocks.as_deref(), |block| block.next())
+            .filter(|block| block.id() != "PB")
+        {
+            notation.push_str(&format!(",opt={}:{}", opt_block.id(), opt_block.data()));
+        }
+        notation
+    }
+
+    /// Create a `KeyBlockHeader` from a map of field names to values.
+    ///
+    /// The recognized field names are `version_id`, `key_usage`, `algorithm`, `mode_of_use`,
+    /// `key_version_number`, `exportability` and `reserved_field`. The key version number and the
+    /// reserved field are optional and default to `00`. Each value is validated by the setter of
+    /// the field.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a field name is unknown, a required field is missing or a value is
+    /// invalid.
+    ///
+    /// # Example
+    /// ```
+    /// use std::collections::HashMap;
+    /// use paysec::keyblock::KeyBlockHeader;
+    ///
+    /// let fields: HashMap<String, String> = [
+    ///     ("version_id", "D"),
+    ///     ("key_usage", "P0"),
+    ///     ("algorithm", "A"),
+    ///     ("mode_of_use", "E"),
+    ///     ("exportability", "E"),
+    /// ]
+    /// .into_iter()
+    /// .map(|(name, value)| (name.to_string(), value.to_string()))
+    /// .collect();
+    ///
+    /// let header = KeyBlockHeader::from_map(&fields).unwrap();
+    /// assert_eq!(header.export_str().unwrap(), "D0000P0AE00E0000");
+    /// ```
+    pub fn from_map(fields: &HashMap<String, String>) -> Result<Self, PaysecError> {
+        const REQUIRED: [&str; 5] = [
+            "version_id",
+            "key_usage",
+            "algorithm",
+            "mode_of_use",
+            "exportability",
+        ];
+        const OPTIONAL: [&str; 2] = ["key_version_number", "reserved_field"];
+
+        // Sort the names, so the error for several unknown fields does not depend on the hasher
+        let mut names: Vec<&String> = fields.keys().collect();
+        names.sort();
+        if let Some(name) = names
+            .into_iter()
+            .find(|name| !REQUIRED.contains(&name.as_str()) && !OPTIONAL.contains(&name.as_str()))
+        {
+            return Err(format!("ERROR TR-31 HEADER: Unknown header field: {}", name).into());
+        }
+
+        let field = |name: &str| {
+            fields
+                .get(name)
+                .map(String::as_str)
+                .ok_or_else(|| format!("ERROR TR-31 HEADER: Missing header field: {}", name))
+        };
 
-            header.opt_blocks = Some(Box::new(opt_block_res.unwrap()));
+        let mut header = KeyBlockHeader::new_with_values(
+            field("version_id")?,
+            field("key_usage")?,
+            field("algorithm")?,
+            field("mode_of_use")?,
+            field("key_version_number").unwrap_or("00"),
+            field("exportability")?,
+        )?;
+        if let Ok(reserved_field) = field("reserved_field") {
+            header.set_reserved_field(reserved_field)?;
         }
 
         Ok(header)
@@ -244,13 +614,15 @@ impl KeyBlockHeader {
     ///
     /// A `Result` containing the string representation of the key block header.
     /// If any field is empty or `kb_length` is zero, or if an error occurs while
-    /// exporting optional blocks, an error is returned as a boxed error.
+    /// exporting optional blocks, a `PaysecError` is returned.
     ///
     /// # Errors
     ///
     /// Returns an error if any field in the header is empty or if `kb_length` is zero.
-    /// Also returns an error if there is a failure in exporting the optional blocks.
-    pub fn export_str(&self) -> Result<String, Box<dyn Error>> {
+    /// Also returns an error if the number of optional blocks does not match the chain of
+    /// optional blocks, e.g. after `set_num_optional_blocks`, or if there is a failure in
+    /// exporting the optional blocks.
+    pub fn export_str(&self) -> Result<String, PaysecError> {
         // Check for empty fields or zero length
         if self.version_id.is_empty()
             || self.key_usage.is_empty()
@@ -265,6 +637,18 @@ impl KeyBlockHeader {
             );
         }
 
+        // The declared number of optional blocks must match the chain, otherwise the exported
+        // header could not be parsed
+        let opt_block_count =
+            std::iter::successors(self.opt_blocks.as_deref(), |block| block.next()).count();
+        if opt_block_count != self.num_opt_blocks as usize {
+            return Err(format!(
+                "ERROR TR-31 HEADER: Export failed, number of optional blocks {} does not match the optional blocks present: {}",
+                self.num_opt_blocks, opt_block_count
+            )
+            .into());
+        }
+
         let mut header_str = String::new();
 
         // Append each field to the header string
@@ -279,13 +663,25 @@ impl KeyBlockHeader {
         header_str.push_str(&self.reserved_field());
 
         // Append optional blocks if present
-        if let Some(ref opt_blocks) = self.opt_blocks {
-            header_str.push_str(&opt_blocks.export_str()?);
-        }
+        header_str.push_str(&self.opt_blocks_str()?);
 
         Ok(header_str)
     }
 
+    /// Check whether two headers produce the same MAC input.
+    ///
+    /// The MAC of a key block binds the exported header string, so headers with the same fields
+    /// but e.g. a different order of optional blocks result in different MACs. This compares the
+    /// `export_str` output of both headers, which helps to find out why a key block does not
+    /// unwrap with a rebuilt header.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if one of the headers cannot be exported.
+    pub fn mac_input_equals(&self, other: &Self) -> Result<bool, PaysecError> {
+        Ok(self.export_str()?.as_bytes() == other.export_str()?.as_bytes())
+    }
+
     /// Set the version ID of the key block header.
     ///
     /// Validates the version ID against allowed values and sets the
@@ -298,17 +694,11 @@ impl KeyBlockHeader {
     ///
     /// # Returns
     ///
-    /// A `Result` which is `Ok` if the value is valid, or an `Err` with a boxed error.
-    pub fn set_version_id(&mut self, value: &str) -> Result<(), Box<dyn Error>> {
-        if ALLOWED_VERSION_IDS.contains(&value) {
-            self.version_id = value.to_string();
-            Ok(())
-        } else {
-            Err(Box::<dyn Error>::from(format!(
-                "ERROR TR-31 HEADER: Invalid version ID: {}",
-                value
-            )))
-        }
+    /// A `Result` which is `Ok` if the value is valid, or an `Err` with a `PaysecError`.
+    pub fn set_version_id(&mut self, value: &str) -> Result<(), PaysecError> {
+        validate_version_id(value)?;
+        self.version_id = value.to_string();
+        Ok(())
     }
 
     /// Get the version ID of the key block header.
@@ -327,10 +717,10 @@ impl KeyBlockHeader {
     ///
     /// # Returns
     ///
-    /// A `Result` which is `Ok` if the length is valid, or an `Err` with a boxed error.
-    pub fn set_kb_length(&mut self, value: u16) -> Result<(), Box<dyn Error>> {
+    /// A `Result` which is `Ok` if the length is valid, or an `Err` with a `PaysecError`.
+    pub fn set_kb_length(&mut self, value: u16) -> Result<(), PaysecError> {
         if value > 9999 {
-            Err(Box::<dyn Error>::from(
+            Err(PaysecError::from(
                 "ERROR TR-31 HEADER: Invalid key block length",
             ))
         } else {
@@ -355,17 +745,11 @@ impl KeyBlockHeader {
     ///
     /// # Returns
     ///
-    /// A `Result` which is `Ok` if the value is valid, or an `Err` with a boxed error.
-    pub fn set_key_usage(&mut self, value: &str) -> Result<(), Box<dyn Error>> {
-        if ALLOWED_KEY_USAGES.contains(&value) {
-            self.key_usage = value.to_string();
-            Ok(())
-        } else {
-            Err(Box::<dyn Error>::from(format!(
-                "ERROR TR-31 HEADER: Invalid key usage: {}",
-                value
-            )))
-        }
+    /// A `Result` which is `Ok` if the value is valid, or an `Err` with a `PaysecError`.
+    pub fn set_key_usage(&mut self, value: &str) -> Result<(), PaysecError> {
+        validate_key_usage(value)?;
+        self.key_usage = value.to_string();
+        Ok(())
     }
 
     /// Get the key usage of the key block header.
@@ -384,17 +768,11 @@ impl KeyBlockHeader {
     ///
     /// # Returns
     ///
-    /// A `Result` which is `Ok` if the value is valid, or an `Err` with a boxed error.
-    pub fn set_algorithm(&mut self, value: &str) -> Result<(), Box<dyn Error>> {
-        if ALLOWED_ALGORITHMS.contains(&value) {
-            self.algorithm = value.to_string();
-            Ok(())
-        } else {
-            Err(Box::<dyn Error>::from(format!(
-                "ERROR TR-31 HEADER: Invalid algorithm: {}",
-                value
-            )))
-        }
+    /// A `Result` which is `Ok` if the value is valid, or an `Err` with a `PaysecError`.
+    pub fn set_algorithm(&mut self, value: &str) -> Result<(), PaysecError> {
+        validate_algorithm(value)?;
+        self.algorithm = value.to_string();
+        Ok(())
     }
 
     /// Get the algorithm of the key block header.
@@ -413,17 +791,11 @@ impl KeyBlockHeader {
     ///
     /// # Returns
     ///
-    /// A `Result` which is `Ok` if the value is valid, or an `Err` with a boxed error.
-    pub fn set_mode_of_use(&mut self, value: &str) -> Result<(), Box<dyn Error>> {
-        if ALLOWED_MODES_OF_USE.contains(&value) {
-            self.mode_of_use = value.to_string();
-            Ok(())
-        } else {
-            Err(Box::<dyn Error>::from(format!(
-                "ERROR TR-31 HEADER: Invalid mode of use: {}",
-                value
-            )))
-        }
+    /// A `Result` which is `Ok` if the value is valid, or an `Err` with a `PaysecError`.
+    pub fn set_mode_of_use(&mut self, value: &str) -> Result<(), PaysecError> {
+        validate_mode_of_use(value)?;
+        self.mode_of_use = value.to_string();
+        Ok(())
     }
 
     /// Get the mode of use of the key block header.
@@ -442,20 +814,9 @@ impl KeyBlockHeader {
     ///
     /// # Returns
     ///
-    /// A `Result` which is `Ok` if the value is valid, or an `Err` with a boxed error.
-    pub fn set_key_version_number(&mut self, value: &str) -> Result<(), Box<dyn Error>> {
-        if value.len() != 2 {
-            return Err(Box::<dyn Error>::from(format!(
-                "ERROR TR-31 HEADER: Key version number must consist of 2 ASCII characters: {}",
-                value
-            )));
-        }
-        if !value.chars().all(|c| c.is_ascii()) {
-            return Err(Box::<dyn Error>::from(format!(
-                "ERROR TR-31 HEADER: Key version number must consist of ASCII characters: {}",
-                value
-            )));
-        }
+    /// A `Result` which is `Ok` if the value is valid, or an `Err` with a `PaysecError`.
+    pub fn set_key_version_number(&mut self, value: &str) -> Result<(), PaysecError> {
+        validate_key_version_number(value)?;
         self.key_version_number = value.to_string();
         Ok(())
     }
@@ -476,17 +837,11 @@ impl KeyBlockHeader {
     ///
     /// # Returns
     ///
-    /// A `Result` which is `Ok` if the value is valid, or an `Err` with a boxed error.
-    pub fn set_exportability(&mut self, value: &str) -> Result<(), Box<dyn Error>> {
-        if ALLOWED_EXPORTABILITIES.contains(&value) {
-            self.exportability = value.to_string();
-            Ok(())
-        } else {
-            Err(Box::<dyn Error>::from(format!(
-                "ERROR TR-31 HEADER: Invalid exportability: {}",
-                value
-            )))
-        }
+    /// A `Result` which is `Ok` if the value is valid, or an `Err` with a `PaysecError`.
+    pub fn set_exportability(&mut self, value: &str) -> Result<(), PaysecError> {
+        validate_exportability(value)?;
+        self.exportability = value.to_string();
+        Ok(())
     }
 
     /// Get the exportability of the key block header.
@@ -505,10 +860,10 @@ impl KeyBlockHeader {
     ///
     /// # Returns
     ///
-    /// A `Result` which is `Ok` if the value is valid, or an `Err` with a boxed error.
-    pub fn set_num_optional_blocks(&mut self, value: u8) -> Result<(), Box<dyn Error>> {
+    /// A `Result` which is `Ok` if the value is valid, or an `Err` with a `PaysecError`.
+    pub fn set_num_optional_blocks(&mut self, value: u8) -> Result<(), PaysecError> {
         if value > 99 {
-            return Err(Box::<dyn Error>::from(
+            return Err(PaysecError::from(
                 "ERROR TR-31 HEADER: Number of opt blocks value is too large",
             ));
         }
@@ -532,17 +887,11 @@ impl KeyBlockHeader {
     ///
     /// # Returns
     ///
-    /// A `Result` which is `Ok` if the value is valid, or an `Err` with a boxed error.
-    pub fn set_reserved_field(&mut self, value: &str) -> Result<(), Box<dyn Error>> {
-        if value == "00" {
-            self.reserved_field = value.to_string();
-            Ok(())
-        } else {
-            return Err(Box::<dyn Error>::from(format!(
-                "ERROR TR-31 HEADER: Invalid value for reserved field: {}",
-                value.to_string()
-            )));
-        }
+    /// A `Result` which is `Ok` if the value is valid, or an `Err` with a `PaysecError`.
+    pub fn set_reserved_field(&mut self, value: &str) -> Result<(), PaysecError> {
+        validate_reserved_field(value)?;
+        self.reserved_field = value.to_string();
+        Ok(())
     }
 
     /// Get the value of the reserved field in the key block header.
@@ -578,6 +927,19 @@ impl KeyBlockHeader {
         }
     }
 
+    /// Return the header with its optional blocks replaced and the number of optional blocks
+    /// recomputed, e.g. to re-wrap a key with an updated set of optional blocks.
+    ///
+    /// The header is not finalized, call `finalize` to add a padding block if needed.
+    ///
+    /// # Arguments
+    ///
+    /// * `opt_blocks` - The new chain of optional blocks, or `None` to remove all of them.
+    pub fn with_opt_blocks(mut self, opt_blocks: Option<Box<OptBlock>>) -> Self {
+        self.set_opt_blocks(opt_blocks);
+        self
+    }
+
     /// Append a linked list of `OptBlock` instances to the end of the existing
     /// optional blocks in the `KeyBlockHeader`.
     ///
@@ -612,11 +974,77 @@ impl KeyBlockHeader {
         self.num_opt_blocks += additional_blocks_count;
     }
 
+    /// Merge optional blocks into the header and finalize it.
+    ///
+    /// The blocks of `other` are appended in order after the existing optional blocks. A `PB`
+    /// padding block of the header or of `other` is dropped and `finalize` adds a new one if
+    /// needed. The `next` links of the blocks in `other` are ignored.
+    ///
+    /// # Arguments
+    ///
+    /// * `other` - The optional blocks to merge.
+    /// * `skip_duplicates` - Whether a block with an ID already present in the header, or earlier
+    ///   in `other`, is skipped. Otherwise it is an error.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `skip_duplicates` is `false` and `other` contains a duplicate ID, or
+    /// if the header cannot be finalized. The header is unchanged on a duplicate ID.
+    pub fn merge_opt_blocks(
+        &mut self,
+        other: Vec<OptBlock>,
+        skip_duplicates: bool,
+    ) -> Result<(), PaysecError> {
+        let mut merged: Vec<OptBlock> =
+            std::iter::successors(self.opt_blocks.as_deref(), |block| block.next())
+                .filter(|block| block.id() != "PB")
+                .map(|block| {
+                    let mut block = block.clone();
+                    block.set_next(None);
+                    block
+                })
+                .collect();
+
+        for mut block in other {
+            if block.id() == "PB" {
+                continue;
+            }
+            if merged.iter().any(|existing| existing.id() == block.id()) {
+                if skip_duplicates {
+                    continue;
+                }
+                return Err(format!(
+                    "ERROR TR-31 HEADER: Duplicate optional block ID: {}",
+                    block.id()
+                )
+                .into());
+            }
+            block.set_next(None);
+            merged.push(block);
+        }
+
+        self.set_opt_blocks(merged.into_iter().collect());
+        self.finalize()
+    }
+
     /// Get a reference to the optional blocks.
     pub fn opt_blocks(&self) -> &Option<Box<OptBlock>> {
         &self.opt_blocks
     }
 
+    /// Return the serialized optional blocks, i.e. the part of the exported header after the
+    /// first 16 characters, or an empty string if the header has no optional blocks.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the optional blocks cannot be exported, see `OptBlock::export_str`.
+    pub fn opt_blocks_str(&self) -> Result<String, PaysecError> {
+        match &self.opt_blocks {
+            Some(opt_blocks) => opt_blocks.export_str(),
+            None => Ok(String::new()),
+        }
+    }
+
     /// Get the header length including the length of optional blocks.
     pub fn len(&self) -> usize {
         // Minimum length of header without optional blocks: 16
@@ -632,7 +1060,7 @@ impl KeyBlockHeader {
 
     /// Finalize the key block header to ensure its length is a multiple of the underlying cipher block size.
     /// A padding block with ID "PB" is appended if necessary.
-    pub fn finalize(&mut self) -> Result<(), Box<dyn Error>> {
+    pub fn finalize(&mut self) -> Result<(), PaysecError> {
         let block_size = if self.version_id == "D" { 16 } else { 8 };
         let header_length = self.len();
 
@@ -663,4 +1091,166 @@ impl KeyBlockHeader {
 
         Ok(())
     }
+
+    /// Check the header for inconsistencies which are not rejected when parsing it.
+    ///
+    /// The following rules are checked:
+    /// - A key with key usage `B1` (Initial DUKPT Key) carries an `IK` optional block with its
+    ///   Initial Key ID.
+    ///
+    /// # Returns
+    ///
+    /// A list of warnings, empty if the header passes all rules.
+    pub fn validate(&self) -> Vec<String> {
+        let mut warnings = Vec::new();
+
+        let has_opt_block = |id: &str| {
+            std::iter::successors(self.opt_blocks.as_deref(), |block| block.next())
+                .any(|block| block.id() == id)
+        };
+
+        if self.key_usage == "B1" && !has_opt_block("IK") {
+            warnings.push(
+                "WARNING TR-31 HEADER: Key usage B1 (Initial DUKPT Key) without IK optional block"
+                    .to_string(),
+            );
+        }
+
+        warnings
+    }
+
+    /// Check whether the `TS` optional block of the header is at most `max_age` old.
+    ///
+    /// The timestamp is parsed in the UTC format of TR-31, `YYYYMMDDhhmmssZ`, optionally with
+    /// fractional seconds, e.g. `YYYYMMDDhhmmss.ssZ`. A timestamp after `now` is fresh.
+    ///
+    /// # Arguments
+    ///
+    /// * `max_age` - The maximum age of the key block.
+    /// * `now` - The current time.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the header has no `TS` optional block or its timestamp is invalid.
+    ///
+    /// # Example
+    /// ```
+    /// use chrono::{Duration, TimeZone, Utc};
+    /// use paysec::keyblock::KeyBlockHeader;
+    ///
+    /// let header = KeyBlockHeader::new_from_str("D0000P0AE00E0200TS1320240101120000ZPB0600").unwrap();
+    /// let now = Utc.with_ymd_and_hms(2024, 1, 2, 12, 0, 0).unwrap();
+    ///
+    /// assert!(header.timestamp_is_fresh(Duration::days(1), now).unwrap());
+    /// assert!(!header.timestamp_is_fresh(Duration::hours(12), now).unwrap());
+    /// ```
+    #[cfg(feature = "chrono")]
+    pub fn timestamp_is_fresh(
+        &self,
+        max_age: Duration,
+        now: DateTime<Utc>,
+    ) -> Result<bool, PaysecError> {
+        let ts = std::iter::successors(self.opt_blocks.as_deref(), |block| block.next())
+            .find(|block| block.id() == "TS")
+            .ok_or("ERROR TR-31 HEADER: No TS optional block present")?
+            .data();
+
+        let timestamp = NaiveDateTime::parse_from_str(ts, "%Y%m%d%H%M%S%.fZ")
+            .map_err(|_| format!("ERROR TR-31 HEADER: Invalid TS timestamp: {}", ts))?
+            .and_utc();
+
+        Ok(now - timestamp <= max_age)
+    }
+}
+
+// Check a version ID against the values allowed by TR-31.
+pub(super) fn validate_version_id(value: &str) -> Result<(), PaysecError> {
+    if ALLOWED_VERSION_IDS.contains(&value) {
+        Ok(())
+    } else {
+        Err(PaysecError::InvalidHeaderField {
+            field: "version ID",
+            value: value.to_string(),
+        })
+    }
+}
+
+// Check a key usage against the values allowed by TR-31.
+pub(super) fn validate_key_usage(value: &str) -> Result<(), PaysecError> {
+    if ALLOWED_KEY_USAGES.contains(&value) {
+        Ok(())
+    } else {
+        Err(PaysecError::InvalidHeaderField {
+            field: "key usage",
+            value: value.to_string(),
+        })
+    }
+}
+
+// Check a algorithm against the values allowed by TR-31.
+pub(super) fn validate_algorithm(value: &str) -> Result<(), PaysecError> {
+    if ALLOWED_ALGORITHMS.contains(&value) {
+        Ok(())
+    } else {
+        Err(PaysecError::InvalidHeaderField {
+            field: "algorithm",
+            value: value.to_string(),
+        })
+    }
+}
+
+// Check a mode of use against the values allowed by TR-31.
+pub(super) fn validate_mode_of_use(value: &str) -> Result<(), PaysecError> {
+    if ALLOWED_MODES_OF_USE.contains(&value) {
+        Ok(())
+    } else {
+        Err(PaysecError::InvalidHeaderField {
+            field: "mode of use",
+            value: value.to_string(),
+        })
+    }
+}
+
+// Check a exportability against the values allowed by TR-31.
+pub(super) fn validate_exportability(value: &str) -> Result<(), PaysecError> {
+    if ALLOWED_EXPORTABILITIES.contains(&value) {
+        Ok(())
+    } else {
+        Err(PaysecError::InvalidHeaderField {
+            field: "exportability",
+            value: value.to_string(),
+        })
+    }
+}
+
+// Check that a key version number consists of 2 ASCII characters.
+pub(super) fn validate_key_version_number(value: &str) -> Result<(), PaysecError> {
+    if value.len() != 2 {
+        return Err(format!(
+            "ERROR TR-31 HEADER: Key version number must consist of 2 ASCII characters: {}",
+            value
+        )
+        .into());
+    }
+    if !value.is_ascii() {
+        return Err(format!(
+            "ERROR TR-31 HEADER: Key version number must consist of ASCII characters: {}",
+            value
+        )
+        .into());
+    }
+    Ok(())
+}
+
+// Check that the reserved field is "00".
+pub(super) fn validate_reserved_field(value: &str) -> Result<(), PaysecError> {
+    if value == "00" {
+        Ok(())
+    } else {
+        Err(format!(
+            "ERROR TR-31 HEADER: Invalid value for reserved field: {}",
+            value
+        )
+        .into())
+    }
 }
@@ -1,5 +1,37 @@
-use soft_aes::aes::aes_cmac;
-use std::error::Error;
+use crate::crypto::aes::aes_cmac;
+use crate::crypto::tdes::tdes_cmac;
+use crate::error::PaysecError;
+
+// KBEK, KBAK and the keying option used for the version 'B' derivation.
+type TdesDerivedKeys = (Vec<u8>, Vec<u8>, TdesKeyVariant);
+
+// Variant constants of the Key Variant Binding Method - TDEA
+const KBEK_VARIANT: u8 = 0x45;
+const KBAK_VARIANT: u8 = 0x4D;
+
+// Input Data for Key Derivation Binding Method - TDEA
+
+// TDEA 2-key (double length)
+const TDES_2KEY_KDI_KBEK: [[u8; 8]; 2] = [
+    [0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x80],
+    [0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x80],
+];
+const TDES_2KEY_KDI_KBAK: [[u8; 8]; 2] = [
+    [0x01, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x80],
+    [0x02, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x80],
+];
+
+// TDEA 3-key (triple length)
+const TDES_3KEY_KDI_KBEK: [[u8; 8]; 3] = [
+    [0x01, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0xC0],
+    [0x02, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0xC0],
+    [0x03, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0xC0],
+];
+const TDES_3KEY_KDI_KBAK: [[u8; 8]; 3] = [
+    [0x01, 0x00, 0x01, 0x00, 0x00, 0x01, 0x00, 0xC0],
+    [0x02, 0x00, 0x01, 0x00, 0x00, 0x01, 0x00, 0xC0],
+    [0x03, 0x00, 0x01, 0x00, 0x00, 0x01, 0x00, 0xC0],
+];
 
 // Input Data for Key Derivation Binding Method - AES
 
@@ -36,40 +68,186 @@ const AES_256_KDI_KBAK_2: [u8; 8] = [0x02, 0x00, 0x01, 0x00, 0x00, 0x04, 0x01, 0
 /// - The first element is the derived Key Block Encryption Key (KBEK).
 /// - The second element is the derived Key Block Authentication Key (KBAK).
 /// If an error occurs, such as an invalid KBPK length or an issue during the AES-CMAC
-/// calculation, the function returns a `Box<dyn Error>`.
+/// calculation, the function returns a `PaysecError`.
 ///
 /// # Errors
 ///
 /// This function returns an error if the KBPK length is not one of the expected sizes
 /// (16, 24, or 32 bytes) or if there is an issue during the AES-CMAC calculation.
-pub fn derive_keys_version_d(kbpk: &[u8]) -> Result<(Vec<u8>, Vec<u8>), Box<dyn Error>> {
+pub fn derive_keys_version_d(kbpk: &[u8]) -> Result<(Vec<u8>, Vec<u8>), PaysecError> {
     match kbpk.len() {
-        16 => {
-            // Derive AES-128 Encryption and Authentication Key
-            let kbek = aes_cmac(&AES_128_KDI_KBEK, kbpk)?.to_vec();
-            let kbak = aes_cmac(&AES_128_KDI_KBAK, kbpk)?.to_vec();
-            Ok((kbek, kbak))
+        16 | 24 | 32 => derive_keys_version_d_with_params(kbpk, kbpk.len()),
+        _ => Err("ERROR TR-31: Invalid KBPK length".into()),
+    }
+}
+
+/// Derive the KBEK and KBAK for TR-31 Key Block Version ID 'D' with a caller-specified length.
+///
+/// **For testing only.** This function bypasses the dispatch on the KBPK length of
+/// `derive_keys_version_d` and derives keys of `derived_len` bytes, with the derivation input
+/// data of that length, from a KBPK of any valid AES key length. A mismatch of both lengths, e.g.
+/// an AES-256 derivation from an AES-128 KBPK, does not occur in valid key blocks but is useful
+/// for negative tests. For matching lengths the result equals `derive_keys_version_d`.
+///
+/// The function is only exported with the `testing` feature.
+///
+/// # Arguments
+///
+/// * `kbpk` - The Key Block Protection Key (KBPK) as a byte slice.
+/// * `derived_len` - The length of the derived keys in bytes: 16, 24 or 32.
+///
+/// # Errors
+///
+/// This function returns an error if `derived_len` is not 16, 24 or 32 or if the KBPK is not a
+/// valid AES key.
+pub fn derive_keys_version_d_with_params(
+    kbpk: &[u8],
+    derived_len: usize,
+) -> Result<(Vec<u8>, Vec<u8>), PaysecError> {
+    let (kbek_kdi, kbak_kdi): (&[[u8; 8]], &[[u8; 8]]) = match derived_len {
+        // AES-128 Encryption and Authentication Key
+        16 => (&[AES_128_KDI_KBEK], &[AES_128_KDI_KBAK]),
+        // AES-192 Encryption and Authentication Key, truncated to 24 bytes
+        24 => (
+            &[AES_192_KDI_KBEK_1, AES_192_KDI_KBEK_2],
+            &[AES_192_KDI_KBAK_1, AES_192_KDI_KBAK_2],
+        ),
+        // AES-256 Encryption and Authentication Key
+        32 => (
+            &[AES_256_KDI_KBEK_1, AES_256_KDI_KBEK_2],
+            &[AES_256_KDI_KBAK_1, AES_256_KDI_KBAK_2],
+        ),
+        _ => {
+            return Err(format!(
+                "ERROR TR-31: Invalid derived key length: {} (expected 16, 24 or 32 bytes)",
+                derived_len
+            )
+            .into())
+        }
+    };
+
+    let derive = |kdis: &[[u8; 8]]| -> Result<Vec<u8>, PaysecError> {
+        let mut key = Vec::with_capacity(kdis.len() * 16);
+        for kdi in kdis {
+            key.extend_from_slice(&aes_cmac(kdi, kbpk)?);
         }
-        24 => {
-            // Derive AES-192 Encryption and Authentication Key
-            let mut kbek = aes_cmac(&AES_192_KDI_KBEK_1, kbpk)?.to_vec();
-            kbek.extend_from_slice(&aes_cmac(&AES_192_KDI_KBEK_2, kbpk)?.to_vec());
-            kbek.truncate(24); // Truncate to 24 bytes for AES-192
+        key.truncate(derived_len);
+        Ok(key)
+    };
 
-            let mut kbak = aes_cmac(&AES_192_KDI_KBAK_1, kbpk)?.to_vec();
-            kbak.extend_from_slice(&aes_cmac(&AES_192_KDI_KBAK_2, kbpk)?.to_vec());
-            kbak.truncate(24); // Truncate to 24 bytes for AES-192
+    Ok((derive(kbek_kdi)?, derive(kbak_kdi)?))
+}
+
+/// The TDEA keying option of a Key Block Protection Key.
+///
+/// The TDEA Key Derivation Binding Method (version 'B') uses different derivation input data for
+/// double-length and triple-length keys, so the variant is determined once from the KBPK length
+/// and reported back to the caller of `derive_keys_version_b`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TdesKeyVariant {
+    /// Double-length (2-key) TDEA key of 16 bytes.
+    TwoKey,
+    /// Triple-length (3-key) TDEA key of 24 bytes.
+    ThreeKey,
+}
 
-            Ok((kbek, kbak))
+impl TdesKeyVariant {
+    /// Determine the TDEA keying option from a key length in bytes.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the length is neither 16 (2-key) nor 24 (3-key) bytes.
+    pub fn from_key_len(key_len: usize) -> Result<Self, PaysecError> {
+        match key_len {
+            16 => Ok(TdesKeyVariant::TwoKey),
+            24 => Ok(TdesKeyVariant::ThreeKey),
+            _ => Err(format!(
+                "ERROR TR-31: Invalid TDES KBPK length: {} (expected 16 or 24 bytes)",
+                key_len
+            )
+            .into()),
         }
-        32 => {
-            // Derive AES-256 Encryption and Authentication Key
-            let mut kbek = aes_cmac(&AES_256_KDI_KBEK_1, kbpk)?.to_vec();
-            kbek.extend_from_slice(&aes_cmac(&AES_256_KDI_KBEK_2, kbpk)?.to_vec());
-            let mut kbak = aes_cmac(&AES_256_KDI_KBAK_1, kbpk)?.to_vec();
-            kbak.extend_from_slice(&aes_cmac(&AES_256_KDI_KBAK_2, kbpk)?.to_vec());
-            Ok((kbek, kbak))
+    }
+
+    /// Return the key length in bytes of the keying option.
+    pub fn key_len(&self) -> usize {
+        match self {
+            TdesKeyVariant::TwoKey => 16,
+            TdesKeyVariant::ThreeKey => 24,
         }
-        _ => Err("ERROR TR-31: Invalid KBPK length".into()),
     }
 }
+
+/// Derive the Key Block Encryption Key (KBEK) and the Key Block Authentication Key (KBAK)
+/// for TR-31 Key Block Version ID 'B' using TDEA-CMAC.
+///
+/// This function uses the TDEA Key Derivation Binding Method to derive KBEK and KBAK from
+/// the Key Block Protection Key (KBPK). Each 8-byte part of the derived keys is the CMAC of
+/// the derivation input data, whose algorithm indicator and length field differ between
+/// double-length (2-key) and triple-length (3-key) KBPKs. The length of the derived keys is
+/// equal to the length of the KBPK.
+///
+/// # Arguments
+///
+/// * `kbpk` - The Key Block Protection Key (KBPK) as a byte slice.
+///
+/// # Returns
+///
+/// This function returns a `Result` containing a tuple of:
+/// - The derived Key Block Encryption Key (KBEK).
+/// - The derived Key Block Authentication Key (KBAK).
+/// - The `TdesKeyVariant` that was used for the derivation.
+///
+/// # Errors
+///
+/// This function returns an error if the KBPK length is neither 16 nor 24 bytes or if there
+/// is an issue during the TDEA-CMAC calculation.
+pub fn derive_keys_version_b(kbpk: &[u8]) -> Result<TdesDerivedKeys, PaysecError> {
+    let variant = TdesKeyVariant::from_key_len(kbpk.len())?;
+
+    let (kdi_kbek, kdi_kbak): (&[[u8; 8]], &[[u8; 8]]) = match variant {
+        TdesKeyVariant::TwoKey => (&TDES_2KEY_KDI_KBEK, &TDES_2KEY_KDI_KBAK),
+        TdesKeyVariant::ThreeKey => (&TDES_3KEY_KDI_KBEK, &TDES_3KEY_KDI_KBAK),
+    };
+
+    let mut kbek = Vec::with_capacity(kbpk.len());
+    for kdi in kdi_kbek {
+        kbek.extend_from_slice(&tdes_cmac(kdi, kbpk)?);
+    }
+
+    let mut kbak = Vec::with_capacity(kbpk.len());
+    for kdi in kdi_kbak {
+        kbak.extend_from_slice(&tdes_cmac(kdi, kbpk)?);
+    }
+
+    Ok((kbek, kbak, variant))
+}
+
+/// Derive the Key Block Encryption Key (KBEK) and the Key Block Authentication Key (KBAK)
+/// for TR-31 Key Block Version ID 'C' using the TDEA Key Variant Binding Method.
+///
+/// The KBEK is the KBPK with every byte XOR-ed with `0x45` ('E'), the KBAK is the KBPK with
+/// every byte XOR-ed with `0x4D` ('M'). The same derivation is used by the deprecated version
+/// 'A'. The length of the derived keys is equal to the length of the KBPK.
+///
+/// # Arguments
+///
+/// * `kbpk` - The Key Block Protection Key (KBPK) as a byte slice.
+///
+/// # Returns
+///
+/// This function returns a `Result` containing a tuple of:
+/// - The derived Key Block Encryption Key (KBEK).
+/// - The derived Key Block Authentication Key (KBAK).
+///
+/// # Errors
+///
+/// This function returns an error if the KBPK length is neither 16 nor 24 bytes.
+pub fn derive_keys_version_c(kbpk: &[u8]) -> Result<(Vec<u8>, Vec<u8>), PaysecError> {
+    TdesKeyVariant::from_key_len(kbpk.len())?;
+
+    let kbek = kbpk.iter().map(|b| b ^ KBEK_VARIANT).collect();
+    let kbak = kbpk.iter().map(|b| b ^ KBAK_VARIANT).collect();
+
+    Ok((kbek, kbak))
+}
@@ -1,15 +1,42 @@
 pub mod header_constants;
+mod header_view;
+mod kcv;
+mod key_block;
 mod key_block_header;
 mod key_derivations;
 mod opt_block;
+mod parse_error;
 mod payload;
+mod provision;
+mod scheme;
+mod secret_key;
 mod tr31;
+mod usage_bound_key;
 
 pub use header_constants as tr31_header_constants;
+pub use header_view::{HeaderView, OptBlockView, OptBlockViews};
+pub use kcv::{
+    compute_kcv_aes, compute_kcv_aes_legacy, compute_kcv_tdes, KcvKind, KCV_AES_LEGACY_LEN,
+    KCV_AES_LEN, KCV_TDES_LEN,
+};
+pub use key_block::KeyBlock;
 pub use key_block_header::*;
+#[cfg(all(feature = "testing", not(feature = "no-clear-secrets")))]
+pub use key_derivations::derive_keys_version_d_with_params;
+pub use key_derivations::TdesKeyVariant;
+#[cfg(not(feature = "no-clear-secrets"))]
+pub use key_derivations::{derive_keys_version_b, derive_keys_version_c};
 pub use opt_block::*;
-pub use payload::calculate_padding_length;
+pub use parse_error::ParseError;
+pub use payload::{calculate_padding_length, calculate_padding_length_with_min, extract_padding};
+pub use provision::{tr31_provision, tr31_regions, KeyBlockRegion, ProvisionReport};
+#[cfg(not(feature = "no-clear-secrets"))]
+pub use scheme::{KeyBlockScheme, VersionBScheme, VersionCScheme, VersionDScheme};
+pub use secret_key::SecretKey;
 pub use tr31::*;
+#[cfg(not(feature = "no-clear-secrets"))]
+pub use usage_bound_key::tr31_unwrap_bound;
+pub use usage_bound_key::{KeyOperation, ModeOfUseViolation, UsageBoundKey};
 
 #[cfg(test)]
 mod tests;
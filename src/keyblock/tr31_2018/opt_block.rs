@@ -52,10 +52,12 @@
 //!
 //! TR-31: 2018, p. 17-18, 27-33.
 
-use std::error::Error;
 use std::fmt::Write;
 
 use super::header_constants::ALLOWED_OPT_BLOCK_IDS;
+use super::parse_error::ParseError;
+use crate::error::PaysecError;
+use crate::validation::ValidationConfig;
 
 /// Represent an optional block as defined in the TR-31 specification.
 ///
@@ -89,7 +91,7 @@ impl OptBlock {
     ///
     /// # Returns
     ///
-    /// A `Result` containing either an `OptBlock` instance or a boxed error.
+    /// A `Result` containing either an `OptBlock` instance or a `PaysecError`.
     ///
     /// # Errors
     ///
@@ -97,7 +99,7 @@ impl OptBlock {
     /// - If the specified `id` is not one of the valid values defined in `ALLOWED_IDS`.
     /// - If the specified `data` contains non-ASCII characters.
     /// - If the total length of the `OptBlock` instance exceeds 65535 characters.
-    pub fn new(id: &str, data: &str, next: Option<OptBlock>) -> Result<Self, Box<dyn Error>> {
+    pub fn new(id: &str, data: &str, next: Option<OptBlock>) -> Result<Self, PaysecError> {
         let mut opt_block = Self::new_empty();
         opt_block.set_id(id)?;
         opt_block.set_data(data)?;
@@ -105,6 +107,37 @@ impl OptBlock {
         Ok(opt_block)
     }
 
+    /// Create a new `OptBlock` which requires the extended length field.
+    ///
+    /// Same as `new` without a next block, but the data must be long enough for the block to
+    /// exceed 255 characters with a regular length field, i.e. at least 252 characters. Tests of
+    /// the extended length path can use this to make sure they do not create a regular block by
+    /// accident.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the data is too short for an extended length field or if `new` fails.
+    ///
+    /// # Example
+    /// ```
+    /// use paysec::keyblock::OptBlock;
+    ///
+    /// let opt_block = OptBlock::new_extended("CT", &"F".repeat(300)).unwrap();
+    /// assert!(opt_block.export_str().unwrap().starts_with("CT00020136"));
+    /// assert!(OptBlock::new_extended("CT", &"F".repeat(251)).is_err());
+    /// ```
+    pub fn new_extended(id: &str, data: &str) -> Result<Self, PaysecError> {
+        let opt_block = Self::new(id, data, None)?;
+        if opt_block.length < 256 {
+            return Err(format!(
+                "ERROR TR-31 OPT BLOCK: Data length '{}' does not require an extended length field (must be min. 252)",
+                data.len()
+            )
+            .into());
+        }
+        Ok(opt_block)
+    }
+
     /// Create a new empty `OptBlock`.
     ///
     /// This function creates a new `OptBlock` instance with empty `id`, `data`, and `next`
@@ -127,7 +160,7 @@ impl OptBlock {
     ///
     /// # Returns
     ///
-    /// A `Result` containing either the parsed `OptBlock` instance or a boxed error.
+    /// A `Result` containing either the parsed `OptBlock` instance or a `PaysecError`.
     ///
     /// # Errors
     ///
@@ -136,63 +169,219 @@ impl OptBlock {
     /// - If the length field is invalid or the string is too short for the given length.
     /// - If `set_id` or `set_data` fails.
     /// - If there are any errors while constructing the linked list of `OptBlock` instances.
-    pub fn new_from_str(s: &str, num_opt_blocks: usize) -> Result<Self, Box<dyn Error>> {
-        if s.len() < 4 {
-            return Err(
-                "ERROR TR-31 OPT BLOCK: String too short. Expected at least 4 characters".into(),
-            );
+    ///
+    /// All errors are `PaysecError::Parse` errors locating the offending region in `s`.
+    pub fn new_from_str(s: &str, num_opt_blocks: usize) -> Result<Self, PaysecError> {
+        Self::new_from_str_with_options(s, num_opt_blocks, &ValidationConfig::default())
+    }
+
+    /// Construct a new `OptBlock` instance by parsing an input string with a validation policy.
+    ///
+    /// Same as `new_from_str`, but if `config.known_opt_block_ids` is disabled, proprietary IDs
+    /// (see `is_proprietary_id`) are accepted in addition to the IDs defined by TR-31.
+    ///
+    /// # Errors
+    ///
+    /// Returns the same errors as `new_from_str`.
+    pub fn new_from_str_with_options(
+        s: &str,
+        num_opt_blocks: usize,
+        config: &ValidationConfig,
+    ) -> Result<Self, PaysecError> {
+        let (id, data, parsed_length) = Self::scan_from_str_with_options(s, config)?;
+        let mut opt_block = Self::new_scanned(id, data)?;
+
+        // A length field of "04" is a block without data, e.g. "KS04". For a length field which is
+        // not extended, the length recomputed from the data matches the parsed length field.
+        debug_assert!(parsed_length >= 256 || opt_block.length == parsed_length);
+
+        // Parsing the next block if more than one block is expected
+        if num_opt_blocks > 1 {
+            // Recursively parse the next block
+            let next_block_str = &s[opt_block.length..];
+            let next_block =
+                OptBlock::new_from_str_with_options(next_block_str, num_opt_blocks - 1, config)
+                    .map_err(|e| ParseError::shift(e, opt_block.length, ""))?;
+
+            // Set the next block
+            opt_block.set_next(Some(next_block));
         }
 
+        Ok(opt_block)
+    }
+
+    /// Create an `OptBlock` from an ID and data returned by `scan_from_str_with_options`, which
+    /// may be a proprietary ID.
+    pub(super) fn new_scanned(id: &str, data: &str) -> Result<Self, PaysecError> {
         let mut opt_block = Self::new_empty();
-        opt_block.set_id(&s[..2])?;
+        opt_block.id = id.to_string();
+        opt_block.set_data(data)?;
+        Ok(opt_block)
+    }
+
+    /// Scan a single optional block at the start of `s` without allocating.
+    ///
+    /// Returns the ID, the data and the length of the block as given by its length field. The
+    /// checks and errors are the same as for a single block of `new_from_str_with_options`.
+    pub(super) fn scan_from_str_with_options<'a>(
+        s: &'a str,
+        config: &ValidationConfig,
+    ) -> Result<(&'a str, &'a str, usize), PaysecError> {
+        if s.len() < 4 {
+            return Err(ParseError::located(
+                "optional block",
+                0,
+                s.len(),
+                "ERROR TR-31 OPT BLOCK: String too short. Expected at least 4 characters",
+            ));
+        }
+
+        let field = |start: usize, end: usize, name: &'static str| {
+            s.get(start..end).ok_or_else(|| {
+                ParseError::located(
+                    name,
+                    start,
+                    end - start,
+                    "ERROR TR-31 OPT BLOCK: Invalid non-ASCII character",
+                )
+            })
+        };
+
+        let id = field(0, 2, "optional block ID")?;
+        if !(Self::is_allowed_id(id)
+            || (!config.known_opt_block_ids && Self::is_proprietary_id(id)))
+        {
+            return Err(ParseError::located_kind(
+                "optional block ID",
+                0,
+                2,
+                PaysecError::InvalidOptBlockId(id.to_string()),
+            ));
+        }
 
+        let len_field = field(2, 4, "optional block length")?;
         let data_start_offset: usize;
-        if &s[2..4] == "00" {
+        let length: usize;
+        if len_field == "00" {
             if s.len() < 256 {
-                return Err("ERROR TR-31 OPT BLOCK: String containing extended length too short. Expected at least 256 characters".into());
+                return Err(ParseError::located(
+                    "optional block length",
+                    2,
+                    s.len() - 2,
+                    "ERROR TR-31 OPT BLOCK: String containing extended length too short. Expected at least 256 characters",
+                ));
             }
-            let ext_block_len = &s[4..10];
-            opt_block.length = Self::ext_len_from_str(ext_block_len)?;
+            let ext_block_len = field(4, 10, "optional block extended length")?;
+            length = Self::ext_len_from_str(ext_block_len)
+                .map_err(|e| ParseError::located("optional block extended length", 4, 6, e))?;
             data_start_offset = 10;
         } else {
-            opt_block.length = Self::len_from_str(&s[2..4])?;
+            length = Self::len_from_str(len_field)
+                .map_err(|e| ParseError::located("optional block length", 2, 2, e))?;
             data_start_offset = 4;
         }
 
-        if s.len() < opt_block.length {
-            return Err(format!(
-                "ERROR TR-31 OPT BLOCK: String too short for given length. Expected at least {} characters.",
-                opt_block.length
-            ).into());
+        if s.len() < length {
+            return Err(ParseError::located(
+                "optional block data",
+                data_start_offset,
+                s.len() - data_start_offset,
+                format!(
+                    "ERROR TR-31 OPT BLOCK: String too short for given length. Expected at least {} characters.",
+                    length
+                ),
+            ));
         }
 
-        opt_block.set_data(&s[data_start_offset..opt_block.length])?;
+        let data = field(data_start_offset, length, "optional block data")?;
+        if !data.is_ascii() {
+            return Err(ParseError::located(
+                "optional block data",
+                data_start_offset,
+                length - data_start_offset,
+                format!(
+                    "ERROR TR-31 OPT BLOCK: Data has non ASCII characters: {}",
+                    data
+                ),
+            ));
+        }
 
-        // Parsing the next block if more than one block is expected
-        if num_opt_blocks > 1 {
-            // Recursively parse the next block
-            let next_block_str = &s[opt_block.length..];
-            let next_block = OptBlock::new_from_str(next_block_str, num_opt_blocks - 1)?;
+        Ok((id, data, length))
+    }
 
-            // Set the next block
-            opt_block.set_next(Some(next_block));
+    /// Create an `IK` optional block from a BDK ID and the Key Serial Number (KSN) of an AES DUKPT
+    /// device.
+    ///
+    /// The 12-byte KSN of AES DUKPT is the concatenation of the 4-byte BDK ID, the 4-byte
+    /// Derivation ID and the 4-byte transaction counter (X9.24-3: 2017). The `IK` block carries
+    /// the Initial Key ID, i.e. the BDK ID followed by the Derivation ID, as 16 hex-ASCII
+    /// characters, so the key block references the Initial DUKPT Key of the KSN.
+    ///
+    /// # Arguments
+    ///
+    /// * `bdk_id` - The 4-byte ID of the Base Derivation Key.
+    /// * `ksn` - The 12-byte Key Serial Number of the device.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if:
+    /// - The BDK ID is not 4 bytes or the KSN is not 12 bytes long.
+    /// - The KSN does not start with the BDK ID.
+    ///
+    /// # Example
+    /// ```
+    /// use paysec::keyblock::OptBlock;
+    ///
+    /// let ksn = hex::decode("123456789012345600000001").unwrap();
+    /// let ik_block = OptBlock::new_ik_from_ksn(&ksn[..4], &ksn).unwrap();
+    /// assert_eq!(ik_block.export_str().unwrap(), "IK141234567890123456");
+    /// ```
+    pub fn new_ik_from_ksn(bdk_id: &[u8], ksn: &[u8]) -> Result<Self, PaysecError> {
+        const BDK_ID_LEN: usize = 4;
+        const DERIVATION_ID_LEN: usize = 4;
+        const KSN_LEN: usize = 12;
+
+        if bdk_id.len() != BDK_ID_LEN {
+            return Err(format!(
+                "ERROR TR-31 OPT BLOCK: Invalid BDK ID length: {} (expected {} bytes)",
+                bdk_id.len(),
+                BDK_ID_LEN
+            )
+            .into());
+        }
+        if ksn.len() != KSN_LEN {
+            return Err(format!(
+                "ERROR TR-31 OPT BLOCK: Invalid KSN length: {} (expected {} bytes)",
+                ksn.len(),
+                KSN_LEN
+            )
+            .into());
+        }
+        if ksn[..BDK_ID_LEN] != *bdk_id {
+            return Err("ERROR TR-31 OPT BLOCK: KSN does not belong to the BDK ID".into());
         }
 
-        Ok(opt_block)
+        let derivation_id = &ksn[BDK_ID_LEN..BDK_ID_LEN + DERIVATION_ID_LEN];
+        let initial_key_id = format!(
+            "{}{}",
+            hex::encode_upper(bdk_id),
+            hex::encode_upper(derivation_id)
+        );
+        Self::new("IK", &initial_key_id, None)
     }
 
     /// Return a string representation of the `OptBlock` and its contents.
     ///
     /// # Returns
     ///
-    /// A `Result` containing either the string representation of the `OptBlock` or a boxed error.
+    /// A `Result` containing either the string representation of the `OptBlock` or a `PaysecError`.
     ///
     /// # Errors
     ///
     /// Returns an error in the following cases:
     /// - If the length of the `OptBlock` is less than 4, indicating an uninitialized `OptBlock`.
     /// - If there are any errors while formatting the length field.
-    pub fn export_str(&self) -> Result<String, Box<dyn Error>> {
+    pub fn export_str(&self) -> Result<String, PaysecError> {
         if self.length < 4 {
             return Err("ERROR TR-31 OPT BLOCK: Length must be greater than 4, indicating uninitialized OptBlock".into());
         }
@@ -228,18 +417,18 @@ impl OptBlock {
     ///
     /// # Returns
     ///
-    /// A `Result` indicating success (`Ok`) or containing a boxed error (`Err`) if an error occurs.
+    /// A `Result` indicating success (`Ok`) or containing a `PaysecError` (`Err`) if an error occurs.
     ///
     /// # Errors
     ///
     /// This function returns an error if the input identifier is not valid. The identifier must be
     /// included in the list of allowed identifiers.
-    pub fn set_id(&mut self, id: &str) -> Result<(), Box<dyn Error>> {
+    pub fn set_id(&mut self, id: &str) -> Result<(), PaysecError> {
         if Self::is_allowed_id(id) {
             self.id = id.to_string();
             Ok(())
         } else {
-            Err(format!("ERROR TR-31 OPT BLOCK: Invalid ID: {}", id).into())
+            Err(PaysecError::InvalidOptBlockId(id.to_string()))
         }
     }
 
@@ -257,7 +446,7 @@ impl OptBlock {
     ///
     /// # Returns
     ///
-    /// A `Result` indicating success (`Ok`) or containing a boxed error (`Err`) if an error occurs.
+    /// A `Result` indicating success (`Ok`) or containing a `PaysecError` (`Err`) if an error occurs.
     ///
     /// # Errors
     ///
@@ -266,7 +455,7 @@ impl OptBlock {
     ///   ASCII string and must be set prior to setting the data.
     /// - If the input `data` string contains non-ASCII characters. The data field must consist only
     ///   of ASCII printable characters.
-    pub fn set_data(&mut self, data: &str) -> Result<(), Box<dyn Error>> {
+    pub fn set_data(&mut self, data: &str) -> Result<(), PaysecError> {
         if self.id.len() != 2 {
             return Err("ERROR TR-31 OPT BLOCK: ID not set (has to be set before data)".into());
         }
@@ -294,13 +483,13 @@ impl OptBlock {
     ///
     /// # Returns
     ///
-    /// A `Result` containing either `Ok(())` if the length is successfully set, or a boxed error.
+    /// A `Result` containing either `Ok(())` if the length is successfully set, or a `PaysecError`.
     ///
     /// # Errors
     ///
-    /// This function returns an error in the form of a `Box<dyn Error>` if the total length of the
+    /// This function returns an error in the form of a `PaysecError` if the total length of the
     /// `OptBlock` instance exceeds 65535 characters.
-    fn set_length(&mut self) -> Result<(), Box<dyn Error>> {
+    fn set_length(&mut self) -> Result<(), PaysecError> {
         // Minimum length containing ID length, length field length and data length
         let min_len: usize = self.id.len() + 2 + self.data.len();
         if min_len < 256 {
@@ -372,6 +561,17 @@ impl OptBlock {
         ALLOWED_OPT_BLOCK_IDS.contains(&id)
     }
 
+    /// Determines whether the given `id` string is a proprietary ID.
+    ///
+    /// TR-31 reserves the IDs starting with a numeric character for proprietary use, so a
+    /// proprietary ID consists of a digit followed by a digit or an upper case letter.
+    pub fn is_proprietary_id(id: &str) -> bool {
+        let bytes = id.as_bytes();
+        bytes.len() == 2
+            && bytes[0].is_ascii_digit()
+            && (bytes[1].is_ascii_digit() || bytes[1].is_ascii_uppercase())
+    }
+
     /// Returns the total length of the `OptBlock`, including its own length and the lengths of all
     /// subsequent `OptBlock`s in the linked list.
     ///
@@ -395,7 +595,7 @@ impl OptBlock {
     ///
     /// # Returns
     ///
-    /// A `Result` containing either the length of the `OptBlock` as a `usize` value or a boxed error.
+    /// A `Result` containing either the length of the `OptBlock` as a `usize` value or a `PaysecError`.
     ///
     /// # Errors
     ///
@@ -403,21 +603,21 @@ impl OptBlock {
     /// - If the length string is not exactly 2 characters long.
     /// - If the string cannot be parsed as a hexadecimal number.
     /// - If the resulting length is less than 4.
-    /// Errors are returned as a `Box<dyn Error>`, which can encompass various error types.
-    fn len_from_str(s: &str) -> Result<usize, Box<dyn Error>> {
+    /// Errors are returned as a `PaysecError`, which can encompass various error types.
+    fn len_from_str(s: &str) -> Result<usize, PaysecError> {
         if s.len() != 2 {
-            return Err(Box::<dyn Error>::from(format!(
+            return Err(PaysecError::from(format!(
             "ERROR TR-31 OPT BLOCK: Invalid length field: Expected a string with 2 characters, found '{}'",
             s
         )));
         }
 
         let len = usize::from_str_radix(s, 16).map_err(|_| { 
-            Box::<dyn Error>::from(format!("ERROR TR-31 OPT BLOCK: Invalid length field: '{}' is not a valid hexadecimal number", s)) 
+            PaysecError::from(format!("ERROR TR-31 OPT BLOCK: Invalid length field: '{}' is not a valid hexadecimal number", s)) 
         })?;
 
         if len < 4 {
-            return Err(Box::<dyn Error>::from(format!(
+            return Err(PaysecError::from(format!(
             "ERROR TR-31 OPT BLOCK: Invalid length field: value {} is too small (must be at least 4)",
             len
         )));
@@ -434,7 +634,7 @@ impl OptBlock {
     ///
     /// # Returns
     ///
-    /// A `Result` containing either the parsed extended length as a `usize` or a boxed error.
+    /// A `Result` containing either the parsed extended length as a `usize` or a `PaysecError`.
     ///
     /// # Errors
     ///
@@ -465,3 +665,31 @@ impl OptBlock {
         Ok(res)
     }
 }
+
+/// Collect optional blocks into a chain in iteration order, as used by
+/// `KeyBlockHeader::set_opt_blocks`.
+///
+/// Each block is appended to the end of the chain, so blocks which already have next blocks are
+/// kept together with them. An empty iterator yields `None`.
+///
+/// # Example
+/// ```
+/// use paysec::keyblock::OptBlock;
+///
+/// let blocks = vec![
+///     OptBlock::new("KS", "00604B", None).unwrap(),
+///     OptBlock::new("PB", "0000", None).unwrap(),
+/// ];
+/// let chain: Option<Box<OptBlock>> = blocks.into_iter().collect();
+/// assert_eq!(chain.unwrap().export_str().unwrap(), "KS0A00604BPB080000");
+/// ```
+impl FromIterator<OptBlock> for Option<Box<OptBlock>> {
+    fn from_iter<I: IntoIterator<Item = OptBlock>>(iter: I) -> Self {
+        let mut iter = iter.into_iter();
+        let mut head = iter.next()?;
+        for opt_block in iter {
+            head.append(opt_block);
+        }
+        Some(Box::new(head))
+    }
+}
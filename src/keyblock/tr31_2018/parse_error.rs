@@ -0,0 +1,129 @@
+//! Structured parse errors for TR-31 key blocks.
+//!
+//! Errors caused by malformed input of `KeyBlockHeader::new_from_str`, `OptBlock::new_from_str`
+//! and `tr31_unwrap` are `PaysecError::Parse` errors with a `ParseError`, which carries the byte
+//! offset and length of the offending region of the parsed string and the name of the field being
+//! parsed:
+//!
+//! ```
+//! use paysec::keyblock::KeyBlockHeader;
+//!
+//! let err = KeyBlockHeader::new_from_str("D01X2P0AE00E0000").unwrap_err();
+//! let parse_error = err.parse_error().unwrap();
+//!
+//! assert_eq!(parse_error.field(), "key block length");
+//! assert_eq!(parse_error.offset(), 1);
+//! assert_eq!(parse_error.length(), 4);
+//! ```
+//!
+//! The `Display` output is the plain error message, so existing error strings are unchanged.
+//! Errors which are also reported as a `PaysecError`, e.g. an invalid optional block ID, carry it
+//! as their `source`, see `PaysecError::find`.
+
+use crate::error::PaysecError;
+use std::error::Error;
+use std::fmt;
+use std::ops::Range;
+
+/// An error caused by malformed key block input, located by byte offset and length.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    field: &'static str,
+    offset: usize,
+    length: usize,
+    message: String,
+    kind: Option<PaysecError>,
+}
+
+impl ParseError {
+    /// Create a new parse error for a region of the parsed string.
+    pub(crate) fn new(field: &'static str, offset: usize, length: usize, message: &str) -> Self {
+        ParseError {
+            field,
+            offset,
+            length,
+            message: message.to_string(),
+            kind: None,
+        }
+    }
+
+    /// Create a `PaysecError::Parse` error for a region of the parsed string, e.g. to locate the
+    /// error of a field setter.
+    pub(crate) fn located(
+        field: &'static str,
+        offset: usize,
+        length: usize,
+        message: impl fmt::Display,
+    ) -> PaysecError {
+        Self::new(field, offset, length, &format!("{:#}", message)).into()
+    }
+
+    /// Create a `PaysecError::Parse` error for a region of the parsed string with a
+    /// `PaysecError` as message and source. An `Other` error is only kept as message.
+    pub(crate) fn located_kind(
+        field: &'static str,
+        offset: usize,
+        length: usize,
+        kind: PaysecError,
+    ) -> PaysecError {
+        let mut parse_error = Self::new(field, offset, length, &format!("{:#}", kind));
+        if !matches!(kind, PaysecError::Other(_)) {
+            parse_error.kind = Some(kind);
+        }
+        parse_error.into()
+    }
+
+    /// Return an error with the region of a parse error shifted by `delta` bytes and the message
+    /// prefixed, e.g. when a nested string was parsed. Other errors are only prefixed.
+    pub(crate) fn shift(err: PaysecError, delta: usize, prefix: &str) -> PaysecError {
+        let message = format!("{}{:#}", prefix, err);
+        match err.parse_error() {
+            Some(parse_error) => ParseError {
+                offset: parse_error.offset + delta,
+                message,
+                ..parse_error.clone()
+            }
+            .into(),
+            None => message.into(),
+        }
+    }
+
+    /// Return the name of the field being parsed.
+    pub fn field(&self) -> &str {
+        self.field
+    }
+
+    /// Return the byte offset of the offending region.
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// Return the length in bytes of the offending region.
+    pub fn length(&self) -> usize {
+        self.length
+    }
+
+    /// Return the byte range of the offending region.
+    pub fn range(&self) -> Range<usize> {
+        self.offset..self.offset + self.length
+    }
+
+    /// Return the error message.
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl Error for ParseError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.kind
+            .as_ref()
+            .map(|kind| kind as &(dyn Error + 'static))
+    }
+}
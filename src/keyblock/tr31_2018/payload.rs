@@ -1,4 +1,5 @@
-use std::error::Error;
+use super::secret_key::SecretKey;
+use crate::error::PaysecError;
 
 /// Constructs the payload for a TR-31 key block.
 ///
@@ -21,34 +22,71 @@ use std::error::Error;
 /// # Errors
 ///
 /// This function returns an error if the key length exceeds the TR-31 maximum length or if the
-/// provided random seed is too short for the required padding.
+/// provided random seed is too short for the required padding. With the `strict-seed` feature
+/// it also returns an error if the padding fails `check_seed_entropy`.
+// The wrapping functions use `construct_payload_with_min`, this default remains for the tests.
+#[allow(dead_code)]
 pub fn construct_payload(
     key: &[u8],
     masked_key_length: usize,
     cipher_block_length: usize,
     random_seed: &[u8],
-) -> Result<Vec<u8>, Box<dyn Error>> {
+) -> Result<Vec<u8>, PaysecError> {
+    construct_payload_with_min(key, masked_key_length, cipher_block_length, 1, random_seed)
+}
+
+/// Constructs the payload for a TR-31 key block with a minimum number of cipher blocks.
+///
+/// Same as `construct_payload`, but the payload is padded to at least `min_payload_blocks`
+/// cipher blocks, see `calculate_padding_length_with_min`.
+///
+/// # Errors
+///
+/// This function returns an error if the key length exceeds the TR-31 maximum length or if the
+/// provided random seed is too short for the required padding.
+pub fn construct_payload_with_min(
+    key: &[u8],
+    masked_key_length: usize,
+    cipher_block_length: usize,
+    min_payload_blocks: usize,
+    random_seed: &[u8],
+) -> Result<Vec<u8>, PaysecError> {
     let key_len = key.len();
 
+    // The key length in bits must fit into the 16-bit length field
+    let key_len_bits = u16::try_from(key_len)
+        .ok()
+        .and_then(|len| len.checked_mul(8))
+        .ok_or("ERROR TR-31 PAYLOAD: Key too large to encode length in bits")?;
+
     // Calculate the padding length
-    let padding_length = calculate_padding_length(key_len, masked_key_length, cipher_block_length)?;
+    let padding_length = calculate_padding_length_with_min(
+        key_len,
+        masked_key_length,
+        cipher_block_length,
+        min_payload_blocks,
+    )?;
+
+    // Check the random seed for the padding before the key is copied into the payload
+    if random_seed.len() < padding_length {
+        return Err(PaysecError::SeedTooShort {
+            context: "ERROR TR-31 PAYLOAD",
+            len: random_seed.len(),
+            required: padding_length,
+        });
+    }
+
+    #[cfg(feature = "strict-seed")]
+    crate::utils::check_seed_entropy(random_seed, padding_length)?;
 
     let mut payload = Vec::with_capacity(key_len + 2 + padding_length);
 
     // Write the key length in bits (16-bit big endian)
-    payload.extend_from_slice(&(8 * key_len as u16).to_be_bytes());
+    payload.extend_from_slice(&key_len_bits.to_be_bytes());
 
     // Append the actual key
     payload.extend_from_slice(key);
 
-    // Use the provided random seed for the padding
-    if random_seed.len() < padding_length {
-        return Err(
-            "ERROR TR-31 PAYLOAD: The provided random seed is too short for the padding requirement"
-                .into(),
-        );
-    }
-
     // Truncate random_seed to padding_length and add it as padding to payload
     payload.extend_from_slice(&random_seed[..padding_length]);
 
@@ -72,7 +110,7 @@ pub fn construct_payload(
 /// # Errors
 ///
 /// This function returns an error if the payload length is too short to contain a valid key length and key.
-pub fn extract_key_from_payload(payload: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+pub fn extract_key_from_payload(payload: &[u8]) -> Result<Vec<u8>, PaysecError> {
     if payload.len() < 2 {
         return Err("ERROR TR-31 PAYLOAD: Payload too short to contain valid key length".into());
     }
@@ -92,6 +130,25 @@ pub fn extract_key_from_payload(payload: &[u8]) -> Result<Vec<u8>, Box<dyn Error
     Ok(key)
 }
 
+/// Extract the padding from a TR-31 payload.
+///
+/// This function reads the key length (in bits) from the first 2 bytes of the payload and
+/// returns the bytes after the key up to the end of the payload, i.e. the random padding used
+/// when the payload was constructed. Together with `extract_key_from_payload` this allows to
+/// reconstruct a received payload.
+///
+/// # Arguments
+///
+/// * `payload`: The TR-31 payload containing the key length, key, and padding.
+///
+/// # Errors
+///
+/// This function returns an error if the payload length is too short to contain a valid key length and key.
+pub fn extract_padding(payload: &[u8]) -> Result<Vec<u8>, PaysecError> {
+    let key = SecretKey::new(extract_key_from_payload(payload)?);
+    Ok(payload[2 + key.len()..].to_vec())
+}
+
 /// Calculate the padding length for a TR-31 key block payload.
 ///
 /// # Arguments
@@ -108,12 +165,40 @@ pub fn calculate_padding_length(
     key_len: usize,
     masked_key_length: usize,
     cipher_block_length: usize,
-) -> Result<usize, Box<dyn Error>> {
+) -> Result<usize, PaysecError> {
+    calculate_padding_length_with_min(key_len, masked_key_length, cipher_block_length, 1)
+}
+
+/// Calculate the padding length for a TR-31 key block payload of a minimum number of blocks.
+///
+/// Same as `calculate_padding_length`, which always pads to at least one cipher block, but the
+/// payload is padded to at least `min_payload_blocks` cipher blocks, e.g. for hosts requiring
+/// two blocks for short keys.
+///
+/// # Arguments
+/// * `key_len`: The length of the key in bytes.
+/// * `masked_key_length`: The minimum length for the key data, used to mask the true length of shorter keys.
+/// * `cipher_block_length`: The block length of the encryption cipher (e.g., 16 for AES).
+/// * `min_payload_blocks`: The minimum number of cipher blocks of the payload.
+///
+/// # Returns
+/// The padding length required for the payload.
+///
+/// # Errors
+/// Returns an error if the calculated total payload length or padding length is invalid.
+pub fn calculate_padding_length_with_min(
+    key_len: usize,
+    masked_key_length: usize,
+    cipher_block_length: usize,
+    min_payload_blocks: usize,
+) -> Result<usize, PaysecError> {
     let raw_key_section_length = 2 + key_len;
     let effective_key_length = std::cmp::max(key_len, masked_key_length);
-    let total_payload_length = ((2 + effective_key_length + (cipher_block_length - 1))
-        / cipher_block_length)
-        * cipher_block_length;
+    let total_payload_length = std::cmp::max(
+        ((2 + effective_key_length + (cipher_block_length - 1)) / cipher_block_length)
+            * cipher_block_length,
+        min_payload_blocks * cipher_block_length,
+    );
 
     if total_payload_length < raw_key_section_length {
         return Err("ERROR TR-31 PAYLOAD: Invalid total payload length".into());
@@ -0,0 +1,170 @@
+//! Module for the provisioning report of a wrapped key.
+//!
+//! # Description
+//!
+//! Provisioning tools usually need more than the key block: the operator confirms the KCV of the
+//! wrapped key and of the KBPK, and a UI highlights the fields of the key block. `tr31_provision`
+//! wraps a key like `tr31_wrap` and returns all of these in a `ProvisionReport`.
+//!
+//! `tr31_regions` splits a key block into labeled regions: the header fields, one region per
+//! optional block, the encrypted payload and the MAC. The regions are contiguous and cover the
+//! whole key block.
+//!
+//! The KCVs are computed for the algorithm of the key (header algorithm `A` or `T`) and of the
+//! KBPK (AES for version 'D', TDES for versions 'B' and 'C'), see `compute_kcv_aes` and
+//! `compute_kcv_tdes`.
+//!
+//! # Example
+//! ```
+//! use paysec::keyblock::{tr31_provision, KeyBlockHeader};
+//!
+//! let header = KeyBlockHeader::new_with_values("D", "P0", "A", "E", "00", "E").unwrap();
+//! let key = hex::decode("3F419E1CB7079442AA37474C2EFBF8B8").unwrap();
+//! let random_seed = hex::decode("1C2965473CE206BB855B01533782").unwrap();
+//! let kbpk =
+//!     hex::decode("88E1AB2A2E3DD38C1FA039A536500CC8A87AB9D62DC92C01058FA79F44657DE6").unwrap();
+//!
+//! let report = tr31_provision(&kbpk, header, &key, 0, &random_seed).unwrap();
+//! assert_eq!(report.key_kcv, "08793E25AB");
+//! assert_eq!(report.kbpk_kcv, "2331550BC9");
+//!
+//! let mac = report.regions.last().unwrap();
+//! assert_eq!((mac.label.as_str(), mac.offset, mac.length), ("MAC", 80, 32));
+//! ```
+
+use crate::error::PaysecError;
+
+use super::header_view::HeaderView;
+use super::kcv::{compute_kcv_aes, compute_kcv_tdes};
+use super::key_block_header::KeyBlockHeader;
+use super::tr31::{binding_method_lengths, tr31_wrap};
+
+/// A labeled region of a key block string.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyBlockRegion {
+    /// The name of the region, e.g. `key usage` or `optional block KS`.
+    pub label: String,
+    /// The byte offset of the region in the key block.
+    pub offset: usize,
+    /// The length of the region in bytes.
+    pub length: usize,
+}
+
+/// The result of `tr31_provision`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProvisionReport {
+    /// The key block.
+    pub key_block: String,
+    /// The KCV of the wrapped key in hex.
+    pub key_kcv: String,
+    /// The KCV of the KBPK in hex.
+    pub kbpk_kcv: String,
+    /// The regions of the key block, see `tr31_regions`.
+    pub regions: Vec<KeyBlockRegion>,
+}
+
+/// Wrap a key like `tr31_wrap` and report the KCVs and the regions of the key block.
+///
+/// # Arguments
+/// * `kbpk` - Key Block Protection Key used for deriving the encryption (KBEK) and
+///   authentication (KBAK) keys.
+/// * `header` - KeyBlockHeader instance containing metadata for the key block.
+/// * `key` - The cryptographic key or sensitive data to be protected.
+/// * `masked_key_len` - Length used to mask the true length of short keys.
+/// * `random_seed` - Random seed used for generating padding in the payload.
+///
+/// # Errors
+/// Returns an error if:
+/// * The header algorithm is neither `A` nor `T`, so no KCV of the key can be computed.
+/// * `tr31_wrap` fails.
+pub fn tr31_provision(
+    kbpk: &[u8],
+    header: KeyBlockHeader,
+    key: &[u8],
+    masked_key_len: usize,
+    random_seed: &[u8],
+) -> Result<ProvisionReport, PaysecError> {
+    let key_kcv = kcv_hex(header.algorithm(), key)?;
+    let kbpk_algorithm = if header.version_id() == "D" { "A" } else { "T" };
+
+    let key_block = tr31_wrap(kbpk, header, key, masked_key_len, random_seed)?;
+    let kbpk_kcv = kcv_hex(kbpk_algorithm, kbpk)?;
+    let regions = tr31_regions(&key_block)?;
+
+    Ok(ProvisionReport {
+        key_block,
+        key_kcv,
+        kbpk_kcv,
+        regions,
+    })
+}
+
+/// Split a key block into labeled regions covering the whole key block.
+///
+/// The regions are the header fields, one region per optional block, the encrypted payload and
+/// the MAC, in the order of the key block. The key block is not decrypted and its MAC is not
+/// checked.
+///
+/// # Errors
+/// Returns an error if the header cannot be parsed, the key block version is not supported or
+/// the key block is shorter than its header and MAC.
+pub fn tr31_regions(key_block: &str) -> Result<Vec<KeyBlockRegion>, PaysecError> {
+    let view = HeaderView::parse(key_block)?;
+    let (_, mac_len) = binding_method_lengths(view.version_id())?;
+    let mac_hex_len = mac_len * 2;
+
+    if key_block.len() < view.len() + mac_hex_len {
+        return Err(format!(
+            "ERROR TR-31: Key block too short for its header and MAC: {}",
+            key_block.len()
+        )
+        .into());
+    }
+
+    let region = |label: &str, offset: usize, length: usize| KeyBlockRegion {
+        label: label.to_string(),
+        offset,
+        length,
+    };
+
+    let mut regions = vec![
+        region("version ID", 0, 1),
+        region("key block length", 1, 4),
+        region("key usage", 5, 2),
+        region("algorithm", 7, 1),
+        region("mode of use", 8, 1),
+        region("key version number", 9, 2),
+        region("exportability", 11, 1),
+        region("number of optional blocks", 12, 2),
+        region("reserved field", 14, 2),
+    ];
+    for opt_block in view.opt_blocks() {
+        regions.push(region(
+            &format!("optional block {}", opt_block.id()),
+            opt_block.offset(),
+            opt_block.length(),
+        ));
+    }
+    let mac_offset = key_block.len() - mac_hex_len;
+    regions.push(region(
+        "encrypted payload",
+        view.len(),
+        mac_offset - view.len(),
+    ));
+    regions.push(region("MAC", mac_offset, mac_hex_len));
+
+    Ok(regions)
+}
+
+// Compute the KCV of a key for a header algorithm in hex.
+fn kcv_hex(algorithm: &str, key: &[u8]) -> Result<String, PaysecError> {
+    match algorithm {
+        "A" => Ok(hex::encode_upper(compute_kcv_aes(key)?)),
+        "T" => Ok(hex::encode_upper(compute_kcv_tdes(key)?)),
+        _ => Err(format!(
+            "ERROR TR-31: KCV not supported for key algorithm {}",
+            algorithm
+        )
+        .into()),
+    }
+}
@@ -0,0 +1,330 @@
+//! Key block binding methods of the TR-31 key block versions.
+//!
+//! # Standard
+//!
+//! Accredited Standards Committee X9: "ASC X9 TR 31-2018: Interoperable Secure Key Exchange Key Block Specification"
+//! In the following referenced as "TR-31: 2018"
+//!
+//! # Description
+//!
+//! A key block binding method defines how the Key Block Encryption Key (KBEK) and the Key Block
+//! Authentication Key (KBAK) are derived from the Key Block Protection Key (KBPK), how the MAC
+//! over the header and the clear payload is computed and how the payload is encrypted.
+//!
+//! The `KeyBlockScheme` trait captures these steps, so the wrapping and unwrapping functions can
+//! be written once for all versions. `VersionBScheme` implements the TDEA Key Derivation Binding
+//! Method of version 'B' and `VersionDScheme` the AES Key Derivation Binding Method of version
+//! 'D'. In both methods the MAC is computed over the clear payload and also used as the IV of the
+//! CBC encryption of the payload (TR-31: 2018, p. 13).
+//!
+//! `VersionCScheme` implements the TDEA Key Variant Binding Method of version 'C', which encrypts
+//! the payload with the first 8 bytes of the header as IV and computes the MAC over the encrypted
+//! payload. The binding methods differ in this order, so the wrapping and unwrapping functions use
+//! `KeyBlockScheme::seal` and `KeyBlockScheme::open`.
+//!
+//! The derived KBEK and KBAK are held as `SecretKey` values, so they are wiped when the scheme is
+//! dropped.
+//!
+//! # Example
+//! ```
+//! # #[cfg(not(feature = "no-clear-secrets"))]
+//! # {
+//! use paysec::keyblock::{KeyBlockScheme, VersionDScheme};
+//!
+//! let kbpk =
+//!     hex::decode("88E1AB2A2E3DD38C1FA039A536500CC8A87AB9D62DC92C01058FA79F44657DE6").unwrap();
+//! let scheme = VersionDScheme::derive(&kbpk).unwrap();
+//!
+//! let header = "D0112P0AE00E0000";
+//! let payload = [0x5Au8; 32];
+//!
+//! let mac = scheme.mac(header, &payload).unwrap();
+//! let encrypted_payload = scheme.encrypt(&payload, &mac).unwrap();
+//! assert_eq!(scheme.decrypt(&encrypted_payload, &mac).unwrap(), payload);
+//! # }
+//! ```
+
+use super::key_derivations::{derive_keys_version_b, derive_keys_version_c, derive_keys_version_d};
+use super::secret_key::SecretKey;
+use crate::crypto::aes::{aes_cmac, aes_dec_cbc, aes_enc_cbc};
+use crate::crypto::tdes::{tdes_cmac, tdes_dec_cbc, tdes_enc_cbc, TDES_BLOCK_LEN};
+use crate::error::PaysecError;
+use std::fmt;
+
+/// The key block binding method of a key block version.
+pub trait KeyBlockScheme: Sized {
+    /// The block length of the cipher used for the payload encryption in bytes.
+    const BLOCK_LEN: usize;
+    /// The length of the key block MAC in bytes.
+    const MAC_LEN: usize;
+
+    /// Derive the KBEK and KBAK from the KBPK.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the KBPK length is invalid for the binding method.
+    fn derive(kbpk: &[u8]) -> Result<Self, PaysecError>;
+
+    /// Compute the MAC over the header string and the payload with the KBAK. The payload is the
+    /// clear payload for the derivation methods and the encrypted payload for the variant
+    /// methods.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the MAC computation fails.
+    fn mac(&self, header: &str, payload: &[u8]) -> Result<Vec<u8>, PaysecError>;
+
+    /// Encrypt the clear payload with the KBEK. The IV is the MAC for the derivation methods and
+    /// the first 8 bytes of the header for the variant methods.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the payload or the IV have an invalid length.
+    fn encrypt(&self, payload: &[u8], iv: &[u8]) -> Result<Vec<u8>, PaysecError>;
+
+    /// Decrypt the encrypted payload with the KBEK and the IV used for the encryption.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the encrypted payload or the IV have an invalid length.
+    fn decrypt(&self, encrypted_payload: &[u8], iv: &[u8]) -> Result<Vec<u8>, PaysecError>;
+
+    /// Compute the MAC and encrypt the clear payload, returning the encrypted payload and the MAC.
+    ///
+    /// The default implementation is the order of the derivation methods: the MAC is computed over
+    /// the clear payload and used as the IV of the encryption.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the MAC computation or the encryption fails.
+    fn seal(&self, header: &str, payload: &[u8]) -> Result<(Vec<u8>, Vec<u8>), PaysecError> {
+        let mac = self.mac(header, payload)?;
+        let encrypted_payload = self.encrypt(payload, &mac)?;
+        Ok((encrypted_payload, mac))
+    }
+
+    /// Decrypt the encrypted payload and verify the MAC, returning the clear payload.
+    ///
+    /// The clear payload is held as a `SecretKey`, so it is also wiped if the MAC check fails.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the decryption fails or the MAC check fails.
+    fn open(
+        &self,
+        header: &str,
+        encrypted_payload: &[u8],
+        mac: &[u8],
+    ) -> Result<SecretKey, PaysecError> {
+        let payload = SecretKey::new(self.decrypt(encrypted_payload, mac)?);
+        if self.mac(header, &payload)? != mac {
+            return Err(PaysecError::MacCheckFailed);
+        }
+        Ok(payload)
+    }
+}
+
+/// The TDEA Key Derivation Binding Method of key block version 'B'.
+///
+/// The KBEK and KBAK are derived with TDEA-CMAC (see `derive_keys_version_b`), the MAC is the
+/// TDEA-CMAC of the header and the clear payload, and the payload is encrypted with TDEA-CBC using
+/// the MAC as IV. Unlike the 4-byte MAC of the variant binding methods of versions 'A' and 'C',
+/// the MAC of version 'B' is the full 8-byte CMAC, encoded as 16 hex characters.
+pub struct VersionBScheme {
+    kbek: SecretKey,
+    kbak: SecretKey,
+}
+
+impl VersionBScheme {
+    // Use the MAC as the IV of the payload encryption.
+    fn iv(mac: &[u8]) -> Result<[u8; TDES_BLOCK_LEN], PaysecError> {
+        mac.try_into()
+            .map_err(|_| format!("ERROR TR-31: Invalid MAC length: {}", mac.len()).into())
+    }
+}
+
+impl fmt::Debug for VersionBScheme {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("VersionBScheme")
+            .field("kbek", &"<redacted>")
+            .field("kbek_len", &self.kbek.len())
+            .field("kbak", &"<redacted>")
+            .field("kbak_len", &self.kbak.len())
+            .finish()
+    }
+}
+
+impl KeyBlockScheme for VersionBScheme {
+    const BLOCK_LEN: usize = TDES_BLOCK_LEN;
+    const MAC_LEN: usize = TDES_BLOCK_LEN;
+
+    fn derive(kbpk: &[u8]) -> Result<Self, PaysecError> {
+        let (kbek, kbak, _) = derive_keys_version_b(kbpk)?;
+        Ok(VersionBScheme {
+            kbek: SecretKey::new(kbek),
+            kbak: SecretKey::new(kbak),
+        })
+    }
+
+    fn mac(&self, header: &str, payload: &[u8]) -> Result<Vec<u8>, PaysecError> {
+        // The MAC input contains the clear payload, so it is wiped after use
+        let mac_input = SecretKey::new([header.as_bytes(), payload].concat());
+        Ok(tdes_cmac(&mac_input, &self.kbak)?.to_vec())
+    }
+
+    fn encrypt(&self, payload: &[u8], iv: &[u8]) -> Result<Vec<u8>, PaysecError> {
+        Ok(tdes_enc_cbc(payload, &self.kbek, &Self::iv(iv)?)?)
+    }
+
+    fn decrypt(&self, encrypted_payload: &[u8], iv: &[u8]) -> Result<Vec<u8>, PaysecError> {
+        Ok(tdes_dec_cbc(encrypted_payload, &self.kbek, &Self::iv(iv)?)?)
+    }
+}
+
+/// The TDEA Key Variant Binding Method of key block version 'C'.
+///
+/// The KBEK and KBAK are variants of the KBPK (see `derive_keys_version_c`). The payload is
+/// encrypted with TDEA-CBC using the first 8 bytes of the header as IV, and the MAC is the
+/// leftmost 4 bytes of the TDEA CBC-MAC (ISO 9797-1 MAC algorithm 1, padding method 1) of the
+/// header and the encrypted payload. The encrypted payload enters the MAC as binary data, not as
+/// its hex encoding, as in the example of TR-31: 2018, A.7.3.1.
+pub struct VersionCScheme {
+    kbek: SecretKey,
+    kbak: SecretKey,
+}
+
+impl VersionCScheme {
+    // The first 8 bytes of the header are the IV of the payload encryption.
+    fn header_iv(header: &str) -> Result<[u8; TDES_BLOCK_LEN], PaysecError> {
+        header
+            .as_bytes()
+            .get(..TDES_BLOCK_LEN)
+            .and_then(|iv| iv.try_into().ok())
+            .ok_or_else(|| format!("ERROR TR-31: Invalid header length: {}", header.len()).into())
+    }
+
+    fn iv(iv: &[u8]) -> Result<[u8; TDES_BLOCK_LEN], PaysecError> {
+        iv.try_into()
+            .map_err(|_| format!("ERROR TR-31: Invalid IV length: {}", iv.len()).into())
+    }
+}
+
+impl fmt::Debug for VersionCScheme {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("VersionCScheme")
+            .field("kbek", &"<redacted>")
+            .field("kbek_len", &self.kbek.len())
+            .field("kbak", &"<redacted>")
+            .field("kbak_len", &self.kbak.len())
+            .finish()
+    }
+}
+
+impl KeyBlockScheme for VersionCScheme {
+    const BLOCK_LEN: usize = TDES_BLOCK_LEN;
+    const MAC_LEN: usize = 4;
+
+    fn derive(kbpk: &[u8]) -> Result<Self, PaysecError> {
+        let (kbek, kbak) = derive_keys_version_c(kbpk)?;
+        Ok(VersionCScheme {
+            kbek: SecretKey::new(kbek),
+            kbak: SecretKey::new(kbak),
+        })
+    }
+
+    fn mac(&self, header: &str, payload: &[u8]) -> Result<Vec<u8>, PaysecError> {
+        // Padding method 1: zero bytes up to a multiple of the block length
+        let mut mac_input = header.as_bytes().to_vec();
+        mac_input.extend_from_slice(payload);
+        let padded_len = mac_input.len().div_ceil(TDES_BLOCK_LEN) * TDES_BLOCK_LEN;
+        mac_input.resize(padded_len, 0);
+
+        let cbc = tdes_enc_cbc(&mac_input, &self.kbak, &[0u8; TDES_BLOCK_LEN])?;
+        let last_block = &cbc[cbc.len() - TDES_BLOCK_LEN..];
+        Ok(last_block[..Self::MAC_LEN].to_vec())
+    }
+
+    fn encrypt(&self, payload: &[u8], iv: &[u8]) -> Result<Vec<u8>, PaysecError> {
+        Ok(tdes_enc_cbc(payload, &self.kbek, &Self::iv(iv)?)?)
+    }
+
+    fn decrypt(&self, encrypted_payload: &[u8], iv: &[u8]) -> Result<Vec<u8>, PaysecError> {
+        Ok(tdes_dec_cbc(encrypted_payload, &self.kbek, &Self::iv(iv)?)?)
+    }
+
+    fn seal(&self, header: &str, payload: &[u8]) -> Result<(Vec<u8>, Vec<u8>), PaysecError> {
+        let encrypted_payload = self.encrypt(payload, &Self::header_iv(header)?)?;
+        let mac = self.mac(header, &encrypted_payload)?;
+        Ok((encrypted_payload, mac))
+    }
+
+    fn open(
+        &self,
+        header: &str,
+        encrypted_payload: &[u8],
+        mac: &[u8],
+    ) -> Result<SecretKey, PaysecError> {
+        if self.mac(header, encrypted_payload)? != mac {
+            return Err(PaysecError::MacCheckFailed);
+        }
+        Ok(SecretKey::new(
+            self.decrypt(encrypted_payload, &Self::header_iv(header)?)?,
+        ))
+    }
+}
+
+/// The AES Key Derivation Binding Method of key block version 'D'.
+///
+/// The KBEK and KBAK are derived with AES-CMAC (see `derive_keys_version_d`), the MAC is the
+/// AES-CMAC of the header and the clear payload, and the payload is encrypted with AES-CBC using
+/// the MAC as IV.
+pub struct VersionDScheme {
+    kbek: SecretKey,
+    kbak: SecretKey,
+}
+
+impl VersionDScheme {
+    // Use the MAC as the IV of the payload encryption.
+    fn iv(mac: &[u8]) -> Result<[u8; 16], PaysecError> {
+        mac.try_into()
+            .map_err(|_| format!("ERROR TR-31: Invalid MAC length: {}", mac.len()).into())
+    }
+}
+
+impl fmt::Debug for VersionDScheme {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("VersionDScheme")
+            .field("kbek", &"<redacted>")
+            .field("kbek_len", &self.kbek.len())
+            .field("kbak", &"<redacted>")
+            .field("kbak_len", &self.kbak.len())
+            .finish()
+    }
+}
+
+impl KeyBlockScheme for VersionDScheme {
+    const BLOCK_LEN: usize = 16;
+    const MAC_LEN: usize = 16;
+
+    fn derive(kbpk: &[u8]) -> Result<Self, PaysecError> {
+        let (kbek, kbak) = derive_keys_version_d(kbpk)?;
+        Ok(VersionDScheme {
+            kbek: SecretKey::new(kbek),
+            kbak: SecretKey::new(kbak),
+        })
+    }
+
+    fn mac(&self, header: &str, payload: &[u8]) -> Result<Vec<u8>, PaysecError> {
+        // The MAC input contains the clear payload, so it is wiped after use
+        let mac_input = SecretKey::new([header.as_bytes(), payload].concat());
+        Ok(aes_cmac(&mac_input, &self.kbak)?.to_vec())
+    }
+
+    fn encrypt(&self, payload: &[u8], iv: &[u8]) -> Result<Vec<u8>, PaysecError> {
+        Ok(aes_enc_cbc(payload, &self.kbek, &Self::iv(iv)?)?)
+    }
+
+    fn decrypt(&self, encrypted_payload: &[u8], iv: &[u8]) -> Result<Vec<u8>, PaysecError> {
+        Ok(aes_dec_cbc(encrypted_payload, &self.kbek, &Self::iv(iv)?)?)
+    }
+}
@@ -0,0 +1,117 @@
+//! A recovered key which is wiped from memory when dropped.
+//!
+//! # Description
+//!
+//! `tr31_unwrap` and the functions based on it return the recovered key as a `SecretKey`, so the
+//! clear key does not remain in memory after the value goes out of scope. A `SecretKey` derefs
+//! to the key bytes and its `Debug` output only shows the key length. With the `zeroize` feature
+//! it implements `Zeroize` and `ZeroizeOnDrop` of the `zeroize` crate.
+//!
+//! # Example
+//! ```
+//! # #[cfg(not(feature = "no-clear-secrets"))]
+//! # {
+//! use paysec::keyblock::tr31_unwrap;
+//!
+//! let key_block = "D0112P0AE00E0000B82679114F470F540165EDFBF7E250FCEA43F810D215F8D207E2E417C07156A27E8E31DA05F7425509593D03A457DC34";
+//! let kbpk =
+//!     hex::decode("88E1AB2A2E3DD38C1FA039A536500CC8A87AB9D62DC92C01058FA79F44657DE6").unwrap();
+//!
+//! let (_, key) = tr31_unwrap(&kbpk, key_block).unwrap();
+//! assert_eq!(key.len(), 16);
+//! assert_eq!(&key[..4], [0x3F, 0x41, 0x9E, 0x1C]);
+//! assert_eq!(format!("{:?}", key), "SecretKey { key: \"<redacted>\", key_len: 16 }");
+//! # }
+//! ```
+
+use crate::utils::wipe;
+use std::fmt;
+use std::ops::Deref;
+
+/// A key which is overwritten with zeros when the value is dropped.
+#[derive(Clone, PartialEq, Eq)]
+pub struct SecretKey(Vec<u8>);
+
+impl SecretKey {
+    /// Take ownership of the key bytes.
+    pub fn new(key: Vec<u8>) -> Self {
+        SecretKey(key)
+    }
+
+    /// Return the key bytes.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    // Return the key bytes for in-place changes, e.g. the parity adjustment of a TDES key.
+    #[cfg(not(feature = "no-clear-secrets"))]
+    pub(crate) fn as_mut_bytes(&mut self) -> &mut [u8] {
+        &mut self.0
+    }
+
+    /// Return the key bytes as a vector.
+    ///
+    /// The returned vector is not wiped when it is dropped.
+    pub fn into_vec(mut self) -> Vec<u8> {
+        std::mem::take(&mut self.0)
+    }
+}
+
+impl From<Vec<u8>> for SecretKey {
+    fn from(key: Vec<u8>) -> Self {
+        SecretKey(key)
+    }
+}
+
+impl Deref for SecretKey {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl AsRef<[u8]> for SecretKey {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl PartialEq<Vec<u8>> for SecretKey {
+    fn eq(&self, other: &Vec<u8>) -> bool {
+        self.0 == *other
+    }
+}
+
+impl PartialEq<[u8]> for SecretKey {
+    fn eq(&self, other: &[u8]) -> bool {
+        self.0 == other
+    }
+}
+
+impl fmt::Debug for SecretKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SecretKey")
+            .field("key", &"<redacted>")
+            .field("key_len", &self.0.len())
+            .finish()
+    }
+}
+
+impl Drop for SecretKey {
+    fn drop(&mut self) {
+        wipe(&mut self.0);
+    }
+}
+
+#[cfg(feature = "zeroize")]
+impl zeroize::Zeroize for SecretKey {
+    /// Wipe the key and truncate it to zero length.
+    fn zeroize(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+// The key is wiped by the `Drop` implementation.
+#[cfg(feature = "zeroize")]
+impl zeroize::ZeroizeOnDrop for SecretKey {}
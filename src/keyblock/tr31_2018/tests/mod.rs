@@ -1,5 +1,16 @@
+#[cfg(not(feature = "no-clear-secrets"))]
+mod test_annex_a;
+mod test_header_constants;
+mod test_header_view;
+mod test_kcv;
+mod test_key_block;
 mod test_key_block_header;
 mod test_key_derivations;
 mod test_opt_block;
 mod test_payload;
+mod test_provision;
 mod test_tr31;
+#[cfg(not(feature = "no-clear-secrets"))]
+mod test_usage_bound_key;
+mod test_scheme;
+mod test_secret_key;
@@ -0,0 +1,23 @@
+use super::super::header_constants::*;
+
+#[test]
+fn test_version_is_deprecated() {
+    assert!(version_is_deprecated("A"));
+    assert!(!version_is_deprecated("B"));
+    assert!(!version_is_deprecated("C"));
+    assert!(!version_is_deprecated("D"));
+}
+
+#[test]
+fn test_version_description() {
+    assert_eq!(
+        version_description("D"),
+        Some("AES Key Derivation Binding Method")
+    );
+    assert_eq!(version_description("A"), Some("Key Variant Binding Method"));
+    assert_eq!(version_description("E"), None);
+
+    for version_id in ALLOWED_VERSION_IDS {
+        assert!(version_description(version_id).is_some());
+    }
+}
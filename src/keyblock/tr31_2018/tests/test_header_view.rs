@@ -0,0 +1,124 @@
+use crate::keyblock::*;
+use crate::validation::ValidationConfig;
+
+const HEADERS: [&str; 6] = [
+    "D0112P0AE00E0000",
+    "B0080P0TE00E0000",
+    "D0144P0AE00E0200KS1800604B120F9292800000PB0800001234",
+    "D0000K1AB01N0400KS0A00604BKV0A010000TS1320240101120000ZPB0900000",
+    "C0072D0TDc1S0100PB080000",
+    "D0000M3AC00N0200CT0CSomeDataPB140000000000000000",
+];
+
+fn assert_view_equals_header(view: &HeaderView<'_>, header: &KeyBlockHeader) {
+    assert_eq!(view.version_id(), header.version_id());
+    assert_eq!(view.kb_length(), header.kb_length());
+    assert_eq!(view.key_usage(), header.key_usage());
+    assert_eq!(view.algorithm(), header.algorithm());
+    assert_eq!(view.mode_of_use(), header.mode_of_use());
+    assert_eq!(view.key_version_number(), header.key_version_number());
+    assert_eq!(view.exportability(), header.exportability());
+    assert_eq!(view.num_optional_blocks(), header.num_optional_blocks());
+    assert_eq!(view.reserved_field(), header.reserved_field());
+    assert_eq!(view.len(), header.len());
+    assert_eq!(view.opt_blocks_str(), header.opt_blocks_str().unwrap());
+
+    let owned_blocks: Vec<_> =
+        std::iter::successors(header.opt_blocks().as_deref(), |block| block.next()).collect();
+    let view_blocks: Vec<_> = view.opt_blocks().collect();
+    assert_eq!(view_blocks.len(), owned_blocks.len());
+    for (view_block, owned_block) in view_blocks.iter().zip(owned_blocks) {
+        assert_eq!(view_block.id(), owned_block.id());
+        assert_eq!(view_block.data(), owned_block.data());
+        assert_eq!(view_block.length(), *owned_block.length());
+    }
+}
+
+#[test]
+fn test_header_view_equals_owned_header() {
+    for header_str in HEADERS {
+        let view = HeaderView::parse(header_str).unwrap();
+        let header = KeyBlockHeader::new_from_str(header_str).unwrap();
+        assert_view_equals_header(&view, &header);
+        assert_eq!(view.as_str(), header.export_str().unwrap());
+
+        let from_view = KeyBlockHeader::from_view(&view).unwrap();
+        assert_eq!(from_view, header);
+    }
+}
+
+#[test]
+fn test_header_view_of_key_block() {
+    let key_block = "D0112P0AE00E0000B82679114F470F540165EDFBF7E250FCEA43F810D215F8D207E2E417C07156A27E8E31DA05F7425509593D03A457DC34";
+    let view = HeaderView::parse(key_block).unwrap();
+    assert_eq!(view.as_str(), "D0112P0AE00E0000");
+    assert_eq!(view.kb_length(), 112);
+    assert_eq!(view.opt_blocks().count(), 0);
+}
+
+#[test]
+fn test_header_view_opt_block_offsets() {
+    let view = HeaderView::parse(HEADERS[3]).unwrap();
+    let offsets: Vec<_> = view
+        .opt_blocks()
+        .map(|block| (block.id(), block.offset(), block.length()))
+        .collect();
+    assert_eq!(
+        offsets,
+        [
+            ("KS", 16, 10),
+            ("KV", 26, 10),
+            ("TS", 36, 19),
+            ("PB", 55, 9)
+        ]
+    );
+}
+
+#[test]
+fn test_header_view_lenient() {
+    let config = ValidationConfig::lenient();
+    for header_str in ["D0000P0A100E0100PB080000", "D0000P0AE00E01001A08ABCD"] {
+        assert!(HeaderView::parse(header_str).is_err());
+
+        let view = HeaderView::parse_with_options(header_str, &config).unwrap();
+        let header = KeyBlockHeader::new_from_str_with_options(header_str, &config).unwrap();
+        assert_view_equals_header(&view, &header);
+        assert_eq!(KeyBlockHeader::from_view(&view).unwrap(), header);
+    }
+}
+
+#[test]
+fn test_header_view_errors_equal_owned_parser() {
+    let invalid_headers = [
+        "D0112P0AE00E000",
+        "D01X2P0AE00E0000",
+        "Z0112P0AE00E0000",
+        "D0112Z9AE00E0000",
+        "D0112P0QE00E0000",
+        "D0112P0AZ00E0000",
+        "D0112P0AE00Z0000",
+        "D0112P0AE00E0001",
+        "D0112P0AE00E0100",
+        "D0112P0AE00E01XX",
+        "D0112P0AE00E0100ZZ08ABCD",
+        "D0112P0AE00E0200KS08ABCDPB0Z00",
+        "D0112P0AE00E0200KS08ABCDPB1000",
+    ];
+
+    for header_str in invalid_headers {
+        let view_err = HeaderView::parse(header_str).unwrap_err();
+        let owned_err = KeyBlockHeader::new_from_str(header_str).unwrap_err();
+        assert_eq!(
+            view_err.to_string(),
+            owned_err.to_string(),
+            "{}",
+            header_str
+        );
+        assert_eq!(
+            view_err.parse_error().map(|e| e.range()),
+            owned_err.parse_error().map(|e| e.range()),
+            "{}",
+            header_str
+        );
+    }
+}
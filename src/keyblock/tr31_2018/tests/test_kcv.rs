@@ -0,0 +1,106 @@
+use super::super::kcv::{compute_kcv_aes, compute_kcv_aes_legacy, compute_kcv_tdes, KcvKind};
+#[cfg(not(feature = "no-clear-secrets"))]
+use super::super::tr31::tr31_unwrap_verified;
+#[cfg(not(feature = "no-clear-secrets"))]
+use super::super::tr31::tr31_wrap;
+#[cfg(not(feature = "no-clear-secrets"))]
+use super::super::KeyBlockHeader;
+use super::super::OptBlock;
+
+#[test]
+fn test_compute_kcv_aes() {
+    let key = hex::decode("3F419E1CB7079442AA37474C2EFBF8B8").unwrap();
+    assert_eq!(
+        compute_kcv_aes(&key).unwrap(),
+        [0x08, 0x79, 0x3E, 0x25, 0xAB]
+    );
+    assert!(compute_kcv_aes(&key[..15]).is_err());
+}
+
+#[test]
+fn test_compute_kcv_aes_key_sizes() {
+    // AES-128, AES-192 and AES-256 keys of NIST SP 800-38B, Appendix D
+    let cases = [
+        (
+            "2B7E151628AED2A6ABF7158809CF4F3C",
+            "7AD386C376",
+            [0x7D, 0xF7, 0x6B],
+        ),
+        (
+            "8E73B0F7DA0E6452C810F32B809079E562F8EAD2522C6B7B",
+            "3A072A425D",
+            [0x22, 0x45, 0x2D],
+        ),
+        (
+            "603DEB1015CA71BE2B73AEF0857D77811F352C073B6108D72D9810A30914DFF4",
+            "1A0B2DF267",
+            [0xE5, 0x68, 0xF6],
+        ),
+    ];
+
+    for (key_hex, cmac_kcv, legacy_kcv) in cases {
+        let key = hex::decode(key_hex).unwrap();
+        assert_eq!(hex::encode_upper(compute_kcv_aes(&key).unwrap()), cmac_kcv);
+        assert_eq!(compute_kcv_aes_legacy(&key).unwrap(), legacy_kcv);
+    }
+
+    assert!(compute_kcv_aes_legacy(&[0x2B; 20]).is_err());
+}
+
+#[test]
+fn test_compute_kcv_tdes() {
+    // Well-known KCV of the TDES test key 0123456789ABCDEFFEDCBA9876543210
+    let key = hex::decode("0123456789ABCDEFFEDCBA9876543210").unwrap();
+    assert_eq!(compute_kcv_tdes(&key).unwrap(), [0x08, 0xD7, 0xB4]);
+    assert!(compute_kcv_tdes(&key[..8]).is_err());
+}
+
+#[test]
+fn test_opt_block_new_kcv_aes() {
+    let kbpk =
+        hex::decode("88E1AB2A2E3DD38C1FA039A536500CC8A87AB9D62DC92C01058FA79F44657DE6").unwrap();
+    let key = hex::decode("3F419E1CB7079442AA37474C2EFBF8B8").unwrap();
+
+    let kp_block = OptBlock::new_kcv(KcvKind::Kp, "A", &kbpk).unwrap();
+    assert_eq!(kp_block.export_str().unwrap(), "KP10012331550BC9");
+
+    let kc_block = OptBlock::new_kcv(KcvKind::Kc, "A", &key).unwrap();
+    assert_eq!(kc_block.export_str().unwrap(), "KC100108793E25AB");
+}
+
+#[test]
+fn test_opt_block_new_kcv_tdes() {
+    let key = hex::decode("0123456789ABCDEFFEDCBA9876543210").unwrap();
+    let kc_block = OptBlock::new_kcv(KcvKind::Kc, "T", &key).unwrap();
+    assert_eq!(kc_block.export_str().unwrap(), "KC0C0008D7B4");
+}
+
+#[test]
+fn test_opt_block_new_kcv_errors() {
+    let key = hex::decode("0123456789ABCDEFFEDCBA9876543210").unwrap();
+    assert_eq!(
+        OptBlock::new_kcv(KcvKind::Kc, "H", &key)
+            .unwrap_err()
+            .to_string(),
+        "PSEC-0000: ERROR TR-31 OPT BLOCK: KCV not supported for key algorithm H"
+    );
+    assert!(OptBlock::new_kcv(KcvKind::Kp, "A", &key[..12]).is_err());
+}
+
+#[cfg(not(feature = "no-clear-secrets"))]
+#[test]
+fn test_opt_block_new_kcv_unwrap_verified() {
+    let kbpk =
+        hex::decode("88E1AB2A2E3DD38C1FA039A536500CC8A87AB9D62DC92C01058FA79F44657DE6").unwrap();
+    let key = hex::decode("3F419E1CB7079442AA37474C2EFBF8B8").unwrap();
+    let random_seed: Vec<u8> = (0..32).collect();
+
+    let mut kp_block = OptBlock::new_kcv(KcvKind::Kp, "A", &kbpk).unwrap();
+    kp_block.append(OptBlock::new_kcv(KcvKind::Kc, "A", &key).unwrap());
+    let mut header = KeyBlockHeader::new_with_values("D", "P0", "A", "E", "00", "E").unwrap();
+    header.set_opt_blocks(Some(Box::new(kp_block)));
+    header.finalize().unwrap();
+
+    let key_block = tr31_wrap(&kbpk, header, &key, 0, &random_seed).unwrap();
+    assert_eq!(tr31_unwrap_verified(&kbpk, &key_block).unwrap().1, key);
+}
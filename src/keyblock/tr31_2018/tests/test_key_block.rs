@@ -0,0 +1,45 @@
+use super::super::key_block::KeyBlock;
+use super::super::tr31::{tr31_validate_structure, tr31_wrap_lowercase};
+use super::super::{KeyBlockHeader, OptBlock};
+
+#[test]
+fn test_key_block_parse_round_trip() {
+    let kbpk =
+        hex::decode("88E1AB2A2E3DD38C1FA039A536500CC8A87AB9D62DC92C01058FA79F44657DE6").unwrap();
+    let key = hex::decode("3F419E1CB7079442AA37474C2EFBF8B8").unwrap();
+    let random_seed = hex::decode("1C2965473CE206BB855B01533782").unwrap();
+    let mut header = KeyBlockHeader::new_with_values("D", "P0", "A", "E", "00", "E").unwrap();
+    header.set_opt_blocks(Some(Box::new(OptBlock::new("KS", "00604B", None).unwrap())));
+    header.finalize().unwrap();
+
+    let s = tr31_wrap_lowercase(&kbpk, header, &key, 0, &random_seed).unwrap();
+    let key_block = KeyBlock::parse(&s).unwrap();
+    let header_len = key_block.header.len();
+
+    assert_eq!(key_block.header.num_optional_blocks(), 2);
+    assert_eq!(key_block.mac.len(), 16);
+    assert_eq!(
+        2 * (key_block.encrypted_payload.len() + key_block.mac.len()),
+        s.len() - header_len
+    );
+    assert_eq!(hex::encode(&key_block.mac), &s[s.len() - 32..]);
+
+    // The hex is formatted in uppercase, the header is unchanged
+    assert_eq!(
+        key_block.to_string(),
+        s[..header_len].to_string() + &s[header_len..].to_uppercase()
+    );
+    assert_eq!(KeyBlock::parse(&key_block.to_string()).unwrap(), key_block);
+}
+
+#[test]
+fn test_key_block_parse_errors() {
+    let s = "D0112P0AE00E0000B82679114F470F540165EDFBF7E250FCEA43F810D215F8D207E2E417C07156A27E8E31DA05F7425509593D03A457DC34";
+
+    for invalid in [&s[..108], "D0112P0AE00E0000", &s.replace('B', "X")] {
+        assert_eq!(
+            KeyBlock::parse(invalid).unwrap_err().to_string(),
+            tr31_validate_structure(invalid).unwrap_err().to_string()
+        );
+    }
+}
@@ -1,4 +1,5 @@
 use crate::keyblock::*;
+use std::collections::HashMap;
 
 #[test]
 fn test_new_empty() {
@@ -31,42 +32,42 @@ fn test_new_with_values_invalid_params() {
     assert!(res.is_err());
     assert_eq!(
         res.err().unwrap().to_string(),
-        "ERROR TR-31 HEADER: Invalid version ID: X"
+        "TR31-0011: ERROR TR-31 HEADER: Invalid version ID: X"
     );
 
     let res = KeyBlockHeader::new_with_values("B", "XX", "A", "B", "01", "E");
     assert!(res.is_err());
     assert_eq!(
         res.err().unwrap().to_string(),
-        "ERROR TR-31 HEADER: Invalid key usage: XX"
+        "TR31-0011: ERROR TR-31 HEADER: Invalid key usage: XX"
     );
 
     let res = KeyBlockHeader::new_with_values("B", "B0", "X", "B", "01", "E");
     assert!(res.is_err());
     assert_eq!(
         res.err().unwrap().to_string(),
-        "ERROR TR-31 HEADER: Invalid algorithm: X"
+        "TR31-0011: ERROR TR-31 HEADER: Invalid algorithm: X"
     );
 
     let res = KeyBlockHeader::new_with_values("B", "B0", "A", "Z", "01", "E");
     assert!(res.is_err());
     assert_eq!(
         res.err().unwrap().to_string(),
-        "ERROR TR-31 HEADER: Invalid mode of use: Z"
+        "TR31-0011: ERROR TR-31 HEADER: Invalid mode of use: Z"
     );
 
     let res = KeyBlockHeader::new_with_values("B", "B0", "A", "B", "X", "E");
     assert!(res.is_err());
     assert_eq!(
         res.err().unwrap().to_string(),
-        "ERROR TR-31 HEADER: Key version number must consist of 2 ASCII characters: X"
+        "PSEC-0000: ERROR TR-31 HEADER: Key version number must consist of 2 ASCII characters: X"
     );
 
     let res = KeyBlockHeader::new_with_values("B", "B0", "A", "B", "01", "X");
     assert!(res.is_err());
     assert_eq!(
         res.err().unwrap().to_string(),
-        "ERROR TR-31 HEADER: Invalid exportability: X"
+        "TR31-0011: ERROR TR-31 HEADER: Invalid exportability: X"
     );
 }
 
@@ -115,7 +116,7 @@ fn test_new_from_str_invalid_length() {
     assert!(result.is_err());
     assert_eq!(
         result.err().unwrap().to_string(),
-        "ERROR TR-31 HEADER: Invalid data length"
+        "TR31-0007: ERROR TR-31 HEADER: Invalid data length"
     );
 }
 
@@ -125,7 +126,7 @@ fn test_new_from_str_invalid_key_block_length() {
     assert!(result.is_err());
     assert_eq!(
         result.err().unwrap().to_string(),
-        "ERROR TR-31 HEADER: Invalid key block length"
+        "TR31-0007: ERROR TR-31 HEADER: Invalid key block length"
     );
 }
 
@@ -135,7 +136,7 @@ fn test_new_from_str_invalid_number_of_optional_blocks() {
     assert!(result.is_err());
     assert_eq!(
         result.err().unwrap().to_string(),
-        "ERROR TR-31 HEADER: Invalid number of optional blocks"
+        "TR31-0007: ERROR TR-31 HEADER: Invalid number of optional blocks"
     );
 }
 
@@ -155,7 +156,7 @@ fn test_new_from_str_failed_to_parse_optional_blocks() {
     let header_str = "B0010B1DB00N0200InvalidOptBlockData";
     let result = KeyBlockHeader::new_from_str(header_str);
     assert!(result.is_err());
-    assert_eq!(result.err().unwrap().to_string(), "ERROR TR-31 HEADER: Failed to parse optional blocks: ERROR TR-31 OPT BLOCK: Invalid ID: In");
+    assert_eq!(result.err().unwrap().to_string(), "TR31-0006: ERROR TR-31 HEADER: Failed to parse optional blocks: ERROR TR-31 OPT BLOCK: Invalid ID: In");
 }
 
 #[test]
@@ -165,7 +166,7 @@ fn test_new_from_str_invalid_header_length_with_optional_blocks() {
     match KeyBlockHeader::new_from_str(header_str) {
         Err(e) => assert_eq!(
             e.to_string(),
-            "ERROR TR-31 HEADER: Invalid header length containing optional blocks"
+            "TR31-0007: ERROR TR-31 HEADER: Invalid header length containing optional blocks"
         ),
         Ok(_) => panic!("Expected an error due to inconsistent header length, but got Ok"),
     }
@@ -210,6 +211,75 @@ fn test_export_str_empty_header_error() {
     );
 }
 
+#[test]
+fn test_export_str_opt_block_count_mismatch() {
+    let mut header = KeyBlockHeader::new_with_values("D", "P0", "A", "E", "00", "E").unwrap();
+    header.set_num_optional_blocks(2).unwrap();
+
+    let result = header.export_str();
+    assert_eq!(
+        result.unwrap_err().to_string(),
+        "PSEC-0000: ERROR TR-31 HEADER: Export failed, number of optional blocks 2 does not match the optional blocks present: 0"
+    );
+}
+
+#[test]
+fn test_with_opt_blocks_replaces_chain() {
+    let header = KeyBlockHeader::new_from_str("B0160B1DB00N0100CT0C11223344").unwrap();
+    assert_eq!(header.num_optional_blocks(), 1);
+
+    let ks_block = OptBlock::new("KS", "00604B120F9292800000", None).unwrap();
+    let opt_blocks = OptBlock::new("KV", "0100", Some(ks_block)).unwrap();
+    let header = header.with_opt_blocks(Some(Box::new(opt_blocks)));
+
+    assert_eq!(header.num_optional_blocks(), 2);
+    assert_eq!(header.opt_blocks().as_ref().unwrap().id(), "KV");
+    assert_eq!(
+        header.export_str().unwrap(),
+        "B0160B1DB00N0200KV080100KS1800604B120F9292800000"
+    );
+
+    let header = header.with_opt_blocks(None);
+    assert_eq!(header.num_optional_blocks(), 0);
+    assert_eq!(header.export_str().unwrap(), "B0160B1DB00N0000");
+}
+
+#[test]
+fn test_mac_input_equals_opt_block_order() {
+    let header_a =
+        KeyBlockHeader::new_from_str("B0160B1DB00N0200KV080100KS1800604B120F9292800000").unwrap();
+    let header_b =
+        KeyBlockHeader::new_from_str("B0160B1DB00N0200KV080100KS1800604B120F9292800000").unwrap();
+    assert!(header_a.mac_input_equals(&header_b).unwrap());
+
+    // Same fields and optional blocks in a different order
+    let header_c =
+        KeyBlockHeader::new_from_str("B0160B1DB00N0200KS1800604B120F9292800000KV080100").unwrap();
+    assert_eq!(header_a.key_usage(), header_c.key_usage());
+    assert_eq!(
+        header_a.num_optional_blocks(),
+        header_c.num_optional_blocks()
+    );
+    assert!(!header_a.mac_input_equals(&header_c).unwrap());
+
+    assert!(header_a
+        .mac_input_equals(&KeyBlockHeader::new_empty())
+        .is_err());
+}
+
+#[test]
+fn test_opt_blocks_str() {
+    let header =
+        KeyBlockHeader::new_from_str("D0144P0TE00N0200KS1800604B120F9292800000PB080000").unwrap();
+
+    let opt_blocks_str = header.opt_blocks_str().unwrap();
+    assert_eq!(opt_blocks_str, "KS1800604B120F9292800000PB080000");
+    assert_eq!(opt_blocks_str, header.export_str().unwrap()[16..]);
+
+    let header = KeyBlockHeader::new_from_str("D0112P0AE00E0000").unwrap();
+    assert_eq!(header.opt_blocks_str().unwrap(), "");
+}
+
 #[test]
 fn test_set_version_id() {
     let mut header = KeyBlockHeader::new_empty();
@@ -220,7 +290,7 @@ fn test_set_version_id() {
     assert!(result.is_err());
     assert_eq!(
         result.err().unwrap().to_string(),
-        "ERROR TR-31 HEADER: Invalid version ID: E"
+        "TR31-0011: ERROR TR-31 HEADER: Invalid version ID: E"
     );
 }
 
@@ -234,7 +304,7 @@ fn test_set_kb_length() {
     assert!(result.is_err());
     assert_eq!(
         result.err().unwrap().to_string(),
-        "ERROR TR-31 HEADER: Invalid key block length"
+        "PSEC-0000: ERROR TR-31 HEADER: Invalid key block length"
     );
 }
 
@@ -248,7 +318,7 @@ fn test_set_key_usage() {
     assert!(result.is_err());
     assert_eq!(
         result.err().unwrap().to_string(),
-        "ERROR TR-31 HEADER: Invalid key usage: ZZ"
+        "TR31-0011: ERROR TR-31 HEADER: Invalid key usage: ZZ"
     );
 }
 
@@ -262,7 +332,7 @@ fn test_set_algorithm() {
     assert!(result.is_err());
     assert_eq!(
         result.err().unwrap().to_string(),
-        "ERROR TR-31 HEADER: Invalid algorithm: Z"
+        "TR31-0011: ERROR TR-31 HEADER: Invalid algorithm: Z"
     );
 }
 
@@ -276,7 +346,7 @@ fn test_set_mode_of_use() {
     assert!(result.is_err());
     assert_eq!(
         result.err().unwrap().to_string(),
-        "ERROR TR-31 HEADER: Invalid mode of use: Z"
+        "TR31-0011: ERROR TR-31 HEADER: Invalid mode of use: Z"
     );
 }
 
@@ -290,14 +360,14 @@ fn test_set_key_version_number() {
     assert!(result.is_err());
     assert_eq!(
         result.err().unwrap().to_string(),
-        "ERROR TR-31 HEADER: Key version number must consist of 2 ASCII characters: 1"
+        "PSEC-0000: ERROR TR-31 HEADER: Key version number must consist of 2 ASCII characters: 1"
     );
 
     let result = header.set_key_version_number("010");
     assert!(result.is_err());
     assert_eq!(
         result.err().unwrap().to_string(),
-        "ERROR TR-31 HEADER: Key version number must consist of 2 ASCII characters: 010"
+        "PSEC-0000: ERROR TR-31 HEADER: Key version number must consist of 2 ASCII characters: 010"
     );
 }
 
@@ -310,7 +380,7 @@ fn test_set_key_version_number_non_ascii_error() {
         Err(e) => assert_eq!(
             e.to_string(),
             format!(
-                "ERROR TR-31 HEADER: Key version number must consist of ASCII characters: {}",
+                "PSEC-0000: ERROR TR-31 HEADER: Key version number must consist of ASCII characters: {}",
                 non_ascii_value
             )
         ),
@@ -328,7 +398,7 @@ fn test_set_exportability() {
     assert!(result.is_err());
     assert_eq!(
         result.err().unwrap().to_string(),
-        "ERROR TR-31 HEADER: Invalid exportability: Z"
+        "TR31-0011: ERROR TR-31 HEADER: Invalid exportability: Z"
     );
 }
 
@@ -342,7 +412,7 @@ fn test_set_num_optional_blocks() {
     assert!(result.is_err());
     assert_eq!(
         result.err().unwrap().to_string(),
-        "ERROR TR-31 HEADER: Number of opt blocks value is too large"
+        "PSEC-0000: ERROR TR-31 HEADER: Number of opt blocks value is too large"
     );
 }
 
@@ -356,7 +426,7 @@ fn test_set_reserved_field() {
     assert!(result.is_err());
     assert_eq!(
         result.err().unwrap().to_string(),
-        "ERROR TR-31 HEADER: Invalid value for reserved field: 01"
+        "PSEC-0000: ERROR TR-31 HEADER: Invalid value for reserved field: 01"
     );
 }
 
@@ -369,7 +439,7 @@ fn test_set_reserved_field_invalid_value_error() {
         Err(e) => assert_eq!(
             e.to_string(),
             format!(
-                "ERROR TR-31 HEADER: Invalid value for reserved field: {}",
+                "PSEC-0000: ERROR TR-31 HEADER: Invalid value for reserved field: {}",
                 invalid_value
             )
         ),
@@ -510,3 +580,365 @@ fn test_finalize_with_long_padding_needed() {
     assert_eq!(header.num_optional_blocks(), 2);
     assert_eq!(header.len(), 48);
 }
+
+#[test]
+fn test_new_from_str_tolerant_extra_pb_included() {
+    let header_str = "D0048P0TE00N0100KS1800604B120F9292800000PB080000";
+    let (header, warnings) = KeyBlockHeader::new_from_str_tolerant(header_str, true).unwrap();
+
+    assert_eq!(header.num_optional_blocks(), 2);
+    assert_eq!(header.len(), 48);
+    assert_eq!(
+        header.export_str().unwrap(),
+        "D0048P0TE00N0200KS1800604B120F9292800000PB080000"
+    );
+    assert_eq!(
+        warnings,
+        vec!["WARNING TR-31 HEADER: Included extra PB optional block beyond declared count: 01"]
+    );
+}
+
+#[test]
+fn test_new_from_str_tolerant_extra_pb_ignored() {
+    let header_str = "D0048P0TE00N0100KS1800604B120F9292800000PB080000";
+    let (header, warnings) = KeyBlockHeader::new_from_str_tolerant(header_str, false).unwrap();
+
+    assert_eq!(header.num_optional_blocks(), 1);
+    assert_eq!(header.len(), 40);
+    assert_eq!(
+        header.export_str().unwrap(),
+        "D0048P0TE00N0100KS1800604B120F9292800000"
+    );
+    assert_eq!(
+        warnings,
+        vec!["WARNING TR-31 HEADER: Ignored extra PB optional block beyond declared count: 01"]
+    );
+}
+
+#[test]
+fn test_new_from_str_tolerant_no_extra_block() {
+    let header_str = "D0048P0TE00N0200KS1800604B120F9292800000PB080000";
+    let (header, warnings) = KeyBlockHeader::new_from_str_tolerant(header_str, true).unwrap();
+
+    assert_eq!(header.num_optional_blocks(), 2);
+    assert!(warnings.is_empty());
+}
+
+#[test]
+fn test_new_from_str_parse_error_bad_length_field() {
+    let err = KeyBlockHeader::new_from_str("D01X2P0AE00E0000").unwrap_err();
+    let parse_error = err.parse_error().unwrap();
+
+    assert_eq!(parse_error.field(), "key block length");
+    assert_eq!(parse_error.offset(), 1);
+    assert_eq!(parse_error.length(), 4);
+}
+
+#[test]
+fn test_new_from_str_parse_error_bad_opt_block_id() {
+    // The second optional block has the invalid ID 'XX'.
+    let header_str = "D0048P0TE00N0200KS1800604B120F9292800000XX080000";
+    let err = KeyBlockHeader::new_from_str(header_str).unwrap_err();
+    let parse_error = err.parse_error().unwrap();
+
+    assert_eq!(parse_error.field(), "optional block ID");
+    assert_eq!(parse_error.range(), 40..42);
+    assert_eq!(&header_str[parse_error.range()], "XX");
+}
+
+#[test]
+fn test_new_from_str_with_options_proprietary_mode_of_use() {
+    let header_str = "D0112P0A700E0000";
+    let config = crate::validation::ValidationConfig::lenient();
+
+    let header = KeyBlockHeader::new_from_str_with_options(header_str, &config).unwrap();
+    assert_eq!(header.mode_of_use(), "7");
+    assert_eq!(header.export_str().unwrap(), header_str);
+
+    // The default policy and the setter still reject the proprietary mode of use
+    let err = KeyBlockHeader::new_from_str(header_str).unwrap_err();
+    let parse_error = err.parse_error().unwrap();
+    assert_eq!(parse_error.field(), "mode of use");
+    assert!(KeyBlockHeader::new_with_values("D", "P0", "A", "7", "00", "E").is_err());
+
+    // Only numeric modes of use are proprietary
+    assert!(KeyBlockHeader::new_from_str_with_options("D0112P0AZ00E0000", &config).is_err());
+}
+
+#[test]
+fn test_short_notation_round_trip() {
+    let notations = [
+        "P0/A/E/N",
+        "P0/A/E/E",
+        "K0/A/B/S",
+        "B0/A/X/E,kv=01",
+        "D0/A/D/N,kv=c1",
+        "D0/A/D/N,kv=c2,opt=KS:00604B",
+        "M3/A/C/N,opt=KS:00604B,opt=TS:20240101120000Z",
+        "K1/T/B/E,v=B",
+        "P0/T/E/N,v=C,kv=09",
+        "B1/A/X/N,opt=IK:1234567890123456",
+        "M1/T/C/N,v=B,kv=c1,opt=KV:0001",
+        "E0/A/G/N,opt=CT:00000000,opt=KP:012331550BC9",
+    ];
+
+    for notation in notations {
+        let header = KeyBlockHeader::from_short_notation(notation).unwrap();
+        assert_eq!(header.to_short_notation(), notation);
+
+        let header_str = header.export_str().unwrap();
+        let reparsed = KeyBlockHeader::new_from_str(&header_str).unwrap();
+        assert_eq!(reparsed.to_short_notation(), notation);
+        assert_eq!(
+            header.len() % if header.version_id() == "D" { 16 } else { 8 },
+            0
+        );
+    }
+}
+
+// Build a field map for `KeyBlockHeader::from_map`.
+fn field_map(fields: &[(&str, &str)]) -> HashMap<String, String> {
+    fields
+        .iter()
+        .map(|&(name, value)| (name.to_string(), value.to_string()))
+        .collect()
+}
+
+#[test]
+fn test_from_map() {
+    let fields = field_map(&[
+        ("version_id", "B"),
+        ("key_usage", "D0"),
+        ("algorithm", "T"),
+        ("mode_of_use", "B"),
+        ("key_version_number", "12"),
+        ("exportability", "N"),
+    ]);
+    assert_eq!(
+        KeyBlockHeader::from_map(&fields).unwrap(),
+        KeyBlockHeader::new_with_values("B", "D0", "T", "B", "12", "N").unwrap()
+    );
+
+    let mut fields = field_map(&[
+        ("version_id", "D"),
+        ("key_usage", "P0"),
+        ("algorithm", "A"),
+        ("mode_of_use", "E"),
+        ("exportability", "E"),
+    ]);
+    assert_eq!(
+        KeyBlockHeader::from_map(&fields).unwrap(),
+        KeyBlockHeader::new_with_values("D", "P0", "A", "E", "00", "E").unwrap()
+    );
+
+    fields.insert("reserved_field".to_string(), "00".to_string());
+    let header = KeyBlockHeader::from_map(&fields).unwrap();
+    assert_eq!(header.export_str().unwrap(), "D0000P0AE00E0000");
+
+    fields.insert("reserved_field".to_string(), "0A".to_string());
+    assert!(KeyBlockHeader::from_map(&fields).is_err());
+}
+
+#[test]
+fn test_from_map_errors() {
+    let mut fields = field_map(&[
+        ("version_id", "D"),
+        ("key_usage", "P0"),
+        ("algorithm", "A"),
+        ("exportability", "E"),
+    ]);
+    assert_eq!(
+        KeyBlockHeader::from_map(&fields).unwrap_err().to_string(),
+        "PSEC-0000: ERROR TR-31 HEADER: Missing header field: mode_of_use"
+    );
+
+    fields.insert("mode_of_use".to_string(), "E".to_string());
+    fields.insert("kb_length".to_string(), "0112".to_string());
+    assert_eq!(
+        KeyBlockHeader::from_map(&fields).unwrap_err().to_string(),
+        "PSEC-0000: ERROR TR-31 HEADER: Unknown header field: kb_length"
+    );
+
+    // Invalid field values are rejected by the setters
+    fields.remove("kb_length");
+    fields.insert("algorithm".to_string(), "Q".to_string());
+    assert!(KeyBlockHeader::from_map(&fields).is_err());
+}
+
+#[test]
+fn test_from_short_notation_fields() {
+    let header = KeyBlockHeader::from_short_notation("D0/A/D/N,kv=c2,opt=KS:00604B,v=B").unwrap();
+    assert_eq!(header.version_id(), "B");
+    assert_eq!(header.key_usage(), "D0");
+    assert_eq!(header.algorithm(), "A");
+    assert_eq!(header.mode_of_use(), "D");
+    assert_eq!(header.key_version_number(), "c2");
+    assert_eq!(header.exportability(), "N");
+    assert_eq!(
+        header.export_str().unwrap(),
+        "B0000D0ADc2N0200KS0A00604BPB0600"
+    );
+    assert_eq!(
+        header.to_short_notation(),
+        "D0/A/D/N,v=B,kv=c2,opt=KS:00604B"
+    );
+}
+
+#[test]
+fn test_from_short_notation_errors() {
+    let test_cases = [
+        (
+            "P0/A/E",
+            "PSEC-0000: ERROR TR-31 HEADER: Short notation must start with usage/algorithm/mode/exportability: P0/A/E",
+        ),
+        (
+            "P0/A/E/N,kx=01",
+            "PSEC-0000: ERROR TR-31 HEADER: Unknown token in short notation: kx=01",
+        ),
+        (
+            "P0/A/E/N,01",
+            "PSEC-0000: ERROR TR-31 HEADER: Unknown token in short notation: 01",
+        ),
+        (
+            "P0/A/E/N,kv=01,kv=02",
+            "PSEC-0000: ERROR TR-31 HEADER: Repeated token in short notation: kv=02",
+        ),
+        (
+            "P0/A/E/N,v=B,v=D",
+            "PSEC-0000: ERROR TR-31 HEADER: Repeated token in short notation: v=D",
+        ),
+        (
+            "P0/A/E/N,opt=KS",
+            "PSEC-0000: ERROR TR-31 HEADER: Optional block in short notation must be ID:data: KS",
+        ),
+    ];
+
+    for (notation, expected_error) in test_cases {
+        assert_eq!(
+            KeyBlockHeader::from_short_notation(notation)
+                .unwrap_err()
+                .to_string(),
+            expected_error
+        );
+    }
+
+    // Invalid field values are rejected by the setters
+    assert!(KeyBlockHeader::from_short_notation("P0/A/E/N,v=Z").is_err());
+    assert!(KeyBlockHeader::from_short_notation("P0/Q/E/N").is_err());
+    assert!(KeyBlockHeader::from_short_notation("P0/A/E/N,opt=ZZ:00").is_err());
+}
+
+#[test]
+fn test_validate_b1_without_ik() {
+    let header = KeyBlockHeader::new_from_str("D0000B1AX00N0000").unwrap();
+    assert_eq!(
+        header.validate(),
+        vec!["WARNING TR-31 HEADER: Key usage B1 (Initial DUKPT Key) without IK optional block"]
+    );
+
+    // Other optional blocks do not satisfy the rule
+    let header =
+        KeyBlockHeader::new_from_str("D0000B1AX00N0200KS1800604B120F9292800000PB080000").unwrap();
+    assert_eq!(header.validate().len(), 1);
+}
+
+#[test]
+fn test_validate_b1_with_ik() {
+    let header = KeyBlockHeader::new_from_str("D0000B1AX00N0100IK141234567890123456").unwrap();
+    assert!(header.validate().is_empty());
+
+    let header = KeyBlockHeader::new_from_str("D0000P0AE00E0000").unwrap();
+    assert!(header.validate().is_empty());
+}
+
+#[test]
+fn test_merge_opt_blocks() {
+    let mut header = KeyBlockHeader::new_from_str("D0000P0AE00E0100CT0C11223344").unwrap();
+    let ks = OptBlock::new("KS", "00604B120F9292800000", None).unwrap();
+
+    header.merge_opt_blocks(vec![ks], false).unwrap();
+
+    let ids: Vec<&str> =
+        std::iter::successors(header.opt_blocks().as_deref(), |block| block.next())
+            .map(|block| block.id())
+            .collect();
+    assert_eq!(ids, ["CT", "KS", "PB"]);
+    assert_eq!(header.num_optional_blocks(), 3);
+    assert_eq!(header.len() % 16, 0);
+    assert_eq!(
+        header.export_str().unwrap(),
+        "D0000P0AE00E0300CT0C11223344KS1800604B120F9292800000PB0C00000000"
+    );
+}
+
+#[test]
+fn test_merge_opt_blocks_duplicates() {
+    let header_str = "D0000P0AE00E0200KS1800604B120F9292800000PB080000";
+    let ks = || OptBlock::new("KS", "00604B120F9292800001", None).unwrap();
+    let kv = || OptBlock::new("KV", "010000", None).unwrap();
+
+    // Skip the duplicate KS block, the existing one is kept
+    let mut header = KeyBlockHeader::new_from_str(header_str).unwrap();
+    header.merge_opt_blocks(vec![ks(), kv()], true).unwrap();
+    assert_eq!(
+        header.opt_blocks_str().unwrap(),
+        "KS1800604B120F9292800000KV0A010000PB0E0000000000"
+    );
+
+    // Fail on the duplicate KS block and leave the header unchanged
+    let mut header = KeyBlockHeader::new_from_str(header_str).unwrap();
+    let err = header
+        .merge_opt_blocks(vec![kv(), ks()], false)
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "PSEC-0000: ERROR TR-31 HEADER: Duplicate optional block ID: KS"
+    );
+    assert_eq!(header.export_str().unwrap(), header_str);
+}
+
+#[cfg(feature = "chrono")]
+#[test]
+fn test_timestamp_is_fresh() {
+    use chrono::{Duration, TimeZone, Utc};
+
+    let header = KeyBlockHeader::new_from_str("D0000P0AE00E0200TS1320240101120000ZPB0600").unwrap();
+    let now = Utc.with_ymd_and_hms(2024, 1, 1, 18, 0, 0).unwrap();
+
+    // Fresh: 6 hours old
+    assert!(header.timestamp_is_fresh(Duration::hours(6), now).unwrap());
+    assert!(header.timestamp_is_fresh(Duration::days(1), now).unwrap());
+    // Stale: older than 5 hours
+    assert!(!header.timestamp_is_fresh(Duration::hours(5), now).unwrap());
+
+    // Fractional seconds
+    let header =
+        KeyBlockHeader::new_from_str("D0000P0AE00E0200TS1620240101120000.25ZPB0C00000000").unwrap();
+    assert!(header.timestamp_is_fresh(Duration::hours(6), now).unwrap());
+}
+
+#[cfg(feature = "chrono")]
+#[test]
+fn test_timestamp_is_fresh_errors() {
+    use chrono::{Duration, TimeZone, Utc};
+
+    let now = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+
+    let header = KeyBlockHeader::new_from_str("D0000P0AE00E0000").unwrap();
+    assert_eq!(
+        header
+            .timestamp_is_fresh(Duration::days(1), now)
+            .unwrap_err()
+            .to_string(),
+        "PSEC-0000: ERROR TR-31 HEADER: No TS optional block present"
+    );
+
+    let header = KeyBlockHeader::new_from_str("D0000P0AE00E0200TS1320241301120000ZPB0600").unwrap();
+    assert_eq!(
+        header
+            .timestamp_is_fresh(Duration::days(1), now)
+            .unwrap_err()
+            .to_string(),
+        "PSEC-0000: ERROR TR-31 HEADER: Invalid TS timestamp: 20241301120000Z"
+    );
+}
@@ -1,4 +1,9 @@
-use super::super::key_derivations::derive_keys_version_d;
+use super::super::key_derivations::{
+    derive_keys_version_b, derive_keys_version_c, derive_keys_version_d,
+    derive_keys_version_d_with_params, TdesKeyVariant,
+};
+use crate::crypto::aes::aes_cmac;
+use crate::crypto::tdes::tdes_cmac;
 use hex::decode as hex_decode;
 
 #[test]
@@ -16,6 +21,26 @@ fn test_derive_keys_version_d_aes_128() {
     );
 }
 
+#[test]
+fn test_derive_keys_version_d_aes_128_single_cmac() {
+    // Same KBPK as the AES-128 vector above. Each key must be exactly one CMAC block of the
+    // derivation data for counter 1, not a concatenation as for AES-192 and AES-256.
+    let kbpk = hex_decode("00112233445566778899AABBCCDDEEFF").unwrap();
+    let (kbek, kbak) = derive_keys_version_d(&kbpk).unwrap();
+
+    let kdi_kbek = hex_decode("0100000000020080").unwrap();
+    let kdi_kbak = hex_decode("0100010000020080").unwrap();
+
+    assert_eq!(kbek.len(), 16);
+    assert_eq!(kbak.len(), 16);
+    assert_eq!(kbek, aes_cmac(&kdi_kbek, &kbpk).unwrap());
+    assert_eq!(kbak, aes_cmac(&kdi_kbak, &kbpk).unwrap());
+    assert_eq!(
+        kbek,
+        hex_decode("37DC7700D70781C3E2498A41A027E0B1").unwrap()
+    );
+}
+
 #[test]
 fn test_derive_keys_version_d_aes_192() {
     let kbpk = hex_decode("00112233445566778899AABBCCDDEEFF0011223344556677").unwrap();
@@ -62,3 +87,133 @@ fn test_derive_keys_version_d_a7422() {
         hex_decode("4EF24317696213840451890756757E573E0673483888F9B7F9B7517827F95022").unwrap()
     );
 }
+
+#[test]
+fn test_derive_keys_version_d_with_params_matching_lengths() {
+    for kbpk_hex in [
+        "00112233445566778899AABBCCDDEEFF",
+        "00112233445566778899AABBCCDDEEFF0011223344556677",
+        "00112233445566778899AABBCCDDEEFF00112233445566778899AABBCCDDEEFF",
+    ] {
+        let kbpk = hex_decode(kbpk_hex).unwrap();
+        assert_eq!(
+            derive_keys_version_d_with_params(&kbpk, kbpk.len()).unwrap(),
+            derive_keys_version_d(&kbpk).unwrap()
+        );
+    }
+}
+
+#[test]
+fn test_derive_keys_version_d_with_params_length_override() {
+    let kbpk_128 = hex_decode("00112233445566778899AABBCCDDEEFF").unwrap();
+    let (kbek, kbak) = derive_keys_version_d_with_params(&kbpk_128, 32).unwrap();
+    assert_eq!(kbek.len(), 32);
+    assert_eq!(kbak.len(), 32);
+
+    // The derivation input data of AES-256 under the AES-128 KBPK
+    let mut expected_kbek = aes_cmac(&hex_decode("0100000000040100").unwrap(), &kbpk_128)
+        .unwrap()
+        .to_vec();
+    expected_kbek
+        .extend_from_slice(&aes_cmac(&hex_decode("0200000000040100").unwrap(), &kbpk_128).unwrap());
+    assert_eq!(kbek, expected_kbek);
+
+    assert_eq!(
+        derive_keys_version_d_with_params(&kbpk_128, 20)
+            .unwrap_err()
+            .to_string(),
+        "PSEC-0000: ERROR TR-31: Invalid derived key length: 20 (expected 16, 24 or 32 bytes)"
+    );
+    assert!(derive_keys_version_d_with_params(&kbpk_128[..15], 16).is_err());
+}
+
+#[test]
+fn test_derive_keys_version_b_tdes_2key() {
+    let kbpk = hex_decode("89E88CF7931444F334BD7547FC3F380C").unwrap();
+    let (kbek, kbak, variant) = derive_keys_version_b(&kbpk).unwrap();
+
+    assert_eq!(variant, TdesKeyVariant::TwoKey);
+    assert_eq!(
+        kbek,
+        hex_decode("12802065300D49CAF1B22A561CBADD78").unwrap()
+    );
+    assert_eq!(
+        kbak,
+        hex_decode("EEB74C38D8E36CFD4DF269B857937CF6").unwrap()
+    );
+}
+
+#[test]
+fn test_derive_keys_version_b_tdes_2key_cmac_blocks() {
+    // Each key is the concatenation of the TDEA-CMAC blocks of the derivation data for counters 1
+    // and 2: counter, key usage (0000 KBEK, 0001 KBAK), separator, algorithm 0000 (2-key TDEA)
+    // and length 0080.
+    let kbpk = hex_decode("89E88CF7931444F334BD7547FC3F380C").unwrap();
+    let (kbek, kbak, _) = derive_keys_version_b(&kbpk).unwrap();
+
+    let cmac_blocks = |usage: &str| -> Vec<u8> {
+        ["01", "02"]
+            .iter()
+            .flat_map(|counter| {
+                let kdi = hex_decode(format!("{}{}0000000080", counter, usage)).unwrap();
+                tdes_cmac(&kdi, &kbpk).unwrap()
+            })
+            .collect()
+    };
+    assert_eq!(kbek, cmac_blocks("0000"));
+    assert_eq!(kbak, cmac_blocks("0001"));
+}
+
+#[test]
+fn test_derive_keys_version_b_tdes_3key() {
+    let kbpk = hex_decode("B0B1B2B3B4B5B6B7B8B9BABBBCBDBEBFC0C1C2C3C4C5C6C7").unwrap();
+    let (kbek, kbak, variant) = derive_keys_version_b(&kbpk).unwrap();
+
+    assert_eq!(variant, TdesKeyVariant::ThreeKey);
+    assert_eq!(
+        kbek,
+        hex_decode("61F62FD4C6499D279C347CB46EF8E769D3A279D56AD9B6F7").unwrap()
+    );
+    assert_eq!(
+        kbak,
+        hex_decode("FB7A6CC93522E7A646CD96DA0EE7C7EF2F3C576394221DDF").unwrap()
+    );
+}
+
+#[test]
+fn test_derive_keys_version_b_invalid_kbpk_length() {
+    let kbpk =
+        hex_decode("00112233445566778899AABBCCDDEEFF00112233445566778899AABBCCDDEEFF").unwrap();
+    let res = derive_keys_version_b(&kbpk);
+
+    assert_eq!(
+        res.err().unwrap().to_string(),
+        "PSEC-0000: ERROR TR-31: Invalid TDES KBPK length: 32 (expected 16 or 24 bytes)"
+    );
+}
+
+#[test]
+fn test_derive_keys_version_c_tdes_2key() {
+    let kbpk = hex_decode("89E88CF7931444F334BD7547FC3F380C").unwrap();
+    let (kbek, kbak) = derive_keys_version_c(&kbpk).unwrap();
+
+    assert_eq!(
+        kbek,
+        hex_decode("CCADC9B2D65101B671F83002B97A7D49").unwrap()
+    );
+    assert_eq!(
+        kbak,
+        hex_decode("C4A5C1BADE5909BE79F0380AB1727541").unwrap()
+    );
+}
+
+#[test]
+fn test_derive_keys_version_c_invalid_kbpk_length() {
+    let kbpk = hex_decode("00112233445566778899AABBCCDDEE").unwrap();
+    let res = derive_keys_version_c(&kbpk);
+
+    assert_eq!(
+        res.err().unwrap().to_string(),
+        "PSEC-0000: ERROR TR-31: Invalid TDES KBPK length: 15 (expected 16 or 24 bytes)"
+    );
+}
@@ -40,7 +40,10 @@ fn test_new_with_values_invalid_id() {
     let error = opt_block.err().unwrap();
 
     // Optionally check the error message
-    assert_eq!(error.to_string(), "ERROR TR-31 OPT BLOCK: Invalid ID: xx");
+    assert_eq!(
+        error.to_string(),
+        "TR31-0006: ERROR TR-31 OPT BLOCK: Invalid ID: xx"
+    );
 }
 
 #[test]
@@ -52,7 +55,7 @@ fn test_new_with_values_invalid_data() {
     // Optionally check the error message
     assert_eq!(
         error.to_string(),
-        "ERROR TR-31 OPT BLOCK: Data has non ASCII characters: ÿÿÿÿ"
+        "PSEC-0000: ERROR TR-31 OPT BLOCK: Data has non ASCII characters: ÿÿÿÿ"
     );
 }
 
@@ -76,6 +79,24 @@ fn test_new_from_string_two_optional_blocks() {
     assert_eq!(result, expected_block1);
 }
 
+#[test]
+fn test_new_from_string_optional_block_without_data() {
+    let s = "KS04";
+    let expected_opt_block = OptBlock::new("KS", "", None).unwrap();
+    let result = OptBlock::new_from_str(s, 1).unwrap();
+    assert_eq!(result, expected_opt_block);
+    assert_eq!(result.data(), "");
+    assert_eq!(*result.length(), 4);
+    assert_eq!(result.export_str().unwrap(), s);
+
+    // A block without data followed by another block
+    let s = "KS04KV080100";
+    let result = OptBlock::new_from_str(s, 2).unwrap();
+    assert_eq!(result.data(), "");
+    assert_eq!(result.next().unwrap().data(), "0100");
+    assert_eq!(result.export_str().unwrap(), s);
+}
+
 #[test]
 fn test_new_from_string_extended_optional_block() {
     let mut s = "CT00020100".to_owned();
@@ -87,6 +108,27 @@ fn test_new_from_string_extended_optional_block() {
     assert_eq!(result, expected_opt_block);
 }
 
+#[test]
+fn test_new_extended() {
+    let data = "F".repeat(300);
+    let opt_block = OptBlock::new_extended("CT", &data).unwrap();
+    assert_eq!(*opt_block.length(), 310);
+    assert_eq!(
+        opt_block.export_str().unwrap(),
+        format!("CT00020136{}", data)
+    );
+    assert_eq!(opt_block, OptBlock::new("CT", &data, None).unwrap());
+
+    // The shortest data with an extended length field
+    assert!(OptBlock::new_extended("CT", &"F".repeat(252)).is_ok());
+    assert_eq!(
+        OptBlock::new_extended("CT", &"F".repeat(251))
+            .unwrap_err()
+            .to_string(),
+        "PSEC-0000: ERROR TR-31 OPT BLOCK: Data length '251' does not require an extended length field (must be min. 252)"
+    );
+}
+
 #[test]
 fn test_new_from_string_invalid_empty_string() {
     let s = "";
@@ -96,7 +138,7 @@ fn test_new_from_string_invalid_empty_string() {
     let error = result.err().unwrap();
     assert_eq!(
         error.to_string(),
-        "ERROR TR-31 OPT BLOCK: String too short. Expected at least 4 characters"
+        "TR31-0007: ERROR TR-31 OPT BLOCK: String too short. Expected at least 4 characters"
     );
 }
 
@@ -108,7 +150,7 @@ fn test_new_from_string_invalid_length() {
     assert!(result.is_err());
     assert_eq!(
         result.unwrap_err().to_string(),
-        "ERROR TR-31 OPT BLOCK: String too short. Expected at least 4 characters"
+        "TR31-0007: ERROR TR-31 OPT BLOCK: String too short. Expected at least 4 characters"
     );
 }
 
@@ -120,7 +162,7 @@ fn test_new_from_string_invalid_id() {
     assert!(result.is_err());
     assert_eq!(
         result.unwrap_err().to_string(),
-        "ERROR TR-31 OPT BLOCK: Invalid ID: xx"
+        "TR31-0006: ERROR TR-31 OPT BLOCK: Invalid ID: xx"
     );
 }
 
@@ -130,7 +172,7 @@ fn test_new_from_string_invalid_extended_length() {
     let num_opt_blocks = 1;
     let result = OptBlock::new_from_str(s, num_opt_blocks);
     assert!(result.is_err());
-    assert_eq!(result.unwrap_err().to_string(), "ERROR TR-31 OPT BLOCK: String containing extended length too short. Expected at least 256 characters");
+    assert_eq!(result.unwrap_err().to_string(), "TR31-0007: ERROR TR-31 OPT BLOCK: String containing extended length too short. Expected at least 256 characters");
 }
 
 #[test]
@@ -141,7 +183,7 @@ fn test_new_from_string_invalid_length_field() {
     assert!(result.is_err());
     assert_eq!(
         result.unwrap_err().to_string(),
-        "ERROR TR-31 OPT BLOCK: Invalid length field: 'xx' is not a valid hexadecimal number"
+        "TR31-0007: ERROR TR-31 OPT BLOCK: Invalid length field: 'xx' is not a valid hexadecimal number"
     );
 }
 
@@ -155,7 +197,7 @@ fn test_new_from_string_invalid_length_of_length() {
     assert!(result.is_err());
     assert_eq!(
         result.unwrap_err().to_string(),
-        "ERROR TR-31 OPT BLOCK: Invalid length of length field: 01"
+        "TR31-0007: ERROR TR-31 OPT BLOCK: Invalid length of length field: 01"
     );
 }
 
@@ -169,7 +211,7 @@ fn test_new_from_string_invalid_extended_length_field() {
     assert!(result.is_err());
     assert_eq!(
         result.unwrap_err().to_string(),
-        "ERROR TR-31 OPT BLOCK: Extended length is not greater than 255: 0000"
+        "TR31-0007: ERROR TR-31 OPT BLOCK: Extended length is not greater than 255: 0000"
     );
 }
 
@@ -181,7 +223,7 @@ fn test_new_from_string_invalid_data_length() {
     assert!(result.is_err());
     assert_eq!(
         result.unwrap_err().to_string(),
-        "ERROR TR-31 OPT BLOCK: String too short for given length. Expected at least 8 characters."
+        "TR31-0007: ERROR TR-31 OPT BLOCK: String too short for given length. Expected at least 8 characters."
     );
 }
 
@@ -195,7 +237,7 @@ fn test_new_from_string_invalid_extended_length_too_short() {
     assert!(result.is_err());
     assert_eq!(
         result.unwrap_err().to_string(),
-        "ERROR TR-31 OPT BLOCK: Extended length is not greater than 255: 00A0"
+        "TR31-0007: ERROR TR-31 OPT BLOCK: Extended length is not greater than 255: 00A0"
     );
 }
 
@@ -209,7 +251,43 @@ fn test_new_from_string_invalid_extended_length_in_second_block() {
     assert!(result.is_err());
     assert_eq!(
         result.unwrap_err().to_string(),
-        "ERROR TR-31 OPT BLOCK: Invalid length field: value 1 is too small (must be at least 4)"
+        "TR31-0007: ERROR TR-31 OPT BLOCK: Invalid length field: value 1 is too small (must be at least 4)"
+    );
+}
+
+#[test]
+fn test_new_ik_from_ksn() {
+    let bdk_id = hex::decode("12345678").unwrap();
+    let ksn = hex::decode("1234567890123456000000FF").unwrap();
+
+    let ik_block = OptBlock::new_ik_from_ksn(&bdk_id, &ksn).unwrap();
+    assert_eq!(ik_block.id(), "IK");
+    assert_eq!(ik_block.data(), "1234567890123456");
+    assert_eq!(ik_block.export_str().unwrap(), "IK141234567890123456");
+}
+
+#[test]
+fn test_new_ik_from_ksn_invalid() {
+    let bdk_id = hex::decode("12345678").unwrap();
+    let ksn = hex::decode("1234567890123456000000FF").unwrap();
+
+    assert_eq!(
+        OptBlock::new_ik_from_ksn(&bdk_id, &ksn[..10])
+            .unwrap_err()
+            .to_string(),
+        "PSEC-0000: ERROR TR-31 OPT BLOCK: Invalid KSN length: 10 (expected 12 bytes)"
+    );
+    assert_eq!(
+        OptBlock::new_ik_from_ksn(&bdk_id[..3], &ksn)
+            .unwrap_err()
+            .to_string(),
+        "PSEC-0000: ERROR TR-31 OPT BLOCK: Invalid BDK ID length: 3 (expected 4 bytes)"
+    );
+    assert_eq!(
+        OptBlock::new_ik_from_ksn(&hex::decode("87654321").unwrap(), &ksn)
+            .unwrap_err()
+            .to_string(),
+        "PSEC-0000: ERROR TR-31 OPT BLOCK: KSN does not belong to the BDK ID"
     );
 }
 
@@ -240,7 +318,7 @@ fn test_export_str_invalid_length() {
     assert!(result.is_err());
     assert_eq!(
         result.unwrap_err().to_string(),
-        "ERROR TR-31 OPT BLOCK: Length must be greater than 4, indicating uninitialized OptBlock"
+        "PSEC-0000: ERROR TR-31 OPT BLOCK: Length must be greater than 4, indicating uninitialized OptBlock"
     );
 }
 
@@ -261,7 +339,7 @@ fn test_set_data_invalid_id_not_set() {
     assert!(result.is_err());
     assert_eq!(
         result.unwrap_err().to_string(),
-        "ERROR TR-31 OPT BLOCK: ID not set (has to be set before data)"
+        "PSEC-0000: ERROR TR-31 OPT BLOCK: ID not set (has to be set before data)"
     );
 }
 
@@ -276,3 +354,41 @@ fn test_append() {
 
     assert_eq!(block1.export_str().unwrap(), "CT0611IK0622PB06FF");
 }
+
+#[test]
+fn test_from_iterator_chain() {
+    let blocks = vec![
+        OptBlock::new("KS", "00604B", None).unwrap(),
+        OptBlock::new("TS", "20240101120000Z", None).unwrap(),
+        OptBlock::new("PB", "0000", None).unwrap(),
+    ];
+
+    let chain: Option<Box<OptBlock>> = blocks.into_iter().collect();
+    let first = chain.unwrap();
+    assert_eq!(first.id(), "KS");
+    let second = first.next().unwrap();
+    assert_eq!(second.id(), "TS");
+    let third = second.next().unwrap();
+    assert_eq!(third.id(), "PB");
+    assert!(third.next().is_none());
+    assert_eq!(
+        first.export_str().unwrap(),
+        "KS0A00604BTS1320240101120000ZPB080000"
+    );
+
+    let empty: Option<Box<OptBlock>> = Vec::<OptBlock>::new().into_iter().collect();
+    assert!(empty.is_none());
+}
+
+#[test]
+fn test_from_iterator_set_opt_blocks() {
+    let mut header = KeyBlockHeader::new_with_values("D", "P0", "A", "E", "00", "E").unwrap();
+    header.set_opt_blocks(
+        ["KS", "KV"]
+            .iter()
+            .map(|id| OptBlock::new(id, "0001", None).unwrap())
+            .collect(),
+    );
+    header.finalize().unwrap();
+    assert_eq!(header.num_optional_blocks(), 2);
+}
@@ -57,3 +57,55 @@ fn test_extract_key_from_payload_a7421() {
     let extracted_key = extract_key_from_payload(&payload).unwrap();
     assert_eq!(extracted_key, expected_key);
 }
+
+#[test]
+fn test_construct_payload_key_too_large() {
+    let random_seed: Vec<u8> = (0..32).collect();
+
+    let result = construct_payload(&vec![0x11u8; 8192], 0, 16, &random_seed);
+    assert_eq!(
+        result.unwrap_err().to_string(),
+        "PSEC-0000: ERROR TR-31 PAYLOAD: Key too large to encode length in bits"
+    );
+
+    // The largest key whose length in bits fits into 16 bits
+    let payload = construct_payload(&vec![0x11u8; 8191], 0, 16, &random_seed).unwrap();
+    assert_eq!(payload[..2], [0xFF, 0xF8]);
+}
+
+#[test]
+fn test_extract_padding_a7421() {
+    let payload =
+        hex::decode("00803F419E1CB7079442AA37474C2EFBF8B81C2965473CE206BB855B01533782").unwrap();
+
+    let padding = extract_padding(&payload).unwrap();
+    assert_eq!(hex::encode_upper(padding), "1C2965473CE206BB855B01533782");
+
+    assert!(extract_padding(&payload[..10]).is_err());
+    assert!(extract_padding(&payload[..18]).unwrap().is_empty());
+}
+
+#[cfg(feature = "strict-seed")]
+#[test]
+fn test_construct_payload_strict_seed() {
+    let key = hex::decode("3F419E1CB7079442AA37474C2EFBF8B8").unwrap();
+
+    assert!(construct_payload(&key, 0, 16, &[0u8; 14]).is_err());
+    assert!(construct_payload(&key, 0, 16, &[0xFF; 14]).is_err());
+
+    let random_seed = hex::decode("1C2965473CE206BB855B01533782").unwrap();
+    assert!(construct_payload(&key, 0, 16, &random_seed).is_ok());
+}
+
+#[test]
+fn test_calculate_padding_length_with_min() {
+    // An 8-byte key fits into a single AES block by default
+    assert_eq!(calculate_padding_length(8, 0, 16).unwrap(), 6);
+    assert_eq!(calculate_padding_length_with_min(8, 0, 16, 1).unwrap(), 6);
+
+    // Forcing a minimum of two blocks
+    assert_eq!(calculate_padding_length_with_min(8, 0, 16, 2).unwrap(), 22);
+
+    // The minimum does not shrink a payload which is already larger
+    assert_eq!(calculate_padding_length_with_min(32, 0, 16, 2).unwrap(), 14);
+}
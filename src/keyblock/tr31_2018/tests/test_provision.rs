@@ -0,0 +1,100 @@
+use super::super::provision::{tr31_provision, tr31_regions, KeyBlockRegion};
+use super::super::tr31::tr31_wrap;
+use super::super::{KeyBlockHeader, OptBlock};
+use crate::crypto::aes::aes_cmac;
+use crate::crypto::tdes::tdes_enc_block;
+
+// Assert that the regions are contiguous and cover the whole key block.
+fn assert_regions_cover(key_block: &str, regions: &[KeyBlockRegion]) {
+    let mut offset = 0;
+    for region in regions {
+        assert_eq!(region.offset, offset, "gap before region {}", region.label);
+        assert!(region.length > 0, "empty region {}", region.label);
+        offset += region.length;
+    }
+    assert_eq!(offset, key_block.len());
+}
+
+#[test]
+fn test_tr31_provision_version_d() {
+    let mut header = KeyBlockHeader::new_with_values("D", "P0", "A", "E", "00", "E").unwrap();
+    header.set_opt_blocks(Some(Box::new(
+        OptBlock::new("KS", "00604B120F9292800000", None).unwrap(),
+    )));
+    header.finalize().unwrap();
+    let key = hex::decode("3F419E1CB7079442AA37474C2EFBF8B8").unwrap();
+    let random_seed = hex::decode("1C2965473CE206BB855B01533782").unwrap();
+    let kbpk =
+        hex::decode("88E1AB2A2E3DD38C1FA039A536500CC8A87AB9D62DC92C01058FA79F44657DE6").unwrap();
+
+    let report = tr31_provision(&kbpk, header.clone(), &key, 0, &random_seed).unwrap();
+
+    assert_eq!(
+        report.key_block,
+        tr31_wrap(&kbpk, header, &key, 0, &random_seed).unwrap()
+    );
+    let kcv = aes_cmac(&[0u8; 16], &key).unwrap();
+    assert_eq!(report.key_kcv, hex::encode_upper(&kcv[..5]));
+    let kcv = aes_cmac(&[0u8; 16], &kbpk).unwrap();
+    assert_eq!(report.kbpk_kcv, hex::encode_upper(&kcv[..5]));
+
+    assert_regions_cover(&report.key_block, &report.regions);
+    let labels: Vec<_> = report.regions.iter().map(|r| r.label.as_str()).collect();
+    assert_eq!(
+        labels,
+        [
+            "version ID",
+            "key block length",
+            "key usage",
+            "algorithm",
+            "mode of use",
+            "key version number",
+            "exportability",
+            "number of optional blocks",
+            "reserved field",
+            "optional block KS",
+            "optional block PB",
+            "encrypted payload",
+            "MAC",
+        ]
+    );
+    let ks = &report.regions[9];
+    assert_eq!(
+        &report.key_block[ks.offset..ks.offset + ks.length],
+        "KS1800604B120F9292800000"
+    );
+}
+
+#[test]
+fn test_tr31_provision_version_b() {
+    let header = KeyBlockHeader::new_with_values("B", "D0", "T", "B", "00", "N").unwrap();
+    let key = hex::decode("89E88CF7931444F334BD7547FC3F380C").unwrap();
+    let random_seed = hex::decode("720DF563D7C1").unwrap();
+    let kbpk = hex::decode("DD7515F2BFC17F85CE48F3CA25CB21F6").unwrap();
+
+    let report = tr31_provision(&kbpk, header, &key, 0, &random_seed).unwrap();
+
+    let kcv = tdes_enc_block(&[0u8; 8], &key).unwrap();
+    assert_eq!(report.key_kcv, hex::encode_upper(&kcv[..3]));
+    let kcv = tdes_enc_block(&[0u8; 8], &kbpk).unwrap();
+    assert_eq!(report.kbpk_kcv, hex::encode_upper(&kcv[..3]));
+
+    assert_regions_cover(&report.key_block, &report.regions);
+    let mac = report.regions.last().unwrap();
+    assert_eq!((mac.label.as_str(), mac.length), ("MAC", 16));
+}
+
+#[test]
+fn test_tr31_provision_unsupported_algorithm() {
+    let header = KeyBlockHeader::new_with_values("D", "P0", "H", "E", "00", "E").unwrap();
+    let key = [0u8; 16];
+    let kbpk = [0u8; 32];
+
+    assert!(tr31_provision(&kbpk, header, &key, 0, &[0u8; 16]).is_err());
+}
+
+#[test]
+fn test_tr31_regions_invalid_key_block() {
+    assert!(tr31_regions("D0112P0AE00E0000").is_err());
+    assert!(tr31_regions("D0112P0AE00").is_err());
+}
@@ -0,0 +1,150 @@
+use super::super::payload::construct_payload;
+use super::super::scheme::{KeyBlockScheme, VersionBScheme, VersionCScheme, VersionDScheme};
+use crate::error::PaysecError;
+
+#[test]
+fn test_version_d_scheme_example_a_7_4() {
+    // Test vectors from TR-31: 2018, A.7.4. Example 3
+    let kbpk =
+        hex::decode("88E1AB2A2E3DD38C1FA039A536500CC8A87AB9D62DC92C01058FA79F44657DE6").unwrap();
+    let key = hex::decode("3F419E1CB7079442AA37474C2EFBF8B8").unwrap();
+    let random_seed = hex::decode("1C2965473CE206BB855B01533782").unwrap();
+    let header = "D0112P0AE00E0000";
+
+    let scheme = VersionDScheme::derive(&kbpk).unwrap();
+    let payload = construct_payload(&key, 16, VersionDScheme::BLOCK_LEN, &random_seed).unwrap();
+
+    let mac = scheme.mac(header, &payload).unwrap();
+    let encrypted_payload = scheme.encrypt(&payload, &mac).unwrap();
+
+    let key_block = format!(
+        "{}{}{}",
+        header,
+        hex::encode_upper(&encrypted_payload),
+        hex::encode_upper(&mac)
+    );
+    assert_eq!(key_block, "D0112P0AE00E0000B82679114F470F540165EDFBF7E250FCEA43F810D215F8D207E2E417C07156A27E8E31DA05F7425509593D03A457DC34");
+
+    // The MAC is the IV of the payload encryption
+    assert_eq!(scheme.decrypt(&encrypted_payload, &mac).unwrap(), payload);
+    assert_eq!(
+        scheme.open(header, &encrypted_payload, &mac).unwrap(),
+        payload
+    );
+
+    // A wrong KBPK fails the MAC check of the decrypted payload
+    let mut wrong_kbpk = kbpk.clone();
+    wrong_kbpk[0] ^= 0x01;
+    let wrong_scheme = VersionDScheme::derive(&wrong_kbpk).unwrap();
+    assert_eq!(
+        wrong_scheme
+            .open(header, &encrypted_payload, &mac)
+            .unwrap_err(),
+        PaysecError::MacCheckFailed
+    );
+}
+
+#[test]
+fn test_version_d_scheme_invalid_mac_length() {
+    let kbpk = hex::decode("00112233445566778899AABBCCDDEEFF").unwrap();
+    let scheme = VersionDScheme::derive(&kbpk).unwrap();
+
+    assert!(scheme.encrypt(&[0u8; 16], &[0u8; 8]).is_err());
+    assert!(VersionDScheme::derive(&kbpk[..15]).is_err());
+}
+
+#[test]
+fn test_version_d_scheme_debug_redacts_keys() {
+    let kbpk = hex::decode("00112233445566778899AABBCCDDEEFF").unwrap();
+    let scheme = VersionDScheme::derive(&kbpk).unwrap();
+
+    let debug = format!("{:?}", scheme);
+    assert_eq!(
+        debug,
+        "VersionDScheme { kbek: \"<redacted>\", kbek_len: 16, kbak: \"<redacted>\", kbak_len: 16 }"
+    );
+}
+
+#[test]
+fn test_version_b_scheme() {
+    // KBPK of TR-31: 2018, A.7.2
+    let kbpk = hex::decode("89E88CF7931444F334BD7547FC3F380C").unwrap();
+    let scheme = VersionBScheme::derive(&kbpk).unwrap();
+    let payload = [0x5Au8; 24];
+
+    let mac = scheme.mac("B0080P0TE00E0000", &payload).unwrap();
+    assert_eq!(mac.len(), VersionBScheme::MAC_LEN);
+
+    let encrypted_payload = scheme.encrypt(&payload, &mac).unwrap();
+    assert_eq!(encrypted_payload.len(), payload.len());
+    assert_eq!(scheme.decrypt(&encrypted_payload, &mac).unwrap(), payload);
+
+    assert!(scheme.encrypt(&payload, &[0u8; 16]).is_err());
+    assert!(VersionBScheme::derive(&[0u8; 32]).is_err());
+
+    // The payload decrypted before the failed MAC check is dropped as a `SecretKey`
+    let (encrypted_payload, mac) = scheme.seal("B0080P0TE00E0000", &payload).unwrap();
+    assert_eq!(
+        scheme
+            .open("B0080P0TE00N0000", &encrypted_payload, &mac)
+            .unwrap_err(),
+        PaysecError::MacCheckFailed
+    );
+    assert_eq!(
+        format!("{:?}", scheme),
+        "VersionBScheme { kbek: \"<redacted>\", kbek_len: 16, kbak: \"<redacted>\", kbak_len: 16 }"
+    );
+}
+
+#[test]
+fn test_version_c_scheme_example_a_7_3_1() {
+    // Test vectors from TR-31: 2018, A.7.3.1
+    let kbpk = hex::decode("B8ED59E0A279A295E9F5ED7944FD06B9").unwrap();
+    let scheme = VersionCScheme::derive(&kbpk).unwrap();
+    let header = "C0096B0TX12S0100KS1800604B120F9292800000";
+    let payload = hex::decode("0080EDB380DD340BC2620247D445F5B8D6788546A8ED98D1").unwrap();
+    let encrypted_payload =
+        hex::decode("BFB9B689CB567E66FC3FEE5AD5F52161FC6545B9D6098901").unwrap();
+    let mac = hex::decode("5D02155C").unwrap();
+
+    assert_eq!(
+        scheme.seal(header, &payload).unwrap(),
+        (encrypted_payload.clone(), mac.clone())
+    );
+    assert_eq!(
+        scheme.open(header, &encrypted_payload, &mac).unwrap(),
+        payload
+    );
+}
+
+#[test]
+fn test_version_c_scheme() {
+    // KBPK of TR-31: 2018, A.7.2
+    let kbpk = hex::decode("89E88CF7931444F334BD7547FC3F380C").unwrap();
+    let scheme = VersionCScheme::derive(&kbpk).unwrap();
+    let header = "C0072P0TE00E0000";
+    let payload = [0x5Au8; 24];
+
+    let (encrypted_payload, mac) = scheme.seal(header, &payload).unwrap();
+    assert_eq!(encrypted_payload.len(), payload.len());
+    assert_eq!(mac.len(), VersionCScheme::MAC_LEN);
+    assert_eq!(
+        scheme.open(header, &encrypted_payload, &mac).unwrap(),
+        payload[..]
+    );
+
+    // The MAC binds the header to the encrypted payload
+    assert_eq!(
+        scheme
+            .open("C0072P0TE00N0000", &encrypted_payload, &mac)
+            .unwrap_err()
+            .to_string(),
+        "TR31-0003: ERROR TR-31: MAC check failed"
+    );
+
+    assert!(VersionCScheme::derive(&[0u8; 32]).is_err());
+    assert_eq!(
+        format!("{:?}", scheme),
+        "VersionCScheme { kbek: \"<redacted>\", kbek_len: 16, kbak: \"<redacted>\", kbak_len: 16 }"
+    );
+}
@@ -0,0 +1,56 @@
+use super::super::SecretKey;
+
+#[test]
+fn test_secret_key_deref() {
+    let bytes = hex::decode("3F419E1CB7079442AA37474C2EFBF8B8").unwrap();
+    let key = SecretKey::new(bytes.clone());
+
+    assert_eq!(&*key, &bytes[..]);
+    assert_eq!(key.as_bytes(), &bytes[..]);
+    assert_eq!(key.len(), 16);
+    assert_eq!(key, bytes);
+    assert_eq!(key.clone().into_vec(), bytes);
+}
+
+#[test]
+fn test_secret_key_debug_redacted() {
+    let key = SecretKey::from(vec![0x3F; 24]);
+    assert_eq!(
+        format!("{:?}", key),
+        "SecretKey { key: \"<redacted>\", key_len: 24 }"
+    );
+}
+
+#[cfg(not(feature = "no-clear-secrets"))]
+#[test]
+fn test_tr31_unwrap_secret_key() {
+    use super::super::tr31_unwrap;
+
+    let key_block = "D0112P0AE00E0000B82679114F470F540165EDFBF7E250FCEA43F810D215F8D207E2E417C07156A27E8E31DA05F7425509593D03A457DC34";
+    let kbpk =
+        hex::decode("88E1AB2A2E3DD38C1FA039A536500CC8A87AB9D62DC92C01058FA79F44657DE6").unwrap();
+
+    let (_, key) = tr31_unwrap(&kbpk, key_block).unwrap();
+    assert_eq!(
+        &*key,
+        &hex::decode("3F419E1CB7079442AA37474C2EFBF8B8").unwrap()[..]
+    );
+}
+
+#[cfg(feature = "zeroize")]
+#[test]
+fn test_secret_key_zeroize() {
+    use zeroize::Zeroize;
+
+    fn assert_zeroize_on_drop<T: zeroize::ZeroizeOnDrop>(_: &T) {}
+
+    let mut key = SecretKey::new(vec![0x3F; 16]);
+    assert_zeroize_on_drop(&key);
+
+    key.zeroize();
+    assert!(key.is_empty());
+    assert_eq!(
+        format!("{:?}", key),
+        "SecretKey { key: \"<redacted>\", key_len: 0 }"
+    );
+}
@@ -1,6 +1,9 @@
 use super::super::tr31::*;
 use super::super::KeyBlockHeader;
 use super::super::OptBlock;
+#[cfg(not(feature = "no-clear-secrets"))]
+use crate::validation::TdesParityCheck;
+use crate::validation::ValidationConfig;
 
 #[test]
 pub fn test_tr31_wrap_example_a_7_4() {
@@ -205,10 +208,304 @@ fn test_tr31_wrap_error_key_block_length_not_multiple_of_block_size() {
 
     assert!(matches!(
         result,
-        Err(e) if e.to_string() == "ERROR TR-31: Total block length is not a multiple of block length: 16"
+        Err(e) if e.to_string() == "PSEC-0000: ERROR TR-31: Header with optional blocks must be finalized before wrapping (call finalize())"
     ));
 }
 
+#[test]
+fn test_tr31_wrap_error_header_not_finalized() {
+    let mut header = KeyBlockHeader::new_with_values("D", "P0", "T", "E", "00", "N").unwrap();
+    let opt_block = OptBlock::new("KS", "00604B120F9292800000", None).unwrap();
+    header.set_opt_blocks(Some(Box::new(opt_block)));
+    let key = hex::decode("FFEEDDCCBBAA99887766554433221100").unwrap();
+    let random_seed = hex::decode("7338958D82B9F482E421E8BFD77E").unwrap();
+    let kbpk =
+        hex::decode("00112233445566778899AABBCCDDEEFF00112233445566778899AABBCCDDEEFF").unwrap();
+
+    let result = tr31_wrap(&kbpk, header, &key, 0, &random_seed);
+
+    assert!(matches!(
+        result,
+        Err(e) if e.to_string() == "PSEC-0000: ERROR TR-31: Header with optional blocks must be finalized before wrapping (call finalize())"
+    ));
+}
+
+#[test]
+fn test_tr31_wrap_error_kbpk_length_not_aes() {
+    let header = KeyBlockHeader::new_from_str("D0000P0AE00E0000").unwrap();
+    let key = hex::decode("3F419E1CB7079442AA37474C2EFBF8B8").unwrap();
+    let masked_key_length = 0;
+    let random_seed = hex::decode("1C2965473CE206BB855B01533782").unwrap();
+    let kbpk = hex::decode("00112233445566778899AABBCCDDEEFF00").unwrap();
+
+    let result = tr31_wrap(&kbpk, header, &key, masked_key_length, &random_seed);
+
+    assert!(matches!(
+        result,
+        Err(e) if e.to_string() == "PSEC-0000: ERROR TR-31: KBPK length 17 is not a valid AES key length (16, 24 or 32 bytes) for key block version D"
+    ));
+}
+
+#[test]
+fn test_tr31_wrap_error_total_length_exceeds_maximum() {
+    let kbpk =
+        hex::decode("88E1AB2A2E3DD38C1FA039A536500CC8A87AB9D62DC92C01058FA79F44657DE6").unwrap();
+    let key = hex::decode("3F419E1CB7079442AA37474C2EFBF8B8").unwrap();
+    let random_seed = hex::decode("1C2965473CE206BB1C2965473CE2").unwrap();
+
+    let mut header = KeyBlockHeader::new_with_values("D", "P0", "A", "E", "00", "E")
+        .unwrap()
+        .with_opt_blocks(Some(Box::new(
+            OptBlock::new("KS", &"0".repeat(9984), None).unwrap(),
+        )));
+    header.finalize().unwrap();
+    assert_eq!(header.len(), 10016);
+
+    let err = tr31_wrap(&kbpk, header, &key, 0, &random_seed).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "PSEC-0000: ERROR TR-31: Total key block length exceeds the maximum of 9999: header 10016, payload 64, MAC 32 characters"
+    );
+
+    // A total length above 65535 must not be truncated to a valid u16 key block length
+    let opt_blocks = [
+        OptBlock::new("KS", &"0".repeat(40000), None).unwrap(),
+        OptBlock::new("HM", &"0".repeat(25516), None).unwrap(),
+    ]
+    .into_iter()
+    .collect();
+    let mut header = KeyBlockHeader::new_with_values("D", "P0", "A", "E", "00", "E")
+        .unwrap()
+        .with_opt_blocks(opt_blocks);
+    header.finalize().unwrap();
+    assert_eq!(header.len() + 64 + 32, 65536 + 112);
+
+    let err = tr31_wrap(&kbpk, header, &key, 0, &random_seed).unwrap_err();
+    assert!(err
+        .to_string()
+        .starts_with("PSEC-0000: ERROR TR-31: Total key block length exceeds the maximum of 9999"));
+}
+
+#[cfg(not(feature = "no-clear-secrets"))]
+#[test]
+pub fn test_tr31_wrap_unwrap_version_b_tdes_2key() {
+    // KBPK, key and padding of TR-31: 2018, A.7.2 (TDEA Key Derivation Binding Method). The
+    // expected key block was cross-checked with an independent TDEA-CMAC implementation (Python
+    // `cryptography`), not copied from the printed version 'B' example of the annex.
+    let kbpk = hex::decode("89E88CF7931444F334BD7547FC3F380C").unwrap();
+    let key = hex::decode("F039121BEC83D26B169BDCD5B22AAF8F").unwrap();
+    let random_seed = hex::decode("720DF563BB07").unwrap();
+    let header = KeyBlockHeader::new_with_values("B", "P0", "T", "E", "00", "E").unwrap();
+    let expected_key_block =
+        "B0080P0TE00E000073C9CFE3A9E1DF3B0F771F4BBEC74CD6A6F3C1F2147F2D3C3940A1D6987B9658";
+
+    let key_block = tr31_wrap(&kbpk, header, &key, 0, &random_seed).unwrap();
+    assert_eq!(key_block, expected_key_block);
+
+    // Header, 24 bytes of encrypted payload and the full 8-byte MAC, each byte as 2 hex characters
+    assert_eq!(key_block.len(), 16 + 2 * 24 + 2 * 8);
+    assert_eq!(&key_block[64..], "3940A1D6987B9658");
+
+    // The MAC is the TDEA-CMAC with the KBAK of the header and the clear payload
+    let kbak = hex::decode("EEB74C38D8E36CFD4DF269B857937CF6").unwrap();
+    let mut mac_input = key_block.as_bytes()[..16].to_vec();
+    mac_input.extend(hex::decode("0080F039121BEC83D26B169BDCD5B22AAF8F720DF563BB07").unwrap());
+    let mac = crate::crypto::tdes::tdes_cmac(&mac_input, &kbak).unwrap();
+    assert_eq!(&key_block[64..], hex::encode_upper(mac));
+
+    let (header, unwrapped_key) = tr31_unwrap(&kbpk, &key_block).unwrap();
+    assert_eq!(header.version_id(), "B");
+    assert_eq!(unwrapped_key, key);
+}
+
+#[cfg(not(feature = "no-clear-secrets"))]
+#[test]
+pub fn test_tr31_wrap_unwrap_version_b_tdes_3key() {
+    let kbpk = hex::decode("B0B1B2B3B4B5B6B7B8B9BABBBCBDBEBFC0C1C2C3C4C5C6C7").unwrap();
+    let key = hex::decode("0123456789ABCDEFFEDCBA987654321089ABCDEF01234567").unwrap();
+    let random_seed = hex::decode("E7C2F1A94D36").unwrap();
+    let header = KeyBlockHeader::new_from_str("B0000K0TB00E0000").unwrap();
+    let expected_key_block = "B0096K0TB00E00001C5C09015454717E86A8B6EF6E959A970CCF8B6510C4AC985BA5B3A68CCC09E32D0765988BDEF747";
+
+    let key_block = tr31_wrap(&kbpk, header, &key, 0, &random_seed).unwrap();
+    assert_eq!(key_block, expected_key_block);
+    assert_eq!(tr31_unwrap(&kbpk, &key_block).unwrap().1, key);
+
+    // The MAC of version 'B' is 8 bytes long
+    let mut tampered = key_block[..key_block.len() - 1].to_string();
+    tampered.push('8');
+    assert_eq!(
+        tr31_unwrap(&kbpk, &tampered).unwrap_err().to_string(),
+        "TR31-0003: ERROR TR-31: MAC check failed"
+    );
+}
+
+#[cfg(not(feature = "no-clear-secrets"))]
+#[test]
+pub fn test_tr31_wrap_unwrap_version_b_optional_blocks() {
+    let kbpk = hex::decode("89E88CF7931444F334BD7547FC3F380C").unwrap();
+    let key = hex::decode("F039121BEC83D26B169BDCD5B22AAF8F").unwrap();
+    let random_seed: Vec<u8> = (0..32).collect();
+
+    // Optional blocks are padded to the TDEA block length of 8
+    let mut header = KeyBlockHeader::new_with_values("B", "P0", "T", "E", "00", "E").unwrap();
+    header.set_opt_blocks(Some(Box::new(OptBlock::new("KS", "00604B", None).unwrap())));
+    header.finalize().unwrap();
+    assert_eq!(header.len() % 8, 0);
+
+    let key_block = tr31_wrap(&kbpk, header, &key, 24, &random_seed).unwrap();
+    let (header, unwrapped_key) = tr31_unwrap(&kbpk, &key_block).unwrap();
+    assert_eq!(header.num_optional_blocks(), 2);
+    assert_eq!(unwrapped_key, key);
+}
+
+#[cfg(not(feature = "no-clear-secrets"))]
+#[test]
+fn test_tr31_unwrap_version_b_below_minimum_length() {
+    // Header, 4 bytes of payload and the 8-byte MAC are below the minimum of one TDEA block of
+    // payload. The structure is checked before the KBPK is used, so even an invalid KBPK yields
+    // the length error.
+    let key_block = "B0040P0TE00E00000DB4BA0E7C2E5C071F292502";
+    assert_eq!(
+        tr31_unwrap(&[0u8; 5], key_block).unwrap_err().to_string(),
+        "TR31-0007: ERROR TR-31: Key block length is below minimum required length"
+    );
+}
+
+#[test]
+fn test_supported_versions() {
+    let versions = supported_versions();
+    assert_eq!(
+        versions,
+        [KeyBlockVersion::B, KeyBlockVersion::C, KeyBlockVersion::D]
+    );
+
+    // Every supported version has a binding method. The deprecated version 'A' can only be
+    // unwrapped and is not reported.
+    for version in versions {
+        assert!(binding_method_lengths(version.id()).is_ok());
+    }
+    assert!(!versions.contains(&KeyBlockVersion::A));
+}
+
+#[test]
+fn test_tr31_wrap_error_version_b_kbpk_length_not_tdes() {
+    let header = KeyBlockHeader::new_from_str("B0000P0TE00E0000").unwrap();
+    let key = hex::decode("F039121BEC83D26B169BDCD5B22AAF8F").unwrap();
+    let random_seed = hex::decode("720DF563C6E5").unwrap();
+    let kbpk =
+        hex::decode("88E1AB2A2E3DD38C1FA039A536500CC8A87AB9D62DC92C01058FA79F44657DE6").unwrap();
+
+    assert_eq!(
+        tr31_wrap(&kbpk, header, &key, 0, &random_seed)
+            .unwrap_err()
+            .to_string(),
+        "PSEC-0000: ERROR TR-31: KBPK length 32 is not a valid TDES key length (16 or 24 bytes) for key block version B"
+    );
+
+    let header = KeyBlockHeader::new_from_str("A0000P0TE00E0000").unwrap();
+    assert_eq!(
+        tr31_wrap(&kbpk[..16], header, &key, 0, &random_seed)
+            .unwrap_err()
+            .to_string(),
+        "TR31-0002: ERROR TR-31: Key block version A is deprecated and only supported for unwrapping"
+    );
+}
+
+#[cfg(not(feature = "no-clear-secrets"))]
+#[test]
+pub fn test_tr31_unwrap_version_a_and_rewrap_as_d() {
+    // Test vectors from TR-31: 2018, A.7.2.1 (Key Variant Binding Method)
+    let kbpk = hex::decode("89E88CF7931444F334BD7547FC3F380C").unwrap();
+    let key = hex::decode("F039121BEC83D26B169BDCD5B22AAF8F").unwrap();
+    let key_block = "A0072P0TE00E0000F5161ED902807AF26F1D62263644BD24192FDB3193C730301CEE8701";
+
+    let (mut header, unwrapped_key) = tr31_unwrap(&kbpk, key_block).unwrap();
+    assert_eq!(header.version_id(), "A");
+    assert_eq!(unwrapped_key, key);
+
+    let mut tampered = key_block.replace("P0TE00E", "P0TE00N");
+    assert_eq!(
+        tr31_unwrap(&kbpk, &tampered).unwrap_err().to_string(),
+        "TR31-0003: ERROR TR-31: MAC check failed"
+    );
+    tampered = key_block.to_string();
+    tampered.replace_range(16..17, "E");
+    assert!(tr31_unwrap(&kbpk, &tampered).is_err());
+
+    // Migrate to version 'D' under an AES KBPK
+    let kbpk_d =
+        hex::decode("88E1AB2A2E3DD38C1FA039A536500CC8A87AB9D62DC92C01058FA79F44657DE6").unwrap();
+    let random_seed = hex::decode("1C2965473CE206BB855B01533782").unwrap();
+    header.set_version_id("D").unwrap();
+    let key_block_d = tr31_wrap(&kbpk_d, header, &unwrapped_key, 0, &random_seed).unwrap();
+    assert!(key_block_d.starts_with("D0112P0TE00E0000"));
+    assert_eq!(tr31_unwrap(&kbpk_d, &key_block_d).unwrap().1, key);
+}
+
+#[cfg(not(feature = "no-clear-secrets"))]
+#[test]
+fn test_tr31_signing_input_version_a_not_supported() {
+    let header = KeyBlockHeader::new_from_str("A0000P0TE00E0000").unwrap();
+    let key = hex::decode("F039121BEC83D26B169BDCD5B22AAF8F").unwrap();
+    assert_eq!(
+        tr31_signing_input(&header, &key, 0, &[0x5A; 6])
+            .unwrap_err()
+            .to_string(),
+        "TR31-0002: ERROR TR-31: Key block version A is deprecated and only supported for unwrapping"
+    );
+}
+
+#[cfg(not(feature = "no-clear-secrets"))]
+#[test]
+pub fn test_tr31_wrap_unwrap_version_c_tdes_2key() {
+    // Test vectors from TR-31: 2018, A.7.3.1 (TDEA Key Variant Binding Method). The MAC is
+    // computed over the header and the binary encrypted payload.
+    let kbpk = hex::decode("B8ED59E0A279A295E9F5ED7944FD06B9").unwrap();
+    let key = hex::decode("EDB380DD340BC2620247D445F5B8D678").unwrap();
+    let random_seed = hex::decode("8546A8ED98D1").unwrap();
+    let header = KeyBlockHeader::new_from_str("C0096B0TX12S0100KS1800604B120F9292800000").unwrap();
+    let expected_key_block = "C0096B0TX12S0100KS1800604B120F9292800000BFB9B689CB567E66FC3FEE5AD5F52161FC6545B9D60989015D02155C";
+
+    let key_block = tr31_wrap(&kbpk, header, &key, 0, &random_seed).unwrap();
+    assert_eq!(key_block, expected_key_block);
+
+    // Header, 24 bytes of encrypted payload and a 4-byte MAC, each byte as 2 hex characters
+    assert_eq!(key_block.len(), 40 + 2 * 24 + 2 * 4);
+
+    let (header, unwrapped_key) = tr31_unwrap(&kbpk, &key_block).unwrap();
+    assert_eq!(header.version_id(), "C");
+    assert_eq!(header.key_usage(), "B0");
+    assert_eq!(header.algorithm(), "T");
+    assert_eq!(header.mode_of_use(), "X");
+    assert_eq!(header.key_version_number(), "12");
+    assert_eq!(header.exportability(), "S");
+    assert_eq!(unwrapped_key, key);
+}
+
+#[cfg(not(feature = "no-clear-secrets"))]
+#[test]
+pub fn test_tr31_wrap_unwrap_version_c_tdes_3key() {
+    let kbpk = hex::decode("B0B1B2B3B4B5B6B7B8B9BABBBCBDBEBFC0C1C2C3C4C5C6C7").unwrap();
+    let key = hex::decode("0123456789ABCDEFFEDCBA987654321089ABCDEF01234567").unwrap();
+    let random_seed = hex::decode("E7C2F1A94D36").unwrap();
+    let header = KeyBlockHeader::new_from_str("C0000K0TB00E0000").unwrap();
+
+    // TR-31: 2018 has no 3-key version 'C' example, so only the round trip is checked
+    let key_block = tr31_wrap(&kbpk, header, &key, 0, &random_seed).unwrap();
+    assert!(key_block.starts_with("C0088K0TB00E0000"));
+    assert_eq!(tr31_unwrap(&kbpk, &key_block).unwrap().1, key);
+
+    // The MAC covers the encrypted payload
+    let mut tampered = key_block.clone();
+    tampered.replace_range(16..17, "8");
+    assert_eq!(
+        tr31_unwrap(&kbpk, &tampered).unwrap_err().to_string(),
+        "TR31-0003: ERROR TR-31: MAC check failed"
+    );
+}
+
+#[cfg(not(feature = "no-clear-secrets"))]
 #[test]
 pub fn test_tr31_unwrap_example_a_7_4() {
     // Key Block from the wrapping test
@@ -243,6 +540,7 @@ pub fn test_tr31_unwrap_example_a_7_4() {
     );
 }
 
+#[cfg(not(feature = "no-clear-secrets"))]
 #[test]
 pub fn test_tr31_unwrap_example_aes_128() {
     // Key Block from the wrapping test
@@ -288,6 +586,7 @@ pub fn test_tr31_unwrap_example_aes_128() {
     );
 }
 
+#[cfg(not(feature = "no-clear-secrets"))]
 #[test]
 pub fn test_tr31_unwrap_example_aes_128_masked_length() {
     // Key Block from the wrapping test
@@ -333,6 +632,7 @@ pub fn test_tr31_unwrap_example_aes_128_masked_length() {
     );
 }
 
+#[cfg(not(feature = "no-clear-secrets"))]
 #[test]
 pub fn test_tr31_unwrap_example_aes_128_two_optional_blocks() {
     // Key Block from the wrapping test
@@ -398,6 +698,7 @@ pub fn test_tr31_unwrap_example_aes_128_two_optional_blocks() {
     );
 }
 
+#[cfg(not(feature = "no-clear-secrets"))]
 #[test]
 pub fn test_tr31_unwrap_example_aes_192_no_optional_blocks_masked_length() {
     let key_block = "D0144B1TE00N00001CF87C5209D461283FAA104730F9A2B13B6DDEE609EECE848C61EF2CAFA48125BA0606C623FAB58D9B7CD820AB20935980478542F7C26DB42F11F3AA89FA9332";
@@ -428,6 +729,7 @@ pub fn test_tr31_unwrap_example_aes_192_no_optional_blocks_masked_length() {
     );
 }
 
+#[cfg(not(feature = "no-clear-secrets"))]
 #[test]
 pub fn test_tr31_unwrap_example_aes_192_two_optional_blocks() {
     let key_block = "D0144P0TE00N0200KS1800604B120F9292800000PB080000F2A795BB540447553D9FA3812E64E76A577DA04A1E0DD9FA9EFDE394BE936D4532BF5BA7E57063B63FCD90F9C2020F77";
@@ -484,6 +786,7 @@ pub fn test_tr31_unwrap_example_aes_192_two_optional_blocks() {
     );
 }
 
+#[cfg(not(feature = "no-clear-secrets"))]
 #[test]
 pub fn test_tr31_unwrap_example_aes_256_no_optional_blocks_no_masked_length() {
     let key_block = "D0112P0TE00N0000E06A6D9B1FA5E7566A7AA874609D7F5790EA3512AE1E671299767ADD2FD32AAE8C4D7284B32846405F6FB8546591371A";
@@ -515,6 +818,7 @@ pub fn test_tr31_unwrap_example_aes_256_no_optional_blocks_no_masked_length() {
     );
 }
 
+#[cfg(not(feature = "no-clear-secrets"))]
 #[test]
 pub fn test_tr31_unwrap_example_aes_256_no_optional_blocks_masked_length() {
     let key_block = "D0208B1TE00N0000F34BCC63BD9EB2A670220DE516F9A6E6A701FC3843E52E232FC22FF4FC41E3B076D7E0AF1AA62DD968281A0F64AEC2A43586841472F93C17C1FAF68D06BC1B5C64890597D46D2BA663962217D18EA412092E1A5DED1B858A378385FA64E4EF63";
@@ -547,6 +851,7 @@ pub fn test_tr31_unwrap_example_aes_256_no_optional_blocks_masked_length() {
     );
 }
 
+#[cfg(not(feature = "no-clear-secrets"))]
 #[test]
 pub fn test_tr31_unwrap_example_aes_256_two_optional_blocks() {
     let key_block = "D0144P0TE00N0200KS1800604B120F9292800000PB080000BB07D34B055CF948CD3FB0C9D55AC064F32D855EBC0AE666E49C6393BC4EA33B356E735F1BEE0612C6E80A5DAB7B9BCA";
@@ -640,6 +945,44 @@ pub fn test_tr31_wrap_with_header_string_example_aes_256_two_optional_blocks() {
     assert_eq!(key_block, expected_key_block, "Complete key block mismatch");
 }
 
+#[cfg(not(feature = "no-clear-secrets"))]
+#[test]
+pub fn test_tr31_wrap_lowercase_roundtrip() {
+    let header = KeyBlockHeader::new_with_values("D", "P0", "A", "E", "00", "E").unwrap();
+    let key = hex::decode("3F419E1CB7079442AA37474C2EFBF8B8").unwrap();
+    let masked_key_length = 0;
+    let random_seed = hex::decode("1C2965473CE206BB855B01533782").unwrap();
+    let kbpk =
+        hex::decode("88E1AB2A2E3DD38C1FA039A536500CC8A87AB9D62DC92C01058FA79F44657DE6").unwrap();
+
+    let key_block =
+        tr31_wrap_lowercase(&kbpk, header, &key, masked_key_length, &random_seed).unwrap();
+
+    let expected_key_block = "D0112P0AE00E0000b82679114f470f540165edfbf7e250fcea43f810d215f8d207e2e417c07156a27e8e31da05f7425509593d03a457dc34";
+    assert_eq!(key_block, expected_key_block, "Complete key block mismatch");
+
+    let (unwrapped_header, unwrapped_key) = tr31_unwrap(&kbpk, &key_block).unwrap();
+    assert_eq!(unwrapped_header.export_str().unwrap(), "D0112P0AE00E0000");
+    assert_eq!(unwrapped_key, key);
+}
+
+#[test]
+pub fn test_tr31_wrap_lowercase_verify() {
+    let header = KeyBlockHeader::new_with_values("D", "P0", "A", "E", "00", "E").unwrap();
+    let key = hex::decode("3F419E1CB7079442AA37474C2EFBF8B8").unwrap();
+    let random_seed = hex::decode("1C2965473CE206BB855B01533782").unwrap();
+    let kbpk =
+        hex::decode("88E1AB2A2E3DD38C1FA039A536500CC8A87AB9D62DC92C01058FA79F44657DE6").unwrap();
+
+    let key_block = tr31_wrap_lowercase(&kbpk, header, &key, 0, &random_seed).unwrap();
+    assert_eq!(&key_block[..16], "D0112P0AE00E0000");
+
+    // The MAC check decodes the lowercase payload and MAC without clear key access
+    let header = tr31_verify(&kbpk, &key_block).unwrap();
+    assert_eq!(header.export_str().unwrap(), "D0112P0AE00E0000");
+}
+
+#[cfg(not(feature = "no-clear-secrets"))]
 #[test]
 pub fn test_tr31_unwrap_wrong_key_block_length() {
     // Key Block from the wrapping test
@@ -658,6 +1001,7 @@ pub fn test_tr31_unwrap_wrong_key_block_length() {
     );
 }
 
+#[cfg(not(feature = "no-clear-secrets"))]
 #[test]
 pub fn test_tr31_unwrap_wrong_mac() {
     // Key Block from the wrapping test
@@ -676,6 +1020,7 @@ pub fn test_tr31_unwrap_wrong_mac() {
     );
 }
 
+#[cfg(not(feature = "no-clear-secrets"))]
 #[test]
 pub fn test_tr31_unwrap_wrong_minimum_length() {
     // Key Block from the wrapping test
@@ -694,6 +1039,7 @@ pub fn test_tr31_unwrap_wrong_minimum_length() {
     );
 }
 
+#[cfg(not(feature = "no-clear-secrets"))]
 #[test]
 pub fn test_tr31_unwrap_unsupported_version() {
     // Key Block from the wrapping test
@@ -711,3 +1057,915 @@ pub fn test_tr31_unwrap_unsupported_version() {
         "Unwrapping should fail due to wrong version"
     );
 }
+
+#[test]
+pub fn test_tr31_header_len_two_optional_blocks() {
+    let header_str = "D0144P0TE00N0200KS1800604B120F9292800000PB080000";
+    assert_eq!(tr31_header_len(header_str).unwrap(), 48);
+
+    // The header length is also computed from a complete key block.
+    let key_block = "D0144P0TE00N0200KS1800604B120F9292800000PB080000F2A795BB540447553D9FA3812E64E76A577DA04A1E0DD9FA9EFDE394BE936D4532BF5BA7E57063B63FCD90F9C2020F77";
+    assert_eq!(
+        tr31_header_len(key_block).unwrap(),
+        KeyBlockHeader::new_from_str(key_block).unwrap().len()
+    );
+}
+
+#[test]
+pub fn test_tr31_header_len_errors() {
+    assert_eq!(tr31_header_len("D0112P0AE00E0000").unwrap(), 16);
+    assert!(tr31_header_len("D0112P0AE00E00").is_err());
+    assert!(tr31_header_len("D0144P0TE00N0200KS1800604B120F9292800000").is_err());
+    assert!(tr31_header_len("D0144P0TE00N0100KSZZ00604B120F9292800000").is_err());
+
+    // A non-ASCII character in the extended length field is an error, not a panic
+    assert!(tr31_header_len("D0000P0AE00E0100KS000é0010").is_err());
+    assert!(tr31_header_len("D0000P0AE00E0100KS00é0010").is_err());
+}
+
+#[cfg(not(feature = "no-clear-secrets"))]
+#[test]
+pub fn test_tr31_unwrap_truncated_mac_parse_error() {
+    // Key Block from the wrapping test with the last 4 characters of the MAC removed
+    let key_block = "D0112P0AE00E0000B82679114F470F540165EDFBF7E250FCEA43F810D215F8D207E2E417C07156A27E8E31DA05F7425509593D03A457";
+
+    let kbpk =
+        hex::decode("88E1AB2A2E3DD38C1FA039A536500CC8A87AB9D62DC92C01058FA79F44657DE6").unwrap();
+
+    let err = tr31_unwrap(&kbpk, key_block).unwrap_err();
+    let parse_error = err.parse_error().unwrap();
+
+    assert_eq!(parse_error.field(), "key block length");
+    assert_eq!(parse_error.range(), 108..112);
+    assert_eq!(
+        err.to_string(),
+        "TR31-0005: ERROR TR-31: Key block length does not match its length in the header: declared 112, actual 108, 4 character(s) too short"
+    );
+}
+
+#[cfg(not(feature = "no-clear-secrets"))]
+#[test]
+pub fn test_tr31_unwrap_trailing_whitespace_length_mismatch() {
+    // Key Block from the wrapping test with a trailing space
+    let key_block = "D0112P0AE00E0000B82679114F470F540165EDFBF7E250FCEA43F810D215F8D207E2E417C07156A27E8E31DA05F7425509593D03A457DC34 ";
+
+    let kbpk =
+        hex::decode("88E1AB2A2E3DD38C1FA039A536500CC8A87AB9D62DC92C01058FA79F44657DE6").unwrap();
+
+    let err = tr31_unwrap(&kbpk, key_block).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "TR31-0005: ERROR TR-31: Key block length does not match its length in the header: declared 112, actual 113, 1 character(s) too long, trailing whitespace"
+    );
+    assert_eq!(err.parse_error().unwrap().range(), 112..113);
+
+    // Other excess characters are not reported as whitespace
+    let err = tr31_unwrap(&kbpk, &format!("{}00", key_block.trim_end())).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "TR31-0005: ERROR TR-31: Key block length does not match its length in the header: declared 112, actual 114, 2 character(s) too long"
+    );
+}
+
+#[cfg(not(feature = "no-clear-secrets"))]
+#[test]
+pub fn test_tr31_unwrap_invalid_mac_character_parse_error() {
+    let key_block = "D0112P0AE00E0000B82679114F470F540165EDFBF7E250FCEA43F810D215F8D207E2E417C07156A27E8E31DA05F7425509593D03A457DCX4";
+
+    let kbpk =
+        hex::decode("88E1AB2A2E3DD38C1FA039A536500CC8A87AB9D62DC92C01058FA79F44657DE6").unwrap();
+
+    let err = tr31_unwrap(&kbpk, key_block).unwrap_err();
+    let parse_error = err.parse_error().unwrap();
+
+    assert_eq!(parse_error.field(), "MAC");
+    assert_eq!(parse_error.offset(), 110);
+    assert_eq!(parse_error.length(), 1);
+}
+
+// Wrap a TDES key with even parity bytes in a key block with algorithm 'T'.
+#[cfg(not(feature = "no-clear-secrets"))]
+fn wrap_bad_parity_tdes_key() -> (Vec<u8>, String) {
+    let header = KeyBlockHeader::new_with_values("D", "P0", "T", "E", "00", "E").unwrap();
+    let key = hex::decode("00112233445566778899AABBCCDDEEFF").unwrap();
+    let random_seed = hex::decode("1C2965473CE206BB855B01533782").unwrap();
+    let kbpk =
+        hex::decode("88E1AB2A2E3DD38C1FA039A536500CC8A87AB9D62DC92C01058FA79F44657DE6").unwrap();
+
+    let key_block = tr31_wrap(&kbpk, header, &key, 0, &random_seed).unwrap();
+    (kbpk, key_block)
+}
+
+#[cfg(not(feature = "no-clear-secrets"))]
+#[test]
+pub fn test_tr31_unwrap_with_options_tdes_parity_off() {
+    let (kbpk, key_block) = wrap_bad_parity_tdes_key();
+
+    let output = tr31_unwrap_with_options(&kbpk, &key_block, &ValidationConfig::default()).unwrap();
+    assert_eq!(
+        hex::encode_upper(&output.key),
+        "00112233445566778899AABBCCDDEEFF"
+    );
+    assert!(!output.parity_warning);
+}
+
+#[cfg(not(feature = "no-clear-secrets"))]
+#[test]
+pub fn test_tr31_unwrap_with_options_tdes_parity_error() {
+    let (kbpk, key_block) = wrap_bad_parity_tdes_key();
+    let options = ValidationConfig {
+        tdes_parity: TdesParityCheck::Error,
+        ..ValidationConfig::default()
+    };
+
+    let result = tr31_unwrap_with_options(&kbpk, &key_block, &options);
+    assert!(matches!(
+        result,
+        Err(e) if e.to_string() == "TR31-0012: ERROR TR-31: Recovered TDES key does not have odd parity"
+    ));
+}
+
+#[cfg(not(feature = "no-clear-secrets"))]
+#[test]
+pub fn test_tr31_unwrap_with_options_tdes_parity_warn() {
+    let (kbpk, key_block) = wrap_bad_parity_tdes_key();
+    let options = ValidationConfig {
+        tdes_parity: TdesParityCheck::Warn,
+        ..ValidationConfig::default()
+    };
+
+    let output = tr31_unwrap_with_options(&kbpk, &key_block, &options).unwrap();
+    assert_eq!(
+        hex::encode_upper(&output.key),
+        "00112233445566778899AABBCCDDEEFF"
+    );
+    assert!(output.parity_warning);
+}
+
+#[cfg(not(feature = "no-clear-secrets"))]
+#[test]
+pub fn test_tr31_unwrap_with_options_tdes_parity_correct() {
+    let (kbpk, key_block) = wrap_bad_parity_tdes_key();
+    let options = ValidationConfig {
+        tdes_parity: TdesParityCheck::Correct,
+        ..ValidationConfig::default()
+    };
+
+    let output = tr31_unwrap_with_options(&kbpk, &key_block, &options).unwrap();
+    assert_eq!(
+        hex::encode_upper(&output.key),
+        "01102332455467768998ABBACDDCEFFE"
+    );
+    assert!(output.parity_warning);
+}
+
+#[cfg(not(feature = "no-clear-secrets"))]
+#[test]
+pub fn test_unwrap_output_debug_redacts_key() {
+    let (kbpk, key_block) = wrap_bad_parity_tdes_key();
+
+    let output = tr31_unwrap_with_options(&kbpk, &key_block, &ValidationConfig::default()).unwrap();
+    let debug = format!("{:?}", output);
+    assert!(!debug.contains(&hex::encode_upper(&output.key)));
+    assert!(!debug.contains(&format!("{:?}", output.key.as_bytes())));
+    assert!(debug.contains("key_len: 16"));
+}
+
+// Wrap the key of A.7.4 with the given `KP` and `KC` optional blocks.
+#[cfg(not(feature = "no-clear-secrets"))]
+fn wrap_with_kcv_blocks(kp_data: &str, kc_data: &str) -> (Vec<u8>, String) {
+    let key = hex::decode("3F419E1CB7079442AA37474C2EFBF8B8").unwrap();
+    let random_seed = hex::decode("1C2965473CE206BB855B01533782").unwrap();
+    let kbpk =
+        hex::decode("88E1AB2A2E3DD38C1FA039A536500CC8A87AB9D62DC92C01058FA79F44657DE6").unwrap();
+
+    let kc_block = OptBlock::new("KC", kc_data, None).unwrap();
+    let kp_block = OptBlock::new("KP", kp_data, Some(kc_block)).unwrap();
+    let mut header = KeyBlockHeader::new_with_values("D", "P0", "A", "E", "00", "E").unwrap();
+    header.set_opt_blocks(Some(Box::new(kp_block)));
+    header.finalize().unwrap();
+
+    let key_block = tr31_wrap(&kbpk, header, &key, 0, &random_seed).unwrap();
+    (kbpk, key_block)
+}
+
+#[cfg(not(feature = "no-clear-secrets"))]
+#[test]
+pub fn test_tr31_unwrap_verified_kcv_blocks() {
+    let (kbpk, key_block) = wrap_with_kcv_blocks("012331550BC9", "0108793E25AB");
+
+    let (header, key) = tr31_unwrap_verified(&kbpk, &key_block).unwrap();
+    assert_eq!(hex::encode_upper(key), "3F419E1CB7079442AA37474C2EFBF8B8");
+    assert_eq!(header.num_optional_blocks(), 2);
+}
+
+#[cfg(not(feature = "no-clear-secrets"))]
+#[test]
+pub fn test_tr31_unwrap_verified_wrong_kbpk() {
+    let (mut kbpk, key_block) = wrap_with_kcv_blocks("012331550BC9", "0108793E25AB");
+    kbpk[0] ^= 0x01;
+
+    assert_eq!(
+        tr31_unwrap(&kbpk, &key_block).unwrap_err().to_string(),
+        "TR31-0003: ERROR TR-31: MAC check failed"
+    );
+    assert_eq!(
+        tr31_unwrap_verified(&kbpk, &key_block)
+            .unwrap_err()
+            .to_string(),
+        "TR31-0004: ERROR TR-31: KBPK KCV mismatch (wrong protection key)"
+    );
+}
+
+#[cfg(not(feature = "no-clear-secrets"))]
+#[test]
+pub fn test_tr31_unwrap_with_options_kbpk_kcv() {
+    use crate::error::PaysecError;
+
+    // The KP block does not match the KBPK the key block is wrapped under
+    let (kbpk, key_block) = wrap_with_kcv_blocks("012331550BCA", "0108793E25AB");
+    let config = ValidationConfig {
+        kbpk_kcv: true,
+        ..ValidationConfig::default()
+    };
+
+    assert!(tr31_unwrap_with_options(&kbpk, &key_block, &ValidationConfig::default()).is_ok());
+    let err = tr31_unwrap_with_options(&kbpk, &key_block, &config).unwrap_err();
+    assert_eq!(PaysecError::find(&err), Some(&PaysecError::KbpkKcvMismatch));
+    assert_eq!(
+        err.to_string(),
+        "TR31-0004: ERROR TR-31: KBPK KCV mismatch (wrong protection key)"
+    );
+
+    // A wrong KBPK is reported by the KCV check instead of the MAC check
+    let (mut kbpk, key_block) = wrap_with_kcv_blocks("012331550BC9", "0108793E25AB");
+    assert!(tr31_unwrap_with_options(&kbpk, &key_block, &config).is_ok());
+    kbpk[0] ^= 0x01;
+    let err = tr31_unwrap_with_options(&kbpk, &key_block, &config).unwrap_err();
+    assert_eq!(PaysecError::find(&err), Some(&PaysecError::KbpkKcvMismatch));
+}
+
+#[cfg(not(feature = "no-clear-secrets"))]
+#[test]
+pub fn test_tr31_unwrap_verified_key_kcv_mismatch() {
+    let (kbpk, key_block) = wrap_with_kcv_blocks("012331550BC9", "0108793E25AC");
+
+    assert!(tr31_unwrap(&kbpk, &key_block).is_ok());
+    assert_eq!(
+        tr31_unwrap_verified(&kbpk, &key_block)
+            .unwrap_err()
+            .to_string(),
+        "TR31-0009: ERROR TR-31: Key KCV mismatch"
+    );
+}
+
+#[cfg(not(feature = "no-clear-secrets"))]
+#[test]
+pub fn test_tr31_unwrap_delimited() {
+    let (kbpk, key_block) = wrap_with_kcv_blocks("012331550BC9", "0108793E25AB");
+    let header_len = tr31_header_len(&key_block).unwrap();
+    let mac_offset = key_block.len() - 32;
+    let delimited = format!(
+        "{}|{}|{}",
+        &key_block[..header_len],
+        &key_block[header_len..mac_offset],
+        &key_block[mac_offset..]
+    );
+
+    assert_eq!(
+        tr31_unwrap_delimited(&kbpk, &delimited, '|').unwrap(),
+        tr31_unwrap(&kbpk, &key_block).unwrap()
+    );
+
+    // Wrong delimiter, misplaced delimiter and missing part
+    assert!(tr31_unwrap_delimited(&kbpk, &delimited, ';').is_err());
+    let misplaced = format!(
+        "{}|{}|{}",
+        &key_block[..header_len],
+        &key_block[header_len..mac_offset - 2],
+        &key_block[mac_offset - 2..]
+    );
+    assert_eq!(
+        tr31_unwrap_delimited(&kbpk, &misplaced, '|')
+            .unwrap_err()
+            .to_string(),
+        "PSEC-0000: ERROR TR-31: MAC part length does not match the MAC length of the version: 32"
+    );
+    assert_eq!(
+        tr31_unwrap_delimited(&kbpk, &delimited[..mac_offset + 1], '|')
+            .unwrap_err()
+            .to_string(),
+        "PSEC-0000: ERROR TR-31: Delimited key block must have 3 parts, found 2"
+    );
+}
+
+#[test]
+pub fn test_tr31_clone_block() {
+    // Version 'D' with a masked key length and optional blocks
+    let header = KeyBlockHeader::new_from_str("D0000P0TE00N0100KS1800604B120F9292800000").unwrap();
+    let (kbpk, key_block) = (
+        hex::decode("00112233445566778899AABBCCDDEEFF0011223344556677").unwrap(),
+        "D0144P0TE00N0200KS1800604B120F9292800000PB080000F2A795BB540447553D9FA3812E64E76A577DA04A1E0DD9FA9EFDE394BE936D4532BF5BA7E57063B63FCD90F9C2020F77",
+    );
+    let config = ValidationConfig {
+        non_exportable: false,
+        ..ValidationConfig::default()
+    };
+    assert_eq!(header.version_id(), "D");
+    assert_eq!(
+        tr31_clone_block_with_options(&kbpk, key_block, &config).unwrap(),
+        key_block
+    );
+
+    // Versions 'B' and 'C'
+    let kbpk = hex::decode("89E88CF7931444F334BD7547FC3F380C").unwrap();
+    let key_block =
+        "B0080P0TE00E000073C9CFE3A9E1DF3B0F771F4BBEC74CD6A6F3C1F2147F2D3C3940A1D6987B9658";
+    assert_eq!(tr31_clone_block(&kbpk, key_block).unwrap(), key_block);
+
+    // TR-31: 2018, A.7.3.1
+    let kbpk_c = hex::decode("B8ED59E0A279A295E9F5ED7944FD06B9").unwrap();
+    let key_block = "C0096B0TX12S0100KS1800604B120F9292800000BFB9B689CB567E66FC3FEE5AD5F52161FC6545B9D60989015D02155C";
+    assert_eq!(tr31_clone_block(&kbpk_c, key_block).unwrap(), key_block);
+
+    // The deprecated version 'A' cannot be wrapped again
+    let key_block = "A0072P0TE00E0000F5161ED902807AF26F1D62263644BD24192FDB3193C730301CEE8701";
+    assert_eq!(
+        tr31_clone_block(&kbpk, key_block).unwrap_err().to_string(),
+        "TR31-0002: ERROR TR-31: Key block version A is deprecated and only supported for unwrapping"
+    );
+}
+
+#[test]
+pub fn test_tr31_clone_block_non_exportable() {
+    let kbpk = hex::decode("89E88CF7931444F334BD7547FC3F380C").unwrap();
+    let key = hex::decode("F039121BEC83D26B169BDCD5B22AAF8F").unwrap();
+    let random_seed = hex::decode("720DF563BB07").unwrap();
+    let header = KeyBlockHeader::new_with_values("C", "P0", "T", "E", "00", "N").unwrap();
+    let key_block = tr31_wrap(&kbpk, header, &key, 0, &random_seed).unwrap();
+
+    // Refused by default and by the strict policy
+    for config in [ValidationConfig::default(), ValidationConfig::strict()] {
+        assert_eq!(
+            tr31_clone_block_with_options(&kbpk, &key_block, &config)
+                .unwrap_err()
+                .to_string(),
+            "TR31-0010: ERROR TR-31: Key block with exportability N cannot be wrapped again"
+        );
+    }
+    assert!(tr31_clone_block(&kbpk, &key_block).is_err());
+
+    // Allowed with the explicit override
+    let config = ValidationConfig {
+        non_exportable: false,
+        ..ValidationConfig::default()
+    };
+    assert_eq!(
+        tr31_clone_block_with_options(&kbpk, &key_block, &config).unwrap(),
+        key_block
+    );
+}
+
+#[test]
+pub fn test_tr31_clone_block_masked_key_length() {
+    let kbpk =
+        hex::decode("88E1AB2A2E3DD38C1FA039A536500CC8A87AB9D62DC92C01058FA79F44657DE6").unwrap();
+    let header = KeyBlockHeader::new_with_values("D", "P0", "A", "E", "00", "E").unwrap();
+    let key = hex::decode("3F419E1CB7079442AA37474C2EFBF8B8").unwrap();
+    let random_seed: Vec<u8> = (0..48).collect();
+
+    let key_block = tr31_wrap(&kbpk, header, &key, 32, &random_seed).unwrap();
+    assert_eq!(tr31_clone_block(&kbpk, &key_block).unwrap(), key_block);
+
+    let mut wrong_kbpk = kbpk.clone();
+    wrong_kbpk[0] ^= 0x01;
+    assert!(tr31_clone_block(&wrong_kbpk, &key_block).is_err());
+}
+
+// Wrap a key under a header which is only accepted by a lenient validation policy.
+#[cfg(not(feature = "no-clear-secrets"))]
+fn wrap_non_conforming_header(header_str: &str) -> (Vec<u8>, String) {
+    let mut header =
+        KeyBlockHeader::new_from_str_with_options(header_str, &ValidationConfig::lenient())
+            .unwrap();
+    header.finalize().unwrap();
+    let key = hex::decode("3F419E1CB7079442AA37474C2EFBF8B8").unwrap();
+    let random_seed = hex::decode("1C2965473CE206BB855B01533782").unwrap();
+    let kbpk =
+        hex::decode("88E1AB2A2E3DD38C1FA039A536500CC8A87AB9D62DC92C01058FA79F44657DE6").unwrap();
+
+    let key_block = tr31_wrap(&kbpk, header, &key, 0, &random_seed).unwrap();
+    (kbpk, key_block)
+}
+
+#[cfg(not(feature = "no-clear-secrets"))]
+#[test]
+pub fn test_validation_config_reserved_field() {
+    let (kbpk, key_block) = wrap_non_conforming_header("D0000P0AE00E00AB");
+
+    let strict = tr31_unwrap_with_options(&kbpk, &key_block, &ValidationConfig::strict());
+    assert!(matches!(
+        strict,
+        Err(e) if e.to_string() == "TR31-0007: ERROR TR-31 HEADER: Invalid value for reserved field: AB"
+    ));
+
+    let lenient =
+        tr31_unwrap_with_options(&kbpk, &key_block, &ValidationConfig::lenient()).unwrap();
+    assert_eq!(lenient.header.reserved_field(), "AB");
+}
+
+#[cfg(not(feature = "no-clear-secrets"))]
+#[test]
+pub fn test_validation_config_proprietary_opt_block_id() {
+    let (kbpk, key_block) = wrap_non_conforming_header("D0000P0AE00E01009C0CPROPDATA");
+
+    assert!(tr31_unwrap(&kbpk, &key_block).is_err());
+    let strict = tr31_unwrap_with_options(&kbpk, &key_block, &ValidationConfig::strict());
+    assert!(matches!(
+        strict,
+        Err(e) if e.to_string() == "TR31-0006: ERROR TR-31 HEADER: Failed to parse optional blocks: ERROR TR-31 OPT BLOCK: Invalid ID: 9C"
+    ));
+
+    let lenient =
+        tr31_unwrap_with_options(&kbpk, &key_block, &ValidationConfig::lenient()).unwrap();
+    let opt_block = lenient.header.opt_blocks().as_ref().unwrap();
+    assert_eq!(opt_block.id(), "9C");
+    assert_eq!(opt_block.data(), "PROPDATA");
+}
+
+#[test]
+pub fn test_validation_config_masked_key_len() {
+    let header = || KeyBlockHeader::new_with_values("D", "P0", "A", "E", "00", "E").unwrap();
+    let key = hex::decode("3F419E1CB7079442AA37474C2EFBF8B8").unwrap();
+    let random_seed = hex::decode("1C2965473CE206BB855B01533782").unwrap();
+    let kbpk =
+        hex::decode("88E1AB2A2E3DD38C1FA039A536500CC8A87AB9D62DC92C01058FA79F44657DE6").unwrap();
+
+    let strict = tr31_wrap_with_options(
+        &kbpk,
+        header(),
+        &key,
+        8,
+        &random_seed,
+        &ValidationConfig::strict(),
+    );
+    assert!(matches!(
+        strict,
+        Err(e) if e.to_string() == "PSEC-0000: ERROR TR-31: Masked key length 8 is shorter than the key length 16"
+    ));
+
+    let lenient = tr31_wrap_with_options(
+        &kbpk,
+        header(),
+        &key,
+        8,
+        &random_seed,
+        &ValidationConfig::lenient(),
+    )
+    .unwrap();
+    assert_eq!(
+        lenient,
+        tr31_wrap(&kbpk, header(), &key, 0, &random_seed).unwrap()
+    );
+}
+
+// With the strict-seed feature the constant seed is rejected regardless of the policy.
+#[cfg(not(feature = "strict-seed"))]
+#[test]
+pub fn test_validation_config_seed_entropy() {
+    let header = || KeyBlockHeader::new_with_values("D", "P0", "A", "E", "00", "E").unwrap();
+    let key = hex::decode("3F419E1CB7079442AA37474C2EFBF8B8").unwrap();
+    let kbpk =
+        hex::decode("88E1AB2A2E3DD38C1FA039A536500CC8A87AB9D62DC92C01058FA79F44657DE6").unwrap();
+    let random_seed = [0u8; 14];
+
+    let strict = tr31_wrap_with_options(
+        &kbpk,
+        header(),
+        &key,
+        0,
+        &random_seed,
+        &ValidationConfig::strict(),
+    );
+    assert!(strict.is_err());
+
+    let config = ValidationConfig {
+        seed_entropy: false,
+        ..ValidationConfig::strict()
+    };
+    assert!(tr31_wrap_with_options(&kbpk, header(), &key, 0, &random_seed, &config).is_ok());
+}
+
+#[test]
+pub fn test_tr31_to_bytes_from_bytes_round_trip() {
+    let key_block = "D0144P0TE00N0200KS1800604B120F9292800000PB080000F2A795BB540447553D9FA3812E64E76A577DA04A1E0DD9FA9EFDE394BE936D4532BF5BA7E57063B63FCD90F9C2020F77";
+
+    let bytes = tr31_to_bytes(key_block);
+    assert_eq!(bytes.len(), 144);
+    assert_eq!(&bytes[..5], b"D0144");
+    assert_eq!(tr31_from_bytes(&bytes).unwrap(), key_block);
+}
+
+#[test]
+pub fn test_tr31_from_bytes_non_ascii() {
+    let mut bytes = tr31_to_bytes("D0112P0AE00E0000B82679114F470F54");
+    bytes[20] = 0xC3;
+
+    let err = tr31_from_bytes(&bytes).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "TR31-0007: ERROR TR-31: Key block contains non-ASCII byte C3 at offset 20"
+    );
+    let parse_error = err.parse_error().unwrap();
+    assert_eq!(parse_error.range(), 20..21);
+}
+
+#[test]
+pub fn test_tr31_compact_bytes_round_trip() {
+    let key_block = "D0144P0TE00N0200KS1800604B120F9292800000PB080000F2A795BB540447553D9FA3812E64E76A577DA04A1E0DD9FA9EFDE394BE936D4532BF5BA7E57063B63FCD90F9C2020F77";
+    let header = KeyBlockHeader::new_from_str(key_block).unwrap();
+
+    let bytes = tr31_to_compact_bytes(key_block).unwrap();
+    assert_eq!(bytes[..4], [TR31_COMPACT_FORMAT_VERSION, 0x00, 0x00, 0x30]);
+    assert_eq!(&bytes[4..52], header.export_str().unwrap().as_bytes());
+    assert_eq!(bytes.len(), 4 + 48 + 48);
+    assert_eq!(tr31_from_compact_bytes(&bytes).unwrap(), key_block);
+
+    let lowercase = format!(
+        "{}{}",
+        &key_block[..48],
+        key_block[48..].to_ascii_lowercase()
+    );
+    let bytes = tr31_to_compact_bytes(&lowercase).unwrap();
+    assert_eq!(bytes[1], 0x01);
+    assert_eq!(tr31_from_compact_bytes(&bytes).unwrap(), lowercase);
+}
+
+#[test]
+pub fn test_tr31_compact_bytes_errors() {
+    let key_block = "D0112P0AE00E0000B82679114F470F540165EDFBF7E250FCEA43F810D215F8D207E2E417C07156A27E8E31DA05F7425509593D03A457DC34";
+    let mixed_case = format!("{}b{}", &key_block[..16], &key_block[17..]);
+    assert_eq!(
+        tr31_to_compact_bytes(&mixed_case).unwrap_err().to_string(),
+        "PSEC-0000: ERROR TR-31: Key block hex with mixed case is not supported by the compact form"
+    );
+    assert!(tr31_to_compact_bytes(&key_block[..108]).is_err());
+
+    let bytes = tr31_to_compact_bytes(key_block).unwrap();
+    let mut wrong_version = bytes.clone();
+    wrong_version[0] = 2;
+    assert_eq!(
+        tr31_from_compact_bytes(&wrong_version)
+            .unwrap_err()
+            .to_string(),
+        "PSEC-0000: ERROR TR-31: Unsupported compact key block format version: 2"
+    );
+
+    let mut wrong_flags = bytes.clone();
+    wrong_flags[1] = 0x80;
+    assert_eq!(
+        tr31_from_compact_bytes(&wrong_flags)
+            .unwrap_err()
+            .to_string(),
+        "PSEC-0000: ERROR TR-31: Unsupported compact key block flags: 80"
+    );
+
+    assert_eq!(
+        tr31_from_compact_bytes(&bytes[..10])
+            .unwrap_err()
+            .to_string(),
+        "PSEC-0000: ERROR TR-31: Compact key block is truncated"
+    );
+    assert!(tr31_from_compact_bytes(&bytes[..bytes.len() - 1]).is_err());
+}
+
+#[test]
+pub fn test_tr31_block_checksum_example_a_7_4() {
+    // Key block of TR-31: 2018, A.7.4. Example 3
+    let key_block = "D0112P0AE00E0000B82679114F470F540165EDFBF7E250FCEA43F810D215F8D207E2E417C07156A27E8E31DA05F7425509593D03A457DC34";
+    assert_eq!(tr31_block_checksum(key_block), [0x9F, 0x43, 0x85, 0x2F]);
+
+    // CRC-32 check value of the standard test input
+    assert_eq!(tr31_block_checksum("123456789"), [0xCB, 0xF4, 0x39, 0x26]);
+    assert_eq!(tr31_block_checksum(""), [0x00, 0x00, 0x00, 0x00]);
+}
+
+#[test]
+pub fn test_tr31_parse_stream_two_key_blocks() {
+    let key_block_1 = "D0112P0AE00E0000B82679114F470F540165EDFBF7E250FCEA43F810D215F8D207E2E417C07156A27E8E31DA05F7425509593D03A457DC34";
+    let key_block_2 = "D0144P0TE00N0200KS1800604B120F9292800000PB080000F2A795BB540447553D9FA3812E64E76A577DA04A1E0DD9FA9EFDE394BE936D4532BF5BA7E57063B63FCD90F9C2020F77";
+    let stream = format!("{}{}", key_block_1, key_block_2);
+
+    let key_blocks: Vec<String> = tr31_parse_stream(&stream)
+        .into_iter()
+        .map(|res| res.unwrap())
+        .collect();
+
+    assert_eq!(key_blocks, vec![key_block_1, key_block_2]);
+    assert!(tr31_parse_stream("").is_empty());
+}
+
+#[test]
+pub fn test_tr31_parse_stream_truncated_key_block() {
+    let key_block_1 = "D0112P0AE00E0000B82679114F470F540165EDFBF7E250FCEA43F810D215F8D207E2E417C07156A27E8E31DA05F7425509593D03A457DC34";
+    let stream = format!("{}{}", key_block_1, &key_block_1[..100]);
+
+    let key_blocks = tr31_parse_stream(&stream);
+    assert_eq!(key_blocks.len(), 2);
+    assert_eq!(key_blocks[0].as_ref().unwrap(), key_block_1);
+
+    let err = key_blocks[1].as_ref().unwrap_err();
+    let parse_error = err.parse_error().unwrap();
+    assert_eq!(parse_error.field(), "key block");
+    assert_eq!(parse_error.range(), 112..212);
+
+    let key_blocks = tr31_parse_stream("D01X2P0AE00E0000");
+    assert_eq!(
+        key_blocks[0].as_ref().unwrap_err().to_string(),
+        "TR31-0007: ERROR TR-31 HEADER: Invalid key block length"
+    );
+}
+
+#[test]
+pub fn test_tr31_validate_structure_valid() {
+    let key_block = "D0144P0TE00N0200KS1800604B120F9292800000PB080000F2A795BB540447553D9FA3812E64E76A577DA04A1E0DD9FA9EFDE394BE936D4532BF5BA7E57063B63FCD90F9C2020F77";
+    assert!(tr31_validate_structure(key_block).is_ok());
+}
+
+#[test]
+pub fn test_tr31_validate_structure_errors() {
+    let valid = "D0112P0AE00E0000B82679114F470F540165EDFBF7E250FCEA43F810D215F8D207E2E417C07156A27E8E31DA05F7425509593D03A457DC34";
+    let payload_24_bytes = "00".repeat(24);
+    let mac = "00".repeat(16);
+
+    let test_cases = [
+        // Header cannot be parsed
+        (
+            format!("D01X2{}", &valid[5..]),
+            "key block length",
+            "TR31-0007: ERROR TR-31 HEADER: Invalid key block length",
+        ),
+        // Key block length does not match the header
+        (
+            valid[..108].to_string(),
+            "key block length",
+            "TR31-0005: ERROR TR-31: Key block length does not match its length in the header: declared 112, actual 108, 4 character(s) too short",
+        ),
+        // Shorter than header, one payload block and MAC
+        (
+            format!("D0064P0AE00E0000{}", "0".repeat(48)),
+            "key block",
+            "TR31-0007: ERROR TR-31: Key block length is below minimum required length",
+        ),
+        // Invalid hex in the encrypted payload
+        (
+            format!("{}G{}", &valid[..16], &valid[17..]),
+            "encrypted payload",
+            "TR31-0007: Invalid character 'G' at position 0",
+        ),
+        // Invalid hex in the MAC
+        (
+            format!("{}G", &valid[..111]),
+            "MAC",
+            "TR31-0007: Invalid character 'G' at position 31",
+        ),
+        // Encrypted payload is not a multiple of the block length
+        (
+            format!("D0096P0AE00E0000{}{}", payload_24_bytes, mac),
+            "encrypted payload",
+            "TR31-0007: ERROR TR-31: Encrypted payload length is not a multiple of block length: 16",
+        ),
+    ];
+
+    for (key_block, expected_field, expected_message) in test_cases {
+        let err = tr31_validate_structure(&key_block).unwrap_err();
+        let parse_error = err.parse_error().unwrap();
+        assert_eq!(parse_error.field(), expected_field, "{}", key_block);
+        assert_eq!(err.to_string(), expected_message, "{}", key_block);
+    }
+}
+
+#[cfg(not(feature = "no-clear-secrets"))]
+#[test]
+pub fn test_tr31_wrap_with_min_payload_blocks() {
+    let header = KeyBlockHeader::new_with_values("D", "P0", "A", "E", "00", "E").unwrap();
+    let key = hex::decode("3F419E1CB7079442").unwrap();
+    let random_seed = hex::decode("1C2965473CE206BB855B01533782A5E4F1D09C3B7E61").unwrap();
+    let kbpk =
+        hex::decode("88E1AB2A2E3DD38C1FA039A536500CC8A87AB9D62DC92C01058FA79F44657DE6").unwrap();
+
+    let key_block =
+        tr31_wrap_with_min_payload_blocks(&kbpk, header, &key, 0, 2, &random_seed).unwrap();
+
+    // Header, two encrypted payload blocks and MAC in hex
+    assert_eq!(key_block.len(), 16 + 2 * 32 + 32);
+    assert_eq!(&key_block[..5], "D0112");
+
+    let (_, unwrapped_key) = tr31_unwrap(&kbpk, &key_block).unwrap();
+    assert_eq!(unwrapped_key, key);
+}
+
+#[test]
+pub fn test_tr31_unwrap_version_c_header_iv() {
+    // Version 'C' encrypts the payload with the first 8 bytes of the header as IV, not with a
+    // zero IV. Key block of TR-31: 2018, A.7.3.1.
+    let kbpk = hex::decode("B8ED59E0A279A295E9F5ED7944FD06B9").unwrap();
+    let key_block = "C0096B0TX12S0100KS1800604B120F9292800000BFB9B689CB567E66FC3FEE5AD5F52161FC6545B9D60989015D02155C";
+    let kbek: Vec<u8> = kbpk.iter().map(|b| b ^ 0x45).collect();
+    let encrypted_payload = hex::decode(&key_block[40..88]).unwrap();
+
+    let iv: [u8; 8] = key_block.as_bytes()[..8].try_into().unwrap();
+    let payload = crate::crypto::tdes::tdes_dec_cbc(&encrypted_payload, &kbek, &iv).unwrap();
+    assert_eq!(
+        hex::encode_upper(&payload),
+        "0080EDB380DD340BC2620247D445F5B8D6788546A8ED98D1"
+    );
+
+    let zero_iv_payload =
+        crate::crypto::tdes::tdes_dec_cbc(&encrypted_payload, &kbek, &[0u8; 8]).unwrap();
+    assert_ne!(zero_iv_payload[..8], payload[..8]);
+}
+
+#[test]
+pub fn test_tr31_verify() {
+    let key_block = "D0112P0AE00E0000B82679114F470F540165EDFBF7E250FCEA43F810D215F8D207E2E417C07156A27E8E31DA05F7425509593D03A457DC34";
+    let kbpk =
+        hex::decode("88E1AB2A2E3DD38C1FA039A536500CC8A87AB9D62DC92C01058FA79F44657DE6").unwrap();
+
+    let header = tr31_verify(&kbpk, key_block).unwrap();
+    assert_eq!(header.export_str().unwrap(), &key_block[..16]);
+
+    // Wrong KBPK and wrong MAC
+    let mut wrong_kbpk = kbpk.clone();
+    wrong_kbpk[0] ^= 0x01;
+    assert!(tr31_verify(&wrong_kbpk, key_block).is_err());
+    let tampered = format!("{}3", &key_block[..key_block.len() - 1]);
+    assert!(tr31_verify(&kbpk, &tampered).is_err());
+}
+
+#[cfg(all(feature = "testing", not(feature = "no-clear-secrets")))]
+#[test]
+pub fn test_tr31_compute_mac_forged_payload() {
+    use super::super::key_derivations::derive_keys_version_d;
+    use crate::crypto::aes::{aes_dec_cbc, aes_enc_cbc};
+
+    let key_block = "D0112P0AE00E0000B82679114F470F540165EDFBF7E250FCEA43F810D215F8D207E2E417C07156A27E8E31DA05F7425509593D03A457DC34";
+    let kbpk =
+        hex::decode("88E1AB2A2E3DD38C1FA039A536500CC8A87AB9D62DC92C01058FA79F44657DE6").unwrap();
+    let (kbek, kbak) = derive_keys_version_d(&kbpk).unwrap();
+
+    let (header, rest) = key_block.split_at(16);
+    let (encrypted_payload, mac) = rest.split_at(rest.len() - 32);
+    let mac: [u8; 16] = hex::decode(mac).unwrap().try_into().unwrap();
+    let mut payload = aes_dec_cbc(&hex::decode(encrypted_payload).unwrap(), &kbek, &mac).unwrap();
+
+    // The MAC of the unaltered payload is the MAC of the key block
+    assert_eq!(tr31_compute_mac(&kbak, header, &payload).unwrap(), mac);
+
+    // Alter the first key byte and forge a consistent key block
+    payload[2] ^= 0xFF;
+    let forged_mac = tr31_compute_mac(&kbak, header, &payload).unwrap();
+    let forged_payload = aes_enc_cbc(&payload, &kbek, &forged_mac).unwrap();
+    let forged_block = format!(
+        "{}{}{}",
+        header,
+        hex::encode_upper(forged_payload),
+        hex::encode_upper(forged_mac)
+    );
+
+    let (_, key) = tr31_unwrap(&kbpk, &forged_block).unwrap();
+    assert_eq!(hex::encode_upper(key), "C0419E1CB7079442AA37474C2EFBF8B8");
+
+    assert!(tr31_compute_mac(&kbak, "B0112P0TE00E0000", &payload).is_err());
+}
+
+#[cfg(not(feature = "no-clear-secrets"))]
+#[test]
+pub fn test_tr31_signing_input() {
+    use super::super::key_derivations::{derive_keys_version_b, derive_keys_version_d};
+    use crate::crypto::aes::aes_cmac;
+    use crate::crypto::tdes::tdes_cmac;
+
+    let key = hex::decode("3F419E1CB7079442AA37474C2EFBF8B8").unwrap();
+    let random_seed = hex::decode("1C2965473CE206BB855B01533782").unwrap();
+
+    // Version 'D': the MAC of the key block is the AES-CMAC of the signing input
+    let kbpk =
+        hex::decode("88E1AB2A2E3DD38C1FA039A536500CC8A87AB9D62DC92C01058FA79F44657DE6").unwrap();
+    let header = KeyBlockHeader::new_with_values("D", "P0", "A", "E", "00", "E").unwrap();
+    let signing_input = tr31_signing_input(&header, &key, 0, &random_seed).unwrap();
+    let key_block = tr31_wrap(&kbpk, header, &key, 0, &random_seed).unwrap();
+
+    let header_len = 16;
+    let payload_len = 2 + 16 + 14;
+    assert_eq!(signing_input.len(), header_len + payload_len);
+    assert_eq!(
+        &signing_input[..header_len],
+        &key_block.as_bytes()[..header_len]
+    );
+    let (_, kbak) = derive_keys_version_d(&kbpk).unwrap();
+    assert_eq!(
+        hex::encode_upper(aes_cmac(&signing_input, &kbak).unwrap()),
+        &key_block[key_block.len() - 32..]
+    );
+
+    // Version 'B' with optional blocks
+    let kbpk = hex::decode("0123456789ABCDEFFEDCBA9876543210").unwrap();
+    let mut header =
+        KeyBlockHeader::new_from_str("B0000P0TE00N0100KS1800604B120F9292800000").unwrap();
+    header.finalize().unwrap();
+    let signing_input = tr31_signing_input(&header, &key, 0, &random_seed[..6]).unwrap();
+    let key_block = tr31_wrap(&kbpk, header.clone(), &key, 0, &random_seed[..6]).unwrap();
+
+    assert_eq!(signing_input.len(), header.len() + 24);
+    let (_, kbak, _) = derive_keys_version_b(&kbpk).unwrap();
+    assert_eq!(
+        hex::encode_upper(tdes_cmac(&signing_input, &kbak).unwrap()),
+        &key_block[key_block.len() - 16..]
+    );
+
+    // Version 'C' MACs the encrypted payload
+    let header = KeyBlockHeader::new_with_values("C", "P0", "T", "E", "00", "E").unwrap();
+    assert!(tr31_signing_input(&header, &key, 0, &random_seed).is_err());
+}
+
+#[cfg(all(feature = "rng", not(feature = "no-clear-secrets")))]
+#[test]
+pub fn test_tr31_wrap_with_rng() {
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    let mut rng = StdRng::seed_from_u64(0x5eed);
+    let key = hex::decode("3F419E1CB7079442AA37474C2EFBF8B8").unwrap();
+    for (version_id, kbpk_hex) in [
+        ("B", "89E88CF7931444F334BD7547FC3F380C"),
+        ("C", "89E88CF7931444F334BD7547FC3F380C"),
+        (
+            "D",
+            "88E1AB2A2E3DD38C1FA039A536500CC8A87AB9D62DC92C01058FA79F44657DE6",
+        ),
+    ] {
+        let kbpk = hex::decode(kbpk_hex).unwrap();
+        for masked_key_len in [0, 24, 32] {
+            let header =
+                KeyBlockHeader::new_with_values(version_id, "P0", "T", "E", "00", "E").unwrap();
+            let key_block =
+                tr31_wrap_with_rng(&kbpk, header, &key, masked_key_len, &mut rng).unwrap();
+            let (_, unwrapped_key) = tr31_unwrap(&kbpk, &key_block).unwrap();
+            assert_eq!(unwrapped_key, key);
+        }
+    }
+
+    let header = KeyBlockHeader::new_with_values("A", "P0", "T", "E", "00", "E").unwrap();
+    assert!(tr31_wrap_with_rng(&[0x11; 16], header, &key, 0, &mut rng).is_err());
+}
+
+#[cfg(feature = "rng")]
+#[test]
+pub fn test_tr31_wrap_with_rng_error() {
+    // A random number generator which always fails
+    struct FailingRng;
+
+    impl rand::RngCore for FailingRng {
+        fn next_u32(&mut self) -> u32 {
+            unreachable!("tr31_wrap_with_rng only calls try_fill_bytes")
+        }
+
+        fn next_u64(&mut self) -> u64 {
+            unreachable!("tr31_wrap_with_rng only calls try_fill_bytes")
+        }
+
+        fn fill_bytes(&mut self, _dest: &mut [u8]) {
+            unreachable!("tr31_wrap_with_rng only calls try_fill_bytes")
+        }
+
+        fn try_fill_bytes(&mut self, _dest: &mut [u8]) -> Result<(), rand::Error> {
+            Err(rand::Error::new("entropy source unavailable"))
+        }
+    }
+
+    impl rand::CryptoRng for FailingRng {}
+
+    let header = KeyBlockHeader::new_with_values("D", "P0", "A", "E", "00", "E").unwrap();
+    let res = tr31_wrap_with_rng(&[0x11; 32], header, &[0x22; 16], 0, &mut FailingRng);
+    assert_eq!(
+        res.unwrap_err().to_string(),
+        "PSEC-0000: ERROR TR-31: Random number generator failed: entropy source unavailable"
+    );
+}
+
+#[cfg(all(feature = "rng", not(feature = "no-clear-secrets")))]
+#[test]
+pub fn test_tr31_wrap_auto_seed() {
+    let kbpk = hex::decode("89E88CF7931444F334BD7547FC3F380C").unwrap();
+    let key = hex::decode("EDB380DD340BC2620247D445F5B8D678").unwrap();
+
+    let header = KeyBlockHeader::new_with_values("B", "P0", "T", "E", "00", "E").unwrap();
+    let first = tr31_wrap_auto_seed(&kbpk, header.clone(), &key, 0).unwrap();
+    let second = tr31_wrap_auto_seed(&kbpk, header, &key, 0).unwrap();
+
+    // The random padding yields a different payload on every call
+    assert_ne!(first, second);
+    assert_eq!(tr31_unwrap(&kbpk, &first).unwrap().1, key);
+    assert_eq!(tr31_unwrap(&kbpk, &second).unwrap().1, key);
+}
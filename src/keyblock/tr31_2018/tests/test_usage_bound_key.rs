@@ -0,0 +1,87 @@
+use crate::error::PaysecError;
+use crate::keyblock::*;
+use crate::utils::seed_from_label;
+
+const KBPK: &str = "88E1AB2A2E3DD38C1FA039A536500CC8A87AB9D62DC92C01058FA79F44657DE6";
+const KEY: &str = "3F419E1CB7079442AA37474C2EFBF8B8";
+
+fn wrap_and_unwrap_bound(key_usage: &str, mode_of_use: &str) -> UsageBoundKey {
+    let kbpk = hex::decode(KBPK).unwrap();
+    let key = hex::decode(KEY).unwrap();
+    let header =
+        KeyBlockHeader::new_with_values("D", key_usage, "A", mode_of_use, "00", "E").unwrap();
+    let seed = seed_from_label("usage bound key", 14);
+
+    let key_block = tr31_wrap(&kbpk, header, &key, 0, &seed).unwrap();
+    tr31_unwrap_bound(&kbpk, &key_block).unwrap()
+}
+
+#[test]
+fn test_tr31_unwrap_bound_modes_of_use() {
+    // (mode of use, encryption permitted, decryption permitted)
+    let cases = [
+        ("E", true, false),
+        ("D", false, true),
+        ("B", true, true),
+        ("N", true, true),
+    ];
+
+    for (mode_of_use, encrypt, decrypt) in cases {
+        let key = wrap_and_unwrap_bound("P0", mode_of_use);
+        assert_eq!(key.key_usage(), "P0");
+        assert_eq!(key.algorithm(), "A");
+        assert_eq!(key.mode_of_use(), mode_of_use);
+        assert_eq!(key.key_unchecked(), hex::decode(KEY).unwrap());
+
+        assert_eq!(
+            key.check(KeyOperation::Encrypt).is_ok(),
+            encrypt,
+            "{}",
+            mode_of_use
+        );
+        assert_eq!(
+            key.check(KeyOperation::Decrypt).is_ok(),
+            decrypt,
+            "{}",
+            mode_of_use
+        );
+        assert_eq!(key.key_for(KeyOperation::Encrypt).is_ok(), encrypt);
+        assert_eq!(key.key_for(KeyOperation::Decrypt).is_ok(), decrypt);
+    }
+}
+
+#[test]
+fn test_mode_of_use_violation() {
+    let key = wrap_and_unwrap_bound("P0", "E");
+
+    let violation = key.check(KeyOperation::Decrypt).unwrap_err();
+    assert_eq!(violation.mode_of_use(), "E");
+    assert_eq!(violation.operation(), KeyOperation::Decrypt);
+    assert_eq!(
+        violation.to_string(),
+        "ERROR TR-31: Mode of use E does not permit decryption"
+    );
+
+    let err = key.key_for(KeyOperation::Decrypt).unwrap_err();
+    assert_eq!(err, PaysecError::ModeOfUseViolation(violation));
+}
+
+#[test]
+fn test_usage_bound_key_new_and_debug() {
+    let header = KeyBlockHeader::new_with_values("D", "P0", "A", "D", "00", "N").unwrap();
+    let key = UsageBoundKey::new(&header, hex::decode(KEY).unwrap());
+    assert_eq!(key.mode_of_use(), "D");
+
+    let debug = format!("{:?}", key);
+    assert!(debug.contains("<redacted>"));
+    assert!(debug.contains("key_len: 16"));
+    assert!(!debug.contains("3F419E1C"));
+}
+
+#[test]
+fn test_usage_bound_key_new_from_secret_key() {
+    let header = KeyBlockHeader::new_with_values("D", "P0", "A", "E", "00", "N").unwrap();
+    let key = UsageBoundKey::new(&header, SecretKey::new(hex::decode(KEY).unwrap()));
+    assert_eq!(key.key_unchecked(), hex::decode(KEY).unwrap());
+    assert_eq!(key, UsageBoundKey::new(&header, hex::decode(KEY).unwrap()));
+}
@@ -8,7 +8,7 @@
 //! # Description
 //!
 //! This module provides functions for wrapping and unwrapping cryptographic keys according to
-//! the TR-31 key block format, version 'D'. TR-31 defines a method consistent with the requirements
+//! the TR-31 key block format, versions 'B', 'C' and 'D'. TR-31 defines a method consistent with the requirements
 //! of ANS X9.24 Retail Financial Services Symmetric Key Management Part 1 for the secure exchange of
 //! keys and other sensitive data between two devices that share a symmetric key exchange key. This
 //! method may also be used for the storage of keys under a symmetric key.
@@ -23,20 +23,24 @@
 //!     - Two bytes indicating the key length.
 //!     - The key/sensitive data that is being exchanged and/or stored.
 //!     - Random padding up to a fixed length or masked length.
-//! 3. A MAC, which is 16 bytes long.
+//! 3. A MAC, which is 4 bytes long for version 'C', 8 bytes long for version 'B' and 16 bytes
+//!    long for version 'D'.
 //!
 //! # Key Block Binding Method (TR-31: 2018, p. 9-13)
 //!
 //! The Key Block Binding Method is the technique use to protect the secrecy and integrity of the
 //! key block. The method uses a Key Block Protection Key (KBPK) that was previously echanged
-//! between two communicating parties. Currently version 'D' is implemented which uses AES-CMAC to
-//! derive the encryption and authentication keys from the KBPK. The key block construction process
+//! between two communicating parties. Version 'B' uses TDEA-CMAC and version 'D' uses AES-CMAC to
+//! derive the encryption and authentication keys from the KBPK, while version 'C' uses variants of
+//! the KBPK (Key Variant Binding Method). The key block construction process
 //! includes key derivation, payload construction, MAC computation, encryption, and assembly of the
 //! final key block.
 //!
 //! # Supported Version
 //!
-//! Only version 'D' is supported for key block wrapping and unwrapping by implementation.
+//! Versions 'B', 'C' and 'D' are supported for key block wrapping and unwrapping by
+//! implementation. The deprecated version 'A' is only supported for unwrapping, e.g. to migrate
+//! archived key blocks to version 'D'.
 //!
 //! # Usage
 //!
@@ -54,8 +58,9 @@
 //!   header properties.
 //! - The random seed must be provided externally; this library does not assess
 //!   entropy or random number generation quality.
-//! - Cryptographic operations use the `soft-aes` crate, which (currently) lacks
-//!   protections against side-channel attacks.
+//! - Cryptographic operations use the `soft-aes` crate by default, which (currently) lacks
+//!   protections against side-channel attacks. The `backend-rustcrypto` feature selects the
+//!   constant-time RustCrypto `aes` and `cmac` crates instead.
 //! - In a production environment, using a hardware security module (HSM) for
 //!   core cryptographic operations and random number generation is recommended.
 //! - Compliance with specific security standards such as PCI DSS, PCI P2PE and PCI PIN
@@ -78,6 +83,8 @@
 //!
 //! # Example 1: Wrapping and Unwrapping a Key:
 //! ```
+//! # #[cfg(not(feature = "no-clear-secrets"))]
+//! # {
 //! use paysec::keyblock::{tr31_wrap, tr31_unwrap};
 //! use paysec::keyblock::{KeyBlockHeader, OptBlock};
 //! use hex;
@@ -120,10 +127,13 @@
 //! // Step 9: Validating the Unwrapped Key
 //! assert_eq!(unwrapped_key, key, "Key unwrapping mismatch");
 //! // Ensure the unwrapped key matches the original key.
+//! # }
 //! ```
 //!
 //! # Example 2: Wrapping and Unwrapping a Key with a Header String:
 //! ```
+//! # #[cfg(not(feature = "no-clear-secrets"))]
+//! # {
 //! use paysec::keyblock::{tr31_wrap_with_header_string, tr31_unwrap};
 //! use hex;
 //!
@@ -157,6 +167,7 @@
 //!
 //! // Validating that the unwrapped key matches the original key.
 //! assert_eq!(unwrapped_key, key, "Key unwrapping mismatch");
+//! # }
 //! ```
 //!
 //! Example 3: Wrapping and Unwrapping a Key with Optional Blocks and Padding
@@ -165,6 +176,8 @@
 //! including the addition of a padding block to finalize the header.
 //!
 //! ```
+//! # #[cfg(not(feature = "no-clear-secrets"))]
+//! # {
 //! use paysec::keyblock::{tr31_wrap, tr31_unwrap};
 //! use paysec::keyblock::{KeyBlockHeader, OptBlock};
 //! use hex;
@@ -201,23 +214,45 @@
 //!
 //! // Validating that the unwrapped key matches the original key.
 //! assert_eq!(unwrapped_key, key, "Key unwrapping mismatch");
+//! # }
 //! ```
 
+use super::kcv::kcv_block_matches;
 use super::key_block_header::KeyBlockHeader;
-use super::key_derivations::derive_keys_version_d;
-use super::payload::{construct_payload, extract_key_from_payload};
-use soft_aes::aes::{aes_cmac, aes_dec_cbc, aes_enc_cbc};
-use std::error::Error;
+use super::opt_block::OptBlock;
+use super::parse_error::ParseError;
+use super::payload::{
+    calculate_padding_length, construct_payload_with_min, extract_key_from_payload, extract_padding,
+};
+use super::scheme::{KeyBlockScheme, VersionBScheme, VersionCScheme, VersionDScheme};
+use super::secret_key::SecretKey;
+#[cfg(feature = "testing")]
+use crate::crypto::aes::aes_cmac;
+use crate::error::PaysecError;
+#[cfg(not(feature = "no-clear-secrets"))]
+use crate::utils::{adjust_odd_parity, has_odd_parity};
+use crate::utils::{check_seed_entropy, ct_hex_decode, ct_hex_encode_upper};
+#[cfg(not(feature = "no-clear-secrets"))]
+use crate::validation::TdesParityCheck;
+use crate::validation::ValidationConfig;
+#[cfg(feature = "rng")]
+use rand::rngs::OsRng;
+#[cfg(feature = "rng")]
+use rand::{CryptoRng, RngCore};
+#[cfg(not(feature = "no-clear-secrets"))]
+use std::fmt;
 
-const TR31_D_MAC_LEN: usize = 16;
-const TR31_D_BLOCK_LEN: usize = 16;
+// Header, encrypted payload and MAC of a key block.
+type KeyBlockParts = (KeyBlockHeader, Vec<u8>, Vec<u8>);
 
-/// Wrap a cryptographic key according to TR-31 key block format version 'D'.
+/// Wrap a cryptographic key according to TR-31 key block format version 'B', 'C' or 'D'.
 ///
-/// This function implements the TR-31 key block wrapping mechanism for version 'D'. It involves
-/// several steps: key derivation, payload construction, MAC computation, encryption, and
-/// assembly of the final key block. It takes the key block protection key (KBPK), a mutable
-/// key block header, the key to be protected, a masked key length, and a random seed as inputs.
+/// This function implements the TR-31 key block wrapping mechanism for version 'B' (TDEA Key
+/// Derivation Binding Method), version 'C' (TDEA Key Variant Binding Method) and version 'D' (AES
+/// Key Derivation Binding Method). It involves several steps: key derivation, payload
+/// construction, MAC computation, encryption, and assembly of the final key block. It takes the
+/// key block protection key (KBPK), a mutable key block header, the key to be protected, a masked
+/// key length, and a random seed as inputs.
 ///
 /// # Arguments
 /// * `kbpk` - Key Block Protection Key used for deriving the encryption (KBEK) and
@@ -237,74 +272,455 @@ const TR31_D_BLOCK_LEN: usize = 16;
 ///
 /// # Errors
 /// Returns an error if:
-/// * The key block version is not supported (currently only 'D' is implemented).
+/// * The key block version is not supported (currently 'B', 'C' and 'D' are implemented). The
+///   deprecated version 'A' is rejected with a dedicated error, it can only be unwrapped.
+/// * The KBPK length is not a valid TDES key length (16 or 24 bytes) for versions 'B' and 'C' or
+///   not a valid AES key length (16, 24 or 32 bytes) for version 'D'.
+/// * The header has optional blocks but was not finalized with `KeyBlockHeader::finalize`.
 /// * The total key block length is not a multiple of the block size for the underlying
 ///   algorithms.
 /// * There are issues with key derivation, payload construction, MAC computation, or encryption.
 /// * The header or payload data are improperly formatted.
 pub fn tr31_wrap(
     kbpk: &[u8],
-    mut header: KeyBlockHeader,
+    header: KeyBlockHeader,
+    key: &[u8],
+    masked_key_len: usize,
+    random_seed: &[u8],
+) -> Result<String, PaysecError> {
+    tr31_wrap_with_min_payload_blocks(kbpk, header, key, masked_key_len, 1, random_seed)
+}
+
+/// Wrap a cryptographic key according to TR-31 key block format version 'B', 'C' or 'D' with a
+/// minimum payload size.
+///
+/// Same as `tr31_wrap`, but the payload is padded to at least `min_payload_blocks` cipher
+/// blocks, for hosts with a policy requiring a larger payload for short keys than the single
+/// block of `tr31_wrap`. The length of the random seed must cover the padding, see
+/// `calculate_padding_length_with_min`.
+///
+/// # Arguments
+/// * `kbpk` - Key Block Protection Key used for deriving the encryption (KBEK) and
+///   authentication (KBAK) keys.
+/// * `header` - KeyBlockHeader instance containing metadata for the key block.
+/// * `key` - The cryptographic key or sensitive data to be protected.
+/// * `masked_key_len` - Length used to mask the true length of short keys.
+/// * `min_payload_blocks` - Minimum number of cipher blocks of the payload.
+/// * `random_seed` - Random seed used for generating padding in the payload.
+///
+/// # Errors
+/// Returns an error in the same cases as `tr31_wrap`.
+pub fn tr31_wrap_with_min_payload_blocks(
+    kbpk: &[u8],
+    header: KeyBlockHeader,
     key: &[u8],
     masked_key_len: usize,
+    min_payload_blocks: usize,
     random_seed: &[u8],
-) -> Result<String, Box<dyn Error>> {
-    if header.version_id() != "D" {
+) -> Result<String, PaysecError> {
+    // The binding method determines the valid KBPK lengths
+    let (valid_kbpk_len, key_type) = match header.version_id() {
+        "B" | "C" => (
+            matches!(kbpk.len(), 16 | 24),
+            "TDES key length (16 or 24 bytes)",
+        ),
+        "D" => (
+            matches!(kbpk.len(), 16 | 24 | 32),
+            "AES key length (16, 24 or 32 bytes)",
+        ),
+        "A" => return Err(PaysecError::DeprecatedVersion("A".to_string())),
+        version_id => return Err(PaysecError::UnsupportedVersion(version_id.to_string())),
+    };
+    if !valid_kbpk_len {
         return Err(format!(
-            "ERROR TR-31: Key block version not supported by implementation: {}",
+            "ERROR TR-31: KBPK length {} is not a valid {} for key block version {}",
+            kbpk.len(),
+            key_type,
             header.version_id()
         )
         .into());
     }
 
+    match header.version_id() {
+        "B" => wrap_with_scheme::<VersionBScheme>(
+            kbpk,
+            header,
+            key,
+            masked_key_len,
+            min_payload_blocks,
+            random_seed,
+        ),
+        "C" => wrap_with_scheme::<VersionCScheme>(
+            kbpk,
+            header,
+            key,
+            masked_key_len,
+            min_payload_blocks,
+            random_seed,
+        ),
+        _ => wrap_with_scheme::<VersionDScheme>(
+            kbpk,
+            header,
+            key,
+            masked_key_len,
+            min_payload_blocks,
+            random_seed,
+        ),
+    }
+}
+
+/// Wrap a cryptographic key like `tr31_wrap` with the padding drawn from a random number
+/// generator.
+///
+/// The length of the padding is calculated with `calculate_padding_length` for the block size of
+/// the key block version, and the padding is filled from `rng`. Use `tr31_wrap` with an explicit
+/// random seed to reproduce test vectors.
+///
+/// The function requires the `rng` feature.
+///
+/// # Arguments
+/// * `kbpk` - Key Block Protection Key used for deriving the encryption (KBEK) and
+///   authentication (KBAK) keys.
+/// * `header` - KeyBlockHeader instance containing metadata for the key block.
+/// * `key` - The cryptographic key or sensitive data to be protected.
+/// * `masked_key_len` - Length used to mask the true length of short keys.
+/// * `rng` - Cryptographically secure random number generator for the padding.
+///
+/// # Errors
+/// Returns an error if:
+/// * The RNG fails to provide the padding bytes.
+/// * `tr31_wrap` fails.
+///
+/// # Example
+/// ```
+/// use paysec::keyblock::{tr31_wrap_with_rng, KeyBlockHeader};
+/// use rand::rngs::OsRng;
+///
+/// let kbpk =
+///     hex::decode("88E1AB2A2E3DD38C1FA039A536500CC8A87AB9D62DC92C01058FA79F44657DE6").unwrap();
+/// let header = KeyBlockHeader::new_with_values("D", "P0", "A", "E", "00", "E").unwrap();
+/// let key = hex::decode("3F419E1CB7079442AA37474C2EFBF8B8").unwrap();
+///
+/// let key_block = tr31_wrap_with_rng(&kbpk, header, &key, 0, &mut OsRng).unwrap();
+/// assert_eq!(&key_block[..16], "D0112P0AE00E0000");
+/// ```
+#[cfg(feature = "rng")]
+pub fn tr31_wrap_with_rng<R: CryptoRng + RngCore + ?Sized>(
+    kbpk: &[u8],
+    header: KeyBlockHeader,
+    key: &[u8],
+    masked_key_len: usize,
+    rng: &mut R,
+) -> Result<String, PaysecError> {
+    let (block_len, _) = binding_method_lengths(header.version_id())?;
+    let padding_len = calculate_padding_length(key.len(), masked_key_len, block_len)?;
+
+    let mut random_seed = vec![0u8; padding_len];
+    rng.try_fill_bytes(&mut random_seed)
+        .map_err(|e| format!("ERROR TR-31: Random number generator failed: {}", e))?;
+
+    tr31_wrap(kbpk, header, key, masked_key_len, &random_seed)
+}
+
+/// Wrap a cryptographic key like `tr31_wrap` with the padding drawn from the operating system
+/// CSPRNG.
+///
+/// Same as `tr31_wrap_with_rng` with `OsRng`. The function requires the `rng` feature.
+///
+/// # Errors
+/// Returns an error in the same cases as `tr31_wrap_with_rng`.
+///
+/// # Example
+/// ```
+/// use paysec::keyblock::{tr31_wrap_auto_seed, KeyBlockHeader};
+///
+/// let kbpk =
+///     hex::decode("88E1AB2A2E3DD38C1FA039A536500CC8A87AB9D62DC92C01058FA79F44657DE6").unwrap();
+/// let header = KeyBlockHeader::new_with_values("D", "P0", "A", "E", "00", "E").unwrap();
+/// let key = hex::decode("3F419E1CB7079442AA37474C2EFBF8B8").unwrap();
+///
+/// let key_block = tr31_wrap_auto_seed(&kbpk, header, &key, 0).unwrap();
+/// assert_eq!(key_block.len(), 112);
+/// ```
+#[cfg(feature = "rng")]
+pub fn tr31_wrap_auto_seed(
+    kbpk: &[u8],
+    header: KeyBlockHeader,
+    key: &[u8],
+    masked_key_len: usize,
+) -> Result<String, PaysecError> {
+    tr31_wrap_with_rng(kbpk, header, key, masked_key_len, &mut OsRng)
+}
+
+/// Return the input of the MAC of a key block without wrapping the key.
+///
+/// The input is the header with the total key block length followed by the clear payload, as
+/// covered by the MAC of the key derivation binding methods of versions 'B' and 'D'. An external
+/// signer, e.g. an HSM, can compute the MAC over these bytes. The payload is constructed like in
+/// `tr31_wrap`, so the same random seed yields the same payload.
+///
+/// The returned bytes contain the clear key.
+///
+/// # Arguments
+/// * `header` - KeyBlockHeader instance containing metadata for the key block.
+/// * `key` - The cryptographic key or sensitive data to be protected.
+/// * `masked_key_len` - Length used to mask the true length of short keys.
+/// * `random_seed` - Random seed used for generating padding in the payload.
+///
+/// # Errors
+/// Returns an error if:
+/// * The key block version is not 'B' or 'D'. The MAC of version 'C' covers the encrypted
+///   payload, which cannot be computed without the KBPK.
+/// * The header, the payload or the total key block length are invalid as in `tr31_wrap`.
+///
+/// # Example
+/// ```
+/// use paysec::keyblock::{tr31_signing_input, KeyBlockHeader};
+///
+/// let header = KeyBlockHeader::new_with_values("D", "P0", "A", "E", "00", "E").unwrap();
+/// let key = hex::decode("3F419E1CB7079442AA37474C2EFBF8B8").unwrap();
+/// let random_seed = hex::decode("1C2965473CE206BB855B01533782").unwrap();
+///
+/// let signing_input = tr31_signing_input(&header, &key, 0, &random_seed).unwrap();
+/// assert_eq!(&signing_input[..16], b"D0112P0AE00E0000");
+/// assert_eq!(signing_input.len(), 16 + 32);
+/// ```
+#[cfg(not(feature = "no-clear-secrets"))]
+pub fn tr31_signing_input(
+    header: &KeyBlockHeader,
+    key: &[u8],
+    masked_key_len: usize,
+    random_seed: &[u8],
+) -> Result<Vec<u8>, PaysecError> {
+    if header.version_id() == "A" {
+        return Err(PaysecError::DeprecatedVersion("A".to_string()));
+    }
+    if header.version_id() == "C" {
+        return Err(
+            "ERROR TR-31: Signing input not available for key block version C, its MAC covers the encrypted payload"
+                .into(),
+        );
+    }
+    let (block_len, mac_len) = binding_method_lengths(header.version_id())?;
+
+    let (header_str, payload) = header_and_payload(
+        header.clone(),
+        key,
+        masked_key_len,
+        1,
+        random_seed,
+        block_len,
+        mac_len,
+    )?;
+
+    let mut signing_input = header_str.into_bytes();
+    signing_input.extend_from_slice(&payload);
+    Ok(signing_input)
+}
+
+// Wrap a key with the binding method of the key block version of the header.
+fn wrap_with_scheme<S: KeyBlockScheme>(
+    kbpk: &[u8],
+    header: KeyBlockHeader,
+    key: &[u8],
+    masked_key_len: usize,
+    min_payload_blocks: usize,
+    random_seed: &[u8],
+) -> Result<String, PaysecError> {
     // Derive keys
-    let (kbek, kbak) = derive_keys_version_d(kbpk)?;
+    let scheme = S::derive(kbpk)?;
+
+    let (header_str, payload) = header_and_payload(
+        header,
+        key,
+        masked_key_len,
+        min_payload_blocks,
+        random_seed,
+        S::BLOCK_LEN,
+        S::MAC_LEN,
+    )?;
+
+    // Calculate the mac and encrypt the payload
+    let (encrypted_payload, mac) = scheme.seal(&header_str, &payload)?;
+
+    // Construct the complete key block in ascii
+    let encrypted_payload_hex = ct_hex_encode_upper(&encrypted_payload);
+    let mac_hex = hex::encode_upper(&mac);
+    let complete_key_block = format!("{}{}{}", header_str, encrypted_payload_hex, mac_hex);
+
+    Ok(complete_key_block)
+}
+
+// Construct the payload and the header string with the total key block length, which are the
+// input of the MAC of the derivation binding methods.
+fn header_and_payload(
+    mut header: KeyBlockHeader,
+    key: &[u8],
+    masked_key_len: usize,
+    min_payload_blocks: usize,
+    random_seed: &[u8],
+    block_len: usize,
+    mac_len: usize,
+) -> Result<(String, SecretKey), PaysecError> {
+    // A header with optional blocks must be padded to the block length by finalize()
+    let header_remainder = header.len() % block_len;
+    if header.opt_blocks().is_some() && header_remainder != 0 {
+        return Err(
+            "ERROR TR-31: Header with optional blocks must be finalized before wrapping (call finalize())"
+                .into(),
+        );
+    }
 
-    // Construct payload
-    let payload = construct_payload(key, masked_key_len, TR31_D_BLOCK_LEN, random_seed)?;
+    // Construct payload, which is wiped when dropped
+    let payload = SecretKey::new(construct_payload_with_min(
+        key,
+        masked_key_len,
+        block_len,
+        min_payload_blocks,
+        random_seed,
+    )?);
 
-    // Calculate total key block length ascii encoded
-    let total_block_length = header.len() + (payload.len() * 2) + (TR31_D_MAC_LEN * 2);
+    // Calculate total key block length ascii encoded, range checked before the conversion to the
+    // u16 of the header, which would silently truncate lengths above 65535
+    let total_block_length = payload
+        .len()
+        .checked_add(mac_len)
+        .and_then(|len| len.checked_mul(2))
+        .and_then(|len| len.checked_add(header.len()))
+        .filter(|&len| len <= 9999)
+        .ok_or_else(|| {
+            format!(
+                "ERROR TR-31: Total key block length exceeds the maximum of 9999: header {}, payload {}, MAC {} characters",
+                header.len(),
+                payload.len().saturating_mul(2),
+                mac_len * 2
+            )
+        })?;
 
-    // Check if total_block_length is a multiple of TR31_D_BLOCK_LEN
-    if total_block_length % TR31_D_BLOCK_LEN != 0 {
+    // Check if total_block_length is a multiple of the block length
+    if total_block_length % block_len != 0 {
         return Err(format!(
             "ERROR TR-31: Total block length is not a multiple of block length: {}",
-            TR31_D_BLOCK_LEN
+            block_len
         )
         .into());
     }
 
     // Update the block length in the header
-    header.set_kb_length(total_block_length as u16)?;
+    header.set_kb_length(u16::try_from(total_block_length)?)?;
 
     // Export the header as string
     let header_str = header.export_str()?;
 
-    // Concatenate header as ascii bytes with the payload to get the mac input
-    let mut mac_input = header_str.as_bytes().to_vec();
-    mac_input.extend_from_slice(&payload);
+    Ok((header_str, payload))
+}
 
-    // Calculate the mac and encrypt the payload
-    let mac = aes_cmac(&mac_input, &kbak)?;
-    let iv: [u8; TR31_D_MAC_LEN] = mac[0..TR31_D_MAC_LEN]
-        .try_into()
-        .expect("ERROR TR-31: Mac slice with incorrect length");
-    let encrypted_payload = aes_enc_cbc(&payload, &kbek, &iv, None)?;
+/// A TR-31 key block version.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum KeyBlockVersion {
+    /// `A`: Key Variant Binding Method, deprecated.
+    A,
+    /// `B`: TDEA Key Derivation Binding Method.
+    B,
+    /// `C`: TDEA Key Variant Binding Method.
+    C,
+    /// `D`: AES Key Derivation Binding Method.
+    D,
+}
 
-    // Construct the complete key block in ascii
-    let encrypted_payload_hex = hex::encode_upper(&encrypted_payload);
-    let mac_hex = hex::encode_upper(&mac);
-    let complete_key_block = format!("{}{}{}", header_str, encrypted_payload_hex, mac_hex);
+impl KeyBlockVersion {
+    /// Returns the version ID of the key block header.
+    pub fn id(&self) -> &'static str {
+        match self {
+            KeyBlockVersion::A => "A",
+            KeyBlockVersion::B => "B",
+            KeyBlockVersion::C => "C",
+            KeyBlockVersion::D => "D",
+        }
+    }
+}
 
-    Ok(complete_key_block)
+/// Get the key block versions supported by `tr31_wrap` and `tr31_unwrap`.
+///
+/// The deprecated version 'A' is not included, since `tr31_unwrap` reads it but `tr31_wrap`
+/// rejects it.
+///
+/// Callers exchanging key blocks with partners can use this to check at runtime whether a
+/// version is implemented, instead of matching on the error of a wrapping attempt.
+///
+/// # Example
+/// ```
+/// use paysec::keyblock::{supported_versions, KeyBlockVersion};
+///
+/// assert!(supported_versions().contains(&KeyBlockVersion::D));
+/// assert!(!supported_versions().contains(&KeyBlockVersion::A));
+/// ```
+pub fn supported_versions() -> &'static [KeyBlockVersion] {
+    &[KeyBlockVersion::B, KeyBlockVersion::C, KeyBlockVersion::D]
+}
+
+// The block length and the MAC length of the binding method of a supported key block version.
+// Version 'A' uses the binding method of version 'C' and is only supported for unwrapping.
+pub(super) fn binding_method_lengths(version_id: &str) -> Result<(usize, usize), PaysecError> {
+    match version_id {
+        "B" => Ok((VersionBScheme::BLOCK_LEN, VersionBScheme::MAC_LEN)),
+        "A" | "C" => Ok((VersionCScheme::BLOCK_LEN, VersionCScheme::MAC_LEN)),
+        "D" => Ok((VersionDScheme::BLOCK_LEN, VersionDScheme::MAC_LEN)),
+        _ => Err(PaysecError::UnsupportedVersion(version_id.to_string())),
+    }
+}
+
+/// Wrap a cryptographic key like `tr31_wrap` with a validation policy.
+///
+/// The following checks are controlled by `config`:
+/// - `masked_key_len`: Reject a masked key length which is neither zero nor at least the key
+///   length. `tr31_wrap` silently ignores such a masked key length.
+/// - `seed_entropy`: Apply `check_seed_entropy` to the part of the random seed used as padding.
+///
+/// # Arguments
+/// * `kbpk` - Key Block Protection Key used for deriving the encryption (KBEK) and
+///   authentication (KBAK) keys.
+/// * `header` - KeyBlockHeader instance containing metadata for the key block.
+/// * `key` - The cryptographic key or sensitive data to be protected.
+/// * `masked_key_len` - Length used to mask the true length of short keys.
+/// * `random_seed` - Random seed used for generating padding in the payload.
+/// * `config` - The validation policy, see `ValidationConfig`.
+///
+/// # Errors
+/// Returns an error if `tr31_wrap` fails or a check enabled in `config` fails.
+pub fn tr31_wrap_with_options(
+    kbpk: &[u8],
+    header: KeyBlockHeader,
+    key: &[u8],
+    masked_key_len: usize,
+    random_seed: &[u8],
+    config: &ValidationConfig,
+) -> Result<String, PaysecError> {
+    if config.masked_key_len && masked_key_len != 0 && masked_key_len < key.len() {
+        return Err(format!(
+            "ERROR TR-31: Masked key length {} is shorter than the key length {}",
+            masked_key_len,
+            key.len()
+        )
+        .into());
+    }
+
+    if config.seed_entropy {
+        let (block_len, _) = binding_method_lengths(header.version_id())?;
+        let padding_len = calculate_padding_length(key.len(), masked_key_len, block_len)?;
+        check_seed_entropy(random_seed, padding_len)?;
+    }
+
+    tr31_wrap(kbpk, header, key, masked_key_len, random_seed)
 }
 
-/// Wrap a cryptographic key according to TR-31 key block format version 'D' with a string header.
+/// Wrap a cryptographic key according to TR-31 key block format version 'B', 'C' or 'D' with a
+/// string header.
 ///
-/// This function wraps a cryptographic key according to the TR-31 key block format version 'D'.
-/// It takes a string representation of the key block header, the Key Block Protection Key (KBPK),
-/// the key to be protected, a masked key length, and a random seed as inputs.
+/// This function wraps a cryptographic key according to the TR-31 key block format version 'B',
+/// 'C' or 'D'. It takes a string representation of the key block header, the Key Block
+/// Protection Key (KBPK), the key to be protected, a masked key length, and a random seed as
+/// inputs.
 ///
 /// # Arguments
 /// * `header_str` - String representation of the key block header.
@@ -320,7 +736,9 @@ pub fn tr31_wrap(
 ///
 /// # Errors
 /// Returns an error if:
-/// * The key block version is not supported (currently only 'D' is implemented).
+/// * The key block version is not supported (currently 'B', 'C' and 'D' are implemented).
+/// * The KBPK length is not a valid TDES key length (16 or 24 bytes) for versions 'B' and 'C' or
+///   not a valid AES key length (16, 24 or 32 bytes) for version 'D'.
 /// * The total key block length is not a multiple of the of the block size for the underlying
 ///   algorithms.
 /// * There are issues with key derivation, payload construction, MAC computation, or encryption.
@@ -331,16 +749,419 @@ pub fn tr31_wrap_with_header_string(
     key: &[u8],
     masked_key_len: usize,
     random_seed: &[u8],
-) -> Result<String, Box<dyn Error>> {
+) -> Result<String, PaysecError> {
     let header = KeyBlockHeader::new_from_str(header_str)?;
 
     tr31_wrap(kbpk, header, key, masked_key_len, random_seed)
 }
 
-/// Unwrap a cryptographic key from a TR-31 key block format version 'D'.
+/// Wrap a cryptographic key according to TR-31 key block format version 'B', 'C' or 'D' with
+/// lowercase hex.
+///
+/// This function behaves like `tr31_wrap` but emits the encrypted payload and the MAC as
+/// lowercase hex digits, while the key block header is kept unchanged. It is intended for
+/// legacy receivers which require lowercase hex.
+///
+/// Note that lowercase hex is not standard: TR-31 specifies uppercase hex for the encrypted
+/// data and the MAC. `tr31_unwrap` accepts both cases.
+///
+/// # Arguments
+/// * `kbpk` - Key Block Protection Key used for deriving the encryption (KBEK) and
+///   authentication (KBAK) keys.
+/// * `header` - KeyBlockHeader instance containing metadata for the key block.
+/// * `key` - The cryptographic key or sensitive data to be protected.
+/// * `masked_key_len` - Length used to mask the true length of short keys.
+/// * `random_seed` - Random seed used for generating padding in the payload.
+///
+/// # Returns
+/// A `Result` containing the TR-31 formatted key block with lowercase payload and MAC hex
+/// as a String or an error if any step in the key block construction process fails.
+///
+/// # Errors
+/// Returns the same errors as `tr31_wrap`.
+pub fn tr31_wrap_lowercase(
+    kbpk: &[u8],
+    header: KeyBlockHeader,
+    key: &[u8],
+    masked_key_len: usize,
+    random_seed: &[u8],
+) -> Result<String, PaysecError> {
+    let header_len = header.len();
+    let key_block = tr31_wrap(kbpk, header, key, masked_key_len, random_seed)?;
+
+    // Keep the header ASCII as is and only lowercase the payload and MAC hex
+    let (header_str, data_hex) = key_block.split_at(header_len);
+
+    Ok(format!("{}{}", header_str, data_hex.to_ascii_lowercase()))
+}
+
+/// Convert a key block to its ASCII bytes, e.g. for a binary transport.
+///
+/// # Example
+/// ```
+/// use paysec::keyblock::{tr31_from_bytes, tr31_to_bytes};
+///
+/// let key_block = "D0112P0AE00E0000B82679114F470F540165EDFBF7E250FCEA43F810D215F8D207E2E417C07156A27E8E31DA05F7425509593D03A457DC34";
+/// let bytes = tr31_to_bytes(key_block);
+/// assert_eq!(tr31_from_bytes(&bytes).unwrap(), key_block);
+/// ```
+pub fn tr31_to_bytes(key_block: &str) -> Vec<u8> {
+    key_block.as_bytes().to_vec()
+}
+
+/// Convert the ASCII bytes of a key block, e.g. from a binary transport, to a string.
+///
+/// Only the character set is checked, the key block itself is not validated.
+///
+/// # Errors
+/// Returns a `PaysecError::Parse` error locating the first byte which is not ASCII.
+pub fn tr31_from_bytes(bytes: &[u8]) -> Result<String, PaysecError> {
+    if let Some(offset) = bytes.iter().position(|b| !b.is_ascii()) {
+        return Err(ParseError::located(
+            "key block",
+            offset,
+            1,
+            format!(
+                "ERROR TR-31: Key block contains non-ASCII byte {:02X} at offset {}",
+                bytes[offset], offset
+            ),
+        ));
+    }
+
+    Ok(bytes.iter().map(|&b| b as char).collect())
+}
+
+/// The format version of the compact binary form of `tr31_to_compact_bytes`.
+pub const TR31_COMPACT_FORMAT_VERSION: u8 = 1;
+
+// Flag of the compact binary form for a key block with lowercase hex.
+const COMPACT_FLAG_LOWERCASE: u8 = 0x01;
+
+/// Convert a key block to a compact binary form, e.g. for caching parsed key blocks.
+///
+/// The compact form is not a TR-31 wire format. It consists of:
+/// - The format version `TR31_COMPACT_FORMAT_VERSION` (1 byte).
+/// - Flags (1 byte), bit 0 is set if the hex of the key block is lowercase.
+/// - The header length (2 bytes, big-endian).
+/// - The ASCII header including the optional blocks.
+/// - The encrypted payload and the MAC as binary, which halves their length compared to hex.
+///
+/// The key block is checked with `tr31_validate_structure` and converted back losslessly by
+/// `tr31_from_compact_bytes`.
+///
+/// # Errors
+/// Returns an error if:
+/// * The structure of the key block is invalid, see `tr31_validate_structure`.
+/// * The hex of the encrypted payload and MAC mixes uppercase and lowercase letters.
+///
+/// # Example
+/// ```
+/// use paysec::keyblock::{tr31_from_compact_bytes, tr31_to_compact_bytes};
+///
+/// let key_block = "D0112P0AE00E0000B82679114F470F540165EDFBF7E250FCEA43F810D215F8D207E2E417C07156A27E8E31DA05F7425509593D03A457DC34";
+/// let bytes = tr31_to_compact_bytes(key_block).unwrap();
+/// assert_eq!(bytes.len(), 4 + 16 + 48);
+/// assert_eq!(tr31_from_compact_bytes(&bytes).unwrap(), key_block);
+/// ```
+pub fn tr31_to_compact_bytes(key_block: &str) -> Result<Vec<u8>, PaysecError> {
+    let (header, encrypted_payload, mac) =
+        parse_key_block_structure(key_block, &ValidationConfig::default())?;
+    let header_len = header.len();
+
+    let data_hex = &key_block[header_len..];
+    let has_lowercase = data_hex.bytes().any(|b| b.is_ascii_lowercase());
+    let has_uppercase = data_hex.bytes().any(|b| b.is_ascii_uppercase());
+    if has_lowercase && has_uppercase {
+        return Err(
+            "ERROR TR-31: Key block hex with mixed case is not supported by the compact form"
+                .into(),
+        );
+    }
+
+    let mut bytes = Vec::with_capacity(4 + header_len + encrypted_payload.len() + mac.len());
+    bytes.push(TR31_COMPACT_FORMAT_VERSION);
+    bytes.push(if has_lowercase {
+        COMPACT_FLAG_LOWERCASE
+    } else {
+        0
+    });
+    bytes.extend_from_slice(&(header_len as u16).to_be_bytes());
+    bytes.extend_from_slice(&key_block.as_bytes()[..header_len]);
+    bytes.extend_from_slice(&encrypted_payload);
+    bytes.extend_from_slice(&mac);
+
+    Ok(bytes)
+}
+
+/// Convert the compact binary form of `tr31_to_compact_bytes` back to a key block.
+///
+/// # Errors
+/// Returns an error if:
+/// * The format version or the flags are not supported.
+/// * The compact form is truncated or the header is not ASCII.
+/// * The resulting key block is structurally invalid, see `tr31_validate_structure`.
+pub fn tr31_from_compact_bytes(bytes: &[u8]) -> Result<String, PaysecError> {
+    if bytes.len() < 4 {
+        return Err("ERROR TR-31: Compact key block is truncated".into());
+    }
+    if bytes[0] != TR31_COMPACT_FORMAT_VERSION {
+        return Err(format!(
+            "ERROR TR-31: Unsupported compact key block format version: {}",
+            bytes[0]
+        )
+        .into());
+    }
+    let flags = bytes[1];
+    if flags & !COMPACT_FLAG_LOWERCASE != 0 {
+        return Err(format!(
+            "ERROR TR-31: Unsupported compact key block flags: {:02X}",
+            flags
+        )
+        .into());
+    }
+
+    let header_len = u16::from_be_bytes([bytes[2], bytes[3]]) as usize;
+    let header = bytes
+        .get(4..4 + header_len)
+        .ok_or("ERROR TR-31: Compact key block is truncated")?;
+    if !header.is_ascii() {
+        return Err("ERROR TR-31: Compact key block header is not ASCII".into());
+    }
+
+    let data_hex = ct_hex_encode_upper(&bytes[4 + header_len..]);
+    let data_hex = if flags & COMPACT_FLAG_LOWERCASE != 0 {
+        data_hex.to_ascii_lowercase()
+    } else {
+        data_hex
+    };
+    let key_block = format!("{}{}", String::from_utf8(header.to_vec())?, data_hex);
+
+    tr31_validate_structure(&key_block)?;
+    Ok(key_block)
+}
+
+/// Compute a CRC-32 checksum over the ASCII representation of a key block.
+///
+/// The checksum is the CRC-32 of ISO-HDLC (as used by zlib and Ethernet, reflected polynomial
+/// `0xEDB88320`) in big-endian byte order. It is an integrity aid for the storage of key blocks,
+/// e.g. to detect corrupted records, and no replacement for the MAC of the key block: it is not
+/// keyed and can be recomputed by anyone modifying the key block.
+///
+/// # Example
+/// ```
+/// use paysec::keyblock::tr31_block_checksum;
+///
+/// let key_block = "D0112P0AE00E0000B82679114F470F540165EDFBF7E250FCEA43F810D215F8D207E2E417C07156A27E8E31DA05F7425509593D03A457DC34";
+/// assert_eq!(tr31_block_checksum(key_block), [0x9F, 0x43, 0x85, 0x2F]);
+/// ```
+pub fn tr31_block_checksum(key_block: &str) -> [u8; 4] {
+    let mut crc = 0xFFFF_FFFFu32;
+    for &byte in key_block.as_bytes() {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    (!crc).to_be_bytes()
+}
+
+/// Compute the length of a key block header from its string representation.
+///
+/// This function reads the number of optional blocks from the header and walks the length
+/// fields of the optional blocks (including extended length fields) to compute the header
+/// length as 16 plus the length of all optional blocks. Neither the header fields nor the IDs
+/// and data of the optional blocks are validated, which makes this a cheap precheck compared
+/// to `KeyBlockHeader::new_from_str` followed by `len()`.
+///
+/// # Arguments
+/// * `header_str` - String starting with the key block header, e.g. a complete key block.
+///
+/// # Returns
+/// A `Result` containing the length of the header including optional blocks.
+///
+/// # Errors
+/// Returns an error if:
+/// * The string is shorter than the fixed header or the declared optional blocks.
+/// * The number of optional blocks or a length field cannot be parsed.
+pub fn tr31_header_len(header_str: &str) -> Result<usize, PaysecError> {
+    let field = |start: usize, end: usize| {
+        header_str
+            .get(start..end)
+            .ok_or("ERROR TR-31 HEADER: Invalid header length containing optional blocks")
+    };
+
+    if header_str.len() < 16 {
+        return Err("ERROR TR-31 HEADER: Invalid data length".into());
+    }
+
+    let num_opt_blocks = field(12, 14)?
+        .parse::<u8>()
+        .map_err(|_| "ERROR TR-31 HEADER: Invalid number of optional blocks")?;
+
+    let mut header_len = 16;
+    for _ in 0..num_opt_blocks {
+        let len_field = field(header_len + 2, header_len + 4)?;
+        let opt_block_len = if len_field == "00" {
+            // Extended length: length of length field (02) followed by the length in hex
+            let ext_len_field = field(header_len + 4, header_len + 10)?;
+            // Non-ASCII input may not split at the field boundaries, so slice with get()
+            let len_of_len = ext_len_field.get(0..2).unwrap_or(ext_len_field);
+            if len_of_len != "02" {
+                return Err(format!(
+                    "ERROR TR-31 OPT BLOCK: Invalid length of length field: {}",
+                    len_of_len
+                )
+                .into());
+            }
+            let ext_len = ext_len_field.get(2..6).unwrap_or(ext_len_field);
+            usize::from_str_radix(ext_len, 16).map_err(|_| {
+                format!(
+                    "ERROR TR-31 OPT BLOCK: Invalid extended length field: {}",
+                    ext_len_field
+                )
+            })?
+        } else {
+            usize::from_str_radix(len_field, 16).map_err(|_| {
+                format!(
+                    "ERROR TR-31 OPT BLOCK: Invalid length field: '{}' is not a valid hexadecimal number",
+                    len_field
+                )
+            })?
+        };
+
+        if opt_block_len < 4 {
+            return Err(format!(
+                "ERROR TR-31 OPT BLOCK: Invalid length field: value {} is too small (must be at least 4)",
+                opt_block_len
+            )
+            .into());
+        }
+
+        header_len += opt_block_len;
+        if header_len > header_str.len() {
+            return Err(
+                "ERROR TR-31 HEADER: Invalid header length containing optional blocks".into(),
+            );
+        }
+    }
+
+    Ok(header_len)
+}
+
+/// Split a string of concatenated key blocks into the individual key blocks.
+///
+/// Each key block is delimited by the key block length declared in its header, so key blocks
+/// can be concatenated without delimiters, e.g. in the output of an HSM. The key blocks are
+/// neither parsed nor verified beyond their length field.
+///
+/// # Arguments
+/// * `s` - String of concatenated key blocks.
+///
+/// # Returns
+/// A vector with the key block strings in order of their appearance. If a key block length
+/// cannot be read or exceeds the remaining string, the vector ends with an error, since the
+/// start of the following key block is unknown.
+///
+/// # Errors
+/// The error entry is a `PaysecError::Parse` error locating the length field or the truncated key block
+/// in `s`.
+///
+/// # Example
+/// ```
+/// use paysec::keyblock::tr31_parse_stream;
+///
+/// let key_block_1 = "D0112P0AE00E0000B82679114F470F540165EDFBF7E250FCEA43F810D215F8D207E2E417C07156A27E8E31DA05F7425509593D03A457DC34";
+/// let key_block_2 = "D0144P0TE00N0200KS1800604B120F9292800000PB080000F2A795BB540447553D9FA3812E64E76A577DA04A1E0DD9FA9EFDE394BE936D4532BF5BA7E57063B63FCD90F9C2020F77";
+///
+/// let key_blocks = tr31_parse_stream(&format!("{}{}", key_block_1, key_block_2));
+/// assert_eq!(key_blocks.len(), 2);
+/// assert_eq!(key_blocks[1].as_ref().unwrap(), key_block_2);
+/// ```
+pub fn tr31_parse_stream(s: &str) -> Vec<Result<String, PaysecError>> {
+    let mut key_blocks = Vec::new();
+    let mut offset = 0;
+
+    while offset < s.len() {
+        let kb_length = s
+            .get(offset + 1..offset + 5)
+            .filter(|len_field| len_field.chars().all(|c| c.is_ascii_digit()))
+            .and_then(|len_field| len_field.parse::<usize>().ok())
+            .filter(|&len| len >= 16);
+
+        let key_block = match kb_length {
+            Some(len) => s.get(offset..offset + len).ok_or_else(|| {
+                ParseError::located(
+                    "key block",
+                    offset,
+                    s.len() - offset,
+                    format!(
+                        "ERROR TR-31: Key block length {} exceeds the remaining stream length {}",
+                        len,
+                        s.len() - offset
+                    ),
+                )
+            }),
+            None => Err(ParseError::located(
+                "key block length",
+                offset + 1,
+                std::cmp::min(4, s.len() - offset - 1),
+                "ERROR TR-31 HEADER: Invalid key block length",
+            )),
+        };
+
+        match key_block {
+            Ok(key_block) => {
+                offset += key_block.len();
+                key_blocks.push(Ok(key_block.to_string()));
+            }
+            Err(e) => {
+                key_blocks.push(Err(e));
+                break;
+            }
+        }
+    }
+
+    key_blocks
+}
+
+/// Validate the structure of a TR-31 key block without the KBPK.
+///
+/// This function performs every non-cryptographic check of `tr31_unwrap` and returns the first
+/// structural problem found, e.g. to reject malformed key blocks at a gateway before attempting
+/// a keyed unwrap. A key block passing this check may still fail the MAC check.
+///
+/// # Arguments
+/// * `key_block` - The TR-31 formatted key block as a String.
+///
+/// # Errors
+/// Returns a `PaysecError::Parse` error locating the problem if:
+/// * The header cannot be parsed.
+/// * The key block length does not match the length declared in the header.
+/// * The key block is shorter than a header, one payload block and the MAC.
+/// * The key block version is not supported (currently 'A', 'B', 'C' and 'D' are implemented).
+/// * The encrypted payload or the MAC is not valid hex.
+/// * The encrypted payload is not a multiple of the cipher block length.
+///
+/// # Example
+/// ```
+/// use paysec::keyblock::tr31_validate_structure;
+///
+/// let key_block = "D0112P0AE00E0000B82679114F470F540165EDFBF7E250FCEA43F810D215F8D207E2E417C07156A27E8E31DA05F7425509593D03A457DC34";
+/// assert!(tr31_validate_structure(key_block).is_ok());
+/// assert!(tr31_validate_structure(&key_block[..108]).is_err());
+/// ```
+pub fn tr31_validate_structure(key_block: &str) -> Result<(), PaysecError> {
+    parse_key_block_structure(key_block, &ValidationConfig::default())?;
+    Ok(())
+}
+
+/// Unwrap a cryptographic key from a TR-31 key block format version 'A', 'B', 'C' or 'D'.
 ///
-/// This function implements the TR-31 key block unwrapping mechanism for version 'D'. It involves
-/// several steps: key derivation, decryption, MAC verification, and payload processing.
+/// This function implements the TR-31 key block unwrapping mechanism for version 'B', 'C' and
+/// 'D'. It involves several steps: key derivation, decryption, MAC verification, and payload processing.
+/// Key blocks of the deprecated version 'A' are unwrapped with the Key Variant Binding Method of
+/// version 'C', but cannot be wrapped.
 ///
 /// # Arguments
 /// * `kbpk` - Key Block Protection Key used for deriving the encryption (KBEK) and
@@ -348,69 +1169,560 @@ pub fn tr31_wrap_with_header_string(
 /// * `key_block` - The TR-31 formatted key block as a String.
 ///
 /// # Returns
-/// A `Result` containing the `KeyBlockHeader` and the extracted key as bytes, or an error if any
-/// step in the key block unwrapping process fails.
+/// A `Result` containing the `KeyBlockHeader` and the extracted key as a `SecretKey`, which is
+/// wiped from memory when dropped, or an error if any step in the key block unwrapping process
+/// fails.
 ///
 /// # Errors
 /// Returns an error if:
-/// * The key block version is not supported (currently only 'D' is implemented).
+/// * The key block version is not supported (currently 'A', 'B', 'C' and 'D' are implemented).
+/// * The structure of the key block is invalid, see `tr31_validate_structure`.
 /// * The MAC check fails.
 /// * There are issues with key derivation, decryption, or payload processing.
 /// * The header or payload data are improperly formatted.
+#[cfg(not(feature = "no-clear-secrets"))]
 pub fn tr31_unwrap(
     kbpk: &[u8],
     key_block: &str,
-) -> Result<(KeyBlockHeader, Vec<u8>), Box<dyn Error>> {
-    // Parse the header from the key block string
-    let header = KeyBlockHeader::new_from_str(&key_block)?;
+) -> Result<(KeyBlockHeader, SecretKey), PaysecError> {
+    unwrap_key_block(kbpk, key_block, &ValidationConfig::default())
+}
+
+/// Verify the MAC of a TR-31 key block without returning the key.
+///
+/// The key block is unwrapped like with `tr31_unwrap`, but only the header is returned and the
+/// recovered key is dropped. This function is available with the `no-clear-secrets` feature,
+/// which removes the functions returning clear keys.
+///
+/// # Arguments
+/// * `kbpk` - Key Block Protection Key used for deriving the encryption (KBEK) and
+///   authentication (KBAK) keys.
+/// * `key_block` - The TR-31 key block as a string.
+///
+/// # Errors
+/// Returns the same errors as `tr31_unwrap`, e.g. if the MAC check fails.
+///
+/// # Example
+/// ```
+/// use paysec::keyblock::tr31_verify;
+///
+/// let key_block = "D0112P0AE00E0000B82679114F470F540165EDFBF7E250FCEA43F810D215F8D207E2E417C07156A27E8E31DA05F7425509593D03A457DC34";
+/// let kbpk =
+///     hex::decode("88E1AB2A2E3DD38C1FA039A536500CC8A87AB9D62DC92C01058FA79F44657DE6").unwrap();
+///
+/// let header = tr31_verify(&kbpk, key_block).unwrap();
+/// assert_eq!(header.key_usage(), "P0");
+/// assert!(tr31_verify(&kbpk[..16], key_block).is_err());
+/// ```
+pub fn tr31_verify(kbpk: &[u8], key_block: &str) -> Result<KeyBlockHeader, PaysecError> {
+    let (header, _) = unwrap_key_block(kbpk, key_block, &ValidationConfig::default())?;
+    Ok(header)
+}
+
+/// Compute the MAC of a version 'D' key block over a header string and a clear payload.
+///
+/// **For testing only.** The MAC is the AES-CMAC of the header and the clear payload with the
+/// KBAK. It allows negative tests to forge a key block with a deliberately altered payload, which
+/// still passes the MAC check. The encrypted payload of such a block is the altered payload
+/// encrypted with the KBEK and the MAC as IV.
+///
+/// The function is only exported with the `testing` feature.
+///
+/// # Arguments
+/// * `kbak` - The Key Block Authentication Key, e.g. derived with `derive_keys_version_d`.
+/// * `header_str` - The complete header string, including the key block length and the optional
+///   blocks.
+/// * `payload` - The clear payload.
+///
+/// # Errors
+/// Returns an error if the header is not a version 'D' header or the KBAK is not a valid AES key.
+#[cfg(feature = "testing")]
+pub fn tr31_compute_mac(
+    kbak: &[u8],
+    header_str: &str,
+    payload: &[u8],
+) -> Result<[u8; 16], PaysecError> {
+    if !header_str.starts_with('D') {
+        return Err(format!(
+            "ERROR TR-31: MAC computation is only supported for version D headers: {}",
+            header_str
+        )
+        .into());
+    }
+
+    let mut mac_input = header_str.as_bytes().to_vec();
+    mac_input.extend_from_slice(payload);
+    Ok(aes_cmac(&mac_input, kbak)?)
+}
+
+// Unwrap a key block with the header parsed according to the validation policy.
+fn unwrap_key_block(
+    kbpk: &[u8],
+    key_block: &str,
+    config: &ValidationConfig,
+) -> Result<(KeyBlockHeader, SecretKey), PaysecError> {
+    let (header, decrypted_payload) = unwrap_payload(kbpk, key_block, config)?;
+
+    // Extract the key from the decrypted payload
+    let key = SecretKey::new(extract_key_from_payload(&decrypted_payload)?);
+
+    Ok((header, key))
+}
+
+// Decrypt the payload of a key block and verify the MAC, returning the header and the clear
+// payload, which is wiped when dropped.
+fn unwrap_payload(
+    kbpk: &[u8],
+    key_block: &str,
+    config: &ValidationConfig,
+) -> Result<(KeyBlockHeader, SecretKey), PaysecError> {
+    // Parse the header and check the structure of the key block
+    let (header, encrypted_payload, mac) = parse_key_block_structure(key_block, config)?;
     let header_len = header.len();
 
-    // Validate key block length
-    let key_block_len = key_block.len();
-    if key_block_len != header.kb_length() as usize {
-        return Err("ERROR TR-31: Key block length does not match its length in the header".into());
+    // Check the KBPK against its KCV before the MAC check reports any wrong KBPK
+    if config.kbpk_kcv {
+        if let Some(kp_block) = find_opt_block(&header, "KP") {
+            let kbpk_algorithm = if header.version_id() == "D" { "A" } else { "T" };
+            if !kcv_block_matches(kp_block, kbpk, kbpk_algorithm)? {
+                return Err(PaysecError::KbpkKcvMismatch);
+            }
+        }
     }
 
-    // Ensure minimum key block length: Min. header + min. payload + mac length.
-    let min_key_block_len = 16 + 2 * TR31_D_BLOCK_LEN + 2 * TR31_D_MAC_LEN;
-    if key_block_len < min_key_block_len {
-        return Err("ERROR TR-31: Key block length is below minimum required length".into());
+    // Decrypt the payload and verify the MAC with the binding method of the version
+    let header_str = &key_block[..header_len];
+    let decrypted_payload = match header.version_id() {
+        "B" => decrypt_with_scheme::<VersionBScheme>(kbpk, header_str, &encrypted_payload, &mac)?,
+        "A" | "C" => {
+            decrypt_with_scheme::<VersionCScheme>(kbpk, header_str, &encrypted_payload, &mac)?
+        }
+        _ => decrypt_with_scheme::<VersionDScheme>(kbpk, header_str, &encrypted_payload, &mac)?,
+    };
+
+    Ok((header, decrypted_payload))
+}
+
+// Decrypt the payload of a key block and verify its MAC.
+fn decrypt_with_scheme<S: KeyBlockScheme>(
+    kbpk: &[u8],
+    header_str: &str,
+    encrypted_payload: &[u8],
+    mac: &[u8],
+) -> Result<SecretKey, PaysecError> {
+    // Derive keys
+    let scheme = S::derive(kbpk)?;
+
+    // Decrypt the payload and verify the MAC
+    scheme.open(header_str, encrypted_payload, mac)
+}
+
+/// The result of `tr31_unwrap_with_options`.
+///
+/// The `Debug` output shows the length of the recovered key instead of its value.
+#[cfg(not(feature = "no-clear-secrets"))]
+pub struct UnwrapOutput {
+    /// The parsed key block header.
+    pub header: KeyBlockHeader,
+    /// The recovered key, wiped when dropped.
+    pub key: SecretKey,
+    /// Set if the recovered TDES key did not have odd parity, see `TdesParityCheck`.
+    pub parity_warning: bool,
+}
+
+#[cfg(not(feature = "no-clear-secrets"))]
+impl fmt::Debug for UnwrapOutput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("UnwrapOutput")
+            .field("header", &self.header)
+            .field("key", &"<redacted>")
+            .field("key_len", &self.key.len())
+            .field("parity_warning", &self.parity_warning)
+            .finish()
     }
+}
 
-    // Validate the version ID
-    if header.version_id() != "D" {
+/// Unwrap a TR-31 key block like `tr31_unwrap` with a validation policy.
+///
+/// The following checks are controlled by `config`:
+/// - `zero_reserved_field` and `known_opt_block_ids` apply to the header, see
+///   `KeyBlockHeader::new_from_str_with_options`.
+/// - `kbpk_kcv` verifies the KCV of the KBPK if the header has a `KP` optional block, like
+///   `tr31_unwrap_verified`.
+/// - `tdes_parity` applies to the recovered key if the header algorithm is 'T'.
+///
+/// # Arguments
+/// * `kbpk` - Key Block Protection Key used for deriving the encryption (KBEK) and
+///   authentication (KBAK) keys.
+/// * `key_block` - The TR-31 key block as a string.
+/// * `config` - The validation policy, see `ValidationConfig`.
+///
+/// # Returns
+/// A `Result` containing the header, the recovered key and the warning flags as `UnwrapOutput`
+/// or an error if the unwrapping or a check fails.
+///
+/// # Errors
+/// Returns an error if:
+/// * `tr31_unwrap` fails, except for the header checks disabled in `config`.
+/// * `config.kbpk_kcv` is set and the KCV of the KBPK does not match the `KP` block, as
+///   `PaysecError::KbpkKcvMismatch`.
+/// * `config.tdes_parity` is `TdesParityCheck::Error` and the recovered key of a key block with
+///   algorithm 'T' does not have odd parity.
+///
+/// # Example
+/// ```
+/// use paysec::keyblock::tr31_unwrap_with_options;
+/// use paysec::validation::ValidationConfig;
+///
+/// let key_block = "D0112P0AE00E0000B82679114F470F540165EDFBF7E250FCEA43F810D215F8D207E2E417C07156A27E8E31DA05F7425509593D03A457DC34";
+/// let kbpk =
+///     hex::decode("88E1AB2A2E3DD38C1FA039A536500CC8A87AB9D62DC92C01058FA79F44657DE6").unwrap();
+///
+/// // The parity check only applies to TDES keys, this key block contains an AES key.
+/// let output = tr31_unwrap_with_options(&kbpk, key_block, &ValidationConfig::strict()).unwrap();
+/// assert!(!output.parity_warning);
+/// ```
+#[cfg(not(feature = "no-clear-secrets"))]
+pub fn tr31_unwrap_with_options(
+    kbpk: &[u8],
+    key_block: &str,
+    config: &ValidationConfig,
+) -> Result<UnwrapOutput, PaysecError> {
+    let (header, mut key) = unwrap_key_block(kbpk, key_block, config)?;
+
+    let mut parity_warning = false;
+    if header.algorithm() == "T" && !has_odd_parity(&key) {
+        match config.tdes_parity {
+            TdesParityCheck::Off => {}
+            TdesParityCheck::Error => {
+                return Err(PaysecError::TdesParity);
+            }
+            TdesParityCheck::Warn => parity_warning = true,
+            TdesParityCheck::Correct => {
+                adjust_odd_parity(key.as_mut_bytes());
+                parity_warning = true;
+            }
+        }
+    }
+
+    Ok(UnwrapOutput {
+        header,
+        key,
+        parity_warning,
+    })
+}
+
+/// Unwrap a TR-31 key block like `tr31_unwrap` and verify the key check values of the `KP` and
+/// `KC` optional blocks if present.
+///
+/// The KCV of the KBPK in a `KP` block is verified before the key block is decrypted, so a
+/// wrong KBPK is reported as such instead of as a generic MAC failure. The KCV of the wrapped
+/// key in a `KC` block is verified after the unwrapping.
+///
+/// The data of both optional blocks is the KCV algorithm, `00` for the legacy KCV (encryption
+/// of a zero block) or `01` for the CMAC KCV (CMAC of a zero block), followed by the leftmost
+/// bytes of the KCV in hex. The KBPK of a version 'B' or 'C' key block is a TDES key and the KBPK
+/// of a version 'D' key block an AES key, the algorithm of the wrapped key is taken from the header
+/// and must be AES or TDES for a `KC` block.
+///
+/// # Arguments
+/// * `kbpk` - Key Block Protection Key used for deriving the encryption (KBEK) and
+///   authentication (KBAK) keys.
+/// * `key_block` - The TR-31 key block as a string.
+///
+/// # Returns
+/// A `Result` containing the `KeyBlockHeader` and the extracted key as a `SecretKey`.
+///
+/// # Errors
+/// Returns an error if:
+/// * The KCV of the KBPK does not match the `KP` block, as `PaysecError::KbpkKcvMismatch`.
+/// * `tr31_unwrap` fails.
+/// * The KCV of the unwrapped key does not match the `KC` block.
+/// * The data of a `KP` or `KC` block is malformed or the KCV algorithm is not supported.
+///
+/// # Example
+/// ```
+/// use paysec::keyblock::{tr31_unwrap_verified, tr31_wrap, KeyBlockHeader, OptBlock};
+///
+/// let kbpk =
+///     hex::decode("88E1AB2A2E3DD38C1FA039A536500CC8A87AB9D62DC92C01058FA79F44657DE6").unwrap();
+/// let key = hex::decode("3F419E1CB7079442AA37474C2EFBF8B8").unwrap();
+/// let random_seed = hex::decode("1C2965473CE206BB855B01533782").unwrap();
+///
+/// let mut header = KeyBlockHeader::new_with_values("D", "P0", "A", "E", "00", "E").unwrap();
+/// header.set_opt_blocks(Some(Box::new(OptBlock::new("KP", "012331550BC9", None).unwrap())));
+/// header.finalize().unwrap();
+/// let key_block = tr31_wrap(&kbpk, header, &key, 0, &random_seed).unwrap();
+///
+/// let (_, unwrapped_key) = tr31_unwrap_verified(&kbpk, &key_block).unwrap();
+/// assert_eq!(unwrapped_key, key);
+/// ```
+#[cfg(not(feature = "no-clear-secrets"))]
+pub fn tr31_unwrap_verified(
+    kbpk: &[u8],
+    key_block: &str,
+) -> Result<(KeyBlockHeader, SecretKey), PaysecError> {
+    let config = ValidationConfig {
+        kbpk_kcv: true,
+        ..ValidationConfig::default()
+    };
+    let (header, key) = unwrap_key_block(kbpk, key_block, &config)?;
+
+    if let Some(kc_block) = find_opt_block(&header, "KC") {
+        if !kcv_block_matches(kc_block, &key, header.algorithm())? {
+            return Err(PaysecError::KeyKcvMismatch);
+        }
+    }
+
+    Ok((header, key))
+}
+
+/// Unwrap a TR-31 key block whose header, encrypted payload and MAC are separated by a
+/// delimiter, e.g. `header|ciphertext|mac` as written by some log formats.
+///
+/// The three parts are checked and reassembled to the canonical key block, which is then
+/// unwrapped with `tr31_unwrap`.
+///
+/// # Arguments
+/// * `kbpk` - Key Block Protection Key used for deriving the encryption (KBEK) and
+///   authentication (KBAK) keys.
+/// * `s` - The delimited key block.
+/// * `delimiter` - The character separating the header, the encrypted payload and the MAC.
+///
+/// # Returns
+/// A `Result` containing the `KeyBlockHeader` and the extracted key as a `SecretKey`, which is
+/// wiped from memory when dropped, or an error if the key block cannot be unwrapped.
+///
+/// # Errors
+/// Returns an error if:
+/// * The string does not consist of exactly three non-empty parts.
+/// * The length of the header part does not match the header length including optional blocks.
+/// * The length of the MAC part does not match the MAC length of the key block version.
+/// * `tr31_unwrap` fails on the reassembled key block.
+///
+/// # Example
+/// ```
+/// use paysec::keyblock::tr31_unwrap_delimited;
+///
+/// let key_block = "D0112P0AE00E0000|B82679114F470F540165EDFBF7E250FCEA43F810D215F8D207E2E417C07156A2|7E8E31DA05F7425509593D03A457DC34";
+/// let kbpk =
+///     hex::decode("88E1AB2A2E3DD38C1FA039A536500CC8A87AB9D62DC92C01058FA79F44657DE6").unwrap();
+///
+/// let (_, key) = tr31_unwrap_delimited(&kbpk, key_block, '|').unwrap();
+/// assert_eq!(hex::encode_upper(key), "3F419E1CB7079442AA37474C2EFBF8B8");
+/// ```
+#[cfg(not(feature = "no-clear-secrets"))]
+pub fn tr31_unwrap_delimited(
+    kbpk: &[u8],
+    s: &str,
+    delimiter: char,
+) -> Result<(KeyBlockHeader, SecretKey), PaysecError> {
+    let parts: Vec<&str> = s.split(delimiter).collect();
+    let [header_str, payload_hex, mac_hex] = parts[..] else {
         return Err(format!(
-            "ERROR TR-31: Key block version not supported by implementation: {}",
-            header.version_id()
+            "ERROR TR-31: Delimited key block must have 3 parts, found {}",
+            parts.len()
         )
         .into());
+    };
+    if header_str.is_empty() || payload_hex.is_empty() || mac_hex.is_empty() {
+        return Err("ERROR TR-31: Delimited key block has an empty part".into());
     }
 
-    // Extract the encrypted payload and MAC from the key block
-    let encrypted_payload_hex = &key_block[header_len..(key_block_len - TR31_D_MAC_LEN * 2)];
-    let mac_hex = &key_block[(key_block_len - TR31_D_MAC_LEN * 2)..];
+    // Check the part boundaries, the MAC check alone would not locate a misplaced delimiter
+    if tr31_header_len(header_str)? != header_str.len() {
+        return Err("ERROR TR-31: Header part length does not match the header length".into());
+    }
+    let (_, mac_len) = binding_method_lengths(header_str.get(..1).unwrap_or(""))?;
+    if mac_hex.len() != 2 * mac_len {
+        return Err(format!(
+            "ERROR TR-31: MAC part length does not match the MAC length of the version: {}",
+            2 * mac_len
+        )
+        .into());
+    }
 
-    // Derive keys
-    let (kbek, kbak) = derive_keys_version_d(kbpk)?;
+    let key_block = [header_str, payload_hex, mac_hex].concat();
+    tr31_unwrap(kbpk, &key_block)
+}
 
-    // Decrypt the payload
-    let encrypted_payload = hex::decode(encrypted_payload_hex)?;
-    let mac = hex::decode(mac_hex)?;
-    let iv: [u8; TR31_D_MAC_LEN] = mac[0..TR31_D_MAC_LEN]
-        .try_into()
-        .expect("ERROR TR-31: Mac slice with incorrect length");
-    let decrypted_payload = aes_dec_cbc(&encrypted_payload, &kbek, &iv, None)?;
+/// Unwrap a TR-31 key block and wrap the key again into an identical key block.
+///
+/// The padding of the decrypted payload is reused as the random seed and the payload length as
+/// the masked key length, so the key block is reconstructed byte for byte. This validates that
+/// wrapping is deterministic given the same padding, e.g. when testing against key blocks of
+/// another implementation. The clear key does not leave the function.
+///
+/// A key block with exportability 'N' (non-exportable) is refused, as the key must not be
+/// wrapped into a new key block. Use `tr31_clone_block_with_options` to override this.
+///
+/// # Arguments
+/// * `kbpk` - Key Block Protection Key used for deriving the encryption (KBEK) and
+///   authentication (KBAK) keys.
+/// * `key_block` - The TR-31 key block as a string.
+///
+/// # Returns
+/// The reconstructed key block. The encrypted payload and the MAC are formatted in uppercase
+/// hex like by `tr31_wrap`.
+///
+/// # Errors
+/// Returns an error if:
+/// * `tr31_unwrap` fails.
+/// * The exportability of the key block is 'N'.
+/// * `tr31_wrap` fails, e.g. for the deprecated version 'A', which can only be unwrapped.
+///
+/// # Example
+/// ```
+/// use paysec::keyblock::tr31_clone_block;
+///
+/// let key_block = "D0112P0AE00E0000B82679114F470F540165EDFBF7E250FCEA43F810D215F8D207E2E417C07156A27E8E31DA05F7425509593D03A457DC34";
+/// let kbpk =
+///     hex::decode("88E1AB2A2E3DD38C1FA039A536500CC8A87AB9D62DC92C01058FA79F44657DE6").unwrap();
+///
+/// assert_eq!(tr31_clone_block(&kbpk, key_block).unwrap(), key_block);
+/// ```
+pub fn tr31_clone_block(kbpk: &[u8], key_block: &str) -> Result<String, PaysecError> {
+    tr31_clone_block_with_options(kbpk, key_block, &ValidationConfig::default())
+}
 
-    // Verify the MAC
-    let mut mac_input = key_block[..header_len].as_bytes().to_vec();
-    mac_input.extend_from_slice(&decrypted_payload);
-    let calculated_mac = aes_cmac(&mac_input, &kbak)?;
-    if mac != calculated_mac {
-        return Err("ERROR TR-31: MAC check failed".into());
+/// Unwrap a TR-31 key block and wrap the key again like `tr31_clone_block` with a validation
+/// policy.
+///
+/// The key block is parsed with `config`, and the following check is controlled by it:
+/// - `non_exportable`: Refuse a key block with exportability 'N'. Disable it to explicitly allow
+///   re-wrapping a non-exportable key, e.g. to reproduce the key block of another
+///   implementation in a test.
+///
+/// # Arguments
+/// * `kbpk` - Key Block Protection Key used for deriving the encryption (KBEK) and
+///   authentication (KBAK) keys.
+/// * `key_block` - The TR-31 key block as a string.
+/// * `config` - The validation policy, see `ValidationConfig`.
+///
+/// # Errors
+/// Returns an error if `tr31_clone_block` fails for a reason other than a check disabled in
+/// `config`.
+///
+/// # Example
+/// ```
+/// use paysec::keyblock::tr31_clone_block_with_options;
+/// use paysec::validation::ValidationConfig;
+///
+/// let key_block = "D0144P0TE00N0200KS1800604B120F9292800000PB080000F2A795BB540447553D9FA3812E64E76A577DA04A1E0DD9FA9EFDE394BE936D4532BF5BA7E57063B63FCD90F9C2020F77";
+/// let kbpk = hex::decode("00112233445566778899AABBCCDDEEFF0011223344556677").unwrap();
+/// let config = ValidationConfig {
+///     non_exportable: false,
+///     ..ValidationConfig::default()
+/// };
+///
+/// assert!(tr31_clone_block_with_options(&kbpk, key_block, &ValidationConfig::default()).is_err());
+/// assert_eq!(tr31_clone_block_with_options(&kbpk, key_block, &config).unwrap(), key_block);
+/// ```
+pub fn tr31_clone_block_with_options(
+    kbpk: &[u8],
+    key_block: &str,
+    config: &ValidationConfig,
+) -> Result<String, PaysecError> {
+    let (header, payload) = unwrap_payload(kbpk, key_block, config)?;
+    if config.non_exportable && header.exportability() == "N" {
+        return Err(PaysecError::NonExportable);
     }
+    let key = SecretKey::new(extract_key_from_payload(&payload)?);
+    let padding = extract_padding(&payload)?;
 
-    // Extract the key from the decrypted payload
-    let key = extract_key_from_payload(&decrypted_payload)?;
+    // A masked key length of the payload length without the length field reproduces the padding
+    // length
+    tr31_wrap(kbpk, header, &key, payload.len() - 2, &padding)
+}
 
-    Ok((header, key))
+// Find the first optional block with the given ID.
+fn find_opt_block<'a>(header: &'a KeyBlockHeader, id: &str) -> Option<&'a OptBlock> {
+    std::iter::successors(header.opt_blocks().as_deref(), |block| block.next())
+        .find(|block| block.id() == id)
+}
+
+// Parse the header and split a key block into the header, the encrypted payload and the MAC,
+// performing all structural checks which do not require the KBPK.
+pub(super) fn parse_key_block_structure(
+    key_block: &str,
+    config: &ValidationConfig,
+) -> Result<KeyBlockParts, PaysecError> {
+    // Parse the header from the key block string
+    let header = KeyBlockHeader::new_from_str_with_options(key_block, config)?;
+    let header_len = header.len();
+
+    // Validate key block length, locating the missing or excess characters
+    let key_block_len = key_block.len();
+    let declared_len = header.kb_length() as usize;
+    if key_block_len != declared_len {
+        let trailing_whitespace = key_block
+            .get(declared_len..)
+            .is_some_and(|excess| excess.chars().all(char::is_whitespace));
+        return Err(ParseError::located_kind(
+            "key block length",
+            std::cmp::min(key_block_len, declared_len),
+            key_block_len.abs_diff(declared_len),
+            PaysecError::KeyBlockLengthMismatch {
+                declared: declared_len,
+                actual: key_block_len,
+                trailing_whitespace,
+            },
+        ));
+    }
+
+    // Validate the version ID, which determines the block and MAC length
+    let (block_len, mac_len) = binding_method_lengths(header.version_id())
+        .map_err(|e| ParseError::located_kind("version ID", 0, 1, e))?;
+
+    // Ensure minimum key block length: Header + min. payload + mac length.
+    let min_key_block_len = std::cmp::max(16, header_len) + 2 * block_len + 2 * mac_len;
+    if key_block_len < min_key_block_len {
+        return Err(ParseError::located(
+            "key block",
+            0,
+            key_block_len,
+            "ERROR TR-31: Key block length is below minimum required length",
+        ));
+    }
+
+    // Extract the encrypted payload and MAC from the key block
+    let mac_offset = key_block_len - mac_len * 2;
+    let encrypted_payload_hex = &key_block[header_len..mac_offset];
+    let mac_hex = &key_block[mac_offset..];
+
+    let encrypted_payload =
+        decode_hex_field(encrypted_payload_hex, header_len, "encrypted payload")?;
+    let mac = decode_hex_field(mac_hex, mac_offset, "MAC")?;
+
+    // The encrypted payload must consist of complete cipher blocks
+    let payload_remainder = encrypted_payload.len() % block_len;
+    if payload_remainder != 0 {
+        return Err(ParseError::located(
+            "encrypted payload",
+            mac_offset - 2 * payload_remainder,
+            2 * payload_remainder,
+            format!(
+                "ERROR TR-31: Encrypted payload length is not a multiple of block length: {}",
+                block_len
+            ),
+        ));
+    }
+
+    Ok((header, encrypted_payload, mac))
+}
+
+// Decode a hex field of a key block in constant time. Invalid fields are decoded again with the
+// `hex` crate to locate invalid characters by their offset in the key block.
+fn decode_hex_field(
+    field_hex: &str,
+    offset: usize,
+    field: &'static str,
+) -> Result<Vec<u8>, PaysecError> {
+    ct_hex_decode(field_hex).map_err(|ct_err| match hex::decode(field_hex) {
+        Err(e @ hex::FromHexError::InvalidHexCharacter { index, .. }) => {
+            ParseError::located(field, offset + index, 1, e)
+        }
+        Err(e) => ParseError::located(field, offset, field_hex.len(), e),
+        Ok(_) => ParseError::located(field, offset, field_hex.len(), ct_err),
+    })
 }
@@ -0,0 +1,203 @@
+//! Module for keys bound to the key usage and mode of use of their key block.
+//!
+//! # Standard
+//!
+//! - TR-31: 2018, p. 18-20 (key usage and mode of use)
+//!
+//! # Description
+//!
+//! `tr31_unwrap` returns the key as raw bytes, so nothing stops application code from using a key
+//! in a way its header does not permit, e.g. deciphering PIN blocks with an encrypt-only key. A
+//! `UsageBoundKey` keeps the key usage and mode of use of the header alongside the key bytes. The
+//! `*_bound` variants of the PIN block functions check them before using the key and return a
+//! `PaysecError::ModeOfUseViolation` if the mode of use does not permit the operation.
+//!
+//! The functions taking raw key slices remain available for users who manage the key policy
+//! themselves.
+//!
+//! # Example
+//! ```
+//! # #[cfg(not(feature = "no-clear-secrets"))]
+//! # {
+//! use paysec::keyblock::{tr31_unwrap_bound, KeyOperation};
+//!
+//! let key_block = "D0112P0AE00E0000B82679114F470F540165EDFBF7E250FCEA43F810D215F8D207E2E417C07156A27E8E31DA05F7425509593D03A457DC34";
+//! let kbpk =
+//!     hex::decode("88E1AB2A2E3DD38C1FA039A536500CC8A87AB9D62DC92C01058FA79F44657DE6").unwrap();
+//!
+//! let key = tr31_unwrap_bound(&kbpk, key_block).unwrap();
+//! assert_eq!((key.key_usage(), key.mode_of_use()), ("P0", "E"));
+//! assert!(key.check(KeyOperation::Encrypt).is_ok());
+//! assert!(key.check(KeyOperation::Decrypt).is_err());
+//! # }
+//! ```
+
+use crate::error::PaysecError;
+use std::fmt;
+
+use super::key_block_header::KeyBlockHeader;
+use super::secret_key::SecretKey;
+#[cfg(not(feature = "no-clear-secrets"))]
+use super::tr31::tr31_unwrap;
+
+/// A cryptographic operation restricted by the mode of use of a key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyOperation {
+    /// Encrypt or wrap data.
+    Encrypt,
+    /// Decrypt or unwrap data.
+    Decrypt,
+}
+
+impl KeyOperation {
+    /// Returns the modes of use permitting the operation.
+    ///
+    /// Mode `N` (no special restrictions) permits both operations, and mode `T` (both sign and
+    /// decrypt) permits decryption.
+    pub fn permitted_modes_of_use(&self) -> &'static [&'static str] {
+        match self {
+            KeyOperation::Encrypt => &["B", "E", "N"],
+            KeyOperation::Decrypt => &["B", "D", "N", "T"],
+        }
+    }
+}
+
+impl fmt::Display for KeyOperation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeyOperation::Encrypt => write!(f, "encryption"),
+            KeyOperation::Decrypt => write!(f, "decryption"),
+        }
+    }
+}
+
+/// The error of an operation not permitted by the mode of use of a `UsageBoundKey`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModeOfUseViolation {
+    mode_of_use: String,
+    operation: KeyOperation,
+}
+
+impl ModeOfUseViolation {
+    /// Return the mode of use of the key.
+    pub fn mode_of_use(&self) -> &str {
+        &self.mode_of_use
+    }
+
+    /// Return the operation which was not permitted.
+    pub fn operation(&self) -> KeyOperation {
+        self.operation
+    }
+}
+
+impl fmt::Display for ModeOfUseViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "ERROR TR-31: Mode of use {} does not permit {}",
+            self.mode_of_use, self.operation
+        )
+    }
+}
+
+impl std::error::Error for ModeOfUseViolation {}
+
+/// A key together with the key usage and mode of use of its key block header.
+///
+/// The key is held as a `SecretKey`, so it is wiped when the value is dropped. The `Debug` output
+/// shows the length of the key instead of its value.
+#[derive(Clone, PartialEq, Eq)]
+pub struct UsageBoundKey {
+    key_usage: String,
+    algorithm: String,
+    mode_of_use: String,
+    key: SecretKey,
+}
+
+impl UsageBoundKey {
+    /// Bind a key to the key usage, algorithm and mode of use of a key block header.
+    ///
+    /// Use this for keys recovered by other means than `tr31_unwrap_bound`, e.g. with
+    /// `tr31_unwrap_with_options`. The key is either a `SecretKey` or a `Vec<u8>`.
+    pub fn new(header: &KeyBlockHeader, key: impl Into<SecretKey>) -> Self {
+        UsageBoundKey {
+            key_usage: header.key_usage().to_string(),
+            algorithm: header.algorithm().to_string(),
+            mode_of_use: header.mode_of_use().to_string(),
+            key: key.into(),
+        }
+    }
+
+    /// Get the key usage of the key.
+    pub fn key_usage(&self) -> &str {
+        &self.key_usage
+    }
+
+    /// Get the algorithm of the key.
+    pub fn algorithm(&self) -> &str {
+        &self.algorithm
+    }
+
+    /// Get the mode of use of the key.
+    pub fn mode_of_use(&self) -> &str {
+        &self.mode_of_use
+    }
+
+    /// Check whether the mode of use of the key permits an operation.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `ModeOfUseViolation` if the mode of use does not permit the operation.
+    pub fn check(&self, operation: KeyOperation) -> Result<(), ModeOfUseViolation> {
+        if operation
+            .permitted_modes_of_use()
+            .contains(&self.mode_of_use.as_str())
+        {
+            Ok(())
+        } else {
+            Err(ModeOfUseViolation {
+                mode_of_use: self.mode_of_use.clone(),
+                operation,
+            })
+        }
+    }
+
+    /// Return the key bytes for an operation permitted by the mode of use.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `PaysecError::ModeOfUseViolation` if the mode of use does not permit the operation.
+    pub fn key_for(&self, operation: KeyOperation) -> Result<&[u8], PaysecError> {
+        self.check(operation)?;
+        Ok(&self.key)
+    }
+
+    /// Return the key bytes without checking the mode of use.
+    pub fn key_unchecked(&self) -> &[u8] {
+        &self.key
+    }
+}
+
+impl fmt::Debug for UsageBoundKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("UsageBoundKey")
+            .field("key_usage", &self.key_usage)
+            .field("algorithm", &self.algorithm)
+            .field("mode_of_use", &self.mode_of_use)
+            .field("key", &"<redacted>")
+            .field("key_len", &self.key.len())
+            .finish()
+    }
+}
+
+/// Unwrap a TR-31 key block like `tr31_unwrap` and bind the key to the key usage and mode of use
+/// of its header.
+///
+/// # Errors
+///
+/// Returns the same errors as `tr31_unwrap`.
+#[cfg(not(feature = "no-clear-secrets"))]
+pub fn tr31_unwrap_bound(kbpk: &[u8], key_block: &str) -> Result<UsageBoundKey, PaysecError> {
+    let (header, key) = tr31_unwrap(kbpk, key_block)?;
+    Ok(UsageBoundKey::new(&header, key))
+}
@@ -1,4 +1,85 @@
-mod utils;
+pub mod error;
+pub mod utils;
+pub mod validation;
 
+#[cfg(any(feature = "keyblock", feature = "pin"))]
+mod crypto;
+
+#[cfg(feature = "keyblock")]
+pub mod ceremony;
+#[cfg(feature = "dukpt")]
+pub mod dukpt;
+#[cfg(feature = "keyblock")]
 pub mod keyblock;
+#[cfg(feature = "mac")]
+pub mod mac;
+#[cfg(feature = "pin")]
 pub mod pin;
+#[cfg(all(feature = "testing", not(feature = "no-clear-secrets")))]
+pub mod testing;
+
+/// The `no-clear-secrets` feature removes the functions returning clear keys or PINs.
+///
+/// ```compile_fail
+/// use paysec::keyblock::tr31_unwrap;
+/// ```
+///
+/// ```compile_fail
+/// use paysec::keyblock::tr31_unwrap_with_options;
+/// ```
+///
+/// ```compile_fail
+/// use paysec::keyblock::tr31_unwrap_verified;
+/// ```
+///
+/// ```compile_fail
+/// use paysec::keyblock::tr31_unwrap_bound;
+/// ```
+///
+/// ```compile_fail
+/// use paysec::keyblock::tr31_signing_input;
+/// ```
+///
+/// ```compile_fail
+/// use paysec::keyblock::{derive_keys_version_b, derive_keys_version_c};
+/// ```
+///
+/// ```compile_fail
+/// use paysec::keyblock::KeyBlockScheme;
+/// ```
+///
+/// ```compile_fail
+/// use paysec::pin::decipher_pinblock_iso_4;
+/// ```
+///
+/// ```compile_fail
+/// use paysec::pin::decipher_pinblock_iso_4_with_options;
+/// ```
+///
+/// ```compile_fail
+/// use paysec::pin::decipher_pinblock_iso_4_with_pan;
+/// ```
+///
+/// ```compile_fail
+/// use paysec::pin::decipher_pinblock_iso_4_bound;
+/// ```
+///
+/// ```compile_fail
+/// use paysec::dukpt::derive_initial_key;
+/// ```
+///
+/// ```compile_fail
+/// use paysec::ceremony::assemble_kbpk;
+/// ```
+///
+/// ```compile_fail
+/// fn key_bytes(key: &paysec::ceremony::CeremonyKey) -> &[u8] {
+///     key.as_bytes()
+/// }
+/// ```
+///
+/// ```compile_fail
+/// use paysec::testing::generate_corpus;
+/// ```
+#[cfg(all(doctest, feature = "no-clear-secrets"))]
+pub struct NoClearSecrets;
@@ -0,0 +1,247 @@
+//! MAC computation with keys of the M-series key usages.
+//!
+//! # Standard
+//!
+//! - ISO/IEC 9797-1:2011: "Message Authentication Codes (MACs) - Part 1: Mechanisms using a block
+//!   cipher"
+//! - NIST SP 800-38B: "Recommendation for Block Cipher Modes of Operation: The CMAC Mode for
+//!   Authentication"
+//! - RFC 2104: "HMAC: Keyed-Hashing for Message Authentication"
+//!
+//! # Description
+//!
+//! The key usages `M0` to `M8` of a TR-31 key block declare the MAC algorithm the key is
+//! intended for. `compute_mac` dispatches on the key usage and the algorithm of the key, so a key
+//! unwrapped from a key block can be used directly with the key usage and algorithm of its
+//! header:
+//!
+//! - `M0`: ISO 16609 MAC algorithm 1, i.e. ISO 9797-1 MAC algorithm 1 with TDEA (algorithm `T`,
+//!   16 or 24 byte key).
+//! - `M1`: ISO 9797-1 MAC algorithm 1 (CBC-MAC) with TDEA (algorithm `T`, 16 or 24 byte key).
+//! - `M3`: ISO 9797-1 MAC algorithm 3 (ANSI X9.19 retail MAC) with DES and a 16 byte key
+//!   (algorithm `T`).
+//! - `M6`: ISO 9797-1 MAC algorithm 5 (CMAC) with AES (algorithm `A`, 16, 24 or 32 byte key) or
+//!   TDEA (algorithm `T`, 16 or 24 byte key).
+//! - `M7`: HMAC with SHA-256 (algorithm `H`).
+//!
+//! ISO 9797-1 algorithms 1 and 3 use padding method 1, i.e. the data is padded with zero bytes
+//! to a multiple of the block length. The full MAC is returned, truncating it is left to the
+//! caller. The key usages `M2`, `M4`, `M5` and `M8` are not supported.
+//!
+//! This module is only available with the `mac` feature.
+//!
+//! # Example
+//! ```
+//! use paysec::mac::compute_mac;
+//!
+//! let key = hex::decode("0123456789ABCDEFFEDCBA9876543210").unwrap();
+//! let mac = compute_mac("M3", "T", &key, b"Now is the time for all ").unwrap();
+//! assert_eq!(hex::encode_upper(mac), "A1C72E74EA3FA9B6");
+//! ```
+
+use crate::crypto::aes::aes_cmac;
+use crate::crypto::tdes::{
+    des_dec_block, des_enc_block, tdes_cmac, tdes_enc_block, TDES_BLOCK_LEN,
+};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::error::Error;
+
+/// Compute the MAC of data with the algorithm declared by a MAC key usage.
+///
+/// # Arguments
+///
+/// * `usage` - The key usage of the MAC key, `M0` to `M8`.
+/// * `algorithm` - The algorithm of the MAC key as in the key block header, e.g. `A` for AES or
+///   `T` for TDEA.
+/// * `key` - The MAC key, e.g. unwrapped from a TR-31 key block.
+/// * `data` - The data to authenticate.
+///
+/// # Errors
+///
+/// Returns an error if:
+/// - The key usage is not a MAC key usage or its algorithm is not supported.
+/// - The key algorithm does not match the key usage.
+/// - The key length is invalid for the algorithm.
+pub fn compute_mac(
+    usage: &str,
+    algorithm: &str,
+    key: &[u8],
+    data: &[u8],
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    match (usage, algorithm) {
+        ("M0" | "M1", "T") => iso9797_1_alg_1_tdes(key, data),
+        ("M3", "T") => iso9797_1_alg_3(key, data),
+        ("M6", "A") => Ok(aes_cmac(data, key)?.to_vec()),
+        ("M6", "T") => Ok(tdes_cmac(data, key)?.to_vec()),
+        ("M7", "H") => hmac_sha256(key, data),
+        ("M2" | "M4" | "M5" | "M8", _) => Err(format!(
+            "ERROR MAC: MAC algorithm of key usage not supported: {}",
+            usage
+        )
+        .into()),
+        ("M0" | "M1" | "M3" | "M6" | "M7", _) => Err(format!(
+            "ERROR MAC: Key algorithm {} not supported for key usage {}",
+            algorithm, usage
+        )
+        .into()),
+        _ => Err(format!("ERROR MAC: Not a MAC key usage: {}", usage).into()),
+    }
+}
+
+// Pad data with zero bytes to a multiple of the block length (ISO 9797-1 padding method 1).
+// Empty data is padded to one block.
+fn pad_method_1(data: &[u8]) -> Vec<u8> {
+    let padded_len = std::cmp::max(1, data.len().div_ceil(TDES_BLOCK_LEN)) * TDES_BLOCK_LEN;
+    let mut padded = data.to_vec();
+    padded.resize(padded_len, 0);
+    padded
+}
+
+// CBC-MAC of padded data with a zero IV, returning the last cipher block.
+fn cbc_mac(
+    padded: &[u8],
+    encrypt: impl Fn(&[u8; TDES_BLOCK_LEN]) -> Result<[u8; TDES_BLOCK_LEN], Box<dyn Error>>,
+) -> Result<[u8; TDES_BLOCK_LEN], Box<dyn Error>> {
+    let mut state = [0u8; TDES_BLOCK_LEN];
+    for block in padded.chunks_exact(TDES_BLOCK_LEN) {
+        for (s, b) in state.iter_mut().zip(block) {
+            *s ^= b;
+        }
+        state = encrypt(&state)?;
+    }
+    Ok(state)
+}
+
+// ISO 9797-1 MAC algorithm 1 with TDEA.
+fn iso9797_1_alg_1_tdes(key: &[u8], data: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+    if !matches!(key.len(), 16 | 24) {
+        return Err(format!("ERROR MAC: Invalid TDES key length: {}", key.len()).into());
+    }
+
+    let mac = cbc_mac(&pad_method_1(data), |block| tdes_enc_block(block, key))?;
+    Ok(mac.to_vec())
+}
+
+// ISO 9797-1 MAC algorithm 3 with DES: CBC-MAC with the left key half, followed by a decryption
+// with the right and an encryption with the left key half of the last block.
+fn iso9797_1_alg_3(key: &[u8], data: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+    if key.len() != 16 {
+        return Err(format!("ERROR MAC: Invalid retail MAC key length: {}", key.len()).into());
+    }
+    let (key_left, key_right) = key.split_at(TDES_BLOCK_LEN);
+
+    let state = cbc_mac(&pad_method_1(data), |block| des_enc_block(block, key_left))?;
+    let mac = des_enc_block(&des_dec_block(&state, key_right)?, key_left)?;
+    Ok(mac.to_vec())
+}
+
+// HMAC with SHA-256.
+fn hmac_sha256(key: &[u8], data: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key)
+        .map_err(|_| format!("ERROR MAC: Invalid HMAC key length: {}", key.len()))?;
+    mac.update(data);
+    Ok(mac.finalize().into_bytes().to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compute_mac_m6_cmac_sp800_38b() {
+        // NIST SP 800-38B, D.1 AES-128, examples 1 and 2
+        let key = hex::decode("2B7E151628AED2A6ABF7158809CF4F3C").unwrap();
+
+        let mac = compute_mac("M6", "A", &key, &[]).unwrap();
+        assert_eq!(hex::encode_upper(mac), "BB1D6929E95937287FA37D129B756746");
+
+        let data = hex::decode("6BC1BEE22E409F96E93D7E117393172A").unwrap();
+        let mac = compute_mac("M6", "A", &key, &data).unwrap();
+        assert_eq!(hex::encode_upper(mac), "070A16B46B4D4144F79BDD9DD04A287C");
+    }
+
+    #[test]
+    fn test_compute_mac_m6_tdes_cmac_sp800_38b() {
+        // NIST SP 800-38B, D.3 two-key TDEA and D.4 three-key TDEA, example 1 (empty message)
+        // and example 2
+        let data = hex::decode("6BC1BEE22E409F96E93D7E117393172A").unwrap();
+
+        let key = hex::decode("4CF15134A2850DD58A3D10BA80570D38").unwrap();
+        let mac = compute_mac("M6", "T", &key, &[]).unwrap();
+        assert_eq!(hex::encode_upper(mac), "BD2EBF9A3BA00361");
+        let mac = compute_mac("M6", "T", &key, &data).unwrap();
+        assert_eq!(hex::encode_upper(mac), "743DA9F41B91EC83");
+
+        let key = hex::decode("8AA83BF8CBDA10620BC1BF19FBB6CD58BC313D4A371CA8B5").unwrap();
+        let mac = compute_mac("M6", "T", &key, &[]).unwrap();
+        assert_eq!(hex::encode_upper(mac), "B7A688E122FFAF95");
+        let mac = compute_mac("M6", "T", &key, &data).unwrap();
+        assert_eq!(hex::encode_upper(mac), "286D394673448197");
+    }
+
+    #[test]
+    fn test_compute_mac_m7_hmac_sha256_rfc_4231() {
+        // RFC 4231, test cases 1 and 2
+        let key = [0x0Bu8; 20];
+        let mac = compute_mac("M7", "H", &key, b"Hi There").unwrap();
+        assert_eq!(
+            hex::encode(mac),
+            "b0344c61d8db38535ca8afceaf0bf12b881dc200c9833da726e9376c2e32cff7"
+        );
+
+        let mac = compute_mac("M7", "H", b"Jefe", b"what do ya want for nothing?").unwrap();
+        assert_eq!(
+            hex::encode(mac),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[test]
+    fn test_compute_mac_m0_m1_m3() {
+        let key = hex::decode("0123456789ABCDEFFEDCBA9876543210").unwrap();
+
+        // ANSI X9.19 retail MAC example
+        let mac = compute_mac("M3", "T", &key, b"Now is the time for all ").unwrap();
+        assert_eq!(hex::encode_upper(mac), "A1C72E74EA3FA9B6");
+
+        // Padding method 1 for data which is not a multiple of the block length
+        let mac = compute_mac("M3", "T", &key, b"Now is the time for it").unwrap();
+        assert_eq!(hex::encode_upper(mac), "2E2B1428CC78254F");
+
+        let mac = compute_mac("M1", "T", &key, b"Now is the time for all ").unwrap();
+        assert_eq!(hex::encode_upper(&mac), "93462A6DB9B4A4D1");
+        assert_eq!(
+            compute_mac("M0", "T", &key, b"Now is the time for all ").unwrap(),
+            mac
+        );
+    }
+
+    #[test]
+    fn test_compute_mac_unsupported_usage() {
+        let key = [0x11u8; 16];
+
+        assert_eq!(
+            compute_mac("M2", "T", &key, b"data")
+                .unwrap_err()
+                .to_string(),
+            "ERROR MAC: MAC algorithm of key usage not supported: M2"
+        );
+        assert_eq!(
+            compute_mac("P0", "T", &key, b"data")
+                .unwrap_err()
+                .to_string(),
+            "ERROR MAC: Not a MAC key usage: P0"
+        );
+        assert!(compute_mac("M3", "T", &key[..8], b"data").is_err());
+
+        // The algorithm of the key must match the key usage
+        assert_eq!(
+            compute_mac("M1", "A", &key, b"data")
+                .unwrap_err()
+                .to_string(),
+            "ERROR MAC: Key algorithm A not supported for key usage M1"
+        );
+        assert!(compute_mac("M7", "T", &key, b"data").is_err());
+    }
+}
@@ -0,0 +1,271 @@
+//! Module for Encoding and Decoding of PIN Blocks in ISO 9564 Format 0.
+//!
+//! This module provides functionalities for handling PIN blocks in compliance with the ISO 9564
+//! format 0 standard, also known as ANSI X9.8 PIN block. Like format 3, a format 0 PIN block
+//! binds a Personal Identification Number (PIN) to the rightmost 12 digits of the Primary Account
+//! Number (PAN) excluding the check digit. Unlike format 3, the PIN field is padded with `F`
+//! nibbles instead of random `A`-`F` nibbles, so the encoding is deterministic and needs no random
+//! seed.
+//!
+//! # Example Usage
+//!
+//! ```
+//! use paysec::pin::{decode_pinblock_iso_0, encode_pinblock_iso_0};
+//!
+//! let pin = "1234";
+//! let pan = "43219876543210987";
+//!
+//! let pin_block = encode_pinblock_iso_0(pin, pan).unwrap();
+//! assert_eq!(hex::encode_upper(pin_block), "0412AC89ABCDEF67");
+//!
+//! let decoded_pin = decode_pinblock_iso_0(&pin_block, pan).unwrap();
+//! assert_eq!(decoded_pin, pin);
+//! ```
+//!
+//! # Disclaimer
+//!
+//! - This library is provided "as is", with no warranty or guarantees regarding its security or
+//!   effectiveness in a production environment.
+//!
+//! # Note
+//!
+//! - Format 0 is only supported for interoperability with systems that do not accept format 3 or
+//!   format 4. Since the padding is constant, equal PINs with equal PANs produce equal PIN
+//!   blocks, format 3 or 4 should be preferred where possible.
+
+use super::pin_format::{block_size, PinFormat};
+use crate::error::PaysecError;
+use crate::utils::xor_byte_arrays;
+
+const ISO0_PIN_BLOCK_LENGTH: usize = block_size(PinFormat::Format0);
+
+/// Encode a PIN block using the ISO 9564 format 0 standard.
+///
+/// This function encodes the PIN and the PAN separately according to the ISO 9564 format 0
+/// specification and combines them with an XOR operation.
+///
+/// # Parameters
+///
+/// * `pin`: A reference to a string slice representing the ASCII-encoded PIN. The PIN must
+///   consist of numeric characters only and have a length between 4 and 12 digits.
+/// * `pan`: A reference to a string slice representing the ASCII-encoded PAN associated with the
+///   PIN. The PAN must consist of numeric characters only and be at least 13 digits long.
+///
+/// # Returns
+///
+/// * `Ok([u8; ISO0_PIN_BLOCK_LENGTH])` - An 8-byte array representing the encoded PIN block.
+/// * `Err(PaysecError)` - If the PIN or the PAN are invalid.
+///
+/// # Errors
+///
+/// This function will return an error if:
+/// - The PIN length is not between 4 and 12 digits.
+/// - The PAN length is less than 13 digits.
+/// - The PIN or PAN contains non-numeric characters.
+///
+/// # Note
+///
+/// This function does not encrypt the resulting PIN block. The encoded PIN block should be
+/// encrypted in a separate step, e.g. with Triple DES.
+pub fn encode_pinblock_iso_0(
+    pin: &str,
+    pan: &str,
+) -> Result<[u8; ISO0_PIN_BLOCK_LENGTH], PaysecError> {
+    let pin_field = encode_pin_field_iso_0(pin)?;
+    let pan_field = encode_pan_field_iso_0(pan)?;
+
+    // XOR the pin_field and pan_field
+    let pin_block = xor_byte_arrays(&pin_field, &pan_field)?;
+
+    Ok(pin_block
+        .try_into()
+        .expect("PIN BLOCK ISO 0 ERROR: XOR result with incorrect length"))
+}
+
+/// Decode a PIN block using the ISO 9564 format 0 standard and extract the PIN.
+///
+/// # Parameters
+///
+/// * `pin_block`: A byte slice representing the encoded PIN block.
+/// * `pan`: A reference to a string slice representing the ASCII-encoded PAN associated with the
+///   PIN. The PAN must consist of numeric characters only and be at least 13 digits long.
+///
+/// # Returns
+///
+/// * `Ok(String)` - A string representing the decoded PIN.
+/// * `Err(PaysecError)` - If there are issues with the input data or if decoding fails.
+///
+/// # Errors
+///
+/// This function will return an error if:
+/// - The PIN block is not 8 bytes long.
+/// - The PAN is invalid, see `encode_pan_field_iso_0`.
+/// - The PIN field is invalid, see `decode_pin_field_iso_0`.
+pub fn decode_pinblock_iso_0(pin_block: &[u8], pan: &str) -> Result<String, PaysecError> {
+    if pin_block.len() != ISO0_PIN_BLOCK_LENGTH {
+        return Err("PIN BLOCK ISO 0 ERROR: Invalid PIN block length".into());
+    }
+
+    let pan_field = encode_pan_field_iso_0(pan)?;
+
+    // XOR the pin_block and pan_field
+    let pin_field = xor_byte_arrays(pin_block, &pan_field)?;
+
+    decode_pin_field_iso_0(&pin_field)
+}
+
+/// Encode a PIN field using the ISO 9564 format 0 PIN block standard.
+///
+/// The PIN field consists of the control field `0`, the PIN length, the PIN digits in BCD and
+/// `F` nibbles as padding.
+///
+/// # Parameters
+///
+/// * `pin`: A reference to a string slice representing the ASCII-encoded PIN to be encoded. The
+///   PIN must consist of numeric characters only and have a length between 4 and 12 digits.
+///
+/// # Returns
+///
+/// * `Ok([u8; ISO0_PIN_BLOCK_LENGTH])` - An 8-byte array representing the encoded PIN field.
+/// * `Err(PaysecError)` - If the PIN is not within the required length or contains
+///   non-numeric characters.
+///
+/// # Errors
+///
+/// This function will return an error if:
+/// - The PIN length is not between 4 and 12 digits.
+/// - The PIN contains characters that are not numeric digits.
+pub fn encode_pin_field_iso_0(pin: &str) -> Result<[u8; ISO0_PIN_BLOCK_LENGTH], PaysecError> {
+    if pin.len() < 4 || pin.len() > 12 {
+        return Err(PaysecError::InvalidPinLength {
+            format: PinFormat::Format0,
+            len: pin.len(),
+        });
+    }
+    if !pin.chars().all(|c| c.is_ascii_digit()) {
+        return Err(PaysecError::InvalidPinDigit {
+            format: PinFormat::Format0,
+        });
+    }
+
+    let mut pin_field = [0xFFu8; ISO0_PIN_BLOCK_LENGTH];
+
+    // Control field (0) and PIN length into the first byte as nibbles
+    pin_field[0] = pin.len() as u8;
+
+    // Replace the padding nibbles by the PIN digits
+    for (i, c) in pin.chars().enumerate() {
+        let digit = c.to_digit(10).unwrap() as u8;
+
+        if i % 2 == 0 {
+            pin_field[1 + i / 2] = (pin_field[1 + i / 2] & 0x0F) | (digit << 4);
+        } else {
+            pin_field[1 + i / 2] = (pin_field[1 + i / 2] & 0xF0) | digit;
+        }
+    }
+
+    Ok(pin_field)
+}
+
+/// Decode a PIN field encoded in ISO 9564 format 0.
+///
+/// # Parameters
+///
+/// * `pin_field`: A byte slice representing the encoded PIN field.
+///
+/// # Returns
+///
+/// * `Ok(String)` - A string representing the decoded PIN.
+/// * `Err(PaysecError)` - If the PIN field is not in the correct format.
+///
+/// # Errors
+///
+/// This function will return an error if:
+/// - The PIN field is not 8 bytes long or not in ISO 9564 format 0.
+/// - The PIN length is not between 4 and 12 digits.
+/// - The PIN is not numeric.
+/// - A padding nibble is not `F`.
+pub fn decode_pin_field_iso_0(pin_field: &[u8]) -> Result<String, PaysecError> {
+    if pin_field.len() != ISO0_PIN_BLOCK_LENGTH {
+        return Err("PIN BLOCK ISO 0 ERROR: PIN field must be 8 bytes long".into());
+    }
+
+    if (pin_field[0] >> 4) != 0x0 {
+        return Err(PaysecError::InvalidControlField {
+            format: PinFormat::Format0,
+            control: pin_field[0] >> 4,
+        });
+    }
+
+    let pin_len = (pin_field[0] & 0x0F) as usize;
+    if !(4..=12).contains(&pin_len) {
+        return Err(PaysecError::InvalidPinLength {
+            format: PinFormat::Format0,
+            len: pin_len,
+        });
+    }
+
+    let mut pin = String::with_capacity(pin_len);
+    for i in 0..14 {
+        // Even positions are in the high nibble, odd positions in the low nibble
+        let nibble = (pin_field[1 + i / 2] >> (4 * (1 - i % 2))) & 0x0F;
+
+        if i < pin_len {
+            if nibble > 9 {
+                return Err(PaysecError::InvalidPinDigit {
+                    format: PinFormat::Format0,
+                });
+            }
+            pin.push(char::from(b'0' + nibble));
+        } else if nibble != 0xF {
+            return Err(PaysecError::InvalidPinFiller {
+                format: PinFormat::Format0,
+            });
+        }
+    }
+
+    Ok(pin)
+}
+
+/// Encode a Primary Account Number (PAN) using the ISO 9564 format 0 PAN field.
+///
+/// The PAN field consists of four `0` nibbles followed by the rightmost 12 digits of the PAN
+/// excluding the check digit in BCD. It is the same as the format 3 PAN field.
+///
+/// # Parameters
+///
+/// * `pan`: A reference to a string slice representing the ASCII-encoded PAN to be encoded.
+///   The PAN must consist of numeric characters only and have a length of at least 13 digits.
+///
+/// # Returns
+///
+/// * `Ok([u8; ISO0_PIN_BLOCK_LENGTH])` - An 8-byte array representing the encoded PAN field.
+/// * `Err(PaysecError)` - If the PAN is too short or contains non-numeric characters.
+///
+/// # Errors
+///
+/// This function will return an error if:
+/// - The PAN is shorter than 13 digits.
+/// - The PAN contains characters that are not ASCII digits.
+pub fn encode_pan_field_iso_0(pan: &str) -> Result<[u8; ISO0_PIN_BLOCK_LENGTH], PaysecError> {
+    if pan.len() < 13 {
+        return Err(
+            "PIN BLOCK ISO 0 ERROR: PAN must be at least 13 digits long for ISO 0 encoding".into(),
+        );
+    }
+
+    // Check all characters before slicing, a multibyte character would make the byte offsets
+    // below panic
+    if !pan.bytes().all(|b| b.is_ascii_digit()) {
+        return Err("PIN BLOCK ISO 0 ERROR: PAN must consist of ASCII digits only".into());
+    }
+
+    // The last 12 digits of the PAN, excluding the check digit, into the last 6 bytes as BCD
+    let pan_last_12 = &pan.as_bytes()[pan.len() - 13..pan.len() - 1];
+    let mut pan_field = [0u8; ISO0_PIN_BLOCK_LENGTH];
+    for (byte, digits) in pan_field[2..].iter_mut().zip(pan_last_12.chunks_exact(2)) {
+        *byte = ((digits[0] - b'0') << 4) | (digits[1] - b'0');
+    }
+
+    Ok(pan_field)
+}
@@ -0,0 +1,175 @@
+//! Module for Encoding and Decoding of PIN Blocks in ISO 9564 Format 1.
+//!
+//! This module provides functionalities for handling PIN blocks in compliance with the ISO 9564
+//! format 1 standard. Format 1 is used where no Primary Account Number (PAN) is available, so the
+//! PIN block is not bound to a PAN. The PIN field consists of the control field `1`, the PIN
+//! length and the PIN digits, followed by a unique transaction field or random padding. This
+//! implementation fills the remaining nibbles with random hex digits from a provided seed.
+//!
+//! # Example Usage
+//!
+//! ```
+//! use paysec::pin::{decode_pinblock_iso_1, encode_pinblock_iso_1};
+//!
+//! let pin = "1234";
+//! let rnd_seed = hex::decode("1C2965473CE206BB").unwrap();
+//!
+//! let pin_block = encode_pinblock_iso_1(pin, rnd_seed).unwrap();
+//! assert_eq!(hex::encode_upper(pin_block), "141234473CE206BB");
+//!
+//! let decoded_pin = decode_pinblock_iso_1(&pin_block).unwrap();
+//! assert_eq!(decoded_pin, pin);
+//! ```
+//!
+//! # Disclaimer
+//!
+//! - This library is provided "as is", with no warranty or guarantees regarding its security or
+//!   effectiveness in a production environment.
+//!
+//! # Note
+//!
+//! - This implementation is suitable for testing and generating test data. It's not intended for
+//!   use in production environments, especially where Hardware Security Modules (HSMs) are required.
+//! - The random seed must be provided externally, and the library does not assess the quality of
+//!   entropy.
+
+use super::pin_format::{block_size, min_seed_len, PinFormat};
+use crate::error::PaysecError;
+
+const ISO1_PIN_BLOCK_LENGTH: usize = block_size(PinFormat::Format1);
+const ISO1_MIN_SEED_LEN: usize = min_seed_len(PinFormat::Format1);
+
+/// Encode a PIN block using the ISO 9564 format 1 standard.
+///
+/// The PIN block consists of the control field `1`, the PIN length and the PIN digits in BCD. The
+/// remaining nibbles are taken from the first 8 bytes of the random seed. No PAN is bound to the
+/// PIN block.
+///
+/// # Parameters
+///
+/// * `pin`: A reference to a string slice representing the ASCII-encoded PIN to be used in the
+///   PIN block. The PIN must consist of numeric characters only and have a length between 4 and
+///   12 digits.
+/// * `rnd_seed`: A vector of bytes representing the random seed used for the padding. It must
+///   be at least 8 bytes long.
+///
+/// # Returns
+///
+/// * `Ok([u8; ISO1_PIN_BLOCK_LENGTH])` - An 8-byte array representing the encoded PIN block.
+/// * `Err(PaysecError)` - If the PIN is invalid or the random seed is too short.
+///
+/// # Errors
+///
+/// This function will return an error if:
+/// - The PIN length is not between 4 and 12 digits.
+/// - The PIN contains characters that are not numeric digits.
+/// - The provided `rnd_seed` is shorter than 8 bytes.
+/// - With the `strict-seed` feature, the first 8 bytes of `rnd_seed` fail `check_seed_entropy`.
+///
+/// # Note
+///
+/// This function does not encrypt the resulting PIN block. The encoded PIN block should be
+/// encrypted in a separate step, e.g. with Triple DES.
+pub fn encode_pinblock_iso_1(
+    pin: &str,
+    rnd_seed: Vec<u8>,
+) -> Result<[u8; ISO1_PIN_BLOCK_LENGTH], PaysecError> {
+    if pin.len() < 4 || pin.len() > 12 {
+        return Err(PaysecError::InvalidPinLength {
+            format: PinFormat::Format1,
+            len: pin.len(),
+        });
+    }
+    if !pin.chars().all(|c| c.is_ascii_digit()) {
+        return Err(PaysecError::InvalidPinDigit {
+            format: PinFormat::Format1,
+        });
+    }
+    if rnd_seed.len() < ISO1_MIN_SEED_LEN {
+        return Err(PaysecError::SeedTooShort {
+            context: "PIN BLOCK ISO 1 ERROR",
+            len: rnd_seed.len(),
+            required: ISO1_MIN_SEED_LEN,
+        });
+    }
+    #[cfg(feature = "strict-seed")]
+    crate::utils::check_seed_entropy(&rnd_seed, ISO1_MIN_SEED_LEN)?;
+
+    let mut pin_block = [0u8; ISO1_PIN_BLOCK_LENGTH];
+    pin_block.copy_from_slice(&rnd_seed[..ISO1_PIN_BLOCK_LENGTH]);
+
+    // Control field (1) and PIN length into the first byte as nibbles
+    pin_block[0] = 0x10 | pin.len() as u8;
+
+    // Replace the random nibbles by the PIN digits
+    for (i, c) in pin.chars().enumerate() {
+        let digit = c.to_digit(10).unwrap() as u8;
+
+        if i % 2 == 0 {
+            pin_block[1 + i / 2] = (pin_block[1 + i / 2] & 0x0F) | (digit << 4);
+        } else {
+            pin_block[1 + i / 2] = (pin_block[1 + i / 2] & 0xF0) | digit;
+        }
+    }
+
+    Ok(pin_block)
+}
+
+/// Decode a PIN block using the ISO 9564 format 1 standard and extract the PIN.
+///
+/// The padding following the PIN digits is not checked, since it may be any hex digit.
+///
+/// # Parameters
+///
+/// * `pin_block`: A byte slice representing the encoded PIN block.
+///
+/// # Returns
+///
+/// * `Ok(String)` - A string representing the decoded PIN.
+/// * `Err(PaysecError)` - If the PIN block is not in the correct format.
+///
+/// # Errors
+///
+/// This function will return an error if:
+/// - The PIN block is not 8 bytes long or not in ISO 9564 format 1.
+/// - The PIN length is not between 4 and 12 digits.
+/// - The PIN is not numeric.
+pub fn decode_pinblock_iso_1(pin_block: &[u8]) -> Result<String, PaysecError> {
+    if pin_block.len() != ISO1_PIN_BLOCK_LENGTH {
+        return Err("PIN BLOCK ISO 1 ERROR: Invalid PIN block length".into());
+    }
+
+    if (pin_block[0] >> 4) != 0x1 {
+        return Err(PaysecError::InvalidControlField {
+            format: PinFormat::Format1,
+            control: pin_block[0] >> 4,
+        });
+    }
+
+    let pin_len = (pin_block[0] & 0x0F) as usize;
+    if !(4..=12).contains(&pin_len) {
+        return Err(PaysecError::InvalidPinLength {
+            format: PinFormat::Format1,
+            len: pin_len,
+        });
+    }
+
+    let mut pin = String::with_capacity(pin_len);
+    for i in 0..pin_len {
+        let digit = if i % 2 == 0 {
+            pin_block[1 + i / 2] >> 4
+        } else {
+            pin_block[1 + i / 2] & 0x0F
+        };
+
+        if digit > 9 {
+            return Err(PaysecError::InvalidPinDigit {
+                format: PinFormat::Format1,
+            });
+        }
+
+        pin.push(char::from(b'0' + digit));
+    }
+
+    Ok(pin)
+}
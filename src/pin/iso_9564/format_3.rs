@@ -28,14 +28,14 @@
 //! // Example data for PIN, PAN, and random seed
 //! let pin = "1234";
 //! let pan = "12345678901234";
-//! let rnd_seed = vec![0xFF; 8];
+//! let rnd_seed = hex::decode("1C2965473CE206BB855B01533782A5E4").unwrap();
 //!
 //! // Encoding the PIN block
 //! let pin_block = encode_pinblock_iso_3(pin, pan, rnd_seed.clone()).unwrap();
 //! let pin_block_hex = hex::encode_upper(pin_block);
 //!
 //! // Expected encoded PIN block in hexadecimal format
-//! let expected_pinblock = "341217BA9876FEDC";
+//! let expected_pinblock = "341217F8AD26FDDC";
 //!
 //! // Asserting the encoded PIN block matches the expected result
 //! assert_eq!(
@@ -68,10 +68,17 @@
 //! - The random seed must be provided externally, and the library does not assess the quality of
 //!   entropy.
 
-use crate::utils::{transform_nibbles_to_af, xor_byte_arrays};
-use std::error::Error;
+use super::pan::Pan;
+use super::pin_format::{block_size, min_seed_len, PinFormat};
+use crate::error::PaysecError;
+use crate::utils::{
+    check_pan_scheme_length, transform_nibbles_to_af, transform_nibbles_to_af_unbiased,
+    xor_byte_arrays,
+};
+use crate::validation::ValidationConfig;
 
-const ISO3_PIN_BLOCK_LENGTH: usize = 8;
+const ISO3_PIN_BLOCK_LENGTH: usize = block_size(PinFormat::Format3);
+const ISO3_MIN_SEED_LEN: usize = min_seed_len(PinFormat::Format3);
 
 /// Encode a PIN block using the ISO 9564 format 3 standard.
 ///
@@ -93,7 +100,7 @@ const ISO3_PIN_BLOCK_LENGTH: usize = 8;
 /// # Returns
 ///
 /// * `Ok([u8; ISO3_PIN_BLOCK_LENGTH])` - An 8-byte array representing the encoded PIN block.
-/// * `Err(Box<dyn Error>)` - If there are issues with the input data (e.g., incorrect lengths
+/// * `Err(PaysecError)` - If there are issues with the input data (e.g., incorrect lengths
 ///                           or non-numeric characters), or if the XOR operation fails.
 ///
 /// # Errors
@@ -113,9 +120,7 @@ pub fn encode_pinblock_iso_3(
     pin: &str,
     pan: &str,
     rnd_seed: Vec<u8>,
-) -> Result<[u8; ISO3_PIN_BLOCK_LENGTH], Box<dyn Error>> {
-    const ISO3_PIN_BLOCK_LENGTH: usize = 8;
-
+) -> Result<[u8; ISO3_PIN_BLOCK_LENGTH], PaysecError> {
     let pin_field = encode_pin_field_iso_3(&pin, &rnd_seed)?;
 
     let pan_field = encode_pan_field_iso_3(&pan)?;
@@ -131,6 +136,31 @@ pub fn encode_pinblock_iso_3(
     }))
 }
 
+/// Encode a PIN block using the ISO 9564 format 3 standard with the legacy padding.
+///
+/// Same as `encode_pinblock_iso_3`, but the PIN field is encoded with
+/// `encode_pin_field_iso_3_legacy`, whose padding is biased. Use this function only to
+/// reproduce existing test vectors.
+///
+/// # Errors
+///
+/// This function will return an error in the same cases as `encode_pinblock_iso_3`.
+pub fn encode_pinblock_iso_3_legacy(
+    pin: &str,
+    pan: &str,
+    rnd_seed: Vec<u8>,
+) -> Result<[u8; ISO3_PIN_BLOCK_LENGTH], PaysecError> {
+    let pin_field = encode_pin_field_iso_3_legacy(pin, &rnd_seed)?;
+    let pan_field = encode_pan_field_iso_3(pan)?;
+
+    // XOR the pin_field and pan_field
+    let pin_block = xor_byte_arrays(&pin_field, &pan_field)?;
+
+    Ok(pin_block
+        .try_into()
+        .expect("PIN BLOCK ISO 3 ERROR: XOR result with incorrect length"))
+}
+
 /// Decode a PIN block using the ISO 9564 format 3 standard and extract the PIN.
 ///
 /// This function takes an encoded PIN block and a PAN, decodes them separately
@@ -147,7 +177,7 @@ pub fn encode_pinblock_iso_3(
 /// # Returns
 ///
 /// * `Ok(String)` - A string representing the decoded PIN.
-/// * `Err(Box<dyn Error>)` - If there are issues with the input data or if decoding fails.
+/// * `Err(PaysecError)` - If there are issues with the input data or if decoding fails.
 ///
 /// # Errors
 ///
@@ -155,12 +185,34 @@ pub fn encode_pinblock_iso_3(
 /// - The PAN length is less than 13 digits.
 /// - The PAN contains non-numeric characters.
 /// - The decoding process fails for any reason.
-pub fn decode_pinblock_iso_3(pin_block: &[u8], pan: &str) -> Result<String, Box<dyn Error>> {
+pub fn decode_pinblock_iso_3(pin_block: &[u8], pan: &str) -> Result<String, PaysecError> {
+    decode_pinblock_iso_3_with_options(pin_block, pan, &ValidationConfig::default())
+}
+
+/// Decode a PIN block using the ISO 9564 format 3 with a validation policy.
+///
+/// Same as `decode_pinblock_iso_3`, but the check of the filler nibbles is controlled by
+/// `config.pin_filler`. If `config.pan_scheme_length` is set, the PAN length is checked against
+/// the card scheme of its BIN range with `check_pan_scheme_length`.
+///
+/// # Errors
+///
+/// Returns the same errors as `decode_pinblock_iso_3`, except for the filler check if it is
+/// disabled in `config`, and an error if the enabled PAN length check fails.
+pub fn decode_pinblock_iso_3_with_options(
+    pin_block: &[u8],
+    pan: &str,
+    config: &ValidationConfig,
+) -> Result<String, PaysecError> {
     // Ensure the pinblock length is 8 bytes
     if pin_block.len() != 8 {
         return Err("PIN BLOCK ISO 3 ERROR: Invalid PIN block length".into());
     }
 
+    if config.pan_scheme_length {
+        check_pan_scheme_length(pan)?;
+    }
+
     // Create PAN block
     let pan_field = encode_pan_field_iso_3(pan)?;
 
@@ -168,61 +220,196 @@ pub fn decode_pinblock_iso_3(pin_block: &[u8], pan: &str) -> Result<String, Box<
     let pin_field = xor_byte_arrays(pin_block, &pan_field)?;
 
     // Decode the pin_field to extract the PIN
-    let pin = decode_pin_field_iso_3(&pin_field)?;
+    let pin = decode_pin_digits_iso_3(&pin_field, config.pin_filler)?;
 
     Ok(pin)
 }
 
+/// Encode a PIN block using the ISO 9564 format 3 standard with a validated PAN.
+///
+/// Same as `encode_pinblock_iso_3`.
+///
+/// # Errors
+///
+/// This function will return an error in the same cases as `encode_pinblock_iso_3`.
+pub fn encode_pinblock_iso_3_with_pan(
+    pin: &str,
+    pan: &Pan,
+    rnd_seed: Vec<u8>,
+) -> Result<[u8; ISO3_PIN_BLOCK_LENGTH], PaysecError> {
+    encode_pinblock_iso_3(pin, pan.as_str(), rnd_seed)
+}
+
+/// Decode a PIN block using the ISO 9564 format 3 standard with a validated PAN.
+///
+/// Same as `decode_pinblock_iso_3`.
+///
+/// # Errors
+///
+/// This function will return an error in the same cases as `decode_pinblock_iso_3`.
+pub fn decode_pinblock_iso_3_with_pan(pin_block: &[u8], pan: &Pan) -> Result<String, PaysecError> {
+    decode_pinblock_iso_3(pin_block, pan.as_str())
+}
+
 /// Encode a PIN field using the ISO 9564 format 3 PIN block standard.
 ///
 /// This function encodes a given Personal Identification Number (PIN) into an 8-byte array
 /// according to the ISO 9564 format 3 specification. The encoding process includes setting a
 /// control field, encoding the PIN length and digits in Binary Coded Decimal (BCD), and padding
-/// with hexadecimal characters from A to F. The padding is derived from a provided random seed,
-/// ensuring variability and security.
+/// with hexadecimal characters from A to F. The padding is sampled uniformly from the A-F range
+/// from a provided random seed, see `transform_nibbles_to_af_unbiased`.
 ///
 /// # Parameters
 ///
 /// * `pin`: A reference to a string slice representing the ASCII-encoded PIN to
-///          be encoded. The PIN must consist of numeric characters only and
-///          have a length between 4 and 12 digits.
-/// * `rnd_seed`: A reference to a vector of bytes representing the random seed used
-///               for padding. The first 8 bytes of the seed are transformed to ensure
-///               they fall within the hexadecimal range A to F.
+///   be encoded. The PIN must consist of numeric characters only and
+///   have a length between 4 and 12 digits.
+/// * `rnd_seed`: A slice of bytes representing the random seed used
+///   for padding. Seed nibbles from 12 to 15 are skipped, so the number of seed bytes
+///   consumed is variable: at least 8 and on average about 11 bytes. With a seed of 32
+///   random bytes the encoding fails with a probability below 10^-17.
 ///
 /// # Returns
 ///
 /// * `Ok([u8; ISO3_PIN_BLOCK_LENGTH])` - An 8-byte array representing the encoded
-///                                       PIN block.
-/// * `Err(Box<dyn Error>)` - If the PIN is not within the required length, contains
-///                           non-numeric characters, or if there are issues with the
-///                           random seed.
+///   PIN block.
+/// * `Err(PaysecError)` - If the PIN is not within the required length, contains
+///   non-numeric characters, or if there are issues with the random seed.
 ///
 /// # Errors
 ///
 /// This function will return an error if:
 /// - The PIN length is not between 4 and 12 digits.
 /// - The PIN contains characters that are not numeric digits.
-/// - The provided `rnd_seed` does not have at least 8 bytes.
+/// - The provided `rnd_seed` is exhausted before the padding is complete.
+/// - With the `strict-seed` feature, the first 8 bytes of `rnd_seed` fail `check_seed_entropy`.
 pub fn encode_pin_field_iso_3(
     pin: &str,
-    rnd_seed: &Vec<u8>,
-) -> Result<[u8; ISO3_PIN_BLOCK_LENGTH], Box<dyn Error>> {
+    rnd_seed: &[u8],
+) -> Result<[u8; ISO3_PIN_BLOCK_LENGTH], PaysecError> {
     // Validate PIN
-    if pin.len() < 4 || pin.len() > 12 || !pin.chars().all(char::is_numeric) {
-        return Err("PIN BLOCK ISO 3 ERROR: PIN must be between 4 and 12 digits long".into());
+    if pin.len() < 4 || pin.len() > 12 {
+        return Err(PaysecError::InvalidPinLength {
+            format: PinFormat::Format3,
+            len: pin.len(),
+        });
+    }
+    if !pin.chars().all(char::is_numeric) {
+        return Err(PaysecError::InvalidPinDigit {
+            format: PinFormat::Format3,
+        });
+    }
+
+    if rnd_seed.len() < ISO3_MIN_SEED_LEN {
+        return Err(PaysecError::SeedTooShort {
+            context: "PIN BLOCK ISO 3 ERROR",
+            len: rnd_seed.len(),
+            required: ISO3_MIN_SEED_LEN,
+        });
+    }
+
+    // Sample the padding uniformly from the A-F range
+    let padding = transform_nibbles_to_af_unbiased(rnd_seed, ISO3_PIN_BLOCK_LENGTH)
+        .map_err(|_| "PIN BLOCK ISO 3 ERROR: Insufficient seed length for PIN block")?;
+    #[cfg(feature = "strict-seed")]
+    crate::utils::check_seed_entropy(rnd_seed, ISO3_MIN_SEED_LEN)?;
+
+    Ok(set_pin_digits_iso_3(pin, &padding))
+}
+
+/// Encode a PIN field using the ISO 9564 format 3 PIN block standard with the legacy padding.
+///
+/// Same as `encode_pin_field_iso_3`, but the first 8 bytes of the seed are mapped to the A-F
+/// range with `transform_nibbles_to_af` as in previous versions of this crate. This mapping is
+/// biased: the padding characters C, D, E and F occur more often than A and B. Use this function
+/// only to reproduce existing test vectors.
+///
+/// # Parameters
+///
+/// * `pin`: A reference to a string slice representing the ASCII-encoded PIN to
+///   be encoded. The PIN must consist of numeric characters only and
+///   have a length between 4 and 12 digits.
+/// * `rnd_seed`: A slice of bytes representing the random seed used
+///   for padding. The first 8 bytes of the seed are used.
+///
+/// # Errors
+///
+/// This function will return an error if:
+/// - The PIN length is not between 4 and 12 digits.
+/// - The PIN contains characters that are not numeric digits.
+/// - The provided `rnd_seed` does not have at least 8 bytes.
+pub fn encode_pin_field_iso_3_legacy(
+    pin: &str,
+    rnd_seed: &[u8],
+) -> Result<[u8; ISO3_PIN_BLOCK_LENGTH], PaysecError> {
+    // Validate PIN
+    if pin.len() < 4 || pin.len() > 12 {
+        return Err(PaysecError::InvalidPinLength {
+            format: PinFormat::Format3,
+            len: pin.len(),
+        });
+    }
+    if !pin.chars().all(char::is_numeric) {
+        return Err(PaysecError::InvalidPinDigit {
+            format: PinFormat::Format3,
+        });
     }
 
     // Transform the first 8 bytes of the random seed to the A-F range
-    let transformed_seed = transform_nibbles_to_af(&rnd_seed);
+    let transformed_seed = transform_nibbles_to_af(rnd_seed);
 
     // Ensure we have at least 8 bytes to avoid panics
-    if transformed_seed.len() < ISO3_PIN_BLOCK_LENGTH {
-        return Err("PIN BLOCK ISO 3 ERROR: Insufficient seed length for PIN block".into());
+    if transformed_seed.len() < ISO3_MIN_SEED_LEN {
+        return Err(PaysecError::SeedTooShort {
+            context: "PIN BLOCK ISO 3 ERROR",
+            len: rnd_seed.len(),
+            required: ISO3_MIN_SEED_LEN,
+        });
+    }
+
+    Ok(set_pin_digits_iso_3(
+        pin,
+        &transformed_seed[..ISO3_PIN_BLOCK_LENGTH],
+    ))
+}
+
+/// Compute the filler nibbles of a format 3 PIN field from a random seed.
+///
+/// Returns the `14 - pin_len` nibbles following the PIN digits in the PIN field encoded by
+/// `encode_pin_field_iso_3` with the same seed, one nibble in the range A-F per byte. This allows
+/// checking the padding of the encoder independently of the PIN digits.
+///
+/// # Parameters
+///
+/// * `rnd_seed`: A slice of bytes representing the random seed used for padding, see
+///   `encode_pin_field_iso_3`.
+/// * `pin_len`: The number of PIN digits, between 4 and 12.
+///
+/// # Errors
+///
+/// This function will return an error if:
+/// - The PIN length is not between 4 and 12 digits.
+/// - The provided `rnd_seed` is exhausted before the padding is complete.
+pub fn expected_filler(rnd_seed: &[u8], pin_len: usize) -> Result<Vec<u8>, PaysecError> {
+    if !(4..=12).contains(&pin_len) {
+        return Err(PaysecError::InvalidPinLength {
+            format: PinFormat::Format3,
+            len: pin_len,
+        });
     }
 
+    let padding = transform_nibbles_to_af_unbiased(rnd_seed, ISO3_PIN_BLOCK_LENGTH)
+        .map_err(|_| "PIN BLOCK ISO 3 ERROR: Insufficient seed length for PIN block")?;
+
+    Ok((pin_len..14)
+        .map(|i| pin_field_nibble(&padding, i))
+        .collect())
+}
+
+// Place the control field, PIN length and PIN digits of a validated PIN into the padding.
+fn set_pin_digits_iso_3(pin: &str, padding: &[u8]) -> [u8; ISO3_PIN_BLOCK_LENGTH] {
     let mut pin_field = [0u8; ISO3_PIN_BLOCK_LENGTH];
-    pin_field.copy_from_slice(&transformed_seed[..ISO3_PIN_BLOCK_LENGTH]);
+    pin_field.copy_from_slice(padding);
 
     // Control field (3) and PIN length into the first byte as nibbles
     pin_field[0] = 0x30 | pin.len() as u8;
@@ -240,7 +427,7 @@ pub fn encode_pin_field_iso_3(
         }
     }
 
-    Ok(pin_field)
+    pin_field
 }
 
 /// Decodes a PIN field encoded in ISO 9564 format 3.
@@ -256,7 +443,7 @@ pub fn encode_pin_field_iso_3(
 /// # Returns
 ///
 /// * `Ok(String)` - A string representing the decoded PIN.
-/// * `Err(Box<dyn Error>)` - If the PIN field is not in the correct format or if decoding fails.
+/// * `Err(PaysecError)` - If the PIN field is not in the correct format or if decoding fails.
 ///
 /// # Errors
 ///
@@ -265,19 +452,30 @@ pub fn encode_pin_field_iso_3(
 /// - The PIN length is not between 4 and 12 digits.
 /// - The filler characters are not within the expected range (A-F).
 /// - The PIN is not numeric.
-pub fn decode_pin_field_iso_3(pin_field: &[u8]) -> Result<String, Box<dyn Error>> {
+pub fn decode_pin_field_iso_3(pin_field: &[u8]) -> Result<String, PaysecError> {
+    decode_pin_digits_iso_3(pin_field, true)
+}
+
+// Decode the PIN of a format 3 PIN field, optionally checking the filler.
+fn decode_pin_digits_iso_3(pin_field: &[u8], check_filler: bool) -> Result<String, PaysecError> {
     if pin_field.len() != 8 {
         return Err("PIN BLOCK ISO 3 ERROR: PIN field must be 8 bytes long".into());
     }
 
     if (pin_field[0] >> 4) != 0x3 {
-        return Err("PIN BLOCK ISO 3 ERROR: PIN block is not ISO format 3.".into());
+        return Err(PaysecError::InvalidControlField {
+            format: PinFormat::Format3,
+            control: pin_field[0] >> 4,
+        });
     }
 
     let pin_len = (pin_field[0] & 0x0F) as usize;
 
     if pin_len < 4 || pin_len > 12 {
-        return Err("PIN BLOCK ISO 3 ERROR: PIN length must be between 4 and 12".into());
+        return Err(PaysecError::InvalidPinLength {
+            format: PinFormat::Format3,
+            len: pin_len,
+        });
     }
 
     let mut pin = String::new();
@@ -289,28 +487,37 @@ pub fn decode_pin_field_iso_3(pin_field: &[u8]) -> Result<String, Box<dyn Error>
         };
 
         if digit > 9 {
-            return Err("PIN BLOCK ISO 3 ERROR: PIN contains invalid digit".into());
+            return Err(PaysecError::InvalidPinDigit {
+                format: PinFormat::Format3,
+            });
         }
 
         pin.push_str(&digit.to_string());
     }
 
     // Check if the filler is correct (A-F for each unused nibble)
-    for i in pin_len..14 {
-        let filler = if i % 2 == 0 {
-            pin_field[1 + i / 2] >> 4
-        } else {
-            pin_field[1 + i / 2] & 0x0F
-        };
-
-        if !(0xA..=0xF).contains(&filler) {
-            return Err("PIN BLOCK ISO 3 ERROR: PIN block filler is incorrect".into());
+    if check_filler {
+        for i in pin_len..14 {
+            let filler = pin_field_nibble(pin_field, i);
+
+            if !(0xA..=0xF).contains(&filler) {
+                return Err(PaysecError::InvalidPinFiller {
+                    format: PinFormat::Format3,
+                });
+            }
         }
     }
 
     Ok(pin)
 }
 
+// Get the nibble at PIN digit position `i` of a PIN field, following the control field and the
+// PIN length.
+fn pin_field_nibble(pin_field: &[u8], i: usize) -> u8 {
+    // Even positions are in the high nibble, odd positions in the low nibble
+    (pin_field[1 + i / 2] >> (4 * (1 - i % 2))) & 0x0F
+}
+
 /// Encode a Primary Account Number (PAN) using the ISO 9564 format 3 PAN field.
 ///
 /// This function encodes a given PAN into an 8-byte array as per the ISO 9564 format 3
@@ -328,14 +535,14 @@ pub fn decode_pin_field_iso_3(pin_field: &[u8]) -> Result<String, Box<dyn Error>
 /// # Returns
 ///
 /// * `Ok([u8; ISO3_PIN_BLOCK_LENGTH])` - An 8-byte array representing the encoded PAN block.
-/// * `Err(Box<dyn Error>)` - If the PAN is shorter than the required length or contains non-numeric characters.
+/// * `Err(PaysecError)` - If the PAN is shorter than the required length or contains non-numeric characters.
 ///
 /// # Errors
 ///
 /// This function will return an error if:
 /// - The PAN is shorter than 13 digits (to ensure at least 12 digits excluding the check digit).
-/// - The PAN contains characters that are not numeric digits.
-pub fn encode_pan_field_iso_3(pan: &str) -> Result<[u8; ISO3_PIN_BLOCK_LENGTH], Box<dyn Error>> {
+/// - The PAN contains characters that are not ASCII digits.
+pub fn encode_pan_field_iso_3(pan: &str) -> Result<[u8; ISO3_PIN_BLOCK_LENGTH], PaysecError> {
     // Ensure PAN length is at least 13 digits (to have 12 digits excluding the check digit)
     if pan.len() < 13 {
         return Err(
@@ -343,6 +550,12 @@ pub fn encode_pan_field_iso_3(pan: &str) -> Result<[u8; ISO3_PIN_BLOCK_LENGTH],
         );
     }
 
+    // Check all characters before slicing, a multibyte character would make the byte offsets
+    // below panic
+    if !pan.bytes().all(|b| b.is_ascii_digit()) {
+        return Err("PIN BLOCK ISO 3 ERROR: PAN must consist of ASCII digits only".into());
+    }
+
     // Extract the last 12 digits of the PAN, excluding the check digit
     let pan_last_12 = &pan[pan.len() - 13..pan.len() - 1];
 
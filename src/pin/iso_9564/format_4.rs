@@ -21,6 +21,8 @@
 //! # Example Usage
 //!
 //! ```
+//! # #[cfg(not(feature = "no-clear-secrets"))]
+//! # {
 //! use paysec::pin::{encipher_pinblock_iso_4, decipher_pinblock_iso_4};
 //! use hex;
 //!
@@ -28,14 +30,14 @@
 //! let key = hex::decode("00112233445566778899AABBCCDDEEFF").expect("Invalid key hex");
 //! let pin = "1234";
 //! let pan = "1234567890123456789";
-//! let rnd_seed = vec![0xFF; 8];
+//! let rnd_seed = hex::decode("1C2965473CE206BB").unwrap();
 //!
 //! // Encrypting the PIN block
 //! let encrypted_pin_block = encipher_pinblock_iso_4(&key, pin, pan, rnd_seed).expect("Failed to encipher pinblock");
 //! let encrypted_pin_block_hex = hex::encode(encrypted_pin_block.clone()).to_uppercase();
 //!
 //! // Expected encrypted PIN block in hexadecimal format
-//! let expected_pinblock = "28B41FDDD29B743E93124BD8E32D921E";
+//! let expected_pinblock = "AA69B908E63B1FAB45124316EA5F3073";
 //!
 //! // Asserting the encrypted PIN block matches the expected result
 //! assert_eq!(encrypted_pin_block_hex, expected_pinblock, "Failed test for PIN: {}, PAN: {}", pin, pan);
@@ -45,6 +47,7 @@
 //!
 //! // Asserting the decrypted PIN matches the original PIN
 //! assert_eq!( decrypted_pin, pin, "Deciphered PIN does not match expected PIN");
+//! # }
 //! ```
 //!
 //! # Disclaimer
@@ -58,16 +61,30 @@
 //!   use in production environments, especially where Hardware Security Modules (HSMs) are required.
 //! - The random seed must be provided externally, and the library does not assess the quality of
 //!   entropy.
-//! - For cryptographic operations, this library uses the `soft-aes` crate, which lacks
-//!   protections against side-channel attacks. In production, a HSM should be used for cryptographic
-//!   operations and random number generation.
-
+//! - By default, this library uses the `soft-aes` crate for cryptographic operations, which lacks
+//!   protections against side-channel attacks. The `backend-rustcrypto` feature selects the
+//!   constant-time RustCrypto `aes` crate instead. In production, a HSM should be used for
+//!   cryptographic operations and random number generation.
+
+use super::pan::Pan;
+use super::pin_format::{block_size, min_seed_len, PinFormat};
+#[cfg(not(feature = "no-clear-secrets"))]
+use crate::crypto::aes::aes_dec_ecb;
+use crate::crypto::aes::aes_enc_ecb;
+#[cfg(feature = "keyblock")]
+use crate::keyblock::{KeyOperation, UsageBoundKey};
+#[cfg(not(feature = "no-clear-secrets"))]
+use crate::utils::check_pan_scheme_length;
+#[cfg(feature = "rng")]
+use crate::utils::generate_random_seed;
 use crate::utils::{left_pad_str, right_pad_str, xor_byte_arrays};
 
-use soft_aes::aes::{aes_dec_ecb, aes_enc_ecb};
-use std::error::Error;
+use crate::error::PaysecError;
+#[cfg(not(feature = "no-clear-secrets"))]
+use crate::validation::ValidationConfig;
 
-const ISO4_PIN_BLOCK_LENGTH: usize = 16;
+const ISO4_PIN_BLOCK_LENGTH: usize = block_size(PinFormat::Format4);
+const ISO4_MIN_SEED_LEN: usize = min_seed_len(PinFormat::Format4);
 
 /// Encode a PIN using the ISO 9564 format 4 PIN block standard.
 ///
@@ -89,7 +106,7 @@ const ISO4_PIN_BLOCK_LENGTH: usize = 16;
 ///
 /// * `Ok([u8; ISO4_PIN_BLOCK_LENGTH])` - A 16-byte array representing the encoded
 ///                                       PIN block.
-/// * `Err(Box<dyn Error>)` - If the PIN is not within the required length, contains
+/// * `Err(PaysecError)` - If the PIN is not within the required length, contains
 ///                           non-numeric characters, or `rnd_seed` is not 8 bytes long.
 ///
 /// # Errors
@@ -98,18 +115,31 @@ const ISO4_PIN_BLOCK_LENGTH: usize = 16;
 /// - The PIN length is not between 4 and 12 digits.
 /// - The PIN contains characters that are not numeric digits.
 /// - The provided `rnd_seed` is not exactly 8 bytes long.
+/// - With the `strict-seed` feature, the first 8 bytes of `rnd_seed` fail `check_seed_entropy`.
 pub fn encode_pin_field_iso_4(
     pin: &str,
     rnd_seed: Vec<u8>,
-) -> Result<[u8; ISO4_PIN_BLOCK_LENGTH], Box<dyn Error>> {
-    const ISO4_PIN_BLOCK_LENGTH: usize = 16;
-
-    if pin.len() < 4 || pin.len() > 12 || !pin.chars().all(char::is_numeric) {
-        return Err("PIN BLOCK ISO 4 ERROR: PIN must be between 4 and 12 digits long".into());
+) -> Result<[u8; ISO4_PIN_BLOCK_LENGTH], PaysecError> {
+    if pin.len() < 4 || pin.len() > 12 {
+        return Err(PaysecError::InvalidPinLength {
+            format: PinFormat::Format4,
+            len: pin.len(),
+        });
+    }
+    if !pin.chars().all(char::is_numeric) {
+        return Err(PaysecError::InvalidPinDigit {
+            format: PinFormat::Format4,
+        });
     }
-    if rnd_seed.len() < 8 {
-        return Err("PIN BLOCK ISO 4 ERROR: Random seed must be at least 8 bytes long".into());
+    if rnd_seed.len() < ISO4_MIN_SEED_LEN {
+        return Err(PaysecError::SeedTooShort {
+            context: "PIN BLOCK ISO 4 ERROR",
+            len: rnd_seed.len(),
+            required: ISO4_MIN_SEED_LEN,
+        });
     }
+    #[cfg(feature = "strict-seed")]
+    crate::utils::check_seed_entropy(&rnd_seed, ISO4_MIN_SEED_LEN)?;
 
     let mut pin_field = [0u8; ISO4_PIN_BLOCK_LENGTH];
 
@@ -149,7 +179,7 @@ pub fn encode_pin_field_iso_4(
 /// # Returns
 ///
 /// * `Ok(String)` - A string representing the decoded ASCII-encoded PIN.
-/// * `Err(Box<dyn Error>)` - If the PIN block is not 16 bytes long, does not
+/// * `Err(PaysecError)` - If the PIN block is not 16 bytes long, does not
 ///                           adhere to the ISO 9564 format 4 standard, or contains
 ///                           invalid data.
 ///
@@ -161,29 +191,32 @@ pub fn encode_pin_field_iso_4(
 /// - The PIN length is not between 4 and 12 digits.
 /// - The PIN contains non-numeric digits.
 /// - The filler bytes are not as per the standard.
-pub fn decode_pin_field_iso_4(pin_field: &[u8]) -> Result<String, Box<dyn Error>> {
+pub fn decode_pin_field_iso_4(pin_field: &[u8]) -> Result<String, PaysecError> {
+    decode_pin_digits_iso_4(pin_field, true)
+}
+
+// Decode the PIN of a format 4 PIN field, optionally checking the filler.
+fn decode_pin_digits_iso_4(pin_field: &[u8], check_filler: bool) -> Result<String, PaysecError> {
     if pin_field.len() != 16 {
         return Err("PIN BLOCK ISO 4 ERROR: PIN field must be 16 bytes long".into());
     }
 
     // Check if the control field is 4 (higher nibble of the first byte)
     if pin_field[0] >> 4 != 0x4 {
-        return Err(format!(
-            "PIN BLOCK ISO 4 ERROR: PIN block is not ISO format 4: control field `{}`",
-            pin_field[0] >> 4
-        )
-        .into());
+        return Err(PaysecError::InvalidControlField {
+            format: PinFormat::Format4,
+            control: pin_field[0] >> 4,
+        });
     }
 
     // Extract PIN length (lower nibble of the first byte)
     let pin_len = (pin_field[0] & 0x0F) as usize;
 
     if pin_len < 4 || pin_len > 12 {
-        return Err(format!(
-            "PIN BLOCK ISO 4 ERROR: PIN length must be between 4 and 12: `{}`",
-            pin_len
-        )
-        .into());
+        return Err(PaysecError::InvalidPinLength {
+            format: PinFormat::Format4,
+            len: pin_len,
+        });
     }
 
     let mut pin = String::new();
@@ -196,22 +229,28 @@ pub fn decode_pin_field_iso_4(pin_field: &[u8]) -> Result<String, Box<dyn Error>
         };
 
         if digit > 9 {
-            return Err("PIN BLOCK ISO 4 ERROR: PIN contains invalid digit".into());
+            return Err(PaysecError::InvalidPinDigit {
+                format: PinFormat::Format4,
+            });
         }
 
         pin.push_str(&digit.to_string());
     }
 
     // Check if the filler is correct (0xA for each unused nibble)
-    for i in pin_len..14 {
-        let filler = if i % 2 == 0 {
-            pin_field[1 + i / 2] >> 4
-        } else {
-            pin_field[1 + i / 2] & 0x0F
-        };
-
-        if filler != 0xA {
-            return Err("PIN BLOCK ISO 4 ERROR: PIN block filler is incorrect".into());
+    if check_filler {
+        for i in pin_len..14 {
+            let filler = if i % 2 == 0 {
+                pin_field[1 + i / 2] >> 4
+            } else {
+                pin_field[1 + i / 2] & 0x0F
+            };
+
+            if filler != 0xA {
+                return Err(PaysecError::InvalidPinFiller {
+                    format: PinFormat::Format4,
+                });
+            }
         }
     }
 
@@ -236,7 +275,7 @@ pub fn decode_pin_field_iso_4(pin_field: &[u8]) -> Result<String, Box<dyn Error>
 ///
 /// * `Ok([u8; ISO4_PIN_BLOCK_LENGTH])` - A 16-byte array representing the encoded
 ///    PAN block.
-/// * `Err(Box<dyn Error>)` - If the PAN is not within the required length or
+/// * `Err(PaysecError)` - If the PAN is not within the required length or
 ///    contains non-numeric characters.
 ///
 /// # Errors
@@ -244,7 +283,7 @@ pub fn decode_pin_field_iso_4(pin_field: &[u8]) -> Result<String, Box<dyn Error>
 /// This function will return an error if:
 /// - The PAN length is not between 1 and 19 digits.
 /// - The PAN contains characters that are not numeric digits.
-pub fn encode_pan_field_iso_4(pan: &str) -> Result<[u8; 16], Box<dyn Error>> {
+pub fn encode_pan_field_iso_4(pan: &str) -> Result<[u8; 16], PaysecError> {
     // Check PAN length
     if pan.len() < 1 || pan.len() > 19 || !pan.chars().all(|c| c.is_ascii_digit()) {
         return Err("PIN BLOCK ISO 4 ERROR: PAN must be between 1 and 19 digits long.".into());
@@ -289,7 +328,7 @@ pub fn encode_pan_field_iso_4(pan: &str) -> Result<[u8; 16], Box<dyn Error>> {
 /// # Returns
 ///
 /// * `Ok(Vec<u8>)` - A `Vec<u8>` representing the encrypted PIN block.
-/// * `Err(Box<dyn Error>)` - If there are issues with the input data (e.g., incorrect lengths or non-numeric characters)
+/// * `Err(PaysecError)` - If there are issues with the input data (e.g., incorrect lengths or non-numeric characters)
 ///                           or if encryption fails.
 ///
 /// # Errors
@@ -303,24 +342,52 @@ pub fn encipher_pinblock_iso_4(
     pin: &str,
     pan: &str,
     rnd_seed: Vec<u8>,
-) -> Result<Vec<u8>, Box<dyn Error>> {
+) -> Result<Vec<u8>, PaysecError> {
     // Step 1: Encode the PIN and PAN fields
     let pin_field = encode_pin_field_iso_4(pin, rnd_seed)?;
     let pan_field = encode_pan_field_iso_4(pan)?;
 
     // Step 2: Encrypt the pin field (intermediate block A)
-    let intermediate_block_a = aes_enc_ecb(&pin_field, key, None)?;
+    let intermediate_block_a = aes_enc_ecb(&pin_field, key)?;
 
     // Step 3: XOR intermediate block A with PAN field
     let intermediate_block_b = xor_byte_arrays(&intermediate_block_a, &pan_field)?;
 
     // Step 4: Encrypt the resulting block (intermediate block B)
-    let encrypted_block = aes_enc_ecb(&intermediate_block_b, key, None)?;
+    let encrypted_block = aes_enc_ecb(&intermediate_block_b, key)?;
 
     // Step 5: Return the final encrypted pinblock
     Ok(encrypted_block)
 }
 
+/// Encipher a PIN block using the ISO 9564 format 4 standard with a random seed from the
+/// operating system CSPRNG.
+///
+/// Same as `encipher_pinblock_iso_4` with a seed from `generate_random_seed`. The function
+/// requires the `rng` feature.
+///
+/// # Errors
+///
+/// This function will return an error in the same cases as `encipher_pinblock_iso_4`.
+///
+/// # Example
+///
+/// ```
+/// use paysec::pin::encipher_pinblock_iso_4_auto_seed;
+///
+/// let key = hex::decode("00112233445566778899AABBCCDDEEFF").unwrap();
+/// let pin_block = encipher_pinblock_iso_4_auto_seed(&key, "1234", "1234567890123456789").unwrap();
+/// assert_eq!(pin_block.len(), 16);
+/// ```
+#[cfg(feature = "rng")]
+pub fn encipher_pinblock_iso_4_auto_seed(
+    key: &[u8],
+    pin: &str,
+    pan: &str,
+) -> Result<Vec<u8>, PaysecError> {
+    encipher_pinblock_iso_4(key, pin, pan, generate_random_seed(ISO4_MIN_SEED_LEN))
+}
+
 /// Decipher an ISO 9564 format 4 PIN block using AES decryption.
 ///
 /// This function decrypts an encrypted PIN block and extracts the original PIN. It
@@ -337,7 +404,7 @@ pub fn encipher_pinblock_iso_4(
 /// # Returns
 ///
 /// * `Ok(String)` - The decoded PIN as a `String`.
-/// * `Err(Box<dyn Error>)` - If the PIN block length is incorrect, if decryption fails, or if the decoded PIN field
+/// * `Err(PaysecError)` - If the PIN block length is incorrect, if decryption fails, or if the decoded PIN field
 ///                           is invalid (e.g., incorrect length, non-numeric characters).
 ///
 /// # Errors
@@ -346,11 +413,35 @@ pub fn encipher_pinblock_iso_4(
 /// - The encrypted PIN block length is not 16 bytes (the AES block size).
 /// - There is a failure in the decryption process.
 /// - The decoded PIN field is invalid (e.g., incorrect length, non-numeric characters).
+///
+/// A wrong PAN yields a random PIN field and is only detected by these format checks, which a
+/// random PIN field passes with a small probability.
+#[cfg(not(feature = "no-clear-secrets"))]
 pub fn decipher_pinblock_iso_4(
     key: &[u8],
     pin_block: &[u8],
     pan: &str,
-) -> Result<String, Box<dyn Error>> {
+) -> Result<String, PaysecError> {
+    decipher_pinblock_iso_4_with_options(key, pin_block, pan, &ValidationConfig::default())
+}
+
+/// Decipher an ISO 9564 format 4 PIN block with a validation policy.
+///
+/// Same as `decipher_pinblock_iso_4`, but the check of the filler nibbles is controlled by
+/// `config.pin_filler`. If `config.pan_scheme_length` is set, the PAN length is checked against
+/// the card scheme of its BIN range with `check_pan_scheme_length`.
+///
+/// # Errors
+///
+/// Returns the same errors as `decipher_pinblock_iso_4`, except for the filler check if it is
+/// disabled in `config`, and an error if the enabled PAN length check fails.
+#[cfg(not(feature = "no-clear-secrets"))]
+pub fn decipher_pinblock_iso_4_with_options(
+    key: &[u8],
+    pin_block: &[u8],
+    pan: &str,
+    config: &ValidationConfig,
+) -> Result<String, PaysecError> {
     if pin_block.len() != 16 {
         return Err(
             "PIN BLOCK ISO 4 ERROR: Data length must be multiple of AES block size 16".into(),
@@ -358,7 +449,11 @@ pub fn decipher_pinblock_iso_4(
     }
 
     // Step 1: Decrypt the PIN block (intermediate block B)
-    let intermediate_block_b = aes_dec_ecb(pin_block, key, None)?;
+    let intermediate_block_b = aes_dec_ecb(pin_block, key)?;
+
+    if config.pan_scheme_length {
+        check_pan_scheme_length(pan)?;
+    }
 
     // Step 2: Encode the PAN
     let pan_field = encode_pan_field_iso_4(pan)?;
@@ -367,10 +462,99 @@ pub fn decipher_pinblock_iso_4(
     let intermediate_block_a = xor_byte_arrays(&intermediate_block_b, &pan_field)?;
 
     // Step 4: Decrypt intermediate block A to get plaintext PIN field
-    let pin_field = aes_dec_ecb(&intermediate_block_a, key, None)?;
+    let pin_field = aes_dec_ecb(&intermediate_block_a, key)?;
 
     // Step 5: Decode and extract the PIN from the plaintext PIN field
-    let pin = decode_pin_field_iso_4(&pin_field)?;
+    let pin = decode_pin_digits_iso_4(&pin_field, config.pin_filler)?;
 
     Ok(pin)
 }
+
+/// Encipher a PIN block using the ISO 9564 format 4 standard with a validated PAN.
+///
+/// Same as `encipher_pinblock_iso_4`.
+///
+/// # Errors
+///
+/// This function will return an error in the same cases as `encipher_pinblock_iso_4`.
+pub fn encipher_pinblock_iso_4_with_pan(
+    key: &[u8],
+    pin: &str,
+    pan: &Pan,
+    rnd_seed: Vec<u8>,
+) -> Result<Vec<u8>, PaysecError> {
+    encipher_pinblock_iso_4(key, pin, pan.as_str(), rnd_seed)
+}
+
+/// Decipher an ISO 9564 format 4 PIN block with a validated PAN.
+///
+/// Same as `decipher_pinblock_iso_4`.
+///
+/// # Errors
+///
+/// This function will return an error in the same cases as `decipher_pinblock_iso_4`.
+#[cfg(not(feature = "no-clear-secrets"))]
+pub fn decipher_pinblock_iso_4_with_pan(
+    key: &[u8],
+    pin_block: &[u8],
+    pan: &Pan,
+) -> Result<String, PaysecError> {
+    decipher_pinblock_iso_4(key, pin_block, pan.as_str())
+}
+
+/// Encipher a PIN block using the ISO 9564 format 4 standard with a usage bound key.
+///
+/// Same as `encipher_pinblock_iso_4`, but the key must be a PIN encryption key (key usage `P0`)
+/// with a mode of use permitting encryption.
+///
+/// # Errors
+///
+/// This function will return an error if:
+/// - The key usage of the key is not `P0`.
+/// - The mode of use of the key does not permit encryption, as a `PaysecError::ModeOfUseViolation`.
+/// - `encipher_pinblock_iso_4` fails.
+#[cfg(feature = "keyblock")]
+pub fn encipher_pinblock_iso_4_bound(
+    key: &UsageBoundKey,
+    pin: &str,
+    pan: &str,
+    rnd_seed: Vec<u8>,
+) -> Result<Vec<u8>, PaysecError> {
+    let key = pin_key_for(key, KeyOperation::Encrypt)?;
+    encipher_pinblock_iso_4(key, pin, pan, rnd_seed)
+}
+
+/// Decipher an ISO 9564 format 4 PIN block with a usage bound key.
+///
+/// Same as `decipher_pinblock_iso_4`, but the key must be a PIN encryption key (key usage `P0`)
+/// with a mode of use permitting decryption.
+///
+/// # Errors
+///
+/// This function will return an error if:
+/// - The key usage of the key is not `P0`.
+/// - The mode of use of the key does not permit decryption, as a `PaysecError::ModeOfUseViolation`.
+/// - `decipher_pinblock_iso_4` fails.
+#[cfg(feature = "keyblock")]
+#[cfg(not(feature = "no-clear-secrets"))]
+pub fn decipher_pinblock_iso_4_bound(
+    key: &UsageBoundKey,
+    pin_block: &[u8],
+    pan: &str,
+) -> Result<String, PaysecError> {
+    let key = pin_key_for(key, KeyOperation::Decrypt)?;
+    decipher_pinblock_iso_4(key, pin_block, pan)
+}
+
+// Check the key usage and mode of use of a PIN encryption key.
+#[cfg(feature = "keyblock")]
+fn pin_key_for(key: &UsageBoundKey, operation: KeyOperation) -> Result<&[u8], PaysecError> {
+    if key.key_usage() != "P0" {
+        return Err(format!(
+            "PIN BLOCK ISO 4 ERROR: Key usage {} is not a PIN encryption key",
+            key.key_usage()
+        )
+        .into());
+    }
+    key.key_for(operation)
+}
@@ -1,8 +1,16 @@
+mod format_0;
+mod format_1;
 mod format_3;
 mod format_4;
+mod pan;
+mod pin_format;
 
+pub use format_0::*;
+pub use format_1::*;
 pub use format_3::*;
 pub use format_4::*;
+pub use pan::*;
+pub use pin_format::*;
 
 #[cfg(test)]
 mod tests;
@@ -0,0 +1,165 @@
+//! Validated Primary Account Number (PAN).
+//!
+//! # Standard
+//!
+//! ISO/IEC 7812-1:2017: "Identification cards - Identification of issuers - Part 1: Numbering
+//! system"
+//!
+//! # Description
+//!
+//! A `Pan` holds a PAN of 8 to 19 decimal digits, validated once on construction. The `*_with_pan`
+//! variants of the PIN block functions accept a `&Pan`, so a PAN does not have to be validated
+//! again for every PIN block and an unvalidated string cannot be passed by mistake. The check digit
+//! is not verified, since test PANs often do not carry a valid one; see `has_valid_check_digit`.
+//!
+//! The `Debug` output of a `Pan` only shows the last four digits, and the first six digits if the
+//! PAN is longer than 12 digits.
+//!
+//! # Example
+//! ```
+//! # #[cfg(not(feature = "no-clear-secrets"))]
+//! # {
+//! use paysec::pin::{decipher_pinblock_iso_4_with_pan, encipher_pinblock_iso_4_with_pan, Pan};
+//!
+//! let key = hex::decode("00112233445566778899AABBCCDDEEFF").unwrap();
+//! let pan = Pan::new("1234567890123456789").unwrap();
+//! let rnd_seed = hex::decode("1C2965473CE206BB").unwrap();
+//!
+//! let pin_block = encipher_pinblock_iso_4_with_pan(&key, "1234", &pan, rnd_seed).unwrap();
+//! assert_eq!(hex::encode_upper(&pin_block), "AA69B908E63B1FAB45124316EA5F3073");
+//! assert_eq!(
+//!     decipher_pinblock_iso_4_with_pan(&key, &pin_block, &pan).unwrap(),
+//!     "1234"
+//! );
+//! # }
+//! ```
+
+use crate::error::PaysecError;
+use std::fmt;
+use std::str::FromStr;
+
+/// The minimum number of digits of a PAN.
+pub const PAN_MIN_LEN: usize = 8;
+/// The maximum number of digits of a PAN.
+pub const PAN_MAX_LEN: usize = 19;
+
+/// A Primary Account Number of 8 to 19 decimal digits.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct Pan(String);
+
+impl Pan {
+    /// Create a `Pan` from a string of decimal digits.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the PAN is not between 8 and 19 digits long or contains characters
+    /// which are not decimal digits.
+    pub fn new(pan: &str) -> Result<Self, PaysecError> {
+        if pan.len() < PAN_MIN_LEN || pan.len() > PAN_MAX_LEN {
+            return Err(format!(
+                "PAN ERROR: PAN must be between {} and {} digits long, found {}",
+                PAN_MIN_LEN,
+                PAN_MAX_LEN,
+                pan.len()
+            )
+            .into());
+        }
+        if !pan.chars().all(|c| c.is_ascii_digit()) {
+            return Err("PAN ERROR: PAN must contain only decimal digits".into());
+        }
+        Ok(Pan(pan.to_string()))
+    }
+
+    /// Returns the digits of the PAN.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Check the last digit of the PAN with the Luhn (modulus 10) algorithm.
+    pub fn has_valid_check_digit(&self) -> bool {
+        luhn_is_valid(&self.0)
+    }
+}
+
+/// Check the last digit of a string of decimal digits with the Luhn (modulus 10) algorithm.
+///
+/// Returns `false` if `digits` is empty or contains characters which are not decimal digits.
+///
+/// # Example
+/// ```
+/// use paysec::pin::luhn_is_valid;
+///
+/// assert!(luhn_is_valid("4111111111111111"));
+/// assert!(!luhn_is_valid("4111111111111112"));
+/// ```
+pub fn luhn_is_valid(digits: &str) -> bool {
+    !digits.is_empty()
+        && digits.bytes().all(|b| b.is_ascii_digit())
+        && luhn_sum(digits, false).is_multiple_of(10)
+}
+
+/// Compute the Luhn (modulus 10) check digit to append to a string of decimal digits.
+///
+/// Returns `None` if `digits` contains characters which are not decimal digits.
+///
+/// # Example
+/// ```
+/// use paysec::pin::luhn_check_digit;
+///
+/// assert_eq!(luhn_check_digit("411111111111111"), Some('1'));
+/// ```
+pub fn luhn_check_digit(digits: &str) -> Option<char> {
+    if !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let check_digit = (10 - luhn_sum(digits, true) % 10) % 10;
+    char::from_digit(check_digit, 10)
+}
+
+// Sum the digits with every second digit from the right doubled, starting with the last digit
+// if `double_last` is set, i.e. if the check digit is still to be appended.
+fn luhn_sum(digits: &str, double_last: bool) -> u32 {
+    let doubled_parity = if double_last { 0 } else { 1 };
+    digits
+        .bytes()
+        .rev()
+        .enumerate()
+        .map(|(i, b)| {
+            let digit = u32::from(b - b'0');
+            if i % 2 == doubled_parity {
+                let doubled = digit * 2;
+                doubled / 10 + doubled % 10
+            } else {
+                digit
+            }
+        })
+        .sum()
+}
+
+impl FromStr for Pan {
+    type Err = PaysecError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Pan::new(s)
+    }
+}
+
+impl AsRef<str> for Pan {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Debug for Pan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let prefix_len = if self.0.len() > 12 { 6 } else { 0 };
+        let suffix_start = self.0.len() - 4;
+        write!(
+            f,
+            "Pan({}{}{})",
+            &self.0[..prefix_len],
+            "*".repeat(suffix_start - prefix_len),
+            &self.0[suffix_start..]
+        )
+    }
+}
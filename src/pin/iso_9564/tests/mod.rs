@@ -1,2 +1,6 @@
+mod test_format_0;
+mod test_format_1;
 mod test_format_3;
 mod test_format_4;
+mod test_pan;
+mod test_pin_format;
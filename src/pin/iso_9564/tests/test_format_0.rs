@@ -0,0 +1,140 @@
+use crate::pin::*;
+
+#[test]
+fn test_encode_pinblock_iso_0() {
+    // Classic ANSI X9.8 example
+    let pin_block = encode_pinblock_iso_0("1234", "43219876543210987").unwrap();
+    assert_eq!(hex::encode_upper(pin_block), "0412AC89ABCDEF67");
+
+    let test_cases = [
+        ("1234", "5432101234567891", "041215FEDCBA9876"),
+        ("123456789012", "5432101234567891", "0C1215575BD57576"),
+    ];
+
+    for (pin, pan, expected_hex) in test_cases {
+        let pin_block = encode_pinblock_iso_0(pin, pan).unwrap();
+        assert_eq!(hex::encode_upper(pin_block), expected_hex, "PIN: {}", pin);
+        assert_eq!(decode_pinblock_iso_0(&pin_block, pan).unwrap(), pin);
+    }
+}
+
+#[test]
+fn test_encode_pin_field_iso_0() {
+    let test_cases = [
+        ("1234", "041234FFFFFFFFFF"),
+        ("12345", "0512345FFFFFFFFF"),
+        ("123456789012", "0C123456789012FF"),
+    ];
+
+    for (pin, expected_hex) in test_cases {
+        let pin_field = encode_pin_field_iso_0(pin).unwrap();
+        assert_eq!(hex::encode_upper(pin_field), expected_hex, "PIN: {}", pin);
+        assert_eq!(decode_pin_field_iso_0(&pin_field).unwrap(), pin);
+    }
+}
+
+#[test]
+fn test_encode_pin_field_iso_0_invalid_pin() {
+    let test_cases = [
+        (
+            "123",
+            "PIN0-0001: PIN BLOCK ISO 0 ERROR: PIN length must be between 4 and 12: 3",
+        ),
+        (
+            "1234567890123",
+            "PIN0-0001: PIN BLOCK ISO 0 ERROR: PIN length must be between 4 and 12: 13",
+        ),
+        (
+            "123A",
+            "PIN0-0004: PIN BLOCK ISO 0 ERROR: PIN contains invalid digit",
+        ),
+        (
+            "12３4",
+            "PIN0-0004: PIN BLOCK ISO 0 ERROR: PIN contains invalid digit",
+        ),
+    ];
+
+    for (pin, expected_error) in test_cases {
+        assert_eq!(
+            encode_pin_field_iso_0(pin).unwrap_err().to_string(),
+            expected_error,
+            "PIN: {}",
+            pin
+        );
+    }
+}
+
+#[test]
+fn test_encode_pan_field_iso_0() {
+    let pan_field = encode_pan_field_iso_0("43219876543210987").unwrap();
+    assert_eq!(hex::encode_upper(pan_field), "0000987654321098");
+
+    // The PAN field of format 0 is the same as of format 3
+    assert_eq!(
+        pan_field,
+        encode_pan_field_iso_3("43219876543210987").unwrap()
+    );
+
+    assert_eq!(
+        encode_pan_field_iso_0("123456789012")
+            .unwrap_err()
+            .to_string(),
+        "PSEC-0000: PIN BLOCK ISO 0 ERROR: PAN must be at least 13 digits long for ISO 0 encoding"
+    );
+    assert_eq!(
+        encode_pan_field_iso_0("43219876543210９")
+            .unwrap_err()
+            .to_string(),
+        "PSEC-0000: PIN BLOCK ISO 0 ERROR: PAN must consist of ASCII digits only"
+    );
+}
+
+#[test]
+fn test_decode_pin_field_iso_0_invalid() {
+    let test_cases = [
+        (
+            "041234FFFFFFFF",
+            "PSEC-0000: PIN BLOCK ISO 0 ERROR: PIN field must be 8 bytes long",
+        ),
+        (
+            "341234FFFFFFFFFF",
+            "PIN0-0003: PIN BLOCK ISO 0 ERROR: PIN block is not ISO format 0: control field 3",
+        ),
+        (
+            "031234FFFFFFFFFF",
+            "PIN0-0001: PIN BLOCK ISO 0 ERROR: PIN length must be between 4 and 12: 3",
+        ),
+        (
+            "0412A4FFFFFFFFFF",
+            "PIN0-0004: PIN BLOCK ISO 0 ERROR: PIN contains invalid digit",
+        ),
+        (
+            "041234FFFFFFFFFE",
+            "PIN0-0002: PIN BLOCK ISO 0 ERROR: PIN block filler is incorrect",
+        ),
+        (
+            "041234AFFFFFFFFF",
+            "PIN0-0002: PIN BLOCK ISO 0 ERROR: PIN block filler is incorrect",
+        ),
+    ];
+
+    for (pin_field_hex, expected_error) in test_cases {
+        let pin_field = hex::decode(pin_field_hex).unwrap();
+        assert_eq!(
+            decode_pin_field_iso_0(&pin_field).unwrap_err().to_string(),
+            expected_error,
+            "PIN field: {}",
+            pin_field_hex
+        );
+    }
+}
+
+#[test]
+fn test_decode_pinblock_iso_0_invalid_length() {
+    assert_eq!(
+        decode_pinblock_iso_0(&[0u8; 7], "43219876543210987")
+            .unwrap_err()
+            .to_string(),
+        "PSEC-0000: PIN BLOCK ISO 0 ERROR: Invalid PIN block length"
+    );
+}
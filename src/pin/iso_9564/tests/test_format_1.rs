@@ -0,0 +1,103 @@
+use crate::pin::*;
+use crate::utils::{DeterministicRng, SeedSource};
+
+#[test]
+fn test_encode_pinblock_iso_1() {
+    let rnd_seed = hex::decode("1C2965473CE206BB855B01533782A5E4").unwrap();
+
+    let test_cases = [
+        ("1234", "141234473CE206BB"),
+        ("12345", "151234573CE206BB"),
+        ("123456789012", "1C123456789012BB"),
+    ];
+
+    for (pin, expected_hex) in test_cases {
+        let pin_block = encode_pinblock_iso_1(pin, rnd_seed.clone()).unwrap();
+        assert_eq!(hex::encode_upper(pin_block), expected_hex, "PIN: {}", pin);
+        assert_eq!(decode_pinblock_iso_1(&pin_block).unwrap(), pin);
+    }
+}
+
+#[test]
+fn test_encode_decode_pinblock_iso_1_round_trip() {
+    let mut rng = DeterministicRng::from_seed(b"iso 1 round trip").unwrap();
+
+    for pin in ["0000", "1234", "98765", "1234567890", "999999999999"] {
+        let pin_block = encode_pinblock_iso_1(pin, rng.seed(8).unwrap()).unwrap();
+        assert_eq!(decode_pinblock_iso_1(&pin_block).unwrap(), pin);
+    }
+}
+
+#[test]
+fn test_encode_pinblock_iso_1_invalid() {
+    let rnd_seed = hex::decode("1C2965473CE206BB").unwrap();
+
+    let test_cases = [
+        (
+            "123",
+            "PIN1-0001: PIN BLOCK ISO 1 ERROR: PIN length must be between 4 and 12: 3",
+        ),
+        (
+            "1234567890123",
+            "PIN1-0001: PIN BLOCK ISO 1 ERROR: PIN length must be between 4 and 12: 13",
+        ),
+        (
+            "123A",
+            "PIN1-0004: PIN BLOCK ISO 1 ERROR: PIN contains invalid digit",
+        ),
+    ];
+
+    for (pin, expected_error) in test_cases {
+        assert_eq!(
+            encode_pinblock_iso_1(pin, rnd_seed.clone())
+                .unwrap_err()
+                .to_string(),
+            expected_error,
+            "PIN: {}",
+            pin
+        );
+    }
+
+    assert_eq!(
+        encode_pinblock_iso_1("1234", rnd_seed[..7].to_vec())
+            .unwrap_err()
+            .to_string(),
+        "SEED-0001: PIN BLOCK ISO 1 ERROR: Random seed is too short: 7 bytes, required 8 bytes of CSPRNG output"
+    );
+}
+
+#[test]
+fn test_decode_pinblock_iso_1_invalid() {
+    let test_cases = [
+        (
+            "141234473CE206",
+            "PSEC-0000: PIN BLOCK ISO 1 ERROR: Invalid PIN block length",
+        ),
+        (
+            "041234473CE206BB",
+            "PIN1-0003: PIN BLOCK ISO 1 ERROR: PIN block is not ISO format 1: control field 0",
+        ),
+        (
+            "131234473CE206BB",
+            "PIN1-0001: PIN BLOCK ISO 1 ERROR: PIN length must be between 4 and 12: 3",
+        ),
+        (
+            "1D1234473CE206BB",
+            "PIN1-0001: PIN BLOCK ISO 1 ERROR: PIN length must be between 4 and 12: 13",
+        ),
+        (
+            "1412A4473CE206BB",
+            "PIN1-0004: PIN BLOCK ISO 1 ERROR: PIN contains invalid digit",
+        ),
+    ];
+
+    for (pin_block_hex, expected_error) in test_cases {
+        let pin_block = hex::decode(pin_block_hex).unwrap();
+        assert_eq!(
+            decode_pinblock_iso_1(&pin_block).unwrap_err().to_string(),
+            expected_error,
+            "PIN block: {}",
+            pin_block_hex
+        );
+    }
+}
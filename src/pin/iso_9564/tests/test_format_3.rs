@@ -1,8 +1,10 @@
 use crate::pin::*;
+use crate::utils::{DeterministicRng, SeedSource};
+use crate::validation::ValidationConfig;
 use hex::FromHex;
 
 #[test]
-fn test_encode_pin_field_iso_3() {
+fn test_encode_pin_field_iso_3_legacy() {
     let test_cases = [
         ("1234", vec![0xFF; 8], "341234FFFFFFFFFF"),
         ("12345", vec![0x55; 8], "3512345FFFFFFFFF"),
@@ -16,7 +18,7 @@ fn test_encode_pin_field_iso_3() {
     ];
 
     for (pin, rnd_seed, expected_hex) in test_cases {
-        let encoded = encode_pin_field_iso_3(pin, &rnd_seed).unwrap();
+        let encoded = encode_pin_field_iso_3_legacy(pin, &rnd_seed).unwrap();
         let encoded_hex = hex::encode(encoded);
 
         assert_eq!(
@@ -28,6 +30,70 @@ fn test_encode_pin_field_iso_3() {
     }
 }
 
+#[test]
+fn test_encode_pin_field_iso_3() {
+    let rnd_seed = hex::decode("1C2965473CE206BB855B01533782A5E4").unwrap();
+
+    let test_cases = [
+        ("1234", "341234BDCAAFFCFF"),
+        ("1234567", "371234567AAFFCFF"),
+        ("123456789012", "3C123456789012FF"),
+    ];
+
+    for (pin, expected_hex) in test_cases {
+        let encoded = encode_pin_field_iso_3(pin, &rnd_seed).unwrap();
+        assert_eq!(hex::encode_upper(encoded), expected_hex, "PIN: {}", pin);
+    }
+}
+
+#[test]
+fn test_encode_pin_field_iso_3_uniform_padding() {
+    let mut rng = DeterministicRng::from_seed(b"iso 3 padding").unwrap();
+    let mut counts = [0usize; 16];
+
+    // 1200 PIN fields with 10 padding nibbles each
+    for _ in 0..1200 {
+        let pin_field = encode_pin_field_iso_3("1234", &rng.seed(32).unwrap()).unwrap();
+        for byte in &pin_field[3..] {
+            counts[(byte >> 4) as usize] += 1;
+            counts[(byte & 0x0F) as usize] += 1;
+        }
+    }
+
+    // Each of A-F is expected 2000 times (standard deviation about 41)
+    for (nibble, &count) in counts.iter().enumerate().skip(0x0A) {
+        assert!(
+            (1800..=2200).contains(&count),
+            "Padding nibble {:X} occurred {} times",
+            nibble,
+            count
+        );
+    }
+}
+
+#[test]
+fn test_expected_filler_matches_encoder() {
+    let mut rng = DeterministicRng::from_seed(b"iso 3 filler").unwrap();
+
+    for pin in ["1234", "12345", "1234567890", "123456789012"] {
+        let rnd_seed = rng.seed(32).unwrap();
+        let pin_field = encode_pin_field_iso_3(pin, &rnd_seed).unwrap();
+        let filler = expected_filler(&rnd_seed, pin.len()).unwrap();
+
+        let filler_hex: String = filler.iter().map(|n| format!("{:X}", n)).collect();
+        assert_eq!(filler.len(), 14 - pin.len());
+        assert_eq!(
+            filler_hex,
+            hex::encode_upper(pin_field)[2 + pin.len()..],
+            "Failed test for PIN: {}",
+            pin
+        );
+    }
+
+    assert!(expected_filler(&[0xAA; 32], 3).is_err());
+    assert!(expected_filler(&[0xFF; 32], 4).is_err());
+}
+
 #[test]
 fn test_encode_pin_field_iso_3_invalid_pin_length() {
     let short_pin = "123"; // Less than 4 digits
@@ -43,11 +109,11 @@ fn test_encode_pin_field_iso_3_invalid_pin_length() {
 
     assert_eq!(
         error_short,
-        "PIN BLOCK ISO 3 ERROR: PIN must be between 4 and 12 digits long"
+        "PIN3-0001: PIN BLOCK ISO 3 ERROR: PIN length must be between 4 and 12: 3"
     );
     assert_eq!(
         error_long,
-        "PIN BLOCK ISO 3 ERROR: PIN must be between 4 and 12 digits long"
+        "PIN3-0001: PIN BLOCK ISO 3 ERROR: PIN length must be between 4 and 12: 13"
     );
 }
 
@@ -62,7 +128,7 @@ fn test_encode_pin_field_iso_3_non_numeric_pin() {
 
     assert_eq!(
         error,
-        "PIN BLOCK ISO 3 ERROR: PIN must be between 4 and 12 digits long"
+        "PIN3-0004: PIN BLOCK ISO 3 ERROR: PIN contains invalid digit"
     );
 }
 
@@ -77,7 +143,7 @@ fn test_encode_pin_field_iso_3_insufficient_seed_length() {
 
     assert_eq!(
         error,
-        "PIN BLOCK ISO 3 ERROR: Insufficient seed length for PIN block"
+        "SEED-0001: PIN BLOCK ISO 3 ERROR: Random seed is too short: 7 bytes, required 8 bytes of CSPRNG output"
     );
 }
 
@@ -114,7 +180,7 @@ fn test_decode_pin_field_iso_3_invalid_control_field() {
         decode_pin_field_iso_3(&invalid_control_field)
             .unwrap_err()
             .to_string(),
-        "PIN BLOCK ISO 3 ERROR: PIN block is not ISO format 3."
+        "PIN3-0003: PIN BLOCK ISO 3 ERROR: PIN block is not ISO format 3: control field 4"
     );
 }
 
@@ -125,7 +191,7 @@ fn test_decode_pin_field_iso_3_invalid_pin_length() {
         decode_pin_field_iso_3(&invalid_pin_length)
             .unwrap_err()
             .to_string(),
-        "PIN BLOCK ISO 3 ERROR: PIN length must be between 4 and 12"
+        "PIN3-0001: PIN BLOCK ISO 3 ERROR: PIN length must be between 4 and 12: 13"
     );
 }
 
@@ -136,7 +202,7 @@ fn test_decode_pin_field_iso_3_invalid_filler() {
         decode_pin_field_iso_3(&invalid_filler)
             .unwrap_err()
             .to_string(),
-        "PIN BLOCK ISO 3 ERROR: PIN block filler is incorrect"
+        "PIN3-0002: PIN BLOCK ISO 3 ERROR: PIN block filler is incorrect"
     );
 }
 
@@ -147,7 +213,7 @@ fn test_decode_pin_field_iso_3_invalid_pin_digits() {
         decode_pin_field_iso_3(&invalid_pin_digits)
             .unwrap_err()
             .to_string(),
-        "PIN BLOCK ISO 3 ERROR: PIN contains invalid digit"
+        "PIN3-0004: PIN BLOCK ISO 3 ERROR: PIN contains invalid digit"
     );
 }
 
@@ -184,12 +250,29 @@ fn test_encode_pan_field_iso_3_pan_too_short() {
 
     assert_eq!(
         error,
-        "PIN BLOCK ISO 3 ERROR: PAN must be at least 13 digits long for ISO 3 encoding"
+        "PSEC-0000: PIN BLOCK ISO 3 ERROR: PAN must be at least 13 digits long for ISO 3 encoding"
     );
 }
 
 #[test]
-fn test_encode_pinblock_iso_3_various_pins() {
+fn test_encode_pan_field_iso_3_non_ascii_pan() {
+    // 15 bytes, the last 13 bytes end within the multibyte character
+    for pan in [
+        "123456789012\u{20AC}",
+        "\u{20AC}1234567890123",
+        "123456789012A",
+    ] {
+        let error = encode_pan_field_iso_3(pan).unwrap_err().to_string();
+
+        assert_eq!(
+            error,
+            "PSEC-0000: PIN BLOCK ISO 3 ERROR: PAN must consist of ASCII digits only"
+        );
+    }
+}
+
+#[test]
+fn test_encode_pinblock_iso_3_legacy_various_pins() {
     let test_cases = [
         ("1234", "12345678901234", "341217BA9876FEDC"),
         ("12345", "1234567890123", "3512266BA9876FED"),
@@ -203,7 +286,7 @@ fn test_encode_pinblock_iso_3_various_pins() {
     let rnd_seed = vec![0xFF; 8]; // Random seed is always 0xFF, 0xFF, 0xFF, ...
 
     for (pin, pan, expected_hex) in test_cases {
-        let pin_block = encode_pinblock_iso_3(pin, pan, rnd_seed.clone()).unwrap();
+        let pin_block = encode_pinblock_iso_3_legacy(pin, pan, rnd_seed.clone()).unwrap();
         let pin_block_hex = hex::encode_upper(pin_block);
 
         assert_eq!(
@@ -240,3 +323,41 @@ fn test_decode_pinblock_iso_3_various_pins() {
         );
     }
 }
+
+#[test]
+fn test_decode_pinblock_iso_3_with_options_filler() {
+    let pan = "1234567890123456";
+    let pin_field = Vec::from_hex("3412340000000000").unwrap();
+    let pan_field = encode_pan_field_iso_3(pan).unwrap();
+    let pin_block: Vec<u8> = pin_field
+        .iter()
+        .zip(pan_field)
+        .map(|(a, b)| a ^ b)
+        .collect();
+
+    let strict = decode_pinblock_iso_3_with_options(&pin_block, pan, &ValidationConfig::strict());
+    assert_eq!(
+        strict.unwrap_err().to_string(),
+        "PIN3-0002: PIN BLOCK ISO 3 ERROR: PIN block filler is incorrect"
+    );
+
+    let lenient = decode_pinblock_iso_3_with_options(&pin_block, pan, &ValidationConfig::lenient());
+    assert_eq!(lenient.unwrap(), "1234");
+}
+
+#[test]
+fn test_decode_pinblock_iso_3_with_options_pan_scheme_length() {
+    // A PAN in the Visa BIN range with 17 digits
+    let pan = "43219876543210987";
+    let rnd_seed = Vec::from_hex("1C2965473CE206BB855B01533782").unwrap();
+    let pin_block = encode_pinblock_iso_3("1234", pan, rnd_seed).unwrap();
+
+    let strict = decode_pinblock_iso_3_with_options(&pin_block, pan, &ValidationConfig::strict());
+    assert_eq!(
+        strict.unwrap_err().to_string(),
+        "PSEC-0000: PAN ERROR: PAN length 17 is not valid for card scheme Visa, expected one of [13, 16, 19]"
+    );
+
+    let default = decode_pinblock_iso_3_with_options(&pin_block, pan, &ValidationConfig::default());
+    assert_eq!(default.unwrap(), "1234");
+}
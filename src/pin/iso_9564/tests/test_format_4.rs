@@ -1,4 +1,8 @@
+#[cfg(not(feature = "no-clear-secrets"))]
+use crate::crypto::aes::aes_enc_ecb;
 use crate::pin::*;
+#[cfg(not(feature = "no-clear-secrets"))]
+use crate::validation::ValidationConfig;
 use hex::decode;
 
 #[test]
@@ -85,7 +89,7 @@ fn test_encode_pin_field_iso_4_too_short() {
     assert!(result.is_err());
     assert_eq!(
         result.unwrap_err().to_string(),
-        "PIN BLOCK ISO 4 ERROR: PIN must be between 4 and 12 digits long"
+        "PIN4-0001: PIN BLOCK ISO 4 ERROR: PIN length must be between 4 and 12: 3"
     );
 }
 
@@ -97,7 +101,7 @@ fn test_encode_pin_field_iso_4_too_long() {
     assert!(result.is_err());
     assert_eq!(
         result.unwrap_err().to_string(),
-        "PIN BLOCK ISO 4 ERROR: PIN must be between 4 and 12 digits long"
+        "PIN4-0001: PIN BLOCK ISO 4 ERROR: PIN length must be between 4 and 12: 13"
     );
 }
 
@@ -127,7 +131,7 @@ fn test_decode_pin_field_iso_4_invalid_length() {
     let pin_field = vec![0u8; 15]; // Less than 16 bytes
     assert!(matches!(
         decode_pin_field_iso_4(&pin_field),
-        Err(e) if e.to_string() == "PIN BLOCK ISO 4 ERROR: PIN field must be 16 bytes long"
+        Err(e) if e.to_string() == "PSEC-0000: PIN BLOCK ISO 4 ERROR: PIN field must be 16 bytes long"
     ));
 }
 
@@ -158,7 +162,7 @@ fn test_decode_pin_field_iso_4_non_numeric_pin() {
     ];
     assert!(matches!(
         decode_pin_field_iso_4(&pin_field),
-        Err(e) if e.to_string() == "PIN BLOCK ISO 4 ERROR: PIN contains invalid digit"
+        Err(e) if e.to_string() == "PIN4-0004: PIN BLOCK ISO 4 ERROR: PIN contains invalid digit"
     ));
 }
 
@@ -170,7 +174,7 @@ fn test_decode_pin_field_iso_4_invalid_filler() {
     ]; // Filler not 0xA
     assert!(matches!(
         decode_pin_field_iso_4(&pin_field),
-        Err(e) if e.to_string() == "PIN BLOCK ISO 4 ERROR: PIN block filler is incorrect"
+        Err(e) if e.to_string() == "PIN4-0002: PIN BLOCK ISO 4 ERROR: PIN block filler is incorrect"
     ));
 }
 
@@ -215,7 +219,7 @@ fn test_encode_pan_field_iso_4_too_short() {
     assert!(result.is_err());
     assert_eq!(
         result.unwrap_err().to_string(),
-        "PIN BLOCK ISO 4 ERROR: PAN must be between 1 and 19 digits long."
+        "PSEC-0000: PIN BLOCK ISO 4 ERROR: PAN must be between 1 and 19 digits long."
     );
 }
 
@@ -226,7 +230,7 @@ fn test_encode_pan_field_iso_4_too_long() {
     assert!(result.is_err());
     assert_eq!(
         result.unwrap_err().to_string(),
-        "PIN BLOCK ISO 4 ERROR: PAN must be between 1 and 19 digits long."
+        "PSEC-0000: PIN BLOCK ISO 4 ERROR: PAN must be between 1 and 19 digits long."
     );
 }
 
@@ -237,10 +241,12 @@ fn test_encode_pan_field_iso_4_invalid_char() {
     assert!(result.is_err());
     assert_eq!(
         result.unwrap_err().to_string(),
-        "PIN BLOCK ISO 4 ERROR: PAN must be between 1 and 19 digits long."
+        "PSEC-0000: PIN BLOCK ISO 4 ERROR: PAN must be between 1 and 19 digits long."
     );
 }
 
+// Uses constant seeds of the test vectors which `strict-seed` rejects.
+#[cfg(not(feature = "strict-seed"))]
 #[test]
 fn test_encipher_pinblock_iso_4_valid() {
     let key = hex::decode("00112233445566778899AABBCCDDEEFF").expect("Invalid key hex");
@@ -257,6 +263,7 @@ fn test_encipher_pinblock_iso_4_valid() {
     assert_eq!(result_hex, expected_pin_block);
 }
 
+#[cfg(not(feature = "no-clear-secrets"))]
 #[test]
 fn test_decipher_pinblock_iso_4_various() {
     let key = hex::decode("00112233445566778899AABBCCDDEEFF").unwrap();
@@ -289,3 +296,153 @@ fn test_decipher_pinblock_iso_4_various() {
         );
     }
 }
+
+#[cfg(not(feature = "no-clear-secrets"))]
+#[test]
+fn test_decipher_pinblock_iso_4_wrong_pan() {
+    // A wrong PAN yields a random PIN field, which fails the format checks. Re-enciphering the
+    // PIN field would reproduce the PIN block for any PAN, so it cannot detect a wrong PAN.
+    let key = hex::decode("00112233445566778899AABBCCDDEEFF").unwrap();
+    let pin_block = hex::decode("018BFEC8B5EF60181A327AD8325A2BA4").unwrap();
+
+    let wrong_pans = [
+        // Same length, last digit changed
+        "1234567890123456780",
+        // Same length class (more than 12 digits), shorter
+        "123456789012345678",
+        // Different length class (12 digits or less)
+        "123456789012",
+    ];
+    for pan in wrong_pans {
+        assert!(
+            decipher_pinblock_iso_4(&key, &pin_block, pan).is_err(),
+            "PAN {} should be rejected",
+            pan
+        );
+    }
+}
+
+#[cfg(feature = "strict-seed")]
+#[test]
+fn test_encode_pin_field_iso_4_strict_seed() {
+    let pin = "1234";
+    assert!(encode_pin_field_iso_4(pin, vec![0u8; 8]).is_err());
+    assert!(encode_pin_field_iso_4(pin, vec![0xFF; 8]).is_err());
+    assert!(encode_pin_field_iso_4(pin, decode("1C2965473CE206BB").unwrap()).is_ok());
+}
+
+#[cfg(not(feature = "no-clear-secrets"))]
+#[test]
+fn test_decipher_pinblock_iso_4_with_options_filler() {
+    let key = hex::decode("00112233445566778899AABBCCDDEEFF").unwrap();
+    let pan = "1234567890123456";
+
+    // Encipher a PIN field with a zero filler
+    let pin_field = decode("441234000000000096D2F1C0A7B4E5A3").unwrap();
+    let pan_field = encode_pan_field_iso_4(pan).unwrap();
+    let intermediate_block_a = aes_enc_ecb(&pin_field, &key).unwrap();
+    let intermediate_block_b: Vec<u8> = intermediate_block_a
+        .iter()
+        .zip(pan_field)
+        .map(|(a, b)| a ^ b)
+        .collect();
+    let pin_block = aes_enc_ecb(&intermediate_block_b, &key).unwrap();
+
+    let strict =
+        decipher_pinblock_iso_4_with_options(&key, &pin_block, pan, &ValidationConfig::strict());
+    assert_eq!(
+        strict.unwrap_err().to_string(),
+        "PIN4-0002: PIN BLOCK ISO 4 ERROR: PIN block filler is incorrect"
+    );
+
+    let lenient =
+        decipher_pinblock_iso_4_with_options(&key, &pin_block, pan, &ValidationConfig::lenient());
+    assert_eq!(lenient.unwrap(), "1234");
+}
+
+#[cfg(feature = "keyblock")]
+#[cfg(not(feature = "no-clear-secrets"))]
+#[test]
+fn test_pinblock_iso_4_bound_modes_of_use() {
+    use crate::error::PaysecError;
+    use crate::keyblock::{KeyBlockHeader, KeyOperation, UsageBoundKey};
+
+    let key = decode("00112233445566778899AABBCCDDEEFF").unwrap();
+    let pan = "1234567890123456789";
+    let rnd_seed = decode("1C2965473CE206BB").unwrap();
+    let pin_block = encipher_pinblock_iso_4(&key, "1234", pan, rnd_seed.clone()).unwrap();
+
+    // (mode of use, encryption permitted, decryption permitted)
+    let cases = [
+        ("E", true, false),
+        ("D", false, true),
+        ("B", true, true),
+        ("N", true, true),
+    ];
+
+    for (mode_of_use, encrypt, decrypt) in cases {
+        let header =
+            KeyBlockHeader::new_with_values("D", "P0", "A", mode_of_use, "00", "E").unwrap();
+        let bound_key = UsageBoundKey::new(&header, key.clone());
+
+        let enciphered = encipher_pinblock_iso_4_bound(&bound_key, "1234", pan, rnd_seed.clone());
+        let deciphered = decipher_pinblock_iso_4_bound(&bound_key, &pin_block, pan);
+
+        if encrypt {
+            assert_eq!(enciphered.unwrap(), pin_block);
+        } else {
+            let violation = match enciphered.unwrap_err() {
+                PaysecError::ModeOfUseViolation(violation) => violation,
+                err => panic!("Unexpected error: {}", err),
+            };
+            assert_eq!(violation.operation(), KeyOperation::Encrypt);
+        }
+
+        if decrypt {
+            assert_eq!(deciphered.unwrap(), "1234");
+        } else {
+            let err = deciphered.unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                format!(
+                    "TR31-0008: ERROR TR-31: Mode of use {} does not permit decryption",
+                    mode_of_use
+                )
+            );
+        }
+    }
+}
+
+#[cfg(feature = "keyblock")]
+#[cfg(not(feature = "no-clear-secrets"))]
+#[test]
+fn test_pinblock_iso_4_bound_key_usage() {
+    use crate::keyblock::{KeyBlockHeader, UsageBoundKey};
+
+    let key = decode("00112233445566778899AABBCCDDEEFF").unwrap();
+    let header = KeyBlockHeader::new_with_values("D", "K0", "A", "B", "00", "E").unwrap();
+    let bound_key = UsageBoundKey::new(&header, key);
+
+    let err =
+        decipher_pinblock_iso_4_bound(&bound_key, &[0u8; 16], "1234567890123456789").unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "PSEC-0000: PIN BLOCK ISO 4 ERROR: Key usage K0 is not a PIN encryption key"
+    );
+}
+
+#[cfg(feature = "rng")]
+#[cfg(not(feature = "no-clear-secrets"))]
+#[test]
+fn test_encipher_pinblock_iso_4_auto_seed() {
+    let key = decode("00112233445566778899AABBCCDDEEFF").unwrap();
+    let pan = "1234567890123456789";
+
+    let first = encipher_pinblock_iso_4_auto_seed(&key, "1234", pan).unwrap();
+    let second = encipher_pinblock_iso_4_auto_seed(&key, "1234", pan).unwrap();
+
+    // The random seed yields a different PIN block on every call
+    assert_ne!(first, second);
+    assert_eq!(decipher_pinblock_iso_4(&key, &first, pan).unwrap(), "1234");
+    assert_eq!(decipher_pinblock_iso_4(&key, &second, pan).unwrap(), "1234");
+}
@@ -0,0 +1,97 @@
+use crate::pin::*;
+use hex::decode;
+
+#[test]
+fn test_pan_new_valid() {
+    for pan_str in ["12345678", "4111111111111111", "1234567890123456789"] {
+        let pan = Pan::new(pan_str).unwrap();
+        assert_eq!(pan.as_str(), pan_str);
+        assert_eq!(pan_str.parse::<Pan>().unwrap(), pan);
+    }
+}
+
+#[test]
+fn test_pan_new_invalid() {
+    assert_eq!(
+        Pan::new("1234567").unwrap_err().to_string(),
+        "PSEC-0000: PAN ERROR: PAN must be between 8 and 19 digits long, found 7"
+    );
+    assert_eq!(
+        Pan::new("12345678901234567890").unwrap_err().to_string(),
+        "PSEC-0000: PAN ERROR: PAN must be between 8 and 19 digits long, found 20"
+    );
+    assert_eq!(
+        Pan::new("4111 1111 1111 1111").unwrap_err().to_string(),
+        "PSEC-0000: PAN ERROR: PAN must contain only decimal digits"
+    );
+    assert!(Pan::new("41111111111111A1").is_err());
+}
+
+#[test]
+fn test_pan_check_digit() {
+    assert!(Pan::new("4111111111111111")
+        .unwrap()
+        .has_valid_check_digit());
+    assert!(Pan::new("79927398713").unwrap().has_valid_check_digit());
+    assert!(!Pan::new("4111111111111112")
+        .unwrap()
+        .has_valid_check_digit());
+}
+
+#[test]
+fn test_luhn_check_digit() {
+    assert_eq!(luhn_check_digit("7992739871"), Some('3'));
+    assert_eq!(luhn_check_digit("411111111111111"), Some('1'));
+    assert_eq!(luhn_check_digit(""), Some('0'));
+    assert_eq!(luhn_check_digit("41111A"), None);
+
+    for payload in ["1234567", "453201511283036", "601100099013942"] {
+        let pan = format!("{}{}", payload, luhn_check_digit(payload).unwrap());
+        assert!(luhn_is_valid(&pan), "{}", pan);
+    }
+    assert!(!luhn_is_valid(""));
+    assert!(!luhn_is_valid("4111 1111 1111 1111"));
+}
+
+#[test]
+fn test_pan_debug_masked() {
+    let pan = Pan::new("4111111111111111").unwrap();
+    assert_eq!(format!("{:?}", pan), "Pan(411111******1111)");
+
+    let pan = Pan::new("123456789012").unwrap();
+    assert_eq!(format!("{:?}", pan), "Pan(********9012)");
+}
+
+#[cfg(not(feature = "no-clear-secrets"))]
+#[test]
+fn test_encipher_pinblock_iso_4_with_pan() {
+    let key = decode("00112233445566778899AABBCCDDEEFF").unwrap();
+    let pan = Pan::new("1234567890123456789").unwrap();
+    let rnd_seed = decode("1C2965473CE206BB").unwrap();
+
+    let pin_block = encipher_pinblock_iso_4_with_pan(&key, "1234", &pan, rnd_seed.clone()).unwrap();
+    assert_eq!(
+        pin_block,
+        encipher_pinblock_iso_4(&key, "1234", pan.as_str(), rnd_seed).unwrap()
+    );
+    assert_eq!(
+        decipher_pinblock_iso_4_with_pan(&key, &pin_block, &pan).unwrap(),
+        "1234"
+    );
+}
+
+#[test]
+fn test_pinblock_iso_3_with_pan() {
+    let pan = Pan::new("12345678901234").unwrap();
+    let rnd_seed = decode("1C2965473CE206BB855B01533782A5E4").unwrap();
+
+    let pin_block = encode_pinblock_iso_3_with_pan("1234", &pan, rnd_seed).unwrap();
+    assert_eq!(
+        decode_pinblock_iso_3_with_pan(&pin_block, &pan).unwrap(),
+        "1234"
+    );
+
+    // A valid PAN which is too short for format 3
+    let pan = Pan::new("123456789012").unwrap();
+    assert!(decode_pinblock_iso_3_with_pan(&pin_block, &pan).is_err());
+}
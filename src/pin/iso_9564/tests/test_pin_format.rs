@@ -0,0 +1,44 @@
+use crate::pin::*;
+
+#[test]
+fn test_block_size() {
+    assert_eq!(block_size(PinFormat::Format0), 8);
+    assert_eq!(block_size(PinFormat::Format1), 8);
+    assert_eq!(block_size(PinFormat::Format2), 8);
+    assert_eq!(block_size(PinFormat::Format3), 8);
+    assert_eq!(block_size(PinFormat::Format4), 16);
+}
+
+#[test]
+fn test_min_seed_len() {
+    assert_eq!(min_seed_len(PinFormat::Format0), 0);
+    assert_eq!(min_seed_len(PinFormat::Format3), 8);
+    assert_eq!(min_seed_len(PinFormat::Format4), 8);
+
+    // The encoders with random padding reject a seed one byte shorter than the minimum. All
+    // nibbles of the seed are accepted by the format 3 sampling.
+    let seed = hex::decode("123456789AB02143").unwrap();
+    let seed_len = min_seed_len(PinFormat::Format3);
+    assert!(encode_pin_field_iso_3("1234", &seed[..seed_len]).is_ok());
+    assert!(encode_pin_field_iso_3("1234", &seed[..seed_len - 1]).is_err());
+    assert!(encode_pin_field_iso_3_legacy("1234", &seed[..seed_len - 1]).is_err());
+
+    let seed_len = min_seed_len(PinFormat::Format4);
+    assert!(encode_pin_field_iso_4("1234", seed[..seed_len].to_vec()).is_ok());
+    assert!(encode_pin_field_iso_4("1234", seed[..seed_len - 1].to_vec()).is_err());
+}
+
+#[test]
+fn test_block_size_matches_pin_blocks() {
+    let pin_block = encode_pinblock_iso_3("1234", "1234567890123456789", decode_seed()).unwrap();
+    assert_eq!(pin_block.len(), block_size(PinFormat::Format3));
+
+    let key = hex::decode("00112233445566778899AABBCCDDEEFF").unwrap();
+    let pin_block =
+        encipher_pinblock_iso_4(&key, "1234", "1234567890123456789", decode_seed()).unwrap();
+    assert_eq!(pin_block.len(), block_size(PinFormat::Format4));
+}
+
+fn decode_seed() -> Vec<u8> {
+    hex::decode("1C2965473CE206BB855B01533782A9F6").unwrap()
+}
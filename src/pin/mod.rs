@@ -1,3 +1,5 @@
 mod iso_9564;
+#[cfg(feature = "rand")]
+pub mod testgen;
 
 pub use iso_9564::*;
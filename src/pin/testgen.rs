@@ -0,0 +1,154 @@
+//! Random PINs and PANs for test data generators.
+//!
+//! # Description
+//!
+//! The functions of this module generate PINs and Luhn-valid PANs of random lengths, e.g. to
+//! produce bulk test data for the PIN block functions, and count the PIN lengths of generated data
+//! to confirm their distribution. They are intended for test data only: the PANs are not assigned
+//! to any issuer, and the PINs are drawn from the given RNG without any weak PIN checks.
+//!
+//! The module requires the `rand` feature.
+//!
+//! # Example
+//! ```
+//! use paysec::pin::luhn_is_valid;
+//! use paysec::pin::testgen::{length_histogram, random_pan, random_pin};
+//! use rand::rngs::StdRng;
+//! use rand::SeedableRng;
+//!
+//! let mut rng = StdRng::seed_from_u64(7);
+//! let pins: Vec<String> = (0..100)
+//!     .map(|_| random_pin(&mut rng, 4, 6).unwrap())
+//!     .collect();
+//! let histogram = length_histogram(pins.iter().map(String::as_str));
+//! assert_eq!(histogram.values().sum::<usize>(), 100);
+//! assert!(histogram.keys().all(|len| (4..=6).contains(len)));
+//!
+//! let pan = random_pan(&mut rng, 16).unwrap();
+//! assert!(luhn_is_valid(&pan));
+//! ```
+
+use crate::error::PaysecError;
+use std::collections::BTreeMap;
+
+use rand::Rng;
+
+use super::{luhn_check_digit, PAN_MAX_LEN, PAN_MIN_LEN};
+
+/// The minimum length of a PIN.
+pub const PIN_MIN_LEN: usize = 4;
+/// The maximum length of a PIN.
+pub const PIN_MAX_LEN: usize = 12;
+
+/// Generate a random PIN with a length chosen uniformly between `min` and `max` digits.
+///
+/// # Errors
+///
+/// Returns an error if `min` is greater than `max` or the lengths are not between 4 and 12.
+pub fn random_pin<R: Rng + ?Sized>(
+    rng: &mut R,
+    min: usize,
+    max: usize,
+) -> Result<String, PaysecError> {
+    if min > max || min < PIN_MIN_LEN || max > PIN_MAX_LEN {
+        return Err(format!(
+            "PIN TESTGEN ERROR: Invalid PIN length range {}..={}, must be within {}..={}",
+            min, max, PIN_MIN_LEN, PIN_MAX_LEN
+        )
+        .into());
+    }
+
+    let len = rng.gen_range(min..=max);
+    Ok(random_digits(rng, len))
+}
+
+/// Generate a random PAN of `len` digits with a valid Luhn check digit.
+///
+/// # Errors
+///
+/// Returns an error if `len` is not between 8 and 19.
+pub fn random_pan<R: Rng + ?Sized>(rng: &mut R, len: usize) -> Result<String, PaysecError> {
+    if !(PAN_MIN_LEN..=PAN_MAX_LEN).contains(&len) {
+        return Err(format!(
+            "PIN TESTGEN ERROR: Invalid PAN length {}, must be within {}..={}",
+            len, PAN_MIN_LEN, PAN_MAX_LEN
+        )
+        .into());
+    }
+
+    let mut pan = random_digits(rng, len - 1);
+    let check_digit = luhn_check_digit(&pan).ok_or("PIN TESTGEN ERROR: Invalid PAN digits")?;
+    pan.push(check_digit);
+    Ok(pan)
+}
+
+/// Count the number of PINs of each length.
+pub fn length_histogram<'a>(pins: impl IntoIterator<Item = &'a str>) -> BTreeMap<usize, usize> {
+    let mut histogram = BTreeMap::new();
+    for pin in pins {
+        *histogram.entry(pin.len()).or_insert(0) += 1;
+    }
+    histogram
+}
+
+fn random_digits<R: Rng + ?Sized>(rng: &mut R, len: usize) -> String {
+    (0..len)
+        .map(|_| char::from(b'0' + rng.gen_range(0..10u8)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pin::luhn_is_valid;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn test_random_pan_luhn_valid() {
+        let mut rng = StdRng::seed_from_u64(0x5eed);
+        for len in PAN_MIN_LEN..=PAN_MAX_LEN {
+            for _ in 0..50 {
+                let pan = random_pan(&mut rng, len).unwrap();
+                assert_eq!(pan.len(), len);
+                assert!(luhn_is_valid(&pan), "{}", pan);
+            }
+        }
+    }
+
+    #[test]
+    fn test_random_pin_lengths() {
+        let mut rng = StdRng::seed_from_u64(0x5eed);
+        let pins: Vec<String> = (0..1000)
+            .map(|_| random_pin(&mut rng, 4, 8).unwrap())
+            .collect();
+        assert!(pins
+            .iter()
+            .all(|pin| pin.bytes().all(|b| b.is_ascii_digit())));
+
+        let histogram = length_histogram(pins.iter().map(String::as_str));
+        assert_eq!(
+            histogram.keys().copied().collect::<Vec<_>>(),
+            [4, 5, 6, 7, 8]
+        );
+        assert_eq!(histogram.values().sum::<usize>(), 1000);
+
+        assert_eq!(random_pin(&mut rng, 6, 6).unwrap().len(), 6);
+    }
+
+    #[test]
+    fn test_random_pin_pan_invalid_lengths() {
+        let mut rng = StdRng::seed_from_u64(0x5eed);
+        assert_eq!(
+            random_pin(&mut rng, 3, 6).unwrap_err().to_string(),
+            "PSEC-0000: PIN TESTGEN ERROR: Invalid PIN length range 3..=6, must be within 4..=12"
+        );
+        assert!(random_pin(&mut rng, 8, 6).is_err());
+        assert!(random_pin(&mut rng, 4, 13).is_err());
+        assert_eq!(
+            random_pan(&mut rng, 20).unwrap_err().to_string(),
+            "PSEC-0000: PIN TESTGEN ERROR: Invalid PAN length 20, must be within 8..=19"
+        );
+        assert!(random_pan(&mut rng, 7).is_err());
+    }
+}
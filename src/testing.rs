@@ -0,0 +1,313 @@
+//! Deterministic corpus of key blocks for interoperability testing.
+//!
+//! # Description
+//!
+//! **Not for production use.** All keys of the corpus are derived from a public seed. The module
+//! requires the `testing` feature and is not available with the `no-clear-secrets` feature, as
+//! the entries contain the clear keys.
+//!
+//! `generate_corpus` builds a corpus of key blocks spanning the supported versions, KBPK
+//! lengths, key algorithms and lengths, masked key lengths and optional block sets of a
+//! `CorpusSpec`. The KBPKs, keys and padding seeds are drawn in a fixed order from a single
+//! `DeterministicRng`, so two parties only need the seed and the crate version to regenerate the
+//! same corpus independently. `corpus_checksum` condenses a corpus to a SHA-256 digest, which can
+//! be compared instead of the whole corpus.
+//!
+//! The default `CorpusSpec` yields 184 key blocks:
+//! - Version 'D' with 16, 24 and 32 byte KBPKs wrapping AES-128, AES-192, AES-256 and 2-key and
+//!   3-key TDES keys.
+//! - Versions 'B' and 'C' with 2-key and 3-key TDES KBPKs wrapping 2-key and 3-key TDES keys.
+//! - Each with unmasked and 32-byte masked key lengths and four optional block sets.
+//!
+//! The key usage and mode of use cycle through `CorpusSpec::key_usages` by entry index.
+//!
+//! # Example
+//! ```
+//! use paysec::testing::{generate_corpus, CorpusSpec};
+//!
+//! let corpus = generate_corpus(b"partner interop 2024", &CorpusSpec::default()).unwrap();
+//! assert_eq!(corpus.len(), 184);
+//!
+//! use paysec::keyblock::tr31_unwrap;
+//!
+//! let entry = &corpus[0];
+//! let (_, key) = tr31_unwrap(&entry.kbpk, &entry.key_block).unwrap();
+//! assert_eq!(key, entry.key);
+//! ```
+
+use sha2::{Digest, Sha256};
+use std::error::Error;
+
+use crate::crypto::aes::AES_BLOCK_LEN;
+use crate::crypto::tdes::TDES_BLOCK_LEN;
+use crate::keyblock::{
+    calculate_padding_length, tr31_wrap_with_header_string, KeyBlockHeader, OptBlock,
+};
+use crate::utils::{adjust_odd_parity, DeterministicRng, SeedSource};
+
+/// The combinations of a key block corpus.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CorpusSpec {
+    /// The key block versions, 'B', 'C' and 'D' are supported.
+    pub versions: Vec<String>,
+    /// The masked key lengths in bytes, 0 for an unmasked key length.
+    pub masked_key_lens: Vec<usize>,
+    /// The sets of optional blocks as pairs of ID and data. Sets which are not empty are padded
+    /// with a `PB` block by `KeyBlockHeader::finalize`.
+    pub opt_block_sets: Vec<Vec<(String, String)>>,
+    /// The pairs of key usage and mode of use, assigned to the entries in turn.
+    pub key_usages: Vec<(String, String)>,
+}
+
+impl Default for CorpusSpec {
+    fn default() -> Self {
+        let pairs = |pairs: &[(&str, &str)]| {
+            pairs
+                .iter()
+                .map(|&(a, b)| (a.to_string(), b.to_string()))
+                .collect::<Vec<_>>()
+        };
+
+        CorpusSpec {
+            versions: vec!["B".to_string(), "C".to_string(), "D".to_string()],
+            masked_key_lens: vec![0, 32],
+            opt_block_sets: vec![
+                Vec::new(),
+                pairs(&[("KS", "00604B120F9292800000")]),
+                pairs(&[("KV", "010000"), ("TS", "20240101120000Z")]),
+                pairs(&[
+                    ("KS", "00604B120F9292800000"),
+                    ("KV", "010000"),
+                    ("TS", "20240101120000Z"),
+                ]),
+            ],
+            key_usages: pairs(&[
+                ("P0", "E"),
+                ("K0", "D"),
+                ("D0", "B"),
+                ("B0", "X"),
+                ("K1", "B"),
+            ]),
+        }
+    }
+}
+
+/// An entry of a key block corpus with the inputs and the resulting key block.
+///
+/// The keys are test keys derived from the corpus seed, so they are shown by `Debug`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CorpusEntry {
+    /// The Key Block Protection Key.
+    pub kbpk: Vec<u8>,
+    /// The finalized header before wrapping, with a key block length of `0000`.
+    pub header: String,
+    /// The wrapped key.
+    pub key: Vec<u8>,
+    /// The masked key length passed to the wrapping.
+    pub masked_key_len: usize,
+    /// The random seed for the padding of the payload.
+    pub padding_seed: Vec<u8>,
+    /// The key block as returned by `tr31_wrap_with_header_string`.
+    pub key_block: String,
+}
+
+/// Generate a deterministic corpus of key blocks.
+///
+/// The entries are generated in the order versions, KBPK lengths, key algorithms and lengths,
+/// masked key lengths and optional block sets. For each entry the KBPK, the key and the padding
+/// seed are drawn in this order from a `DeterministicRng` seeded with `seed`. TDES keys and
+/// KBPKs are adjusted to odd parity.
+///
+/// # Errors
+///
+/// Returns an error if a version of `spec` is not supported, `spec.key_usages` is empty or
+/// contains invalid values, or an optional block of `spec` is invalid.
+pub fn generate_corpus(seed: &[u8], spec: &CorpusSpec) -> Result<Vec<CorpusEntry>, Box<dyn Error>> {
+    if spec.key_usages.is_empty() {
+        return Err("ERROR CORPUS: At least one key usage is required".into());
+    }
+
+    let mut rng = DeterministicRng::from_seed(seed)?;
+    let mut corpus = Vec::new();
+
+    for version in &spec.versions {
+        let (block_len, kbpk_algorithm, kbpk_lens, keys): (_, _, &[usize], &[(&str, usize)]) =
+            match version.as_str() {
+                "B" => (TDES_BLOCK_LEN, "T", &[16, 24], &[("T", 16), ("T", 24)]),
+                "C" => (TDES_BLOCK_LEN, "T", &[16, 24], &[("T", 16), ("T", 24)]),
+                "D" => (
+                    AES_BLOCK_LEN,
+                    "A",
+                    &[16, 24, 32],
+                    &[("A", 16), ("A", 24), ("A", 32), ("T", 16), ("T", 24)],
+                ),
+                _ => {
+                    return Err(
+                        format!("ERROR CORPUS: Unsupported key block version: {}", version).into(),
+                    )
+                }
+            };
+
+        for &kbpk_len in kbpk_lens {
+            for &(algorithm, key_len) in keys {
+                for &masked_key_len in &spec.masked_key_lens {
+                    for opt_blocks in &spec.opt_block_sets {
+                        let (key_usage, mode_of_use) =
+                            &spec.key_usages[corpus.len() % spec.key_usages.len()];
+
+                        let mut header = KeyBlockHeader::new_with_values(
+                            version,
+                            key_usage,
+                            algorithm,
+                            mode_of_use,
+                            "00",
+                            "E",
+                        )?;
+                        if !opt_blocks.is_empty() {
+                            let chain = opt_blocks
+                                .iter()
+                                .map(|(id, data)| OptBlock::new(id, data, None))
+                                .collect::<Result<Vec<_>, _>>()?
+                                .into_iter()
+                                .collect();
+                            header.set_opt_blocks(chain);
+                            header.finalize()?;
+                        }
+                        let header = header.export_str()?;
+
+                        let mut kbpk = rng.seed(kbpk_len)?;
+                        if kbpk_algorithm == "T" {
+                            adjust_odd_parity(&mut kbpk);
+                        }
+                        let mut key = rng.seed(key_len)?;
+                        if algorithm == "T" {
+                            adjust_odd_parity(&mut key);
+                        }
+                        let padding_len =
+                            calculate_padding_length(key_len, masked_key_len, block_len)?;
+                        let padding_seed = rng.seed(padding_len)?;
+
+                        let key_block = tr31_wrap_with_header_string(
+                            &header,
+                            &kbpk,
+                            &key,
+                            masked_key_len,
+                            &padding_seed,
+                        )?;
+
+                        corpus.push(CorpusEntry {
+                            kbpk,
+                            header,
+                            key,
+                            masked_key_len,
+                            padding_seed,
+                            key_block,
+                        });
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(corpus)
+}
+
+/// Compute a checksum pinning the inputs and key blocks of a corpus.
+///
+/// The checksum is the SHA-256 digest of one line per entry with the hex KBPK, the header, the
+/// hex key, the masked key length, the hex padding seed and the key block, separated by `|`.
+pub fn corpus_checksum(corpus: &[CorpusEntry]) -> [u8; 32] {
+    let lines: String = corpus
+        .iter()
+        .map(|entry| {
+            format!(
+                "{}|{}|{}|{}|{}|{}\n",
+                hex::encode_upper(&entry.kbpk),
+                entry.header,
+                hex::encode_upper(&entry.key),
+                entry.masked_key_len,
+                hex::encode_upper(&entry.padding_seed),
+                entry.key_block
+            )
+        })
+        .collect();
+    Sha256::digest(lines.as_bytes()).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keyblock::tr31_unwrap;
+
+    const SEED: &[u8] = b"paysec interop corpus";
+
+    #[test]
+    fn test_generate_corpus_golden_checksum() {
+        let corpus = generate_corpus(SEED, &CorpusSpec::default()).unwrap();
+        assert_eq!(corpus.len(), 184);
+        assert_eq!(
+            hex::encode_upper(corpus_checksum(&corpus)),
+            "ACD13BDCF99F41D9B938A7C69407A8E68F206AD9F44064E319B74FB5BC4943F2"
+        );
+    }
+
+    #[test]
+    fn test_generate_corpus_deterministic() {
+        let corpus = generate_corpus(SEED, &CorpusSpec::default()).unwrap();
+        assert_eq!(
+            corpus,
+            generate_corpus(SEED, &CorpusSpec::default()).unwrap()
+        );
+
+        let other = generate_corpus(b"other seed", &CorpusSpec::default()).unwrap();
+        assert_ne!(corpus_checksum(&corpus), corpus_checksum(&other));
+    }
+
+    #[test]
+    fn test_generate_corpus_entries_unwrap() {
+        let corpus = generate_corpus(SEED, &CorpusSpec::default()).unwrap();
+
+        for entry in &corpus {
+            let (header, key) = tr31_unwrap(&entry.kbpk, &entry.key_block).unwrap();
+            assert_eq!(key, entry.key);
+            assert_eq!(&entry.key_block[5..header.len()], &entry.header[5..]);
+            assert_eq!(&entry.header[1..5], "0000");
+            assert_eq!(
+                tr31_wrap_with_header_string(
+                    &entry.header,
+                    &entry.kbpk,
+                    &entry.key,
+                    entry.masked_key_len,
+                    &entry.padding_seed
+                )
+                .unwrap(),
+                entry.key_block
+            );
+        }
+
+        for version in ["B", "C", "D"] {
+            assert!(corpus.iter().any(|e| e.key_block.starts_with(version)));
+        }
+        for (key_usage, _) in CorpusSpec::default().key_usages {
+            assert!(corpus.iter().any(|e| e.header[5..7] == key_usage));
+        }
+    }
+
+    #[test]
+    fn test_generate_corpus_invalid_spec() {
+        let spec = CorpusSpec {
+            versions: vec!["A".to_string()],
+            ..CorpusSpec::default()
+        };
+        assert_eq!(
+            generate_corpus(SEED, &spec).unwrap_err().to_string(),
+            "ERROR CORPUS: Unsupported key block version: A"
+        );
+
+        let spec = CorpusSpec {
+            key_usages: Vec::new(),
+            ..CorpusSpec::default()
+        };
+        assert!(generate_corpus(SEED, &spec).is_err());
+    }
+}
@@ -1,3 +1,12 @@
+//! Utility functions and test helpers shared by the key block and PIN block modules.
+
+#[cfg(any(feature = "keyblock", feature = "pin"))]
+use crate::crypto::aes::{aes_cmac, aes_enc_ecb, AES_BLOCK_LEN};
+use crate::error::PaysecError;
+use std::error::Error;
+#[cfg(feature = "keyblock")]
+use std::sync::atomic::{compiler_fence, Ordering};
+
 /// Perform bitwise XOR operation between two byte arrays of equal length.
 ///
 /// This function takes two byte arrays `a` and `b` and performs a bitwise XOR
@@ -119,10 +128,608 @@ fn transform_nibble(nibble: u8) -> u8 {
     }
 }
 
+/// Generate bytes with nibbles uniformly distributed in the A-F hexadecimal range.
+///
+/// `transform_nibbles_to_af` maps the 16 possible nibble values onto the 6 values A-F, so C, D,
+/// E and F occur more often than A and B. This function uses rejection sampling instead: each
+/// nibble of the input is accepted if it is below 12 and mapped to A-F by its value modulo 6,
+/// nibbles 12 to 15 are skipped. Since a quarter of the input nibbles is rejected on average, the
+/// input consumed is variable and must be longer than the output.
+///
+/// # Parameters
+///
+/// * `input`: A slice of random bytes (`&[u8]`) to sample from.
+/// * `output_len`: The number of bytes to generate.
+///
+/// # Returns
+///
+/// * `Ok(Vec<u8>)` - `output_len` bytes with all nibbles in the A-F range.
+/// * `Err(String)` - If the input has too few accepted nibbles.
+///
+/// # Errors
+///
+/// This function will return an error if the input is exhausted before `output_len` bytes are
+/// generated. For 8 output bytes an input of 32 random bytes fails with a probability below
+/// 10^-17.
+pub fn transform_nibbles_to_af_unbiased(
+    input: &[u8],
+    output_len: usize,
+) -> Result<Vec<u8>, String> {
+    let mut nibbles = input
+        .iter()
+        .flat_map(|&byte| [byte >> 4, byte & 0x0F])
+        .filter(|&nibble| nibble < 12)
+        .map(|nibble| 0x0A + nibble % 6);
+
+    let mut output = Vec::with_capacity(output_len);
+    for _ in 0..output_len {
+        match (nibbles.next(), nibbles.next()) {
+            (Some(high), Some(low)) => output.push((high << 4) | low),
+            _ => return Err("Input too short to sample the requested output length".to_string()),
+        }
+    }
+
+    Ok(output)
+}
+
+/// Encode bytes as uppercase hex string in constant time.
+///
+/// Unlike the lookup table of `hex::encode_upper`, the hex digits are computed with branch-free
+/// nibble arithmetic, so the timing does not depend on the encoded data. Use this function for
+/// secret material such as keys, payloads and check values.
+///
+/// # Parameters
+///
+/// * `data`: The bytes to encode.
+///
+/// # Returns
+///
+/// The uppercase hex string of `data`.
+pub fn ct_hex_encode_upper(data: &[u8]) -> String {
+    let mut hex = String::with_capacity(2 * data.len());
+    for &byte in data {
+        hex.push(ct_hex_digit(byte >> 4) as char);
+        hex.push(ct_hex_digit(byte & 0x0F) as char);
+    }
+    hex
+}
+
+/// Decode a hex string in constant time.
+///
+/// Upper- and lowercase hex digits are accepted. Each character is decoded with branch-free
+/// arithmetic and the validity of all characters is only evaluated after the whole string was
+/// decoded, so the timing depends on the length of `hex` but not on its content.
+///
+/// # Parameters
+///
+/// * `hex`: The hex string to decode.
+///
+/// # Returns
+///
+/// * `Ok(Vec<u8>)` - The decoded bytes.
+/// * `Err(Box<dyn Error>)` - If the string is not valid hex.
+///
+/// # Errors
+///
+/// This function will return an error if:
+/// - The string has an odd number of characters.
+/// - The string contains a character which is not a hex digit.
+pub fn ct_hex_decode(hex: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+    let hex = hex.as_bytes();
+    if hex.len() % 2 == 1 {
+        return Err("ERROR HEX: Odd number of hex digits".into());
+    }
+
+    let mut invalid = 0u8;
+    let mut bytes = Vec::with_capacity(hex.len() / 2);
+    for pair in hex.chunks_exact(2) {
+        let (high, high_valid) = ct_hex_value(pair[0]);
+        let (low, low_valid) = ct_hex_value(pair[1]);
+        invalid |= !(high_valid & low_valid);
+        bytes.push((high << 4) | low);
+    }
+
+    if invalid != 0 {
+        return Err("ERROR HEX: Invalid hex character".into());
+    }
+    Ok(bytes)
+}
+
+/// Decode a hex string, e.g. a key or a PAN-derived field, into bytes.
+///
+/// The functions of this crate take keys and seeds as byte slices. This thin wrapper around the
+/// `hex` crate saves callers a separate dependency for producing them. Use `ct_hex_decode` for
+/// secret material if the timing of the decoding matters.
+///
+/// # Parameters
+///
+/// * `hex`: The hex string to decode, upper- or lowercase.
+///
+/// # Returns
+///
+/// * `Ok(Vec<u8>)` - The decoded bytes.
+/// * `Err(Box<dyn Error>)` - If the string is not valid hex.
+///
+/// # Example
+/// ```
+/// # #[cfg(feature = "keyblock")]
+/// # {
+/// use paysec::keyblock::{tr31_wrap, KeyBlockHeader};
+/// use paysec::utils::{decode_hex, encode_hex_upper};
+///
+/// let kbpk = decode_hex("88E1AB2A2E3DD38C1FA039A536500CC8A87AB9D62DC92C01058FA79F44657DE6").unwrap();
+/// let key = decode_hex("3F419E1CB7079442AA37474C2EFBF8B8").unwrap();
+/// let seed = decode_hex("1C2965473CE206BB855B01533782").unwrap();
+/// assert_eq!(encode_hex_upper(&key), "3F419E1CB7079442AA37474C2EFBF8B8");
+///
+/// let header = KeyBlockHeader::new_with_values("D", "P0", "A", "E", "00", "E").unwrap();
+/// assert!(tr31_wrap(&kbpk, header, &key, 0, &seed).is_ok());
+/// # }
+/// ```
+pub fn decode_hex(hex: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+    hex::decode(hex).map_err(|e| format!("ERROR HEX: {}", e).into())
+}
+
+/// Encode bytes as uppercase hex string.
+///
+/// Thin wrapper around `hex::encode_upper`. Use `ct_hex_encode_upper` for secret material.
+///
+/// # Parameters
+///
+/// * `data`: The bytes to encode.
+///
+/// # Returns
+///
+/// The uppercase hex string of `data`.
+pub fn encode_hex_upper(data: &[u8]) -> String {
+    hex::encode_upper(data)
+}
+
+// Convert a nibble to its uppercase hex digit: '0' + n, plus 7 to skip to 'A' if n > 9.
+fn ct_hex_digit(nibble: u8) -> u8 {
+    let nibble = nibble as u16;
+    let above_nine = (9u16.wrapping_sub(nibble) >> 8) & 7;
+    (b'0' as u16 + nibble + above_nine) as u8
+}
+
+// Convert a hex digit to its value, returned together with a mask of 0xFF if the digit is valid
+// and 0x00 otherwise. The masks are all ones if `lower <= c <= upper`, computed from the sign of
+// `(lower - 1 - c) & (c - upper - 1)`.
+fn ct_hex_value(c: u8) -> (u8, u8) {
+    let c = c as i16;
+    let is_digit = ((0x2F - c) & (c - 0x3A)) >> 8;
+    let is_upper = ((0x40 - c) & (c - 0x47)) >> 8;
+    let is_lower = ((0x60 - c) & (c - 0x67)) >> 8;
+
+    let value = (is_digit & (c - 0x30)) | (is_upper & (c - 0x37)) | (is_lower & (c - 0x57));
+    let valid = is_digit | is_upper | is_lower;
+
+    (value as u8, valid as u8)
+}
+
+/// A card scheme identified by the leading digits (BIN) of a PAN.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CardScheme {
+    Visa,
+    Mastercard,
+    Amex,
+    Discover,
+    Jcb,
+    DinersClub,
+    UnionPay,
+}
+
+impl CardScheme {
+    /// Returns the name of the card scheme.
+    pub fn name(&self) -> &'static str {
+        match self {
+            CardScheme::Visa => "Visa",
+            CardScheme::Mastercard => "Mastercard",
+            CardScheme::Amex => "American Express",
+            CardScheme::Discover => "Discover",
+            CardScheme::Jcb => "JCB",
+            CardScheme::DinersClub => "Diners Club",
+            CardScheme::UnionPay => "UnionPay",
+        }
+    }
+
+    /// Returns the PAN lengths issued by the card scheme.
+    pub fn valid_lengths(&self) -> &'static [usize] {
+        match self {
+            CardScheme::Visa => &[13, 16, 19],
+            CardScheme::Mastercard => &[16],
+            CardScheme::Amex => &[15],
+            CardScheme::Discover | CardScheme::Jcb | CardScheme::UnionPay => &[16, 17, 18, 19],
+            CardScheme::DinersClub => &[14, 15, 16, 17, 18, 19],
+        }
+    }
+
+    // Identify the card scheme from the BIN ranges only, regardless of the PAN length.
+    fn from_bin(pan: &str) -> Option<Self> {
+        if !pan.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        // The numeric value of the first `len` digits, if the PAN is long enough
+        let prefix = |len: usize| pan.get(..len).and_then(|p| p.parse::<u32>().ok());
+        let in_range = |len: usize, first: u32, last: u32| {
+            prefix(len).is_some_and(|p| (first..=last).contains(&p))
+        };
+
+        if in_range(1, 4, 4) {
+            Some(CardScheme::Visa)
+        } else if in_range(2, 51, 55) || in_range(4, 2221, 2720) {
+            Some(CardScheme::Mastercard)
+        } else if in_range(2, 34, 34) || in_range(2, 37, 37) {
+            Some(CardScheme::Amex)
+        } else if in_range(4, 6011, 6011) || in_range(3, 644, 649) || in_range(2, 65, 65) {
+            Some(CardScheme::Discover)
+        } else if in_range(4, 3528, 3589) {
+            Some(CardScheme::Jcb)
+        } else if in_range(3, 300, 305) || in_range(2, 36, 36) || in_range(2, 38, 39) {
+            Some(CardScheme::DinersClub)
+        } else if in_range(2, 62, 62) {
+            Some(CardScheme::UnionPay)
+        } else {
+            None
+        }
+    }
+}
+
+/// Detect the card scheme of a PAN from its BIN range and length.
+///
+/// This is metadata for realistic test data only: the BIN ranges are a simplified subset of
+/// the published ranges and may overlap between schemes in practice.
+///
+/// # Parameters
+///
+/// * `pan`: The PAN as a string of decimal digits.
+///
+/// # Returns
+///
+/// The card scheme if the leading digits are in one of its BIN ranges and the length is issued
+/// by the scheme, `None` otherwise.
+///
+/// # Example
+/// ```
+/// use paysec::utils::{detect_pan_scheme, CardScheme};
+///
+/// assert_eq!(detect_pan_scheme("4111111111111111"), Some(CardScheme::Visa));
+/// assert_eq!(detect_pan_scheme("378282246310005"), Some(CardScheme::Amex));
+/// assert_eq!(detect_pan_scheme("41111111111111111"), None);
+/// ```
+pub fn detect_pan_scheme(pan: &str) -> Option<CardScheme> {
+    CardScheme::from_bin(pan).filter(|scheme| scheme.valid_lengths().contains(&pan.len()))
+}
+
+/// Check the length of a PAN against the card scheme of its BIN range.
+///
+/// PANs whose leading digits are not in a known BIN range pass the check, e.g. test PANs
+/// starting with `1`.
+///
+/// # Errors
+///
+/// Returns an error if the leading digits belong to a card scheme which does not issue PANs of
+/// this length.
+pub fn check_pan_scheme_length(pan: &str) -> Result<(), Box<dyn Error>> {
+    match CardScheme::from_bin(pan) {
+        Some(scheme) if !scheme.valid_lengths().contains(&pan.len()) => Err(format!(
+            "PAN ERROR: PAN length {} is not valid for card scheme {}, expected one of {:?}",
+            pan.len(),
+            scheme.name(),
+            scheme.valid_lengths()
+        )
+        .into()),
+        _ => Ok(()),
+    }
+}
+
+// Overwrite a buffer with zeros using volatile writes, so the compiler cannot elide them.
+#[cfg(feature = "keyblock")]
+pub(crate) fn wipe(buf: &mut [u8]) {
+    for byte in buf.iter_mut() {
+        // SAFETY: `byte` is a valid, aligned and exclusive reference into the buffer.
+        unsafe { std::ptr::write_volatile(byte, 0) };
+    }
+    compiler_fence(Ordering::SeqCst);
+}
+
+/// Sanity check a caller-provided random seed.
+///
+/// The random seeds used for key block padding and PIN fields must be output of a
+/// cryptographically secure random number generator (CSPRNG). This check cannot assess the
+/// quality of the seed, but it rejects seeds which are obviously not random, e.g. placeholder
+/// values from test harnesses. With the `strict-seed` feature it is applied to the seeds of
+/// `construct_payload` and the PIN field encoders.
+///
+/// # Parameters
+///
+/// * `seed`: The random seed to check.
+/// * `required_len`: The number of seed bytes required by the caller.
+///
+/// # Errors
+///
+/// This function will return an error if:
+/// - The seed is shorter than `required_len`.
+/// - The first `required_len` bytes of the seed are all one byte value.
+/// - The first `required_len` bytes of the seed strictly repeat a 2-byte pattern.
+pub fn check_seed_entropy(seed: &[u8], required_len: usize) -> Result<(), Box<dyn Error>> {
+    if seed.len() < required_len {
+        return Err(PaysecError::SeedTooShort {
+            context: "ERROR SEED",
+            len: seed.len(),
+            required: required_len,
+        }
+        .into());
+    }
+
+    let seed = &seed[..required_len];
+    if seed.len() >= 2 && seed.iter().all(|&b| b == seed[0]) {
+        return Err(format!(
+            "ERROR SEED: Random seed consists of the single byte value {:02X}, the caller must supply CSPRNG output",
+            seed[0]
+        )
+        .into());
+    }
+    if seed.len() >= 4 && seed.iter().skip(2).zip(seed).all(|(a, b)| a == b) {
+        return Err(format!(
+            "ERROR SEED: Random seed repeats the 2-byte pattern {}, the caller must supply CSPRNG output",
+            hex::encode_upper(&seed[..2])
+        )
+        .into());
+    }
+
+    Ok(())
+}
+
+/// Check whether every byte of a DES/TDES key has odd parity.
+///
+/// The least significant bit of each DES key byte is a parity bit which is set so that the byte
+/// has an odd number of 1 bits.
+///
+/// # Parameters
+///
+/// * `key`: The DES or TDES key to check.
+///
+/// # Returns
+///
+/// `true` if all bytes have odd parity, `false` otherwise.
+pub fn has_odd_parity(key: &[u8]) -> bool {
+    key.iter().all(|byte| byte.count_ones() % 2 == 1)
+}
+
+/// Adjust the parity bit of every byte of a DES/TDES key to odd parity in place.
+///
+/// # Parameters
+///
+/// * `key`: The DES or TDES key to adjust.
+pub fn adjust_odd_parity(key: &mut [u8]) {
+    for byte in key.iter_mut() {
+        if byte.count_ones() % 2 == 0 {
+            *byte ^= 0x01;
+        }
+    }
+}
+
+/// Compute the AES-CMAC subkeys K1 and K2 (NIST SP 800-38B, section 6.1).
+///
+/// The AES-CMAC used throughout this crate (e.g. for the TR-31 version 'D' key derivation and
+/// MAC) masks the last message block with one of these subkeys: K1 if the last block is
+/// complete, K2 if it is padded. Exposing them helps to debug CMAC mismatches against
+/// reference implementations and the NIST SP 800-38B examples.
+///
+/// The subkeys are derived from `L = AES(key, 0^128)` by a left shift of one bit, followed by
+/// an XOR with `0x87` in the last byte if the most significant bit was set: `K1 = dbl(L)` and
+/// `K2 = dbl(K1)`.
+///
+/// # Parameters
+///
+/// * `key`: The AES key of 16, 24 or 32 bytes.
+///
+/// # Returns
+///
+/// * `Ok(([u8; 16], [u8; 16]))` - The subkeys K1 and K2.
+///
+/// # Errors
+///
+/// This function will return an error if the key length is invalid.
+#[cfg(any(feature = "keyblock", feature = "pin"))]
+pub fn aes_cmac_subkeys(
+    key: &[u8],
+) -> Result<([u8; AES_BLOCK_LEN], [u8; AES_BLOCK_LEN]), Box<dyn Error>> {
+    let l = aes_enc_ecb(&[0u8; AES_BLOCK_LEN], key)?;
+    let k1 = cmac_dbl(&l);
+    let k2 = cmac_dbl(&k1);
+
+    Ok((k1, k2))
+}
+
+// Multiply a block by x in GF(2^128) with the CMAC constant Rb = 0x87.
+#[cfg(any(feature = "keyblock", feature = "pin"))]
+fn cmac_dbl(block: &[u8]) -> [u8; AES_BLOCK_LEN] {
+    let mut output = [0u8; AES_BLOCK_LEN];
+    for i in 0..AES_BLOCK_LEN {
+        let carry = block.get(i + 1).map_or(0, |next| next >> 7);
+        output[i] = (block[i] << 1) | carry;
+    }
+    if block[0] & 0x80 != 0 {
+        output[AES_BLOCK_LEN - 1] ^= 0x87;
+    }
+    output
+}
+
+/// A source of seed bytes for the random padding of key blocks and PIN blocks.
+///
+/// All functions of this crate that need randomness take the seed bytes as an explicit
+/// argument. Implementations of this trait produce these seed bytes, e.g. from a CSPRNG in
+/// production or from a `DeterministicRng` in tests.
+pub trait SeedSource {
+    /// Fill `dest` completely with seed bytes.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the source is unable to provide the requested bytes.
+    fn fill_seed(&mut self, dest: &mut [u8]) -> Result<(), Box<dyn Error>>;
+
+    /// Return `len` seed bytes as a new vector.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the source is unable to provide the requested bytes.
+    fn seed(&mut self, len: usize) -> Result<Vec<u8>, Box<dyn Error>> {
+        let mut seed = vec![0u8; len];
+        self.fill_seed(&mut seed)?;
+        Ok(seed)
+    }
+}
+
+/// A reproducible byte stream for tests and fixture generation.
+///
+/// **Not for production use.** The output is fully determined by the seed passed to
+/// `from_seed`, it is intended to make test data stable across releases only.
+///
+/// # Stream Definition
+///
+/// The stream is stable and defined as follows:
+/// 1. The AES-128 stream key is `K = AES-CMAC(0x00 * 16, seed)`.
+/// 2. The output is the AES-128-CTR keystream under `K`, i.e. the concatenation of
+///    `AES-ECB(K, counter)` with a 16-byte big-endian counter starting at zero.
+///
+/// # Example
+/// ```
+/// use paysec::utils::{DeterministicRng, SeedSource};
+///
+/// let mut rng = DeterministicRng::from_seed(b"paysec test fixtures").unwrap();
+/// let padding_seed = rng.seed(14).unwrap();
+/// assert_eq!(padding_seed.len(), 14);
+/// ```
+#[cfg(any(feature = "keyblock", feature = "pin"))]
+pub struct DeterministicRng {
+    key: [u8; AES_BLOCK_LEN],
+    counter: u128,
+    block: [u8; AES_BLOCK_LEN],
+    block_pos: usize,
+}
+
+#[cfg(any(feature = "keyblock", feature = "pin"))]
+impl DeterministicRng {
+    /// Create a new deterministic stream from a seed of arbitrary length.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the derivation of the stream key fails.
+    pub fn from_seed(seed: &[u8]) -> Result<Self, Box<dyn Error>> {
+        let key = aes_cmac(seed, &[0u8; AES_BLOCK_LEN])?;
+
+        Ok(DeterministicRng {
+            key,
+            counter: 0,
+            block: [0u8; AES_BLOCK_LEN],
+            block_pos: AES_BLOCK_LEN,
+        })
+    }
+
+    // Encrypt the next counter value into the internal keystream block.
+    fn refill(&mut self) -> Result<(), Box<dyn Error>> {
+        let keystream = aes_enc_ecb(&self.counter.to_be_bytes(), &self.key)?;
+        self.block.copy_from_slice(&keystream);
+        self.block_pos = 0;
+        self.counter = self.counter.wrapping_add(1);
+        Ok(())
+    }
+}
+
+// The stream key and the buffered keystream are redacted.
+#[cfg(any(feature = "keyblock", feature = "pin"))]
+impl std::fmt::Debug for DeterministicRng {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DeterministicRng")
+            .field("key", &"<redacted>")
+            .field("counter", &self.counter)
+            .finish()
+    }
+}
+
+#[cfg(any(feature = "keyblock", feature = "pin"))]
+impl SeedSource for DeterministicRng {
+    fn fill_seed(&mut self, dest: &mut [u8]) -> Result<(), Box<dyn Error>> {
+        for byte in dest.iter_mut() {
+            if self.block_pos == AES_BLOCK_LEN {
+                self.refill()?;
+            }
+            *byte = self.block[self.block_pos];
+            self.block_pos += 1;
+        }
+        Ok(())
+    }
+}
+
+/// Derive a deterministic seed of `len` bytes from a label.
+///
+/// **Not for production use.** This gives tests distinct, reproducible seeds without
+/// hardcoding hex values. The seed is the first `len` bytes of the `DeterministicRng` stream
+/// seeded with the label, so it is stable across releases.
+///
+/// # Example
+/// ```
+/// use paysec::utils::seed_from_label;
+///
+/// let seed = seed_from_label("version b round trip", 14);
+/// assert_eq!(seed.len(), 14);
+/// assert_eq!(seed, seed_from_label("version b round trip", 14));
+/// assert_ne!(seed, seed_from_label("version d round trip", 14));
+/// ```
+#[cfg(any(feature = "keyblock", feature = "pin"))]
+pub fn seed_from_label(label: &str, len: usize) -> Vec<u8> {
+    // The stream uses a fixed AES-128 key for the derivation and its own AES-128 stream key, so
+    // the AES operations cannot fail.
+    DeterministicRng::from_seed(label.as_bytes())
+        .and_then(|mut rng| rng.seed(len))
+        .expect("AES-128 keys of the deterministic stream are always valid")
+}
+
+/// Generate a random seed of `len` bytes from the operating system CSPRNG.
+///
+/// The seed can be passed to the functions taking an explicit random seed, e.g. `tr31_wrap` or
+/// `encode_pinblock_iso_3`. The function requires the `rng` feature.
+///
+/// # Panics
+///
+/// Panics if the operating system random number generator fails.
+///
+/// # Example
+/// ```
+/// use paysec::utils::generate_random_seed;
+///
+/// let seed = generate_random_seed(14);
+/// assert_eq!(seed.len(), 14);
+/// assert_ne!(seed, generate_random_seed(14));
+/// ```
+#[cfg(feature = "rng")]
+pub fn generate_random_seed(len: usize) -> Vec<u8> {
+    use rand::RngCore;
+
+    let mut seed = vec![0u8; len];
+    rand::rngs::OsRng.fill_bytes(&mut seed);
+    seed
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[cfg(feature = "keyblock")]
+    #[test]
+    fn test_wipe() {
+        let mut buf = hex::decode("3F419E1CB7079442AA37474C2EFBF8B8").unwrap();
+        wipe(&mut buf);
+        assert_eq!(buf, [0u8; 16]);
+
+        // A partial wipe leaves the rest of the buffer unchanged
+        let mut buf = vec![0xA5; 8];
+        wipe(&mut buf[2..6]);
+        assert_eq!(buf, [0xA5, 0xA5, 0, 0, 0, 0, 0xA5, 0xA5]);
+    }
+
     #[test]
     fn test_xor_byte_arrays() {
         // Test case 1: Equal-length arrays, result should be XORed correctly.
@@ -183,4 +790,243 @@ mod tests {
             "Nibbles were not correctly transformed to A-F range."
         );
     }
+
+    #[test]
+    fn test_transform_nibbles_to_af_unbiased() {
+        // The nibble C of 0xBC is rejected
+        let input = vec![0x45, 0x82, 0x1A, 0xBC, 0x09, 0x34];
+
+        assert_eq!(
+            transform_nibbles_to_af_unbiased(&input, 5).unwrap(),
+            vec![0xEF, 0xCC, 0xBE, 0xFA, 0xDD]
+        );
+        assert!(transform_nibbles_to_af_unbiased(&input, 6).is_err());
+        assert!(transform_nibbles_to_af_unbiased(&[0xFF; 32], 1).is_err());
+    }
+
+    #[test]
+    fn test_detect_pan_scheme() {
+        // Visa and American Express test PANs
+        assert_eq!(
+            detect_pan_scheme("4111111111111111"),
+            Some(CardScheme::Visa)
+        );
+        assert_eq!(detect_pan_scheme("4222222222222"), Some(CardScheme::Visa));
+        assert_eq!(detect_pan_scheme("378282246310005"), Some(CardScheme::Amex));
+        assert_eq!(detect_pan_scheme("371449635398431"), Some(CardScheme::Amex));
+
+        assert_eq!(
+            detect_pan_scheme("5555555555554444"),
+            Some(CardScheme::Mastercard)
+        );
+        assert_eq!(
+            detect_pan_scheme("2223003122003222"),
+            Some(CardScheme::Mastercard)
+        );
+        assert_eq!(
+            detect_pan_scheme("6011111111111117"),
+            Some(CardScheme::Discover)
+        );
+        assert_eq!(detect_pan_scheme("3530111333300000"), Some(CardScheme::Jcb));
+        assert_eq!(
+            detect_pan_scheme("30569309025904"),
+            Some(CardScheme::DinersClub)
+        );
+        assert_eq!(
+            detect_pan_scheme("6200000000000005"),
+            Some(CardScheme::UnionPay)
+        );
+
+        // Wrong length for the scheme, unknown BIN range and non-digits
+        assert_eq!(detect_pan_scheme("3782822463100051"), None);
+        assert_eq!(detect_pan_scheme("1234567890123456"), None);
+        assert_eq!(detect_pan_scheme("4111 1111 1111 1111"), None);
+    }
+
+    #[test]
+    fn test_check_pan_scheme_length() {
+        assert!(check_pan_scheme_length("4111111111111111").is_ok());
+        assert!(check_pan_scheme_length("1234567890123456789").is_ok());
+        assert_eq!(
+            check_pan_scheme_length("43219876543210987")
+                .unwrap_err()
+                .to_string(),
+            "PAN ERROR: PAN length 17 is not valid for card scheme Visa, expected one of [13, 16, 19]"
+        );
+    }
+
+    #[test]
+    fn test_check_seed_entropy_all_zero() {
+        let res = check_seed_entropy(&[0u8; 14], 14);
+        assert_eq!(
+            res.err().unwrap().to_string(),
+            "ERROR SEED: Random seed consists of the single byte value 00, the caller must supply CSPRNG output"
+        );
+    }
+
+    #[test]
+    fn test_check_seed_entropy_ff_repeat() {
+        assert!(check_seed_entropy(&[0xFF; 8], 8).is_err());
+
+        let res = check_seed_entropy(&hex::decode("FF00FF00FF00FF00").unwrap(), 8);
+        assert_eq!(
+            res.err().unwrap().to_string(),
+            "ERROR SEED: Random seed repeats the 2-byte pattern FF00, the caller must supply CSPRNG output"
+        );
+    }
+
+    #[test]
+    fn test_check_seed_entropy_too_short() {
+        let res = check_seed_entropy(&hex::decode("1C2965473CE206").unwrap(), 8);
+        assert_eq!(
+            res.err().unwrap().to_string(),
+            "SEED-0001: ERROR SEED: Random seed is too short: 7 bytes, required 8 bytes of CSPRNG output"
+        );
+    }
+
+    #[test]
+    fn test_check_seed_entropy_normal_seed() {
+        let seed = hex::decode("1C2965473CE206BB855B01533782").unwrap();
+        assert!(check_seed_entropy(&seed, 14).is_ok());
+
+        // Only the required prefix of the seed is checked
+        assert!(check_seed_entropy(&seed, 0).is_ok());
+        assert!(check_seed_entropy(&[0x1C, 0x1C, 0x1C, 0x1C, 0x29], 4).is_err());
+    }
+
+    #[test]
+    fn test_ct_hex_encode_upper_all_bytes() {
+        let data: Vec<u8> = (0..=255).collect();
+        assert_eq!(ct_hex_encode_upper(&data), hex::encode_upper(&data));
+        assert_eq!(ct_hex_encode_upper(&[]), "");
+    }
+
+    #[test]
+    fn test_ct_hex_decode_all_bytes() {
+        let data: Vec<u8> = (0..=255).collect();
+        assert_eq!(ct_hex_decode(&hex::encode_upper(&data)).unwrap(), data);
+        assert_eq!(ct_hex_decode(&hex::encode(&data)).unwrap(), data);
+    }
+
+    #[test]
+    fn test_decode_hex() {
+        assert_eq!(decode_hex("0aFF").unwrap(), vec![0x0A, 0xFF]);
+        assert_eq!(encode_hex_upper(&[0x0A, 0xFF]), "0AFF");
+        assert!(decode_hex("0A F").is_err());
+        assert!(decode_hex("0G").is_err());
+    }
+
+    #[test]
+    fn test_ct_hex_decode_invalid() {
+        for c in (0..=255u8).map(char::from) {
+            let input = format!("{}0", c);
+            assert_eq!(
+                ct_hex_decode(&input).is_ok(),
+                hex::decode(&input).is_ok(),
+                "{:?}",
+                input
+            );
+        }
+
+        assert_eq!(
+            ct_hex_decode("ABC").err().unwrap().to_string(),
+            "ERROR HEX: Odd number of hex digits"
+        );
+        assert_eq!(
+            ct_hex_decode("AG").err().unwrap().to_string(),
+            "ERROR HEX: Invalid hex character"
+        );
+    }
+
+    #[test]
+    fn test_odd_parity() {
+        let mut key = hex::decode("00112233445566778899AABBCCDDEEFF").unwrap();
+        assert!(!has_odd_parity(&key));
+
+        adjust_odd_parity(&mut key);
+        assert_eq!(hex::encode_upper(&key), "01102332455467768998ABBACDDCEFFE");
+        assert!(has_odd_parity(&key));
+    }
+
+    #[cfg(any(feature = "keyblock", feature = "pin"))]
+    #[test]
+    fn test_deterministic_rng_first_64_bytes() {
+        let seed = hex::decode("000102030405060708090A0B0C0D0E0F").unwrap();
+        let mut rng = DeterministicRng::from_seed(&seed).unwrap();
+
+        assert_eq!(
+            hex::encode_upper(rng.seed(64).unwrap()),
+            "869A625BD744E2CA8CB9F468DEF26419BB6623DE3AFC568C4A99BCAB121FC377\
+             B97E2D9BC966DF3004014E4CAAF9E255412FA40401EA6DA2637E367E7A3BF233"
+        );
+    }
+
+    #[cfg(any(feature = "keyblock", feature = "pin"))]
+    #[test]
+    fn test_deterministic_rng_chunked_reads_match() {
+        let seed = b"paysec";
+        let mut rng_a = DeterministicRng::from_seed(seed).unwrap();
+        let mut rng_b = DeterministicRng::from_seed(seed).unwrap();
+
+        let whole = rng_a.seed(40).unwrap();
+        let mut chunked = rng_b.seed(7).unwrap();
+        chunked.extend(rng_b.seed(20).unwrap());
+        chunked.extend(rng_b.seed(13).unwrap());
+
+        assert_eq!(whole, chunked);
+        assert_ne!(
+            DeterministicRng::from_seed(b"other")
+                .unwrap()
+                .seed(40)
+                .unwrap(),
+            whole
+        );
+    }
+
+    #[cfg(any(feature = "keyblock", feature = "pin"))]
+    #[test]
+    fn test_deterministic_rng_debug_redacts_key() {
+        let rng = DeterministicRng::from_seed(b"paysec test fixtures").unwrap();
+
+        let debug = format!("{:?}", rng);
+        assert!(!debug.contains(&hex::encode_upper(rng.key)));
+        assert!(!debug.contains(&format!("{:?}", rng.key)));
+        assert_eq!(
+            debug,
+            "DeterministicRng { key: \"<redacted>\", counter: 0 }"
+        );
+    }
+
+    #[cfg(any(feature = "keyblock", feature = "pin"))]
+    #[test]
+    fn test_seed_from_label() {
+        let seed = seed_from_label("tr31 version b", 32);
+        assert_eq!(seed.len(), 32);
+        assert_eq!(seed, seed_from_label("tr31 version b", 32));
+        assert_ne!(seed, seed_from_label("tr31 version d", 32));
+
+        // Shorter seeds are a prefix of longer seeds of the same label
+        assert_eq!(seed_from_label("tr31 version b", 5), seed[..5]);
+        assert!(seed_from_label("tr31 version b", 0).is_empty());
+
+        let mut rng = DeterministicRng::from_seed(b"tr31 version b").unwrap();
+        assert_eq!(seed, rng.seed(32).unwrap());
+        check_seed_entropy(&seed, 32).unwrap();
+    }
+
+    #[cfg(any(feature = "keyblock", feature = "pin"))]
+    #[test]
+    fn test_aes_cmac_subkeys_sp800_38b() {
+        let key = hex::decode("2B7E151628AED2A6ABF7158809CF4F3C").unwrap();
+        let (k1, k2) = aes_cmac_subkeys(&key).unwrap();
+
+        assert_eq!(hex::encode_upper(k1), "FBEED618357133667C85E08F7236A8DE");
+        assert_eq!(hex::encode_upper(k2), "F7DDAC306AE266CCF90BC11EE46D513B");
+    }
+
+    #[cfg(any(feature = "keyblock", feature = "pin"))]
+    #[test]
+    fn test_aes_cmac_subkeys_invalid_key_length() {
+        assert!(aes_cmac_subkeys(&[0u8; 17]).is_err());
+    }
 }
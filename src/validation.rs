@@ -0,0 +1,158 @@
+//! Validation policy shared by the parsing, wrapping, unwrapping and deciphering functions.
+//!
+//! # Description
+//!
+//! Some checks of this crate are a matter of policy rather than of the standards, e.g. whether
+//! proprietary optional block IDs are accepted from a peer or whether the filler of a PIN block
+//! is verified. A `ValidationConfig` collects these checks in one value, so an integrator can set
+//! a single policy for a connection profile and pass it to every `*_with_options` function:
+//!
+//! - `KeyBlockHeader::new_from_str_with_options`
+//! - `tr31_wrap_with_options`
+//! - `tr31_unwrap_with_options`
+//! - `tr31_clone_block_with_options`
+//! - `decode_pinblock_iso_3_with_options`
+//! - `decipher_pinblock_iso_4_with_options`
+//!
+//! Each function only applies the checks relevant to it. `ValidationConfig::default()` is the
+//! behaviour of the functions without options, `ValidationConfig::strict()` enables all checks
+//! and `ValidationConfig::lenient()` disables all optional checks. Single checks are overridden
+//! with struct update syntax.
+//!
+//! # Example
+//! ```
+//! use paysec::validation::{TdesParityCheck, ValidationConfig};
+//!
+//! // Strict policy, but accept key blocks with proprietary optional blocks.
+//! let config = ValidationConfig {
+//!     known_opt_block_ids: false,
+//!     ..ValidationConfig::strict()
+//! };
+//!
+//! assert!(config.zero_reserved_field);
+//! assert_eq!(config.tdes_parity, TdesParityCheck::Error);
+//! ```
+
+/// Handling of the DES parity of keys recovered from key blocks with algorithm 'T' (TDES).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TdesParityCheck {
+    /// Do not check the parity of the recovered key.
+    #[default]
+    Off,
+    /// Fail the unwrapping if the recovered key does not have odd parity.
+    Error,
+    /// Set `parity_warning` in the result if the recovered key does not have odd parity.
+    Warn,
+    /// Adjust the recovered key to odd parity and set `parity_warning` if it was changed.
+    Correct,
+}
+
+/// The policy of the optional validation checks.
+///
+/// A field set to `true` enables the check.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidationConfig {
+    /// Reject key block headers whose reserved field is not "00".
+    pub zero_reserved_field: bool,
+    /// Reject optional block IDs not defined by TR-31. If disabled, proprietary IDs of two
+    /// alphanumeric characters starting with a digit are accepted.
+    pub known_opt_block_ids: bool,
+    /// Reject modes of use not defined by TR-31. If disabled, proprietary numeric modes of use
+    /// are accepted.
+    pub known_modes_of_use: bool,
+    /// Reject a masked key length which is neither zero nor at least the key length, as it
+    /// would not mask the length of the key.
+    pub masked_key_len: bool,
+    /// Apply `check_seed_entropy` to the padding seed when wrapping.
+    pub seed_entropy: bool,
+    /// Verify the filler nibbles of ISO 9564 format 3 and 4 PIN blocks.
+    pub pin_filler: bool,
+    /// Reject a PAN whose length is not issued by the card scheme of its BIN range, see
+    /// `check_pan_scheme_length`.
+    pub pan_scheme_length: bool,
+    /// Verify the KCV of the KBPK against a `KP` optional block before unwrapping, so a wrong
+    /// KBPK is reported as `PaysecError::KbpkKcvMismatch` instead of as a MAC failure.
+    pub kbpk_kcv: bool,
+    /// Refuse to wrap the key of a key block with exportability 'N' (non-exportable) into a new
+    /// key block.
+    pub non_exportable: bool,
+    /// Parity handling of TDES keys, applied only if the header algorithm is 'T'.
+    pub tdes_parity: TdesParityCheck,
+}
+
+impl ValidationConfig {
+    /// Enable all checks and fail on TDES keys without odd parity.
+    pub fn strict() -> Self {
+        ValidationConfig {
+            zero_reserved_field: true,
+            known_opt_block_ids: true,
+            known_modes_of_use: true,
+            masked_key_len: true,
+            seed_entropy: true,
+            pin_filler: true,
+            pan_scheme_length: true,
+            kbpk_kcv: true,
+            non_exportable: true,
+            tdes_parity: TdesParityCheck::Error,
+        }
+    }
+
+    /// Disable all optional checks, e.g. to interoperate with peers producing non-conforming
+    /// key blocks or PIN blocks.
+    pub fn lenient() -> Self {
+        ValidationConfig {
+            zero_reserved_field: false,
+            known_opt_block_ids: false,
+            known_modes_of_use: false,
+            masked_key_len: false,
+            seed_entropy: false,
+            pin_filler: false,
+            pan_scheme_length: false,
+            kbpk_kcv: false,
+            non_exportable: false,
+            tdes_parity: TdesParityCheck::Off,
+        }
+    }
+}
+
+impl Default for ValidationConfig {
+    /// The checks applied by the functions without options.
+    fn default() -> Self {
+        ValidationConfig {
+            zero_reserved_field: true,
+            known_opt_block_ids: true,
+            known_modes_of_use: true,
+            masked_key_len: false,
+            seed_entropy: false,
+            pin_filler: true,
+            pan_scheme_length: false,
+            kbpk_kcv: false,
+            non_exportable: true,
+            tdes_parity: TdesParityCheck::Off,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_between_lenient_and_strict() {
+        let default = ValidationConfig::default();
+
+        assert_ne!(default, ValidationConfig::strict());
+        assert_ne!(default, ValidationConfig::lenient());
+        assert_eq!(
+            ValidationConfig {
+                masked_key_len: true,
+                seed_entropy: true,
+                pan_scheme_length: true,
+                kbpk_kcv: true,
+                tdes_parity: TdesParityCheck::Error,
+                ..default
+            },
+            ValidationConfig::strict()
+        );
+    }
+}
@@ -0,0 +1,71 @@
+//! Heap allocations of `HeaderView` parsing.
+//!
+//! The counting global allocator is installed for this test binary only, so the unit tests of
+//! the library run with the system allocator.
+#![cfg(feature = "keyblock")]
+
+use paysec::keyblock::{HeaderView, KeyBlockHeader};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+// Counts the heap allocations of the current thread, so the tests running in parallel do not
+// disturb each other.
+struct CountingAllocator;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+fn allocations<T>(f: impl FnOnce() -> T) -> (T, usize) {
+    let before = ALLOCATIONS.with(Cell::get);
+    let result = f();
+    (result, ALLOCATIONS.with(Cell::get) - before)
+}
+
+const HEADERS: [&str; 6] = [
+    "D0112P0AE00E0000",
+    "B0080P0TE00E0000",
+    "D0144P0AE00E0200KS1800604B120F9292800000PB0800001234",
+    "D0000K1AB01N0400KS0A00604BKV0A010000TS1320240101120000ZPB0900000",
+    "C0072D0TDc1S0100PB080000",
+    "D0000M3AC00N0200CT0CSomeDataPB140000000000000000",
+];
+
+#[test]
+fn test_header_view_parse_does_not_allocate() {
+    for header_str in HEADERS {
+        let (view, count) = allocations(|| HeaderView::parse(header_str).unwrap());
+        assert_eq!(count, 0, "{}", header_str);
+
+        let (blocks, count) = allocations(|| {
+            view.opt_blocks()
+                .map(|block| block.data().len())
+                .sum::<usize>()
+        });
+        assert!(blocks <= view.len());
+        assert_eq!(count, 0, "{}", header_str);
+    }
+
+    // The counter detects allocations
+    let (_, count) = allocations(|| KeyBlockHeader::new_from_str(HEADERS[2]).unwrap());
+    assert!(count > 0);
+}